@@ -1,13 +1,43 @@
-use anyhow::Result;
+use anyhow::{Context, Result, anyhow};
 use crossterm::event::{Event, KeyCode};
+use ratatui::layout::Rect;
+use regex::Regex;
 use std::collections::HashSet;
 use std::env;
+use std::ffi::{OsStr, OsString};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinHandle;
 
-use crate::sftp::{FileInfo, SftpClient};
+use crate::config::{self, AppConfig};
+use crate::history::InputHistory;
+use crate::hook::HookSide;
+use crate::keymap::{Action, Keymap};
+use crate::osc52;
+use crate::owners::OwnerNames;
+use crate::recent::RecentHosts;
+use crate::rename::{self, PlannedRename, RenameStatus};
+use crate::search::{self, SearchResults};
+use crate::sftp::{self, ConnectCancel, ConnectParams, DiskSpace, FileInfo, SftpClient, SftpError};
+use crate::shell;
 use crate::ssh_config::{SshConfig, SshHost};
+use crate::store::Stored;
+use crate::transfer::{
+    self, ContentCheck, TransferClient, TransferOptions, TransferOutcome, TransferProgress,
+    TransferResult,
+};
+use crate::transfer_log::TransferLog;
+use crate::tree::TreeView;
+use crate::trusted_hosts::TrustedHosts;
 use crate::ui::Ui;
+use crate::view_state::ViewState;
+use crate::watcher::{self, LocalWatcher};
+
+/// Remote files larger than this aren't opened in the editor, since they are
+/// downloaded in full first.
+const MAX_EDIT_BYTES: u64 = 16 * 1024 * 1024;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Pane {
@@ -15,7 +45,56 @@ pub enum Pane {
     Remote,
 }
 
-#[derive(Debug, Clone)]
+/// Which kinds of entry a pane lists. `..` is always listed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TypeFilter {
+    #[default]
+    All,
+    Directories,
+    Files,
+}
+
+impl TypeFilter {
+    /// The filter after this one, round to `All` again.
+    fn next(self) -> Self {
+        match self {
+            TypeFilter::All => TypeFilter::Directories,
+            TypeFilter::Directories => TypeFilter::Files,
+            TypeFilter::Files => TypeFilter::All,
+        }
+    }
+
+    fn keeps(self, file: &FileInfo) -> bool {
+        match self {
+            TypeFilter::All => true,
+            _ if file.name == ".." => true,
+            TypeFilter::Directories => file.is_dir,
+            TypeFilter::Files => !file.is_dir,
+        }
+    }
+
+    /// Shown after the pane's path while the filter is on.
+    pub fn label(self) -> &'static str {
+        match self {
+            TypeFilter::All => "",
+            TypeFilter::Directories => " [dirs]",
+            TypeFilter::Files => " [files]",
+        }
+    }
+}
+
+impl Pane {
+    /// Where the pane is drawn: 0 for the left (or top) half, 1 for the
+    /// other, with the sides exchanged when `swapped`.
+    pub fn column(&self, swapped: bool) -> usize {
+        match (self, swapped) {
+            (Pane::Local, false) | (Pane::Remote, true) => 0,
+            (Pane::Remote, false) | (Pane::Local, true) => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TransferDirection {
     Upload,
     Download,
@@ -26,103 +105,611 @@ pub struct TransferItem {
     pub source: PathBuf,
     pub destination: PathBuf,
     pub direction: TransferDirection,
+    /// Delete the source once it has been transferred, making this a move.
+    pub remove_source: bool,
+    /// Disabled items stay in the queue but are skipped when it runs.
+    pub enabled: bool,
+}
+
+impl TransferItem {
+    /// Whether running this item changes the server: an upload, or a download
+    /// that deletes its remote source.
+    pub fn writes_remote(&self) -> bool {
+        match self.direction {
+            TransferDirection::Upload => true,
+            TransferDirection::Download => self.remove_source,
+        }
+    }
+
+    /// Whether the item's destination is its own source, which happens when
+    /// the server is this machine. `remote_realpath` resolves a remote path
+    /// the way the server sees it. Paths that can't be resolved count as
+    /// different.
+    fn overwrites_source(&self, remote_realpath: impl Fn(&Path) -> Option<PathBuf>) -> bool {
+        // The destination may not exist yet, but its directory does
+        let resolve = |path: &Path, realpath: &dyn Fn(&Path) -> Option<PathBuf>| {
+            realpath(path).or_else(|| Some(realpath(path.parent()?)?.join(path.file_name()?)))
+        };
+        let local_realpath = |path: &Path| fs::canonicalize(path).ok();
+        let (local, remote) = match self.direction {
+            TransferDirection::Upload => (&self.source, &self.destination),
+            TransferDirection::Download => (&self.destination, &self.source),
+        };
+        match (
+            resolve(local, &local_realpath),
+            resolve(remote, &remote_realpath),
+        ) {
+            (Some(local), Some(remote)) => local == remote,
+            _ => false,
+        }
+    }
+}
+
+/// A destination being typed in the transfer dialog, before the queue runs.
+#[derive(Debug, Clone)]
+pub struct DestinationEdit {
+    pub input: String,
+    pub scope: DestinationScope,
+    /// Why the last Enter was refused; the prompt stays open.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DestinationScope {
+    /// The full destination of the queued item at this index, so it can be
+    /// renamed on the way.
+    Item(usize),
+    /// The directory every queued item going this way is transferred into.
+    All(TransferDirection),
+}
+
+/// One remote connection and where it is browsing. The active tab's state
+/// lives in `App`'s remote fields; `App::tabs` holds the others.
+#[derive(Default)]
+pub struct RemoteTab {
+    pub sftp_client: Option<Arc<SftpClient>>,
+    pub connection: Option<ConnectParams>,
+    pub current_host: Option<String>,
+    pub remote_path: PathBuf,
+    pub remote_realpath: Option<PathBuf>,
+    pub remote_space: Option<DiskSpace>,
+    pub remote_files: Vec<FileInfo>,
+    pub remote_cursor: usize,
+    pub remote_selected: HashSet<usize>,
+    pub remote_tree: Option<TreeView>,
+}
+
+impl RemoteTab {
+    /// Forgets the tab's connection once it has dropped, so its title reads
+    /// "Not Connected" again. Where it was browsing is kept for a reconnect.
+    fn disconnect(&mut self) {
+        self.sftp_client = None;
+        self.connection = None;
+        self.current_host = None;
+        self.remote_realpath = None;
+        self.remote_space = None;
+        self.remote_files.clear();
+        self.remote_selected.clear();
+        self.remote_cursor = 0;
+    }
+}
+
+/// A queue waiting for the user to confirm it because of its size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LargeTransfer {
+    /// Total size of the items that could be sized up front.
+    pub bytes: u64,
+    /// Items whose size isn't known before they run, such as remote
+    /// directories. Any of these asks first, since it could be anything.
+    pub unsized_items: usize,
+}
+
+/// A server key seen for the first time, waiting for the user to trust it.
+#[derive(Debug, Clone)]
+pub struct PendingHostKey {
+    /// The host being connected to, connected again once the key is trusted.
+    pub host: SshHost,
+    /// The `hostname:port` that presented the key, which may be a jump host.
+    pub address: String,
+    pub fingerprint: String,
+}
+
+/// A connection being opened in the background.
+pub struct Connecting {
+    pub params: ConnectParams,
+    task: JoinHandle<Result<SftpClient>>,
+    cancel: ConnectCancel,
+}
+
+/// A `=` comparison hashing both copies of a file in the background.
+pub struct Comparing {
+    name: String,
+    size: u64,
+    task: JoinHandle<Result<ContentCheck>>,
+}
+
+/// A typed-in host, resolved against ssh_config and waiting for the user to
+/// check its settings before connecting.
+#[derive(Debug, Clone)]
+pub struct PendingHostSettings {
+    pub host: SshHost,
+    /// Headers of the ssh_config blocks the settings came from.
+    pub matched: Vec<String>,
+}
+
+/// A search through the remote tree below `root`, started with `F`.
+pub struct DeepSearch {
+    pub query: String,
+    /// Still typing the query; the walk starts on Enter.
+    pub editing: bool,
+    pub root: PathBuf,
+    pub results: Arc<Mutex<SearchResults>>,
+    pub cursor: usize,
+    cancel: Arc<AtomicBool>,
+    task: Option<JoinHandle<Result<()>>>,
+}
+
+/// The `R` dialog: a regex find/replace over the marked names, previewed
+/// before anything is renamed.
+#[derive(Debug, Clone, Default)]
+pub struct BulkRename {
+    /// The marked entries' paths, in listing order. In the tree view they
+    /// may be in different directories.
+    pub paths: Vec<PathBuf>,
+    pub find: String,
+    pub replace: String,
+    /// Typing goes to the replacement rather than the pattern.
+    pub editing_replace: bool,
+    /// What happens to each of `paths`, in the same order.
+    pub plan: Vec<PlannedRename>,
+    /// Why `find` isn't a valid pattern, if it isn't.
+    pub error: Option<String>,
+}
+
+/// What a transfer batch's worker hands back when it is done.
+struct FinishedBatch {
+    results: Vec<TransferResult>,
+    /// The worker's client, which may have reconnected along the way.
+    client: Arc<SftpClient>,
+    /// The first error writing the transfer log, if any.
+    log_error: Option<String>,
+}
+
+/// Paths marked in one pane, waiting to be pasted somewhere else.
+#[derive(Debug, Clone)]
+pub struct Clipboard {
+    pub pane: Pane,
+    pub paths: Vec<PathBuf>,
 }
 
 pub struct App {
+    pub config: AppConfig,
     pub ssh_config: SshConfig,
-    pub sftp_client: Option<SftpClient>,
+    pub sftp_client: Option<Arc<SftpClient>>,
+    pub connection: Option<ConnectParams>,
     pub current_host: Option<String>,
     pub available_hosts: Vec<SshHost>,
+    pub recent_hosts: Stored<RecentHosts>,
+    /// Queries entered at the `/` and `F` prompts, for Up and Down there.
+    pub search_history: Stored<InputHistory>,
+    pub find_history: Stored<InputHistory>,
+    /// The hidden-file toggle as last left, restored on the next launch.
+    pub view_state: Stored<ViewState>,
+    /// Local user and group names for the owner column.
+    pub owner_names: OwnerNames,
+    pub trusted_hosts: Stored<TrustedHosts>,
+    pub pending_host_key: Option<PendingHostKey>,
+    pub pending_host_settings: Option<PendingHostSettings>,
+    pub connecting: Option<Connecting>,
+    comparing: Option<Comparing>,
+    /// Every remote tab. The slot at `active_tab` is an empty placeholder
+    /// while its state is swapped into the fields above and below.
+    pub tabs: Vec<RemoteTab>,
+    pub active_tab: usize,
 
     pub active_pane: Pane,
     pub local_path: PathBuf,
     pub remote_path: PathBuf,
+    /// Canonical form of `remote_path` as the server resolves it, when that
+    /// is known. Navigation keeps following the logical path.
+    pub remote_realpath: Option<PathBuf>,
+    /// Room left on the remote filesystem, if the server would say.
+    pub remote_space: Option<DiskSpace>,
     pub local_files: Vec<FileInfo>,
     pub remote_files: Vec<FileInfo>,
     pub local_cursor: usize,
     pub remote_cursor: usize,
     pub local_selected: HashSet<usize>,
     pub remote_selected: HashSet<usize>,
+    pub show_hidden: bool,
+    /// Show entries in columns across each pane instead of one per line.
+    pub grid_view: bool,
+    /// Stack the panes top and bottom instead of side by side.
+    pub vertical_split: bool,
+    /// Draw the remote pane on the left (or top) and the local one opposite.
+    /// Only the layout changes; which pane is which stays the same.
+    pub panes_swapped: bool,
+    /// Colour entries by whether the other pane's directory has them too.
+    pub compare_dirs: bool,
+    /// Enter symlinked directories; when off, show where they point instead.
+    pub follow_symlinks: bool,
+    /// Show pane titles as `~/…/dir` instead of the full path.
+    pub abbreviate_paths: bool,
+    /// Areas of the local and remote panes as last drawn.
+    pane_areas: [Rect; 2],
+    /// Refuse everything that would change the server.
+    pub readonly: bool,
+    local_watcher: Option<LocalWatcher>,
+    pub cut_buffer: Option<Clipboard>,
+    pub copy_buffer: Option<Clipboard>,
+    /// Which action each key triggers in the main view.
+    pub keymap: Keymap,
+    /// First press of a two-press action such as `yy`.
+    pending_action: Option<Action>,
+    /// Repeat count typed before a motion, as in `5j`.
+    pending_count: Option<usize>,
 
     pub show_connection_dialog: bool,
     pub connection_cursor: usize,
+    /// Narrows the connection dialog's host list as the user types.
+    pub connection_filter: String,
+    pub custom_host_input: Option<String>,
+    pub custom_host_error: Option<String>,
+    /// Name being typed for a new empty file in the active pane's directory.
+    pub new_file_input: Option<String>,
+    pub new_file_error: Option<String>,
+    pub bulk_rename: Option<BulkRename>,
+    /// The entry shown in the details dialog, freshly stat'ed.
+    pub file_details: Option<FileInfo>,
     pub show_transfer_dialog: bool,
     pub transfer_queue: Vec<TransferItem>,
+    pub queue_cursor: usize,
+    pub destination_edit: Option<DestinationEdit>,
+    /// A queue waiting for confirmation because it is over
+    /// `confirm_transfer_bytes`, or holds items that can't be sized.
+    pub large_transfer: Option<LargeTransfer>,
+    pub transfer_progress: Option<Arc<Mutex<TransferProgress>>>,
+    transfer_task: Option<JoinHandle<FinishedBatch>>,
+    /// Set to stop the running batch.
+    transfer_cancel: Arc<AtomicBool>,
+    /// How the last batch went, shown until dismissed.
+    pub transfer_results: Option<Vec<TransferResult>>,
+    /// The summary row the cursor is on, which the list scrolls to keep in
+    /// view.
+    pub summary_cursor: usize,
 
+    /// A `/` filter is applied to the panes.
     pub search_mode: bool,
+    /// The query is still being typed; cleared by Enter, after which `n`/`N`
+    /// step through the matches.
+    pub search_editing: bool,
     pub search_query: String,
     pub filtered_local_files: Vec<FileInfo>,
     pub filtered_remote_files: Vec<FileInfo>,
+    /// Kinds of entry each pane lists, on top of any `/` query.
+    pub local_type_filter: TypeFilter,
+    pub remote_type_filter: TypeFilter,
+    /// Each pane's tree view, while it is shown as one instead of a flat list.
+    pub local_tree: Option<TreeView>,
+    pub remote_tree: Option<TreeView>,
+    pub deep_search: Option<DeepSearch>,
+
+    pub resume_downloads: bool,
 
+    pub status_message: Option<String>,
     pub should_quit: bool,
+
+    ui: Option<Ui>,
 }
 
 impl App {
-    pub async fn new(initial_host: Option<String>) -> Result<Self> {
-        let ssh_config = SshConfig::new()?;
-        let available_hosts = ssh_config.get_all_hosts();
+    pub async fn new(
+        initial_host: Option<String>,
+        resume_downloads: bool,
+        readonly: bool,
+        compress: bool,
+        ssh_config_path: Option<PathBuf>,
+    ) -> Result<Self> {
+        let mut config = AppConfig::load()?;
+        config.compression |= compress;
+        let ssh_config = match ssh_config_path {
+            Some(path) => SshConfig::from_path(&path)?,
+            None => SshConfig::new()?,
+        };
+        let (local_path, fallback) = startup_dir(env::current_dir(), dirs::home_dir());
+
+        let warning = ssh_config.warning().map(str::to_string);
+        let mut app = App::with_config(config, ssh_config, local_path);
+        app.status_message = match (fallback, warning) {
+            (Some(fallback), Some(warning)) => Some(format!("{fallback}; {warning}")),
+            (fallback, warning) => fallback.or(warning),
+        };
+        app.resume_downloads = resume_downloads;
+        app.readonly |= readonly;
+        app.recent_hosts = RecentHosts::load();
+        if app.config.save_input_history {
+            app.search_history = InputHistory::load("search_history");
+            app.find_history = InputHistory::load("find_history");
+        }
+        app.view_state = ViewState::load();
+        if let Some(show_hidden) = app.view_state.show_hidden {
+            app.show_hidden = show_hidden;
+        }
+        app.trusted_hosts = TrustedHosts::load();
+
+        app.refresh_local_files()?;
+
+        if app.config.watch_local {
+            match LocalWatcher::new() {
+                Ok(watcher) => app.local_watcher = Some(watcher),
+                Err(e) => {
+                    app.status_message = Some(format!("Can't watch local directory: {e}"));
+                }
+            }
+        }
+
+        if let Some(spec) = initial_host {
+            let host_config = app
+                .ssh_config
+                .host_for_spec(&spec)
+                .with_context(|| format!("Invalid --host '{spec}'"))?;
+            // A new host key is confirmed in the UI rather than failing startup
+            if let Err(e) = app.connect_with_config(host_config).await
+                && app.pending_host_key.is_none()
+            {
+                return Err(e);
+            }
+        }
+
+        Ok(app)
+    }
 
-        let local_path = env::current_dir()?;
+    fn with_config(config: AppConfig, ssh_config: SshConfig, local_path: PathBuf) -> Self {
+        let mut available_hosts = ssh_config.get_all_hosts();
+        available_hosts.sort_by_key(|host| host.host.to_lowercase());
         let remote_path = PathBuf::from("/");
+        let show_hidden = config.show_hidden;
+        let readonly = config.readonly;
+        let vertical_split = config.vertical_split;
+        let follow_symlinks = config.follow_symlinks;
+        let abbreviate_paths = config.abbreviate_paths;
+        let keymap = Keymap::new(&config.keys);
 
-        let mut app = App {
+        App {
+            config,
             ssh_config,
             sftp_client: None,
+            connection: None,
             current_host: None,
             available_hosts,
+            recent_hosts: Stored::default(),
+            search_history: Stored::default(),
+            find_history: Stored::default(),
+            view_state: Stored::default(),
+            owner_names: OwnerNames::load(),
+            trusted_hosts: Stored::default(),
+            pending_host_key: None,
+            pending_host_settings: None,
+            connecting: None,
+            comparing: None,
+            tabs: vec![RemoteTab::default()],
+            active_tab: 0,
 
             active_pane: Pane::Local,
             local_path,
             remote_path,
+            remote_realpath: None,
+            remote_space: None,
             local_files: Vec::new(),
             remote_files: Vec::new(),
             local_cursor: 0,
             remote_cursor: 0,
             local_selected: HashSet::new(),
             remote_selected: HashSet::new(),
+            show_hidden,
+            readonly,
+            local_watcher: None,
+            cut_buffer: None,
+            copy_buffer: None,
+            keymap,
+            pending_action: None,
+            pending_count: None,
 
             show_connection_dialog: false,
             connection_cursor: 0,
+            connection_filter: String::new(),
+            custom_host_input: None,
+            custom_host_error: None,
+            new_file_input: None,
+            bulk_rename: None,
+            file_details: None,
+            new_file_error: None,
             show_transfer_dialog: false,
             transfer_queue: Vec::new(),
+            queue_cursor: 0,
+            destination_edit: None,
+            large_transfer: None,
+            transfer_progress: None,
+            transfer_task: None,
+            transfer_cancel: Arc::new(AtomicBool::new(false)),
+            transfer_results: None,
+            summary_cursor: 0,
 
             search_mode: false,
+            search_editing: false,
             search_query: String::new(),
             filtered_local_files: Vec::new(),
             filtered_remote_files: Vec::new(),
+            local_type_filter: TypeFilter::All,
+            remote_type_filter: TypeFilter::All,
+            local_tree: None,
+            remote_tree: None,
+            deep_search: None,
 
-            should_quit: false,
-        };
+            resume_downloads: false,
 
-        app.refresh_local_files()?;
+            status_message: None,
+            should_quit: false,
 
-        if let Some(host) = initial_host {
-            app.connect_to_host(&host).await?;
+            ui: None,
+            grid_view: false,
+            vertical_split,
+            panes_swapped: false,
+            compare_dirs: false,
+            follow_symlinks,
+            abbreviate_paths,
+            pane_areas: [Rect::new(0, 0, 80, 24); 2],
         }
-
-        Ok(app)
     }
 
     pub async fn run(&mut self) -> Result<()> {
-        let mut ui = Ui::new()?;
+        self.ui = Some(Ui::new()?);
+        let result = self.event_loop().await;
+        // Restore the terminal before any error is printed
+        self.ui = None;
+        result
+    }
 
+    async fn event_loop(&mut self) -> Result<()> {
         loop {
             if self.should_quit {
                 break;
             }
 
-            ui.draw(self)?;
+            self.redraw()?;
+
+            let event = match &self.ui {
+                Some(ui) => ui.handle_events()?,
+                None => None,
+            };
+            if let Some(event) = event
+                && let Err(e) = self.handle_event(event).await
+            {
+                self.handle_error(e)?;
+            }
+
+            if let Err(e) = self.poll_transfers().await {
+                self.handle_error(e)?;
+            }
+
+            if let Err(e) = self.poll_deep_search().await {
+                self.handle_error(e)?;
+            }
+
+            if let Err(e) = self.poll_connect().await {
+                self.handle_error(e)?;
+            }
+
+            if let Err(e) = self.poll_compare().await {
+                self.handle_error(e)?;
+            }
+
+            self.poll_local_watcher();
+
+            if let Err(e) = self.send_keepalive() {
+                self.handle_error(e)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn redraw(&mut self) -> Result<()> {
+        self.resolve_visible_links();
+        if let Some(mut ui) = self.ui.take() {
+            let result = ui.draw(self);
+            self.ui = Some(ui);
+            self.pane_areas = result?;
+        }
+        Ok(())
+    }
+
+    /// Recovers from a dropped connection by going back to the disconnected
+    /// state. Any other error is passed on.
+    fn handle_error(&mut self, error: anyhow::Error) -> Result<()> {
+        if !sftp::is_connection_error(&error) {
+            return Err(error);
+        }
+
+        let host = self.current_host.take().unwrap_or_default();
+        self.sftp_client = None;
+        self.connection = None;
+        self.remote_realpath = None;
+        self.remote_space = None;
+        self.remote_files.clear();
+        self.remote_selected.clear();
+        self.filtered_remote_files.clear();
+        self.remote_cursor = 0;
+        self.status_message = Some(format!(
+            "Connection to {host} lost ({error}). Press C to reconnect"
+        ));
+
+        Ok(())
+    }
+
+    /// Runs `op` on the connected client, reconnecting and retrying once if the
+    /// connection has dropped. Returns `None` when not connected.
+    fn with_client<T>(&mut self, op: impl FnMut(&SftpClient) -> Result<T>) -> Result<Option<T>> {
+        let (Some(mut client), Some(params)) = (self.sftp_client.clone(), self.connection.clone())
+        else {
+            return Ok(None);
+        };
+
+        let previous = Arc::clone(&client);
+        let reconnect = || {
+            self.status_message = Some(format!("Reconnecting to {}...", params.host.host));
+            let _ = self.redraw();
+            params.connect()
+        };
+        let result = sftp::with_reconnect(&mut client, reconnect, op);
+
+        if !Arc::ptr_eq(&previous, &client) {
+            self.status_message = Some(format!("Reconnected to {}", params.host.host));
+        }
+        self.sftp_client = Some(client);
+
+        result.map(Some)
+    }
 
-            if let Some(event) = ui.handle_events()? {
-                self.handle_event(event).await?;
+    fn send_keepalive(&mut self) -> Result<()> {
+        // Transfers keep the connection busy, and would hold up the UI
+        if self.transfer_task.is_some() {
+            return Ok(());
+        }
+
+        // Background tabs that lost their connection quietly go disconnected
+        for tab in &mut self.tabs {
+            if let Some(client) = &tab.sftp_client
+                && client.send_keepalive().is_err()
+            {
+                tab.disconnect();
             }
         }
 
+        self.with_client(|client| client.send_keepalive())?;
         Ok(())
     }
 
     async fn handle_event(&mut self, event: Event) -> Result<()> {
         if let Event::Key(key) = event {
+            // Status messages only live until the next keypress
+            self.status_message = None;
+
+            if self.connecting.is_some() {
+                if key.code == KeyCode::Esc {
+                    self.cancel_connect();
+                }
+                return Ok(());
+            }
+
+            if self.pending_host_key.is_some() {
+                return self.handle_host_key_event(key.code).await;
+            }
+
+            if self.pending_host_settings.is_some() {
+                return self.handle_host_settings_event(key.code).await;
+            }
+
             if self.show_connection_dialog {
                 return self.handle_connection_dialog_event(key.code).await;
             }
@@ -131,42 +718,217 @@ impl App {
                 return self.handle_transfer_dialog_event(key.code).await;
             }
 
-            if self.search_mode {
+            if self.transfer_results.is_some() {
+                self.handle_transfer_summary_event(key.code);
+                return Ok(());
+            }
+
+            if self.deep_search.is_some() {
+                return self.handle_deep_search_event(key.code).await;
+            }
+
+            if self.new_file_input.is_some() {
+                return self.handle_new_file_event(key.code).await;
+            }
+
+            if self.file_details.is_some() {
+                if matches!(key.code, KeyCode::Esc | KeyCode::Enter) {
+                    self.file_details = None;
+                }
+                return Ok(());
+            }
+
+            if self.bulk_rename.is_some() {
+                return self.handle_bulk_rename_event(key.code).await;
+            }
+
+            if self.search_editing {
                 return self.handle_search_event(key.code).await;
             }
 
-            match key.code {
-                KeyCode::Char('q') | KeyCode::Char('Q') => {
+            let pending_action = self.pending_action.take();
+            let count = self.pending_count.take();
+
+            // Digits always make a count, so they can't be rebound
+            if let KeyCode::Char(c @ '0'..='9') = key.code
+                && (count.is_some() || c != '0')
+            {
+                let digit = c.to_digit(10).unwrap_or(0) as usize;
+                self.pending_count =
+                    Some(count.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+                return Ok(());
+            }
+
+            let Some(action) = self.keymap.action(&key) else {
+                return Ok(());
+            };
+            match action {
+                Action::Quit => {
                     self.should_quit = true;
                 }
-                KeyCode::Tab => {
-                    self.active_pane = match self.active_pane {
-                        Pane::Local => Pane::Remote,
-                        Pane::Remote => Pane::Local,
-                    };
+                Action::SwitchPane => {
+                    self.switch_pane();
+                }
+                Action::Up => {
+                    self.move_cursor_up(count.unwrap_or(1) * self.grid_columns());
+                }
+                Action::Down => {
+                    self.move_cursor_down(count.unwrap_or(1) * self.grid_columns());
                 }
-                KeyCode::Up | KeyCode::Char('k') | KeyCode::Char('K') => {
-                    self.move_cursor_up();
+                // In the grid, sideways keys move between columns
+                Action::Left if self.grid_view => {
+                    self.move_cursor_up(count.unwrap_or(1));
                 }
-                KeyCode::Down | KeyCode::Char('j') | KeyCode::Char('J') => {
-                    self.move_cursor_down();
+                Action::Right if self.grid_view => {
+                    self.move_cursor_down(count.unwrap_or(1));
                 }
-                KeyCode::Enter => {
+                Action::Home => {
+                    self.go_home().await?;
+                }
+                Action::ToggleGrid => {
+                    self.grid_view = !self.grid_view;
+                }
+                Action::ToggleSplit => {
+                    self.vertical_split = !self.vertical_split;
+                }
+                Action::SwapPanes => {
+                    self.panes_swapped = !self.panes_swapped;
+                }
+                Action::NewFile => {
+                    self.start_new_file();
+                }
+                Action::CompareDirs => {
+                    self.compare_dirs = !self.compare_dirs;
+                }
+                Action::Rename => {
+                    self.start_bulk_rename();
+                }
+                Action::FollowSymlinks => {
+                    self.follow_symlinks = !self.follow_symlinks;
+                    self.status_message = Some(if self.follow_symlinks {
+                        "Following symlinked directories".to_string()
+                    } else {
+                        "Not following symlinked directories".to_string()
+                    });
+                }
+                Action::AbbreviatePaths => {
+                    self.abbreviate_paths = !self.abbreviate_paths;
+                }
+                Action::GoToEntry => {
+                    self.jump_to_entry(count);
+                }
+                Action::FindInSubdirs => {
+                    self.start_deep_search();
+                }
+                Action::Open if self.cursor_on_tree_dir() => {
+                    self.toggle_expansion()?;
+                }
+                Action::Open | Action::Right => {
                     self.change_directory().await?;
                 }
-                KeyCode::Char(' ') => {
+                Action::Parent | Action::Left => {
+                    self.go_to_parent().await?;
+                }
+                Action::ToggleSelection if self.cursor_on_tree_dir() => {
+                    self.toggle_expansion()?;
+                }
+                Action::ToggleSelection if self.config.space_advances => {
+                    self.select_and_advance(count.unwrap_or(1));
+                }
+                Action::ToggleSelection => {
                     self.toggle_selection();
                 }
-                KeyCode::Char('c') | KeyCode::Char('C') => {
+                Action::SelectAndDown => {
+                    self.select_and_advance(count.unwrap_or(1));
+                }
+                Action::SelectAll => {
+                    self.select_all();
+                }
+                Action::Cancel if self.search_mode => {
+                    self.leave_search();
+                }
+                Action::Cancel => {
+                    self.clear_selection();
+                }
+                Action::NextMatch => {
+                    if self.search_mode {
+                        self.step_match(true);
+                    }
+                }
+                Action::PreviousMatch => {
+                    if self.search_mode {
+                        self.step_match(false);
+                    }
+                }
+                Action::Connect => {
                     self.show_connection_dialog = true;
                 }
-                KeyCode::Char('t') | KeyCode::Char('T') => {
+                Action::Transfer => {
                     self.prepare_transfer()?;
                 }
-                KeyCode::Char('/') => {
+                Action::TransferCursor => {
+                    self.prepare_cursor_transfer();
+                }
+                Action::Search => {
                     self.start_search();
                 }
-                _ => {}
+                Action::ToggleHidden => {
+                    self.toggle_hidden().await?;
+                }
+                Action::CompareFile => {
+                    self.compare_cursor_file()?;
+                }
+                Action::Info => {
+                    self.show_file_details()?;
+                }
+                Action::Edit => {
+                    self.edit_cursor_file().await?;
+                }
+                Action::Cut => {
+                    self.cut_marked();
+                }
+                Action::PasteCut => {
+                    self.paste_cut().await?;
+                }
+                Action::Yank if pending_action == Some(Action::Yank) => {
+                    self.yank_marked();
+                }
+                Action::Yank => {
+                    self.pending_action = Some(Action::Yank);
+                }
+                Action::PasteCopy => {
+                    self.paste_copy().await?;
+                }
+                Action::Duplicate => {
+                    self.duplicate_cursor_file().await?;
+                }
+                Action::Shell => {
+                    self.open_shell().await?;
+                }
+                Action::RevealLocal => {
+                    self.reveal_local();
+                }
+                Action::CopySshCommand => {
+                    self.copy_ssh_command();
+                }
+                Action::CycleTypeFilter => {
+                    self.cycle_type_filter();
+                }
+                Action::ToggleTree => {
+                    self.toggle_tree();
+                }
+                Action::NewTab => {
+                    self.new_tab();
+                }
+                Action::NextTab => {
+                    self.switch_tab((self.active_tab + 1) % self.tabs.len());
+                }
+                Action::PreviousTab => {
+                    self.switch_tab((self.active_tab + self.tabs.len() - 1) % self.tabs.len());
+                }
+                Action::CloseTab => {
+                    self.close_tab();
+                }
             }
         }
 
@@ -174,24 +936,42 @@ impl App {
     }
 
     async fn handle_connection_dialog_event(&mut self, key: KeyCode) -> Result<()> {
+        if self.custom_host_input.is_some() {
+            return self.handle_custom_host_event(key).await;
+        }
+
+        let host_count = self.connection_hosts().len();
+
         match key {
             KeyCode::Esc => {
-                self.show_connection_dialog = false;
+                self.close_connection_dialog();
             }
-            KeyCode::Up => {
-                if self.connection_cursor > 0 {
-                    self.connection_cursor -= 1;
-                }
+            KeyCode::Up if self.connection_cursor > 0 => {
+                self.connection_cursor -= 1;
             }
-            KeyCode::Down => {
-                if self.connection_cursor < self.available_hosts.len().saturating_sub(1) {
-                    self.connection_cursor += 1;
-                }
+            // Index 0 is the "Enter custom host" entry, hosts follow it
+            KeyCode::Down if self.connection_cursor < host_count => {
+                self.connection_cursor += 1;
+            }
+            KeyCode::Char(c) => {
+                self.connection_filter.push(c);
+                self.reset_connection_cursor();
+            }
+            KeyCode::Backspace => {
+                self.connection_filter.pop();
+                self.reset_connection_cursor();
+            }
+            KeyCode::Enter if self.connection_cursor == 0 => {
+                self.custom_host_input = Some(String::new());
+                self.custom_host_error = None;
             }
             KeyCode::Enter => {
-                if let Some(host) = self.available_hosts.get(self.connection_cursor).cloned() {
-                    self.connect_to_host(&host.host).await?;
-                    self.show_connection_dialog = false;
+                let host = self
+                    .connection_hosts()
+                    .get(self.connection_cursor - 1)
+                    .map(|host| host.host.clone());
+                if let Some(host) = host {
+                    self.start_connect(self.resolve_host(&host));
                 }
             }
             _ => {}
@@ -200,286 +980,491 @@ impl App {
         Ok(())
     }
 
-    async fn handle_transfer_dialog_event(&mut self, key: KeyCode) -> Result<()> {
+    fn close_connection_dialog(&mut self) {
+        self.show_connection_dialog = false;
+        self.connection_filter.clear();
+    }
+
+    /// Puts the cursor on the first host left by the filter, or on the custom
+    /// host entry when nothing matches.
+    fn reset_connection_cursor(&mut self) {
+        let matches = self.connection_hosts();
+        self.connection_cursor = if matches.is_empty() { 0 } else { 1 };
+    }
+
+    /// Hosts offered by the connection dialog: recently connected ones first,
+    /// then the rest alphabetically, narrowed by the filter.
+    pub fn connection_hosts(&self) -> Vec<&SshHost> {
+        let recent = self.recent_hosts.hosts();
+        let mut hosts = filter_hosts(&self.available_hosts, &self.connection_filter);
+        // Stable, so hosts that aren't recent keep their alphabetical order
+        hosts.sort_by_key(|host| {
+            recent
+                .iter()
+                .position(|name| *name == host.host)
+                .unwrap_or(recent.len())
+        });
+        hosts
+    }
+
+    async fn handle_custom_host_event(&mut self, key: KeyCode) -> Result<()> {
+        let Some(input) = self.custom_host_input.as_mut() else {
+            return Ok(());
+        };
+
         match key {
             KeyCode::Esc => {
-                self.show_transfer_dialog = false;
-                self.transfer_queue.clear();
+                self.custom_host_input = None;
+                self.custom_host_error = None;
             }
-            KeyCode::Enter => {
-                self.execute_transfers().await?;
-                self.show_transfer_dialog = false;
+            KeyCode::Backspace => {
+                input.pop();
+                self.custom_host_error = None;
+            }
+            KeyCode::Char(c) => {
+                input.push(c);
+                self.custom_host_error = None;
             }
+            KeyCode::Enter => match self.ssh_config.host_for_spec(input) {
+                Ok(host_config) if self.config.confirm_custom_hosts => {
+                    let matched = self.ssh_config.matched_blocks(&host_config.host);
+                    self.pending_host_settings = Some(PendingHostSettings {
+                        host: host_config,
+                        matched,
+                    });
+                }
+                Ok(host_config) => self.start_connect(host_config),
+                Err(e) => {
+                    self.custom_host_error = Some(e.to_string());
+                }
+            },
             _ => {}
         }
 
         Ok(())
     }
 
-    async fn connect_to_host(&mut self, host_name: &str) -> Result<()> {
-        let host_config = self
-            .ssh_config
-            .get_host(host_name)
-            .unwrap_or_else(|| SshHost {
-                host: host_name.to_string(),
-                hostname: Some(host_name.to_string()),
-                user: None,
-                port: None,
-                identity_file: None,
-                proxy_jump: None,
-            });
+    /// y or Enter connects with the settings shown; anything else listed goes
+    /// back to the host input to fix it.
+    async fn handle_host_settings_event(&mut self, key: KeyCode) -> Result<()> {
+        let Some(pending) = self.pending_host_settings.take() else {
+            return Ok(());
+        };
 
-        let client = SftpClient::connect(&host_config)?;
-        self.sftp_client = Some(client);
-        self.current_host = Some(host_name.to_string());
-        self.remote_path = PathBuf::from("/");
-        self.refresh_remote_files().await?;
+        match key {
+            KeyCode::Char('y') | KeyCode::Char('Y') | KeyCode::Enter => {
+                self.start_connect(pending.host);
+            }
+            KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => {}
+            _ => self.pending_host_settings = Some(pending),
+        }
 
         Ok(())
     }
 
-    fn refresh_local_files(&mut self) -> Result<()> {
-        self.local_files.clear();
-
-        // Add parent directory entry if not at root
-        if let Some(parent) = self.local_path.parent() {
-            self.local_files.push(FileInfo {
-                name: "..".to_string(),
-                path: parent.to_path_buf(),
-                is_dir: true,
-                size: 0,
-                permissions: 0o755,
-            });
+    /// Asks for the name of an empty file to create in the active pane, or of
+    /// a directory to create and enter when it ends in `/`.
+    fn start_new_file(&mut self) {
+        if self.active_pane == Pane::Remote {
+            if self.sftp_client.is_none() {
+                self.status_message = Some("New file: not connected".to_string());
+                return;
+            }
+            if !self.allow_remote_write("creating remote files") {
+                return;
+            }
         }
+        self.new_file_input = Some(String::new());
+        self.new_file_error = None;
+    }
 
-        for entry in fs::read_dir(&self.local_path)? {
-            let entry = entry?;
-            let path = entry.path();
-            let metadata = entry.metadata()?;
-
-            let name = path
-                .file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("Unknown")
-                .to_string();
-
-            self.local_files.push(FileInfo {
-                name,
-                path,
-                is_dir: metadata.is_dir(),
-                size: metadata.len(),
-                permissions: 0o755,
-            });
-        }
+    async fn handle_new_file_event(&mut self, key: KeyCode) -> Result<()> {
+        let Some(input) = self.new_file_input.as_mut() else {
+            return Ok(());
+        };
 
-        // Sort with .. always first, then directories, then files
-        self.local_files.sort_by(|a, b| {
-            if a.name == ".." {
-                std::cmp::Ordering::Less
-            } else if b.name == ".." {
-                std::cmp::Ordering::Greater
-            } else {
-                match (a.is_dir, b.is_dir) {
-                    (true, false) => std::cmp::Ordering::Less,
-                    (false, true) => std::cmp::Ordering::Greater,
-                    _ => a.name.cmp(&b.name),
-                }
+        match key {
+            KeyCode::Esc => {
+                self.new_file_input = None;
+                self.new_file_error = None;
             }
-        });
-
-        self.local_cursor = 0;
-        self.local_selected.clear();
+            KeyCode::Backspace => {
+                input.pop();
+                self.new_file_error = None;
+            }
+            KeyCode::Char(c) => {
+                input.push(c);
+                self.new_file_error = None;
+            }
+            KeyCode::Enter => {
+                let name = input.clone();
+                self.create_entry(&name).await?;
+            }
+            _ => {}
+        }
 
         Ok(())
     }
 
-    async fn refresh_remote_files(&mut self) -> Result<()> {
-        if let Some(client) = &self.sftp_client {
-            self.remote_files = client.list_directory(&self.remote_path)?;
+    /// Creates the empty file `name` in the active pane's directory and puts
+    /// the cursor on it. A `name/` is created as a directory instead, and the
+    /// pane moves into it. Problems are shown in the prompt, which stays open.
+    async fn create_entry(&mut self, name: &str) -> Result<()> {
+        let (name, directory) = match name.strip_suffix('/') {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        if let Err(e) = validate_file_name(name) {
+            self.new_file_error = Some(e.to_string());
+            return Ok(());
+        }
 
-            // Add parent directory entry if not at root
-            if self.remote_path != PathBuf::from("/")
-                && let Some(parent) = self.remote_path.parent()
-            {
-                self.remote_files.insert(
-                    0,
-                    FileInfo {
-                        name: "..".to_string(),
-                        path: parent.to_path_buf(),
-                        is_dir: true,
-                        size: 0,
-                        permissions: 0o755,
-                    },
-                );
+        let path = match self.active_pane {
+            Pane::Local => self.local_path.join(name),
+            Pane::Remote => self.remote_path.join(name),
+        };
+        let result = match self.active_pane {
+            Pane::Local if directory => fs::create_dir(&path).map_err(anyhow::Error::from),
+            Pane::Local => fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .map(|_| ())
+                .map_err(anyhow::Error::from),
+            Pane::Remote => self
+                .with_client(|client| {
+                    if directory {
+                        client.create_directory(&path)
+                    } else {
+                        client.create_file(&path)
+                    }
+                })
+                .map(|_| ()),
+        };
+        match result {
+            Ok(()) => {}
+            Err(e) if sftp::is_connection_error(&e) => {
+                self.new_file_input = None;
+                return Err(e);
+            }
+            Err(e) => {
+                self.new_file_error = Some(format!("Can't create {name}: {e}"));
+                return Ok(());
             }
-
-            self.remote_cursor = 0;
-            self.remote_selected.clear();
         }
 
-        Ok(())
-    }
-
-    fn move_cursor_up(&mut self) {
+        self.new_file_input = None;
+        if directory {
+            self.status_message = Some(format!("Created {name}/"));
+            return self.enter_directory(path).await;
+        }
+        self.status_message = Some(format!("Created {name}"));
         match self.active_pane {
             Pane::Local => {
-                if self.local_cursor > 0 {
-                    self.local_cursor -= 1;
-                }
+                self.refresh_local_files()?;
+                self.local_cursor = restore_cursor(
+                    self.get_current_local_files(),
+                    Some(name),
+                    self.local_cursor,
+                );
             }
             Pane::Remote => {
-                if self.remote_cursor > 0 {
-                    self.remote_cursor -= 1;
-                }
+                self.refresh_remote_files().await?;
+                self.remote_cursor = restore_cursor(
+                    self.get_current_remote_files(),
+                    Some(name),
+                    self.remote_cursor,
+                );
             }
         }
+
+        Ok(())
     }
 
-    fn move_cursor_down(&mut self) {
-        match self.active_pane {
-            Pane::Local => {
-                let files_len = self.get_current_local_files().len();
-                if self.local_cursor < files_len.saturating_sub(1) {
-                    self.local_cursor += 1;
-                }
+    /// Opens the bulk rename dialog for the active pane's marked entries.
+    fn start_bulk_rename(&mut self) {
+        if self.active_pane == Pane::Remote {
+            if self.sftp_client.is_none() {
+                self.status_message = Some("Rename: not connected".to_string());
+                return;
             }
-            Pane::Remote => {
-                let files_len = self.get_current_remote_files().len();
-                if self.remote_cursor < files_len.saturating_sub(1) {
-                    self.remote_cursor += 1;
-                }
+            if !self.allow_remote_write("renaming remote files") {
+                return;
             }
         }
+
+        // Patterns only apply to text, so names that aren't UTF-8 are left be
+        let paths: Vec<PathBuf> = self
+            .marked_paths()
+            .into_iter()
+            .filter(|path| path.file_name().and_then(|name| name.to_str()).is_some())
+            .collect();
+        if paths.is_empty() {
+            self.status_message = Some("Nothing to rename".to_string());
+            return;
+        }
+
+        let mut rename = BulkRename {
+            paths,
+            ..BulkRename::default()
+        };
+        self.update_rename_plan(&mut rename);
+        self.bulk_rename = Some(rename);
     }
 
-    async fn change_directory(&mut self) -> Result<()> {
-        match self.active_pane {
-            Pane::Local => {
-                let files = self.get_current_local_files();
-                if let Some(file) = files.get(self.local_cursor)
-                    && file.is_dir
-                {
-                    self.local_path = file.path.clone();
-                    self.search_mode = false;
-                    self.search_query.clear();
-                    self.clear_search_filter();
-                    self.refresh_local_files()?;
-                }
+    async fn handle_bulk_rename_event(&mut self, key: KeyCode) -> Result<()> {
+        let Some(mut rename) = self.bulk_rename.take() else {
+            return Ok(());
+        };
+
+        let field = if rename.editing_replace {
+            &mut rename.replace
+        } else {
+            &mut rename.find
+        };
+        match key {
+            KeyCode::Esc => return Ok(()),
+            KeyCode::Tab => rename.editing_replace = !rename.editing_replace,
+            KeyCode::Backspace => {
+                field.pop();
+                self.update_rename_plan(&mut rename);
             }
-            Pane::Remote => {
-                let files = self.get_current_remote_files();
-                if let Some(file) = files.get(self.remote_cursor)
-                    && file.is_dir
-                {
-                    self.remote_path = file.path.clone();
-                    self.search_mode = false;
-                    self.search_query.clear();
-                    self.clear_search_filter();
-                    self.refresh_remote_files().await?;
-                }
+            KeyCode::Char(c) => {
+                field.push(c);
+                self.update_rename_plan(&mut rename);
+            }
+            KeyCode::Enter if rename.error.is_none() => {
+                return self.apply_renames(&rename).await;
             }
+            _ => {}
         }
 
+        self.bulk_rename = Some(rename);
         Ok(())
     }
 
-    fn toggle_selection(&mut self) {
-        match self.active_pane {
-            Pane::Local => {
-                if self.local_selected.contains(&self.local_cursor) {
-                    self.local_selected.remove(&self.local_cursor);
-                } else {
-                    self.local_selected.insert(self.local_cursor);
-                }
+    /// Recomputes the preview after the pattern or replacement changed.
+    /// Each entry is checked against the directory it is in.
+    fn update_rename_plan(&self, rename: &mut BulkRename) {
+        // An empty pattern would match between every character
+        let pattern = if rename.find.is_empty() {
+            Regex::new("^$")
+        } else {
+            Regex::new(&rename.find)
+        };
+        let pattern = match pattern {
+            Ok(pattern) => pattern,
+            Err(e) => {
+                rename.error = Some(e.to_string());
+                return;
             }
-            Pane::Remote => {
-                if self.remote_selected.contains(&self.remote_cursor) {
-                    self.remote_selected.remove(&self.remote_cursor);
-                } else {
-                    self.remote_selected.insert(self.remote_cursor);
-                }
+        };
+
+        let mut dirs: Vec<&Path> = Vec::new();
+        for path in &rename.paths {
+            let dir = path.parent().unwrap_or(path);
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+
+        let mut plan = vec![None; rename.paths.len()];
+        for dir in dirs {
+            let indices: Vec<usize> = (0..rename.paths.len())
+                .filter(|&i| rename.paths[i].parent().unwrap_or(&rename.paths[i]) == dir)
+                .collect();
+            let names: Vec<String> = indices
+                .iter()
+                .filter_map(|&i| rename.paths[i].file_name()?.to_str())
+                .map(str::to_string)
+                .collect();
+            let existing: Vec<String> = self
+                .listed_entries(dir)
+                .iter()
+                .map(|file| file.name.clone())
+                .collect();
+            let planned = rename::plan_renames(&names, &pattern, &rename.replace, &existing);
+            for (i, entry) in indices.into_iter().zip(planned) {
+                plan[i] = Some(entry);
             }
         }
+        rename.plan = plan.into_iter().flatten().collect();
+        rename.error = None;
     }
 
-    fn prepare_transfer(&mut self) -> Result<()> {
-        self.transfer_queue.clear();
+    /// What the active pane lists of `dir`: its entries if it is the pane's
+    /// directory, or those of an expanded directory in the tree view.
+    fn listed_entries(&self, dir: &Path) -> &[FileInfo] {
+        let (root, files, tree) = match self.active_pane {
+            Pane::Local => (&self.local_path, &self.local_files, &self.local_tree),
+            Pane::Remote => (&self.remote_path, &self.remote_files, &self.remote_tree),
+        };
+        if dir == root.as_path() {
+            files
+        } else {
+            tree.as_ref()
+                .and_then(|tree| tree.entries(dir))
+                .unwrap_or_default()
+        }
+    }
 
-        for &index in &self.local_selected {
-            if let Some(file) = self.local_files.get(index) {
-                let destination = self.remote_path.join(&file.name);
-                self.transfer_queue.push(TransferItem {
-                    source: file.path.clone(),
-                    destination,
-                    direction: TransferDirection::Upload,
-                });
+    /// Renames every entry of the plan marked for it, next to where it is.
+    /// Failures are reported together once the rest are done.
+    /// Nothing is renamed over an existing entry, including ones hidden or
+    /// filtered out of the listing the plan was checked against.
+    async fn apply_renames(&mut self, rename: &BulkRename) -> Result<()> {
+        let mut renamed = 0;
+        let mut failures = Vec::new();
+        for (from, entry) in rename
+            .paths
+            .iter()
+            .zip(&rename.plan)
+            .filter(|(_, entry)| entry.status == RenameStatus::Rename)
+        {
+            let to = from.with_file_name(&entry.to);
+            let result = match self.active_pane {
+                Pane::Local => {
+                    if fs::symlink_metadata(&to).is_ok() {
+                        Err(anyhow!("{} already exists", entry.to))
+                    } else {
+                        fs::rename(from, to).map_err(anyhow::Error::from)
+                    }
+                }
+                Pane::Remote => self
+                    .with_client(|client| client.rename(from, &to))
+                    .map(|_| ()),
+            };
+            match result {
+                Ok(()) => renamed += 1,
+                Err(e) if sftp::is_connection_error(&e) => return Err(e),
+                Err(e) => failures.push(format!("{}: {e}", entry.from)),
             }
         }
 
-        for &index in &self.remote_selected {
-            if let Some(file) = self.remote_files.get(index) {
-                let destination = self.local_path.join(&file.name);
-                self.transfer_queue.push(TransferItem {
-                    source: file.path.clone(),
-                    destination,
-                    direction: TransferDirection::Download,
-                });
-            }
+        self.status_message = Some(match failures.first() {
+            None => format!("Renamed {renamed} item(s)"),
+            Some(first) => format!(
+                "Renamed {renamed} item(s), {} failed ({first})",
+                failures.len()
+            ),
+        });
+        match self.active_pane {
+            Pane::Local => self.refresh_local_files(),
+            Pane::Remote => self.refresh_remote_files().await,
         }
+    }
 
-        if !self.transfer_queue.is_empty() {
-            self.show_transfer_dialog = true;
+    async fn handle_transfer_dialog_event(&mut self, key: KeyCode) -> Result<()> {
+        // The queue can't be changed while it is being worked on, only
+        // stopped
+        if self.transfer_task.is_some() {
+            if key == KeyCode::Esc {
+                self.cancel_transfers();
+            }
+            return Ok(());
         }
 
-        Ok(())
-    }
+        if self.destination_edit.is_some() {
+            return self.handle_destination_edit_event(key);
+        }
 
-    async fn execute_transfers(&mut self) -> Result<()> {
-        if let Some(client) = &self.sftp_client {
-            for item in &self.transfer_queue {
-                match item.direction {
-                    TransferDirection::Upload => {
-                        // Check if source is a directory
-                        if item.source.is_dir() {
-                            client.upload_directory(&item.source, &item.destination)?;
-                        } else {
-                            client.upload_file(&item.source, &item.destination)?;
-                        }
-                    }
-                    TransferDirection::Download => {
-                        client.download_file(&item.source, &item.destination)?;
-                    }
+        if self.large_transfer.is_some() {
+            match key {
+                // Only y: a second Enter must not start a batch this big
+                KeyCode::Char('y') => {
+                    self.large_transfer = None;
+                    self.execute_transfers();
                 }
+                KeyCode::Char('n') | KeyCode::Esc => {
+                    self.large_transfer = None;
+                }
+                _ => {}
             }
+            return Ok(());
         }
 
-        self.transfer_queue.clear();
-        self.local_selected.clear();
-        self.remote_selected.clear();
-
-        self.refresh_local_files()?;
-        self.refresh_remote_files().await?;
+        match key {
+            KeyCode::Esc => {
+                self.show_transfer_dialog = false;
+                self.transfer_queue.clear();
+            }
+            KeyCode::Enter => {
+                self.confirm_transfers();
+            }
+            KeyCode::Up | KeyCode::Char('k') if self.queue_cursor > 0 => {
+                self.queue_cursor -= 1;
+            }
+            KeyCode::Down | KeyCode::Char('j')
+                if self.queue_cursor + 1 < self.transfer_queue.len() =>
+            {
+                self.queue_cursor += 1;
+            }
+            KeyCode::Char(' ') => {
+                if let Some(item) = self.transfer_queue.get_mut(self.queue_cursor) {
+                    item.enabled = !item.enabled;
+                }
+            }
+            KeyCode::Char('e') | KeyCode::Char('E') => {
+                self.start_destination_edit(key == KeyCode::Char('E'));
+            }
+            KeyCode::Char('d') if self.queue_cursor < self.transfer_queue.len() => {
+                self.transfer_queue.remove(self.queue_cursor);
+                self.queue_cursor = self
+                    .queue_cursor
+                    .min(self.transfer_queue.len().saturating_sub(1));
+                if self.transfer_queue.is_empty() {
+                    self.show_transfer_dialog = false;
+                }
+            }
+            _ => {}
+        }
 
         Ok(())
     }
 
-    async fn handle_search_event(&mut self, key: KeyCode) -> Result<()> {
+    /// Opens the destination prompt for the queued item under the cursor,
+    /// or with `all`, for the directory of every item going the same way.
+    fn start_destination_edit(&mut self, all: bool) {
+        let Some(item) = self.transfer_queue.get(self.queue_cursor) else {
+            return;
+        };
+        let (scope, path) = if all {
+            (
+                DestinationScope::All(item.direction.clone()),
+                item.destination.parent().unwrap_or(&item.destination),
+            )
+        } else {
+            (
+                DestinationScope::Item(self.queue_cursor),
+                &*item.destination,
+            )
+        };
+        self.destination_edit = Some(DestinationEdit {
+            input: path.display().to_string(),
+            scope,
+            error: None,
+        });
+    }
+
+    fn handle_destination_edit_event(&mut self, key: KeyCode) -> Result<()> {
+        let Some(edit) = self.destination_edit.as_mut() else {
+            return Ok(());
+        };
+
         match key {
             KeyCode::Esc => {
-                self.search_mode = false;
-                self.search_query.clear();
-                self.clear_search_filter();
-            }
-            KeyCode::Enter => {
-                self.search_mode = false;
+                self.destination_edit = None;
             }
             KeyCode::Backspace => {
-                self.search_query.pop();
-                self.update_search_filter();
+                edit.input.pop();
+                edit.error = None;
             }
             KeyCode::Char(c) => {
-                self.search_query.push(c);
-                self.update_search_filter();
+                edit.input.push(c);
+                edit.error = None;
+            }
+            KeyCode::Enter => {
+                self.apply_destination_edit()?;
             }
             _ => {}
         }
@@ -487,102 +1472,5856 @@ impl App {
         Ok(())
     }
 
-    fn start_search(&mut self) {
-        self.search_mode = true;
-        self.search_query.clear();
-        self.clear_search_filter();
-        self.local_cursor = 0;
-        self.remote_cursor = 0;
-    }
+    /// Points the queued items in the edit's scope at the typed destination.
+    /// A relative path starts from the pane on the receiving side, and an
+    /// item's path ending in `/` keeps its name. The directory the items go
+    /// into must already exist; otherwise the prompt stays open and says so.
+    fn apply_destination_edit(&mut self) -> Result<()> {
+        let Some(edit) = self.destination_edit.clone() else {
+            return Ok(());
+        };
+        let fail = |app: &mut Self, error: String| {
+            if let Some(edit) = app.destination_edit.as_mut() {
+                edit.error = Some(error);
+            }
+        };
+        if edit.input.is_empty() {
+            fail(self, "Type a destination".to_string());
+            return Ok(());
+        }
 
-    fn update_search_filter(&mut self) {
-        if self.search_query.is_empty() {
-            self.clear_search_filter();
-            return;
+        let direction = match &edit.scope {
+            DestinationScope::Item(index) => match self.transfer_queue.get(*index) {
+                Some(item) => item.direction.clone(),
+                None => {
+                    self.destination_edit = None;
+                    return Ok(());
+                }
+            },
+            DestinationScope::All(direction) => direction.clone(),
+        };
+        let base = match direction {
+            TransferDirection::Upload => &self.remote_path,
+            TransferDirection::Download => &self.local_path,
+        };
+        let mut path = base.join(&edit.input);
+        if let DestinationScope::Item(index) = edit.scope
+            && edit.input.ends_with('/')
+            && let Some(name) = self.transfer_queue[index].destination.file_name()
+        {
+            path.push(name);
         }
+        let dir = match edit.scope {
+            DestinationScope::Item(_) => path.parent().unwrap_or(&path).to_path_buf(),
+            DestinationScope::All(_) => path.clone(),
+        };
 
-        let query = self.search_query.to_lowercase();
+        let exists = match direction {
+            TransferDirection::Download => dir.is_dir(),
+            TransferDirection::Upload => self
+                .with_client(|client| Ok(client.is_dir(&dir)))?
+                .unwrap_or(false),
+        };
+        if !exists {
+            fail(self, format!("{} is not a directory", dir.display()));
+            return Ok(());
+        }
 
-        // Filter local files
-        self.filtered_local_files = self
-            .local_files
-            .iter()
-            .filter(|file| file.name.to_lowercase().contains(&query))
-            .cloned()
-            .collect();
+        match edit.scope {
+            DestinationScope::Item(index) => self.transfer_queue[index].destination = path,
+            DestinationScope::All(direction) => {
+                for item in &mut self.transfer_queue {
+                    if item.direction == direction
+                        && let Some(name) = item.destination.file_name()
+                    {
+                        item.destination = dir.join(name);
+                    }
+                }
+            }
+        }
+        self.destination_edit = None;
 
-        // Filter remote files
-        self.filtered_remote_files = self
-            .remote_files
-            .iter()
-            .filter(|file| file.name.to_lowercase().contains(&query))
-            .cloned()
-            .collect();
-    }
+        // A new destination may be the source itself
+        if let Some(client) = self
+            .sftp_client
+            .clone()
+            .filter(|_| self.server_is_this_machine())
+        {
+            self.skip_self_overwrites(|path| client.realpath(path).ok());
+            self.queue_cursor = self
+                .queue_cursor
+                .min(self.transfer_queue.len().saturating_sub(1));
+            self.show_transfer_dialog = !self.transfer_queue.is_empty();
+        }
 
-    fn clear_search_filter(&mut self) {
-        self.filtered_local_files.clear();
-        self.filtered_remote_files.clear();
+        Ok(())
     }
 
-    pub fn get_current_local_files(&self) -> &[FileInfo] {
-        if self.search_mode && !self.search_query.is_empty() {
-            &self.filtered_local_files
-        } else {
-            &self.local_files
+    fn handle_transfer_summary_event(&mut self, key: KeyCode) {
+        let count = self.transfer_results.as_ref().map_or(0, Vec::len);
+        match key {
+            KeyCode::Up | KeyCode::Char('k') if self.summary_cursor > 0 => {
+                self.summary_cursor -= 1;
+            }
+            KeyCode::Down | KeyCode::Char('j') if self.summary_cursor + 1 < count => {
+                self.summary_cursor += 1;
+            }
+            KeyCode::Enter | KeyCode::Esc => {
+                self.transfer_results = None;
+            }
+            KeyCode::Char('r') | KeyCode::Char('R') => {
+                self.retry_failed_transfers();
+            }
+            _ => {}
         }
     }
 
-    pub fn get_current_remote_files(&self) -> &[FileInfo] {
-        if self.search_mode && !self.search_query.is_empty() {
-            &self.filtered_remote_files
-        } else {
-            &self.remote_files
-        }
+    /// Puts every item that didn't complete back in the queue for another go.
+    fn retry_failed_transfers(&mut self) {
+        let Some(results) = self.transfer_results.take() else {
+            return;
+        };
+
+        self.transfer_queue = results
+            .into_iter()
+            .filter(|result| result.outcome != TransferOutcome::Done)
+            .map(|result| result.item)
+            .collect();
+        self.open_transfer_dialog();
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// The ssh_config entry for `host_name`, or a bare entry for a host the
+    /// config doesn't mention.
+    fn resolve_host(&self, host_name: &str) -> SshHost {
+        self.ssh_config
+            .get_host(host_name)
+            .unwrap_or_else(|| SshHost {
+                host: host_name.to_string(),
+                hostname: Some(host_name.to_string()),
+                ..SshHost::default()
+            })
+    }
 
-    #[test]
-    fn test_pane_toggle() {
-        let pane = Pane::Local;
-        assert_eq!(pane, Pane::Local);
+    fn connect_params(&self, host_config: SshHost) -> ConnectParams {
+        ConnectParams {
+            host: host_config,
+            ssh_config: self.ssh_config.clone(),
+            keepalive_interval: self.config.keepalive_interval,
+            compression: self.config.compression,
+            trusted_hosts: TrustedHosts::clone(&self.trusted_hosts),
+            retries: self.config.sftp_retries,
+        }
+    }
 
-        let pane = Pane::Remote;
-        assert_eq!(pane, Pane::Remote);
+    /// Connects the active tab to `host_config`, waiting for it. Used before
+    /// the interface is up; once it is, `start_connect` keeps it responsive.
+    async fn connect_with_config(&mut self, host_config: SshHost) -> Result<()> {
+        let params = self.connect_params(host_config);
+        let result = params.connect();
+        self.finish_connect(params, result).await
     }
 
-    #[test]
-    fn test_transfer_item_upload() {
-        let item = TransferItem {
-            source: PathBuf::from("/source/file.txt"),
-            destination: PathBuf::from("/dest/file.txt"),
-            direction: TransferDirection::Upload,
-        };
+    /// Starts connecting the active tab to `host_config` in the background.
+    /// The current connection stays in use until the new one is ready, and
+    /// Esc abandons the attempt.
+    fn start_connect(&mut self, host_config: SshHost) {
+        let params = self.connect_params(host_config);
+        let cancel = ConnectCancel::default();
+        let task = tokio::task::spawn_blocking({
+            let params = params.clone();
+            let cancel = cancel.clone();
+            move || params.connect_cancellable(&cancel)
+        });
+        self.connecting = Some(Connecting {
+            params,
+            task,
+            cancel,
+        });
+    }
 
-        assert_eq!(item.source, PathBuf::from("/source/file.txt"));
-        assert_eq!(item.destination, PathBuf::from("/dest/file.txt"));
-        assert!(matches!(item.direction, TransferDirection::Upload));
+    /// Gives up on the connection being opened. Its sockets are shut down so
+    /// the handshake fails at once, rather than leaving a thread behind that
+    /// would hold up quitting.
+    fn cancel_connect(&mut self) {
+        if let Some(connecting) = self.connecting.take() {
+            connecting.cancel.cancel();
+            self.status_message = Some(format!(
+                "Cancelled connecting to {}",
+                connecting.params.host.host
+            ));
+        }
     }
 
-    #[test]
-    fn test_transfer_item_download() {
-        let item = TransferItem {
-            source: PathBuf::from("/remote/file.txt"),
-            destination: PathBuf::from("/local/file.txt"),
-            direction: TransferDirection::Download,
+    /// Takes over the background connection once it has finished: dialogs
+    /// close on success, and a failure is reported where it was started.
+    async fn poll_connect(&mut self) -> Result<()> {
+        let Some(connecting) = self
+            .connecting
+            .take_if(|connecting| connecting.task.is_finished())
+        else {
+            return Ok(());
         };
 
-        assert!(matches!(item.direction, TransferDirection::Download));
-    }
-
-    #[test]
-    fn test_transfer_direction_clone() {
+        let result = connecting.task.await?;
+        match self.finish_connect(connecting.params, result).await {
+            Ok(()) => {
+                self.custom_host_input = None;
+                self.close_connection_dialog();
+            }
+            // A new host key (perhaps of the next jump host) asks first
+            Err(_) if self.pending_host_key.is_some() => {}
+            Err(e) => {
+                let message = connect_error_message(e)?;
+                // Leave the dialog open so another host can be picked
+                if self.custom_host_input.is_some() {
+                    self.custom_host_error = Some(message);
+                } else {
+                    self.status_message = Some(message);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Switches the active tab to a freshly opened connection. A server key
+    /// that hasn't been trusted yet is kept in `pending_host_key` for the
+    /// user to confirm.
+    async fn finish_connect(
+        &mut self,
+        params: ConnectParams,
+        result: Result<SftpClient>,
+    ) -> Result<()> {
+        let host_config = params.host.clone();
+        let client = match result {
+            Ok(client) => client,
+            Err(e) => {
+                if let Some(SftpError::UnknownHostKey {
+                    address,
+                    fingerprint,
+                }) = e.downcast_ref::<SftpError>()
+                {
+                    self.pending_host_key = Some(PendingHostKey {
+                        host: host_config,
+                        address: address.clone(),
+                        fingerprint: fingerprint.clone(),
+                    });
+                }
+                return Err(e);
+            }
+        };
+        if host_config.compression(self.config.compression) && !client.compressed() {
+            self.status_message =
+                Some("Server declined compression; continuing uncompressed".to_string());
+        }
+        self.remote_path =
+            self.host_remote_dir(&host_config.host, client.home(), |path| client.is_dir(path));
+        self.sftp_client = Some(Arc::new(client));
+        self.connection = Some(params);
+        self.forget_remote_clipboards();
+        self.refresh_remote_files().await?;
+        self.apply_host_local_dir(&host_config.host)?;
+
+        // Only ssh_config hosts are offered in the dialog, so only they are
+        // worth remembering
+        if self
+            .available_hosts
+            .iter()
+            .any(|h| h.host == host_config.host)
+        {
+            self.recent_hosts.record(&host_config.host);
+            if let Err(e) = self.recent_hosts.save() {
+                self.status_message = Some(format!("Can't save recent hosts: {e}"));
+            }
+        }
+        self.current_host = Some(host_config.host);
+
+        Ok(())
+    }
+
+    async fn handle_host_key_event(&mut self, key: KeyCode) -> Result<()> {
+        let Some(pending) = self.pending_host_key.take() else {
+            return Ok(());
+        };
+
+        match key {
+            KeyCode::Char('y') | KeyCode::Char('Y') => {
+                self.trusted_hosts
+                    .trust(&pending.address, &pending.fingerprint);
+                if let Err(e) = self.trusted_hosts.save() {
+                    self.status_message = Some(format!("Can't save trusted hosts: {e}"));
+                }
+
+                self.start_connect(pending.host);
+            }
+            KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => {
+                self.status_message = Some(format!(
+                    "Host key for {} not trusted; not connected",
+                    pending.address
+                ));
+            }
+            _ => self.pending_host_key = Some(pending),
+        }
+
+        Ok(())
+    }
+
+    /// Remote paths cut or yanked on another host mean nothing on this one.
+    fn forget_remote_clipboards(&mut self) {
+        for buffer in [&mut self.cut_buffer, &mut self.copy_buffer] {
+            if buffer.as_ref().is_some_and(|b| b.pane == Pane::Remote) {
+                *buffer = None;
+            }
+        }
+    }
+
+    /// Moves the active tab's remote state out of `App`, leaving it disconnected.
+    fn take_active_tab(&mut self) -> RemoteTab {
+        RemoteTab {
+            sftp_client: self.sftp_client.take(),
+            connection: self.connection.take(),
+            current_host: self.current_host.take(),
+            remote_path: std::mem::replace(&mut self.remote_path, PathBuf::from("/")),
+            remote_realpath: self.remote_realpath.take(),
+            remote_space: self.remote_space.take(),
+            remote_files: std::mem::take(&mut self.remote_files),
+            remote_cursor: std::mem::take(&mut self.remote_cursor),
+            remote_selected: std::mem::take(&mut self.remote_selected),
+            remote_tree: self.remote_tree.take(),
+        }
+    }
+
+    fn restore_tab(&mut self, tab: RemoteTab) {
+        self.sftp_client = tab.sftp_client;
+        self.connection = tab.connection;
+        self.current_host = tab.current_host;
+        self.remote_path = tab.remote_path;
+        self.remote_realpath = tab.remote_realpath;
+        self.remote_space = tab.remote_space;
+        self.remote_files = tab.remote_files;
+        self.remote_cursor = tab.remote_cursor;
+        self.remote_selected = tab.remote_selected;
+        self.remote_tree = tab.remote_tree;
+        self.update_filters();
+    }
+
+    fn switch_tab(&mut self, index: usize) {
+        if index == self.active_tab || index >= self.tabs.len() {
+            return;
+        }
+
+        self.end_search();
+        self.forget_remote_clipboards();
+
+        self.tabs[self.active_tab] = self.take_active_tab();
+        let tab = std::mem::take(&mut self.tabs[index]);
+        self.restore_tab(tab);
+        self.active_tab = index;
+    }
+
+    /// Opens a disconnected tab after the current one and offers to connect it.
+    fn new_tab(&mut self) {
+        self.tabs.insert(self.active_tab + 1, RemoteTab::default());
+        self.switch_tab(self.active_tab + 1);
+        self.remote_path = PathBuf::from("/");
+        self.active_pane = Pane::Remote;
+        self.show_connection_dialog = true;
+    }
+
+    /// Closes the active tab, dropping its connection. The last tab is
+    /// disconnected instead of closed.
+    fn close_tab(&mut self) {
+        let closed = self.take_active_tab();
+        self.forget_remote_clipboards();
+
+        if self.tabs.len() > 1 {
+            self.tabs.remove(self.active_tab);
+            self.active_tab = self.active_tab.min(self.tabs.len() - 1);
+            let tab = std::mem::take(&mut self.tabs[self.active_tab]);
+            self.restore_tab(tab);
+            self.end_search();
+        }
+
+        if let Some(host) = closed.current_host {
+            self.status_message = Some(format!("Closed connection to {host}"));
+        }
+        // Dropping the last handle closes the session and its proxy tunnels
+        drop(closed.sftp_client);
+    }
+
+    /// Labels for the tab bar, one per tab.
+    pub fn tab_titles(&self) -> Vec<String> {
+        self.tabs
+            .iter()
+            .enumerate()
+            .map(|(i, tab)| {
+                let host = if i == self.active_tab {
+                    self.current_host.as_deref()
+                } else {
+                    tab.current_host.as_deref()
+                };
+                format!("{}: {}", i + 1, host.unwrap_or("Not Connected"))
+            })
+            .collect()
+    }
+
+    /// Switches the local pane to the host's configured `local_dir`, if any.
+    fn apply_host_local_dir(&mut self, host_name: &str) -> Result<()> {
+        let Some(local_dir) = self
+            .config
+            .host(host_name)
+            .and_then(|settings| settings.local_dir.as_deref())
+            .map(config::expand_tilde)
+        else {
+            return Ok(());
+        };
+
+        if local_dir.is_dir() {
+            self.local_path = local_dir;
+            self.refresh_local_files()?;
+        } else {
+            self.status_message = Some(format!(
+                "Warning: local_dir {} for {} is not a directory",
+                local_dir.display(),
+                host_name
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// The directory to open after connecting to `host_name`: its configured
+    /// `remote_dir` if that is a directory on the server, else `/`.
+    fn host_remote_dir(
+        &mut self,
+        host_name: &str,
+        home: &Path,
+        is_dir: impl Fn(&Path) -> bool,
+    ) -> PathBuf {
+        let Some(remote_dir) = self
+            .config
+            .host(host_name)
+            .and_then(|settings| settings.remote_dir.as_deref())
+            .map(|dir| sftp::normalize_remote_path(&home.join(dir)))
+        else {
+            return PathBuf::from("/");
+        };
+
+        if is_dir(&remote_dir) {
+            remote_dir
+        } else {
+            self.status_message = Some(format!(
+                "Warning: remote_dir {} for {} is not a directory",
+                remote_dir.display(),
+                host_name
+            ));
+            PathBuf::from("/")
+        }
+    }
+
+    /// The entries of a local directory as the pane lists them, without `..`,
+    /// and how many of them couldn't be read.
+    fn list_local_dir(&self, dir: &Path) -> Result<(Vec<FileInfo>, usize)> {
+        let entries = fs::read_dir(dir)?.map(|entry| {
+            let entry = entry?;
+            let path = entry.path();
+            let metadata = entry.metadata()?;
+            Ok(local_file_info(path, &metadata, &self.owner_names))
+        });
+        let (mut files, inaccessible) = accessible_entries(entries);
+        if !self.show_hidden {
+            files.retain(|file| !is_hidden(&file.name));
+        }
+
+        let symlinks_apart = self.config.sort_symlinks_apart;
+        files.sort_by(|a, b| sftp::compare_entries(a, b, symlinks_apart));
+        Ok((files, inaccessible))
+    }
+
+    fn refresh_local_files(&mut self) -> Result<()> {
+        let (files, inaccessible) = self.list_local_dir(&self.local_path)?;
+        self.local_files = files;
+
+        // Add parent directory entry if not at root
+        if let Some(parent) = self.local_path.parent() {
+            self.local_files.insert(
+                0,
+                FileInfo {
+                    name: "..".to_string(),
+                    path: parent.to_path_buf(),
+                    is_dir: true,
+                    size: 0,
+                    permissions: 0o755,
+                    ..FileInfo::default()
+                },
+            );
+        }
+
+        if inaccessible > 0 {
+            self.status_message = Some(format!("{inaccessible} items not accessible"));
+        }
+
+        self.relist_local_tree();
+        self.update_filters();
+
+        self.local_cursor = 0;
+        self.local_selected.clear();
+
+        Ok(())
+    }
+
+    /// Lists the local tree view's expanded directories again, collapsing the
+    /// ones that are gone and forgetting the ones outside the pane's directory.
+    fn relist_local_tree(&mut self) {
+        let Some(mut tree) = self.local_tree.take() else {
+            return;
+        };
+        tree.retain_under(&self.local_path);
+        for dir in tree.expanded_dirs() {
+            match self.list_local_dir(&dir) {
+                Ok((entries, _)) => tree.expand(dir, entries),
+                Err(_) => tree.collapse(&dir),
+            }
+        }
+        self.local_tree = Some(tree);
+    }
+
+    /// Relists the local pane if the watcher saw its directory change, and
+    /// moves the watcher along when the pane has changed directory. A
+    /// directory that can't be listed any more is reported on the status line
+    /// rather than ending the session.
+    fn poll_local_watcher(&mut self) {
+        let Some(local_watcher) = self.local_watcher.as_mut() else {
+            return;
+        };
+
+        if let Err(e) = local_watcher.watch(&self.local_path) {
+            self.status_message = Some(format!("Can't watch {}: {e}", self.local_path.display()));
+        }
+
+        if local_watcher.take_change(watcher::DEBOUNCE)
+            && let Err(e) = self.reload_local_files()
+        {
+            self.leave_vanished_local_dir(e);
+        }
+    }
+
+    /// Moves the local pane to the nearest ancestor that still exists after
+    /// its directory was deleted or renamed outside sftui, and watches that
+    /// instead. If the directory is still there, only the error is shown.
+    fn leave_vanished_local_dir(&mut self, error: anyhow::Error) {
+        let gone = self.local_path.clone();
+        let ancestor = gone.ancestors().skip(1).find(|dir| dir.is_dir());
+        let Some(ancestor) = ancestor.filter(|_| !gone.is_dir()) else {
+            self.status_message = Some(format!("Can't list {}: {error}", gone.display()));
+            return;
+        };
+
+        self.local_path = ancestor.to_path_buf();
+        if let Err(e) = self.refresh_local_files() {
+            self.status_message = Some(format!("Can't list {}: {e}", self.local_path.display()));
+            return;
+        }
+        self.status_message = Some(format!(
+            "{} is gone; moved to {}",
+            gone.display(),
+            self.local_path.display()
+        ));
+        if let Some(local_watcher) = self.local_watcher.as_mut()
+            && let Err(e) = local_watcher.watch(&self.local_path)
+        {
+            self.status_message = Some(format!("Can't watch {}: {e}", self.local_path.display()));
+        }
+    }
+
+    /// Relists the local pane after an outside change, keeping the cursor and
+    /// selection on the same names.
+    fn reload_local_files(&mut self) -> Result<()> {
+        let files = self.get_current_local_files();
+        let cursor_name = files.get(self.local_cursor).map(|f| f.name.clone());
+        let selected: HashSet<String> = self
+            .local_selected
+            .iter()
+            .filter_map(|&i| files.get(i))
+            .map(|f| f.name.clone())
+            .collect();
+        let cursor = self.local_cursor;
+
+        self.refresh_local_files()?;
+
+        let files = self.get_current_local_files();
+        let local_cursor = restore_cursor(files, cursor_name.as_deref(), cursor);
+        self.local_selected = files
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| selected.contains(&f.name))
+            .map(|(i, _)| i)
+            .collect();
+        self.local_cursor = local_cursor;
+
+        Ok(())
+    }
+
+    /// The entries of a remote directory as the pane lists them, without
+    /// `..`, and how many of them couldn't be read. None when not connected.
+    fn list_remote_dir(&mut self, dir: &Path) -> Result<Option<(Vec<FileInfo>, usize)>> {
+        let symlinks_apart = self.config.sort_symlinks_apart;
+        let by_type = self.remote_type_filter != TypeFilter::All;
+        let show_hidden = self.show_hidden;
+        let listing = self.with_client(|client| {
+            let entries = client.list_directory_lazy(dir)?;
+            let (mut files, inaccessible) = accessible_entries(entries.into_iter());
+            // Sorting links with what they point to, or filtering by it, needs
+            // every target now; otherwise only the ones scrolled near are
+            // looked up
+            if !symlinks_apart || by_type {
+                for file in &mut files {
+                    client.resolve_link(file);
+                }
+            }
+            Ok((files, inaccessible))
+        })?;
+
+        Ok(listing.map(|(mut files, inaccessible)| {
+            if !show_hidden {
+                files.retain(|file| !is_hidden(&file.name));
+            }
+            files.sort_by(|a, b| sftp::compare_entries(a, b, symlinks_apart));
+            (files, inaccessible)
+        }))
+    }
+
+    async fn refresh_remote_files(&mut self) -> Result<()> {
+        let remote_path = self.remote_path.clone();
+        if let Some((files, inaccessible)) = self.list_remote_dir(&remote_path)? {
+            self.show_remote_listing(files, inaccessible)?;
+
+            // Servers without realpath support just show the logical path
+            self.remote_realpath = self
+                .sftp_client
+                .as_ref()
+                .and_then(|client| client.realpath(&remote_path).ok());
+            self.remote_space = self
+                .sftp_client
+                .as_ref()
+                .and_then(|client| client.disk_space(&remote_path).ok());
+        }
+
+        Ok(())
+    }
+
+    /// Puts a fresh listing of the remote directory in the pane, with `..`
+    /// first, and reports the entries that couldn't be read.
+    fn show_remote_listing(&mut self, files: Vec<FileInfo>, inaccessible: usize) -> Result<()> {
+        self.remote_files = files;
+
+        // Add parent directory entry if not at root
+        if let Some(parent) = sftp::remote_parent(&self.remote_path) {
+            self.remote_files.insert(
+                0,
+                FileInfo {
+                    name: "..".to_string(),
+                    path: parent,
+                    is_dir: true,
+                    size: 0,
+                    permissions: 0o755,
+                    ..FileInfo::default()
+                },
+            );
+        }
+
+        if inaccessible > 0 {
+            self.status_message = Some(format!("{inaccessible} items not accessible"));
+        }
+
+        self.relist_remote_tree()?;
+        self.update_filters();
+
+        self.remote_cursor = 0;
+        self.remote_selected.clear();
+
+        Ok(())
+    }
+
+    /// Lists the remote tree view's expanded directories again, like
+    /// `relist_local_tree`.
+    fn relist_remote_tree(&mut self) -> Result<()> {
+        let Some(mut tree) = self.remote_tree.take() else {
+            return Ok(());
+        };
+        tree.retain_under(&self.remote_path);
+        for dir in tree.expanded_dirs() {
+            match self.list_remote_dir(&dir) {
+                Ok(Some((entries, _))) => tree.expand(dir, entries),
+                Err(e) if sftp::is_connection_error(&e) => {
+                    self.remote_tree = Some(tree);
+                    return Err(e);
+                }
+                _ => tree.collapse(&dir),
+            }
+        }
+        self.remote_tree = Some(tree);
+        Ok(())
+    }
+
+    /// Looks up the symlinks on and around the remote pane's screen, so a huge
+    /// listing only pays for the links scrolled near.
+    fn resolve_visible_links(&mut self) {
+        let Some(client) = self.sftp_client.clone() else {
+            return;
+        };
+        let area = self.pane_areas[1];
+        let mut page = crate::ui::list_rows(area);
+        if self.grid_view {
+            let longest = crate::ui::longest_name(self.get_current_remote_files());
+            page *= crate::ui::grid_columns(area.width, longest);
+        }
+        let cursor = self.remote_cursor;
+        resolve_links_near(self.current_remote_files_mut(), cursor, page, |file| {
+            client.resolve_link(file)
+        });
+    }
+
+    /// Opens the file under the cursor in `$EDITOR`, through a local copy
+    /// for remote files.
+    async fn edit_cursor_file(&mut self) -> Result<()> {
+        match self.active_pane {
+            Pane::Local => self.edit_local_file(),
+            Pane::Remote => self.edit_remote_file().await,
+        }
+    }
+
+    /// Opens the local file under the cursor in `$EDITOR`, then relists the
+    /// pane so a changed size shows up.
+    fn edit_local_file(&mut self) -> Result<()> {
+        let Some(file) = self.get_current_local_files().get(self.local_cursor) else {
+            return Ok(());
+        };
+        let path = file.path.clone();
+        if !fs::metadata(&path).is_ok_and(|m| m.is_file()) {
+            self.status_message = Some("Edit: select a regular file".to_string());
+            return Ok(());
+        }
+
+        if self.run_editor(&path)? {
+            self.reload_local_files()?;
+        }
+        Ok(())
+    }
+
+    /// Edits the remote file under the cursor through a local copy, which is
+    /// uploaded back only if the editor changed it. When the download fails
+    /// the status line says why; when the upload fails the copy is kept so
+    /// the changes aren't lost.
+    async fn edit_remote_file(&mut self) -> Result<()> {
+        let Some(file) = self.get_current_remote_files().get(self.remote_cursor) else {
+            return Ok(());
+        };
+        if file.is_dir {
+            self.status_message = Some("Edit: select a regular file".to_string());
+            return Ok(());
+        }
+        if file.size > MAX_EDIT_BYTES {
+            self.status_message = Some(format!(
+                "Edit: {} is too large to edit ({}, limit {})",
+                file.name,
+                transfer::format_size(file.size),
+                transfer::format_size(MAX_EDIT_BYTES)
+            ));
+            return Ok(());
+        }
+        let (name, remote_file) = (file.name.clone(), file.path.clone());
+        if self.sftp_client.is_none() {
+            self.status_message = Some("Edit: not connected".to_string());
+            return Ok(());
+        }
+        if !self.allow_remote_write("editing remote files") {
+            return Ok(());
+        }
+
+        // A directory of its own keeps the file's name, so the editor can
+        // still tell its type
+        let temp_dir = edit_temp_dir();
+        let temp_file = temp_dir.join(&name);
+        let downloaded =
+            self.with_client(|client| download_for_edit(client, &remote_file, &temp_file));
+        match downloaded {
+            Ok(_) => {}
+            Err(e) if sftp::is_connection_error(&e) => return Err(e),
+            Err(e) => {
+                self.status_message = Some(format!("Edit: downloading {name} failed: {e}"));
+                return Ok(());
+            }
+        }
+
+        let before = sftp::sha256_reader(&mut fs::File::open(&temp_file)?)?;
+        if !self.run_editor(&temp_file)? {
+            let _ = fs::remove_dir_all(&temp_dir);
+            return Ok(());
+        }
+        let after = sftp::sha256_reader(&mut fs::File::open(&temp_file)?)?;
+        if before == after {
+            let _ = fs::remove_dir_all(&temp_dir);
+            self.status_message = Some(format!("{name} unchanged"));
+            return Ok(());
+        }
+
+        let uploaded = self
+            .with_client(|client| client.upload_file(&temp_file, &remote_file, &mut |_| Ok(())));
+        if let Err(e) = uploaded {
+            self.status_message = Some(format!(
+                "Edit: uploading {name} failed: {e}. Your changes are in {}",
+                temp_file.display()
+            ));
+            return Ok(());
+        }
+        let _ = fs::remove_dir_all(&temp_dir);
+
+        let cursor = self.remote_cursor;
+        self.refresh_remote_files().await?;
+        self.remote_cursor = restore_cursor(&self.remote_files, Some(&name), cursor);
+        self.status_message = Some(format!("Uploaded {name}"));
+        Ok(())
+    }
+
+    /// Hands the terminal to a shell on the connected host until it exits,
+    /// then relists the remote directory in case the shell changed it.
+    async fn open_shell(&mut self) -> Result<()> {
+        if self.sftp_client.is_none() {
+            self.status_message = Some("Shell: not connected".to_string());
+            return Ok(());
+        }
+        if let Some(task) = self.session_in_use() {
+            self.status_message = Some(format!("Shell: wait until {task} finishes"));
+            return Ok(());
+        }
+        let Some(client) = self.sftp_client.clone() else {
+            return Ok(());
+        };
+        if !self.allow_remote_write("opening a shell") {
+            return Ok(());
+        }
+        let Some(ui) = self.ui.as_mut() else {
+            self.status_message = Some("Shell: no terminal to run it in".to_string());
+            return Ok(());
+        };
+
+        match ui.suspend(|| shell::run(&client))? {
+            Ok(()) => {}
+            Err(e) if sftp::is_connection_error(&e) => return Err(e),
+            Err(e) => self.status_message = Some(format!("Shell: {e}")),
+        }
+
+        let name = self
+            .get_current_remote_files()
+            .get(self.remote_cursor)
+            .map(|f| f.name.clone());
+        let cursor = self.remote_cursor;
+        self.end_search();
+        self.refresh_remote_files().await?;
+        self.remote_cursor = restore_cursor(&self.remote_files, name.as_deref(), cursor);
+        Ok(())
+    }
+
+    /// What is still using the session in the background, if anything. The
+    /// shell switches the session to non-blocking mode, which would break
+    /// their requests halfway.
+    fn session_in_use(&self) -> Option<&'static str> {
+        if self.transfer_task.is_some() {
+            Some("the transfer")
+        } else if self.comparing.is_some() {
+            Some("the comparison")
+        } else if self.deep_search.as_ref().is_some_and(|s| s.task.is_some()) {
+            Some("the search")
+        } else if self
+            .sftp_client
+            .as_ref()
+            .is_some_and(|client| Arc::strong_count(client) > 1)
+        {
+            // A walk that was cancelled but hasn't returned yet
+            Some("the background task")
+        } else {
+            None
+        }
+    }
+
+    /// Stats the entry under the cursor again and opens the details dialog
+    /// for it.
+    fn show_file_details(&mut self) -> Result<()> {
+        let file = match self.active_pane {
+            Pane::Local => self.get_current_local_files().get(self.local_cursor),
+            Pane::Remote => self.get_current_remote_files().get(self.remote_cursor),
+        };
+        let Some(path) = file.filter(|f| f.name != "..").map(|f| f.path.clone()) else {
+            self.status_message = Some("Info: select a file".to_string());
+            return Ok(());
+        };
+
+        let details = match self.active_pane {
+            Pane::Local => fs::symlink_metadata(&path)
+                .map(|metadata| local_file_info(path.clone(), &metadata, &self.owner_names))
+                .map_err(anyhow::Error::from),
+            Pane::Remote => self
+                .with_client(|client| client.file_info(&path))
+                .and_then(|info| info.context("not connected")),
+        };
+        match details {
+            Ok(details) => self.file_details = Some(details),
+            Err(e) if sftp::is_connection_error(&e) => return Err(e),
+            Err(e) => self.status_message = Some(format!("Info: {e}")),
+        }
+        Ok(())
+    }
+
+    /// Opens the directory holding the local cursor entry in the system file
+    /// manager, leaving it running on its own.
+    fn reveal_local(&mut self) {
+        if self.active_pane != Pane::Local {
+            self.status_message = Some("Reveal: only for local files".to_string());
+            return;
+        }
+
+        let program = FILE_MANAGER_OPENER;
+        self.status_message = Some(match spawn_detached(program, &self.local_path) {
+            Ok(()) => format!("Opened {} in the file manager", self.local_path.display()),
+            Err(e) => format!("Reveal: couldn't start {program}: {e}"),
+        });
+    }
+
+    /// Copies the `ssh` command for the connected host to the terminal's
+    /// clipboard.
+    fn copy_ssh_command(&mut self) {
+        let Some(connection) = &self.connection else {
+            self.status_message = Some("Copy ssh command: not connected".to_string());
+            return;
+        };
+        if self.ui.is_none() {
+            self.status_message = Some("Copy ssh command: no terminal to copy through".to_string());
+            return;
+        }
+
+        let command = connection.host.ssh_command();
+        self.status_message = Some(match osc52::copy(&command) {
+            Ok(()) => format!("Copied: {command}"),
+            Err(e) => format!("Copy ssh command: {e}"),
+        });
+    }
+
+    /// Runs the user's editor on `path` with the interface suspended. Returns
+    /// whether the editor exited successfully; otherwise the reason is left
+    /// in the status line.
+    fn run_editor(&mut self, path: &Path) -> Result<bool> {
+        let (program, args) = editor_command(env::var("EDITOR").ok().as_deref());
+        let Some(ui) = self.ui.as_mut() else {
+            self.status_message = Some("Edit: no terminal to run the editor in".to_string());
+            return Ok(false);
+        };
+
+        let status = ui.suspend(|| {
+            std::process::Command::new(&program)
+                .args(&args)
+                .arg(path)
+                .status()
+        })?;
+        match status {
+            Ok(status) if status.success() => Ok(true),
+            Ok(status) => {
+                self.status_message = Some(format!("Edit: {program} exited with {status}"));
+                Ok(false)
+            }
+            Err(e) => {
+                self.status_message = Some(format!("Edit: couldn't start {program}: {e}"));
+                Ok(false)
+            }
+        }
+    }
+
+    /// The local and remote paths of `file`, an entry of the active pane,
+    /// taken relative to each pane's directory. In the tree view it may be
+    /// in a directory below the pane's.
+    fn compare_paths(&self, file: &FileInfo) -> (PathBuf, PathBuf) {
+        let root = match self.active_pane {
+            Pane::Local => &self.local_path,
+            Pane::Remote => &self.remote_path,
+        };
+        let relative = file
+            .path
+            .strip_prefix(root)
+            .map_or_else(|_| PathBuf::from(file.file_name()), Path::to_path_buf);
+        (
+            self.local_path.join(&relative),
+            self.remote_path.join(&relative),
+        )
+    }
+
+    /// Compares the cursor file with the file at the same place under the
+    /// other pane's directory, first by size and then by SHA-256. Files of the same size
+    /// are hashed in the background, and `poll_compare` reports the result.
+    fn compare_cursor_file(&mut self) -> Result<()> {
+        let file = match self.active_pane {
+            Pane::Local => self.get_current_local_files().get(self.local_cursor),
+            Pane::Remote => self.get_current_remote_files().get(self.remote_cursor),
+        };
+        let Some(file) = file.filter(|f| !f.is_dir) else {
+            self.status_message = Some("Compare: select a file".to_string());
+            return Ok(());
+        };
+        if self.sftp_client.is_none() {
+            self.status_message = Some("Compare: not connected".to_string());
+            return Ok(());
+        }
+
+        let name = file.name.clone();
+        let (local_file, remote_file) = self.compare_paths(file);
+
+        let local_size = match fs::metadata(&local_file) {
+            Ok(metadata) if metadata.is_file() => metadata.len(),
+            _ => {
+                self.status_message = Some(format!("Compare: {name} not found locally"));
+                return Ok(());
+            }
+        };
+        let Some(remote_size) = self
+            .with_client(|client| Ok(client.file_size(&remote_file)))?
+            .flatten()
+        else {
+            self.status_message = Some(format!("Compare: {name} not found on remote"));
+            return Ok(());
+        };
+
+        if local_size != remote_size {
+            self.status_message = Some(format!(
+                "{name}: differ (local {local_size} bytes, remote {remote_size} bytes)"
+            ));
+            return Ok(());
+        }
+        let Some(client) = self.sftp_client.clone() else {
+            return Ok(());
+        };
+
+        self.status_message = Some(format!("Comparing {name}..."));
+        self.comparing = Some(Comparing {
+            name,
+            size: local_size,
+            task: tokio::task::spawn_blocking(move || {
+                transfer::same_content(&*client, &local_file, &remote_file)
+            }),
+        });
+
+        Ok(())
+    }
+
+    /// Reports a finished `=` comparison in the status line.
+    async fn poll_compare(&mut self) -> Result<()> {
+        let Some(Comparing { name, size, task }) = self
+            .comparing
+            .take_if(|comparing| comparing.task.is_finished())
+        else {
+            return Ok(());
+        };
+
+        self.status_message = Some(match task.await? {
+            Ok(check) => {
+                let verdict = if check.identical {
+                    "identical"
+                } else {
+                    "differ"
+                };
+                match check.server_hash_error {
+                    None => format!("{name}: {verdict} ({size} bytes)"),
+                    Some(reason) => format!(
+                        "{name}: {verdict} ({size} bytes; hashed here, the server couldn't: {reason})"
+                    ),
+                }
+            }
+            Err(e) if sftp::is_connection_error(&e) => return Err(e),
+            Err(e) => format!("Compare: hashing {name} failed: {e}"),
+        });
+
+        Ok(())
+    }
+
+    /// Flips dotfile visibility and relists both panes, keeping each cursor on
+    /// the same entry when it is still listed.
+    async fn toggle_hidden(&mut self) -> Result<()> {
+        self.show_hidden = !self.show_hidden;
+        self.view_state.show_hidden = Some(self.show_hidden);
+        if let Err(e) = self.view_state.save() {
+            self.status_message = Some(format!("Can't save view state: {e}"));
+        }
+
+        let local_name = self
+            .get_current_local_files()
+            .get(self.local_cursor)
+            .map(|f| f.name.clone());
+        let remote_name = self
+            .get_current_remote_files()
+            .get(self.remote_cursor)
+            .map(|f| f.name.clone());
+        let (local_cursor, remote_cursor) = (self.local_cursor, self.remote_cursor);
+
+        self.end_search();
+        self.refresh_local_files()?;
+        self.refresh_remote_files().await?;
+
+        self.local_cursor = restore_cursor(
+            self.get_current_local_files(),
+            local_name.as_deref(),
+            local_cursor,
+        );
+        self.remote_cursor = restore_cursor(
+            self.get_current_remote_files(),
+            remote_name.as_deref(),
+            remote_cursor,
+        );
+
+        Ok(())
+    }
+
+    /// Entries per row in the active pane: the grid's column count, or 1 in
+    /// the list view.
+    fn grid_columns(&self) -> usize {
+        if !self.grid_view {
+            return 1;
+        }
+        let width = match self.active_pane {
+            Pane::Local => self.pane_areas[0].width,
+            Pane::Remote => self.pane_areas[1].width,
+        };
+        crate::ui::grid_columns(width, crate::ui::longest_name(self.active_files()))
+    }
+
+    /// Moves up `count` entries, stopping at the top. With `wrap_cursor` set,
+    /// moving up from the top goes to the bottom.
+    fn move_cursor_up(&mut self, count: usize) {
+        let last = self.active_files().len().saturating_sub(1);
+        let wrap = self.config.wrap_cursor;
+        let cursor = match self.active_pane {
+            Pane::Local => &mut self.local_cursor,
+            Pane::Remote => &mut self.remote_cursor,
+        };
+        *cursor = if wrap && *cursor == 0 {
+            last
+        } else {
+            cursor.saturating_sub(count)
+        };
+    }
+
+    /// Moves down `count` entries, stopping at the bottom. With `wrap_cursor`
+    /// set, moving down from the bottom goes to the top.
+    fn move_cursor_down(&mut self, count: usize) {
+        let last = self.active_files().len().saturating_sub(1);
+        let wrap = self.config.wrap_cursor;
+        let cursor = match self.active_pane {
+            Pane::Local => &mut self.local_cursor,
+            Pane::Remote => &mut self.remote_cursor,
+        };
+        *cursor = if wrap && *cursor >= last {
+            0
+        } else {
+            cursor.saturating_add(count).min(last)
+        };
+    }
+
+    /// Puts the cursor on the `line`th entry (counting from 1), or on the last
+    /// entry without a count, like vim's `G`.
+    fn jump_to_entry(&mut self, line: Option<usize>) {
+        let last = self.active_files().len().saturating_sub(1);
+        let target = line.map_or(last, |line| line.saturating_sub(1).min(last));
+        match self.active_pane {
+            Pane::Local => self.local_cursor = target,
+            Pane::Remote => self.remote_cursor = target,
+        }
+    }
+
+    fn active_files(&self) -> &[FileInfo] {
+        match self.active_pane {
+            Pane::Local => self.get_current_local_files(),
+            Pane::Remote => self.get_current_remote_files(),
+        }
+    }
+
+    async fn change_directory(&mut self) -> Result<()> {
+        let file = match self.active_pane {
+            Pane::Local => self.get_current_local_files().get(self.local_cursor),
+            Pane::Remote => self.get_current_remote_files().get(self.remote_cursor),
+        };
+
+        if let Some(file) = file.filter(|f| f.is_dir).cloned() {
+            if file.name == ".." {
+                return self.go_to_parent().await;
+            }
+            if let Some(target) = &file.symlink_target
+                && !self.follow_symlinks
+            {
+                self.status_message = Some(format!(
+                    "{} -> {} (not following symlinks; L to follow)",
+                    file.name,
+                    target.display()
+                ));
+                return Ok(());
+            }
+            self.enter_directory(file.path).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Makes `path` the active pane's directory and relists it.
+    async fn enter_directory(&mut self, path: PathBuf) -> Result<()> {
+        self.end_search();
+
+        let result = match self.active_pane {
+            Pane::Local => {
+                let previous = std::mem::replace(&mut self.local_path, path);
+                self.refresh_local_files()
+                    .inspect_err(|_| self.local_path = previous)
+            }
+            Pane::Remote => {
+                let path = sftp::normalize_remote_path(&path);
+                let previous = std::mem::replace(&mut self.remote_path, path);
+                let result = self.refresh_remote_files().await;
+                if result.is_err() {
+                    self.remote_path = previous;
+                }
+                result
+            }
+        };
+
+        // Stay where we were rather than show a directory we can't read
+        match result {
+            Err(e) if sftp::is_permission_denied(&e) => {
+                self.status_message = Some(format!("Can't open directory: {e}"));
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Moves the active pane up one level and puts the cursor on the directory
+    /// it came from. Does nothing at the root.
+    async fn go_to_parent(&mut self) -> Result<()> {
+        let (current, parent) = match self.active_pane {
+            Pane::Local => (
+                self.local_path.clone(),
+                self.local_path.parent().map(Path::to_path_buf),
+            ),
+            Pane::Remote => (
+                self.remote_path.clone(),
+                sftp::remote_parent(&self.remote_path),
+            ),
+        };
+        // Nothing above the root
+        let Some(parent) = parent else {
+            return Ok(());
+        };
+        if self.active_pane == Pane::Remote && self.sftp_client.is_none() {
+            return Ok(());
+        }
+
+        self.enter_directory(parent).await?;
+
+        match self.active_pane {
+            Pane::Local => {
+                if let Some(index) = self
+                    .get_current_local_files()
+                    .iter()
+                    .position(|f| f.path == current)
+                {
+                    self.local_cursor = index;
+                }
+            }
+            Pane::Remote => {
+                if let Some(index) = self
+                    .get_current_remote_files()
+                    .iter()
+                    .position(|f| f.path == current)
+                {
+                    self.remote_cursor = index;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves the active pane to the home directory: the user's own locally,
+    /// the login directory remotely.
+    async fn go_home(&mut self) -> Result<()> {
+        let home = match self.active_pane {
+            Pane::Local => dirs::home_dir(),
+            Pane::Remote => self
+                .sftp_client
+                .as_ref()
+                .map(|client| client.home().to_path_buf()),
+        };
+        let Some(home) = home else {
+            if self.active_pane == Pane::Local {
+                self.status_message = Some("Can't find your home directory".to_string());
+            }
+            return Ok(());
+        };
+
+        self.enter_directory(home).await
+    }
+
+    fn switch_pane(&mut self) {
+        self.active_pane = match self.active_pane {
+            Pane::Local => Pane::Remote,
+            Pane::Remote => Pane::Local,
+        };
+    }
+
+    /// Toggles `count` entries from the cursor down, leaving the cursor
+    /// below the last of them. Stops at the end of the listing, so a count
+    /// running past it neither toggles the last entry again nor wraps around
+    /// to the top.
+    fn select_and_advance(&mut self, count: usize) {
+        for _ in 0..count {
+            self.toggle_selection();
+            let before = self.active_cursor();
+            self.move_cursor_down(1);
+            if self.active_cursor() <= before {
+                break;
+            }
+        }
+    }
+
+    fn active_cursor(&self) -> usize {
+        match self.active_pane {
+            Pane::Local => self.local_cursor,
+            Pane::Remote => self.remote_cursor,
+        }
+    }
+
+    fn toggle_selection(&mut self) {
+        // The parent entry is for navigation only and can't be transferred
+        let on_parent = match self.active_pane {
+            Pane::Local => self.get_current_local_files().get(self.local_cursor),
+            Pane::Remote => self.get_current_remote_files().get(self.remote_cursor),
+        }
+        .is_some_and(|file| file.name == "..");
+        if on_parent {
+            return;
+        }
+
+        match self.active_pane {
+            Pane::Local => {
+                if self.local_selected.contains(&self.local_cursor) {
+                    self.local_selected.remove(&self.local_cursor);
+                } else {
+                    self.local_selected.insert(self.local_cursor);
+                }
+            }
+            Pane::Remote => {
+                if self.remote_selected.contains(&self.remote_cursor) {
+                    self.remote_selected.remove(&self.remote_cursor);
+                } else {
+                    self.remote_selected.insert(self.remote_cursor);
+                }
+            }
+        }
+    }
+
+    /// Selects every entry in the active pane except `..`.
+    fn select_all(&mut self) {
+        let (files, selected) = match self.active_pane {
+            Pane::Local => (self.get_current_local_files(), &self.local_selected),
+            Pane::Remote => (self.get_current_remote_files(), &self.remote_selected),
+        };
+        let all: HashSet<usize> = files
+            .iter()
+            .enumerate()
+            .filter(|(_, file)| file.name != "..")
+            .map(|(i, _)| i)
+            .collect();
+        let all = selected.union(&all).copied().collect();
+
+        match self.active_pane {
+            Pane::Local => self.local_selected = all,
+            Pane::Remote => self.remote_selected = all,
+        }
+    }
+
+    /// Clears the active pane's selection set. Does nothing when it is already empty.
+    fn clear_selection(&mut self) {
+        match self.active_pane {
+            Pane::Local => self.local_selected.clear(),
+            Pane::Remote => self.remote_selected.clear(),
+        }
+    }
+
+    fn prepare_transfer(&mut self) -> Result<()> {
+        self.transfer_queue.clear();
+
+        for &index in &self.local_selected {
+            if let Some(file) = self.get_current_local_files().get(index)
+                && file.name != ".."
+            {
+                let destination = self.remote_path.join(file.file_name());
+                self.transfer_queue.push(TransferItem {
+                    source: file.path.clone(),
+                    destination,
+                    direction: TransferDirection::Upload,
+                    remove_source: false,
+                    enabled: true,
+                });
+            }
+        }
+
+        for &index in &self.remote_selected {
+            if let Some(file) = self.get_current_remote_files().get(index)
+                && file.name != ".."
+            {
+                let destination = self.local_path.join(file.file_name());
+                self.transfer_queue.push(TransferItem {
+                    source: file.path.clone(),
+                    destination,
+                    direction: TransferDirection::Download,
+                    remove_source: false,
+                    enabled: true,
+                });
+            }
+        }
+
+        self.open_transfer_dialog();
+
+        Ok(())
+    }
+
+    /// Queues just the entry under the cursor, ignoring the selection.
+    fn prepare_cursor_transfer(&mut self) {
+        let (file, destination_dir, direction) = match self.active_pane {
+            Pane::Local => (
+                self.get_current_local_files().get(self.local_cursor),
+                &self.remote_path,
+                TransferDirection::Upload,
+            ),
+            Pane::Remote => (
+                self.get_current_remote_files().get(self.remote_cursor),
+                &self.local_path,
+                TransferDirection::Download,
+            ),
+        };
+        let Some(file) = file.filter(|f| f.name != "..") else {
+            return;
+        };
+
+        self.transfer_queue = vec![TransferItem {
+            source: file.path.clone(),
+            destination: destination_dir.join(file.file_name()),
+            direction,
+            remove_source: false,
+            enabled: true,
+        }];
+        self.open_transfer_dialog();
+    }
+
+    /// Shows the transfer dialog for whatever is queued. In read-only mode,
+    /// items that would change the server are dropped from the queue first.
+    fn open_transfer_dialog(&mut self) {
+        if self.readonly {
+            let before = self.transfer_queue.len();
+            self.transfer_queue.retain(|item| !item.writes_remote());
+            let blocked = before - self.transfer_queue.len();
+            if blocked > 0 {
+                self.status_message = Some(format!(
+                    "Read-only mode: left out {blocked} item(s) that would change the server"
+                ));
+            }
+        }
+
+        if let Some(client) = self
+            .sftp_client
+            .clone()
+            .filter(|_| self.server_is_this_machine())
+        {
+            self.skip_self_overwrites(|path| client.realpath(path).ok());
+        }
+
+        // Shown as an uploads section then a downloads one, so the cursor
+        // and the run order follow the same layout
+        self.transfer_queue
+            .sort_by_key(|item| item.direction == TransferDirection::Download);
+        self.queue_cursor = 0;
+        self.show_transfer_dialog = !self.transfer_queue.is_empty();
+    }
+
+    /// Whether the connected host is this machine's own SSH server, so
+    /// remote paths are paths on this machine too: a loopback address on the
+    /// default port, reached directly. Another port is likely forwarded to a
+    /// VM or container, and a jump host resolves the name on its side.
+    fn server_is_this_machine(&self) -> bool {
+        let Some(host) = self.connection.as_ref().map(|params| &params.host) else {
+            return false;
+        };
+        if host.port.unwrap_or(22) != 22 || !host.proxy_jumps().is_empty() {
+            return false;
+        }
+        let hostname = host.hostname.as_deref().unwrap_or(&host.host);
+        hostname.eq_ignore_ascii_case("localhost")
+            || hostname
+                .trim_matches(['[', ']'])
+                .parse::<std::net::IpAddr>()
+                .is_ok_and(|ip| ip.is_loopback())
+    }
+
+    /// Leaves out queued items that would copy a file onto itself, which
+    /// would at best do nothing and at worst truncate it, and says so.
+    fn skip_self_overwrites(&mut self, remote_realpath: impl Fn(&Path) -> Option<PathBuf>) {
+        let mut skipped = Vec::new();
+        self.transfer_queue.retain(|item| {
+            let overwrites = item.overwrites_source(&remote_realpath);
+            if overwrites {
+                skipped.push(item.source.clone());
+            }
+            !overwrites
+        });
+        if let Some(first) = skipped.first() {
+            let name = first
+                .file_name()
+                .map(sftp::display_name)
+                .unwrap_or_else(|| first.display().to_string());
+            self.status_message = Some(match skipped.len() {
+                1 => format!("Skipped {name}: its destination is its own source"),
+                n => {
+                    format!("Skipped {n} items whose destination is their own source, like {name}")
+                }
+            });
+        }
+    }
+
+    /// Every handler that changes the server asks this first. In read-only
+    /// mode it refuses, saying why in the status line.
+    fn allow_remote_write(&mut self, action: &str) -> bool {
+        if self.readonly {
+            self.status_message = Some(format!("Read-only mode: {action} is disabled"));
+        }
+        !self.readonly
+    }
+
+    /// Paths of the active pane's selection, or of the cursor entry when
+    /// nothing is selected. Never includes `..`.
+    fn marked_paths(&self) -> Vec<PathBuf> {
+        let (files, selected, cursor) = match self.active_pane {
+            Pane::Local => (
+                self.get_current_local_files(),
+                &self.local_selected,
+                self.local_cursor,
+            ),
+            Pane::Remote => (
+                self.get_current_remote_files(),
+                &self.remote_selected,
+                self.remote_cursor,
+            ),
+        };
+
+        let mut indices: Vec<usize> = if selected.is_empty() {
+            vec![cursor]
+        } else {
+            selected.iter().copied().collect()
+        };
+        indices.sort_unstable();
+
+        indices
+            .into_iter()
+            .filter_map(|i| files.get(i))
+            .filter(|file| file.name != "..")
+            .map(|file| file.path.clone())
+            .collect()
+    }
+
+    /// Puts the marked entries in the cut buffer, to be moved by `paste_cut`.
+    fn cut_marked(&mut self) {
+        let paths = self.marked_paths();
+        if paths.is_empty() {
+            self.status_message = Some("Nothing to cut".to_string());
+            return;
+        }
+
+        self.status_message = Some(format!(
+            "Cut {} item(s). Press P in the destination directory to move them",
+            paths.len()
+        ));
+        self.cut_buffer = Some(Clipboard {
+            pane: self.active_pane.clone(),
+            paths,
+        });
+        self.clear_selection();
+    }
+
+    /// Moves the cut entries into the active pane's directory. Moves within a
+    /// pane are renames; moves across panes are queued as transfers that
+    /// delete their source.
+    async fn paste_cut(&mut self) -> Result<()> {
+        let Some(cut) = self.cut_buffer.clone() else {
+            self.status_message = Some("Nothing to paste. Press X to cut first".to_string());
+            return Ok(());
+        };
+        if cut.pane == Pane::Remote
+            && self.active_pane == Pane::Remote
+            && !self.allow_remote_write("moving remote files")
+        {
+            return Ok(());
+        }
+        if self.active_pane == Pane::Remote && self.sftp_client.is_none() {
+            self.status_message = Some("Paste: not connected".to_string());
+            return Ok(());
+        }
+        if cut.pane != self.active_pane {
+            self.queue_clipboard_transfer(&cut, true);
+            return Ok(());
+        }
+
+        let destination_dir = match self.active_pane {
+            Pane::Local => self.local_path.clone(),
+            Pane::Remote => self.remote_path.clone(),
+        };
+
+        let mut moved = 0;
+        let mut failures = Vec::new();
+        for source in &cut.paths {
+            let Some(name) = source.file_name() else {
+                failures.push(format!("{}: no file name to paste as", source.display()));
+                continue;
+            };
+            let destination = destination_dir.join(name);
+            if destination == *source {
+                continue;
+            }
+
+            let result = match cut.pane {
+                Pane::Local => move_local(source, &destination),
+                Pane::Remote => self
+                    .with_client(|client| client.rename(source, &destination))
+                    .map(|_| ()),
+            };
+            match result {
+                Ok(()) => moved += 1,
+                Err(e) if sftp::is_connection_error(&e) => return Err(e),
+                Err(e) => failures.push(format!("{}: {e}", name.to_string_lossy())),
+            }
+        }
+
+        if failures.is_empty() {
+            self.cut_buffer = None;
+            self.status_message = Some(format!("Moved {moved} item(s)"));
+        } else {
+            self.status_message = Some(format!(
+                "Moved {moved} item(s), {} failed: {}",
+                failures.len(),
+                failures.join(", ")
+            ));
+        }
+
+        self.refresh_local_files()?;
+        self.refresh_remote_files().await
+    }
+
+    /// Puts the marked entries in the copy buffer. It stays there, across
+    /// directory changes, until the next yank.
+    fn yank_marked(&mut self) {
+        let paths = self.marked_paths();
+        if paths.is_empty() {
+            self.status_message = Some("Nothing to yank".to_string());
+            return;
+        }
+
+        self.status_message = Some(format!(
+            "Yanked {} item(s). Press p in the destination directory to copy them",
+            paths.len()
+        ));
+        self.copy_buffer = Some(Clipboard {
+            pane: self.active_pane.clone(),
+            paths,
+        });
+        self.clear_selection();
+    }
+
+    /// Copies the yanked entries into the active pane's directory. A name that
+    /// is already taken gets ` (copy)` appended. Copies across panes are
+    /// queued as ordinary transfers.
+    async fn paste_copy(&mut self) -> Result<()> {
+        let Some(yanked) = self.copy_buffer.clone() else {
+            self.status_message = Some("Nothing to paste. Press yy to yank first".to_string());
+            return Ok(());
+        };
+        if yanked.pane == Pane::Remote
+            && self.active_pane == Pane::Remote
+            && !self.allow_remote_write("copying remote files")
+        {
+            return Ok(());
+        }
+        if self.active_pane == Pane::Remote && self.sftp_client.is_none() {
+            self.status_message = Some("Paste: not connected".to_string());
+            return Ok(());
+        }
+        if yanked.pane != self.active_pane {
+            self.queue_clipboard_transfer(&yanked, false);
+            return Ok(());
+        }
+
+        let destination_dir = match self.active_pane {
+            Pane::Local => self.local_path.clone(),
+            Pane::Remote => self.remote_path.clone(),
+        };
+
+        let mut copied = 0;
+        let mut failures = Vec::new();
+        for source in &yanked.paths {
+            let Some(name) = source.file_name() else {
+                failures.push(format!("{}: no file name to paste as", source.display()));
+                continue;
+            };
+
+            let result = match yanked.pane {
+                Pane::Local => {
+                    let name = copy_name(name, |n| destination_dir.join(n).exists());
+                    copy_local(source, &destination_dir.join(name))
+                }
+                Pane::Remote => self
+                    .with_client(|client| {
+                        let name = copy_name(name, |n| client.exists(&destination_dir.join(n)));
+                        client.copy(source, &destination_dir.join(name))
+                    })
+                    .map(|_| ()),
+            };
+            match result {
+                Ok(()) => copied += 1,
+                Err(e) if sftp::is_connection_error(&e) => return Err(e),
+                Err(e) => failures.push(format!("{}: {e}", sftp::display_name(name))),
+            }
+        }
+
+        self.status_message = Some(if failures.is_empty() {
+            format!("Copied {copied} item(s)")
+        } else {
+            format!(
+                "Copied {copied} item(s), {} failed: {}",
+                failures.len(),
+                failures.join(", ")
+            )
+        });
+
+        self.refresh_local_files()?;
+        self.refresh_remote_files().await
+    }
+
+    /// Copies the file under the cursor into the same directory under a
+    /// free `name.copy.ext` name, then puts the cursor on the copy.
+    async fn duplicate_cursor_file(&mut self) -> Result<()> {
+        let file = match self.active_pane {
+            Pane::Local => self.get_current_local_files().get(self.local_cursor),
+            Pane::Remote => self.get_current_remote_files().get(self.remote_cursor),
+        };
+        let Some(file) = file.filter(|file| !file.is_dir && file.name != "..") else {
+            self.status_message = Some("Duplicate: select a file".to_string());
+            return Ok(());
+        };
+        let source = file.path.clone();
+        let name = file.file_name().to_os_string();
+        let Some(dir) = source.parent().map(Path::to_path_buf) else {
+            return Ok(());
+        };
+        if self.active_pane == Pane::Remote && !self.allow_remote_write("duplicating remote files")
+        {
+            return Ok(());
+        }
+
+        let result = match self.active_pane {
+            Pane::Local => {
+                let copy = duplicate_name(&name, |n| dir.join(n).exists());
+                copy_local(&source, &dir.join(&copy)).map(|_| copy)
+            }
+            Pane::Remote => self
+                .with_client(|client| {
+                    let copy = duplicate_name(&name, |n| client.exists(&dir.join(n)));
+                    client.copy(&source, &dir.join(&copy)).map(|_| copy)
+                })
+                .and_then(|copy| copy.ok_or_else(|| anyhow!("not connected"))),
+        };
+        let copy = match result {
+            Ok(copy) => sftp::display_name(&copy),
+            Err(e) if sftp::is_connection_error(&e) => return Err(e),
+            Err(e) => {
+                self.status_message = Some(format!(
+                    "Can't duplicate {}: {e}",
+                    sftp::display_name(&name)
+                ));
+                return Ok(());
+            }
+        };
+
+        self.status_message = Some(format!("Duplicated as {copy}"));
+        match self.active_pane {
+            Pane::Local => {
+                self.refresh_local_files()?;
+                self.local_cursor = restore_cursor(
+                    self.get_current_local_files(),
+                    Some(&copy),
+                    self.local_cursor,
+                );
+            }
+            Pane::Remote => {
+                self.refresh_remote_files().await?;
+                self.remote_cursor = restore_cursor(
+                    self.get_current_remote_files(),
+                    Some(&copy),
+                    self.remote_cursor,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Queues clipboard entries for transfer into the other pane's directory.
+    /// With `remove_source` set, each source is deleted once it has arrived.
+    fn queue_clipboard_transfer(&mut self, clipboard: &Clipboard, remove_source: bool) {
+        let (destination_dir, direction) = match clipboard.pane {
+            Pane::Local => (&self.remote_path, TransferDirection::Upload),
+            Pane::Remote => (&self.local_path, TransferDirection::Download),
+        };
+
+        self.transfer_queue = clipboard
+            .paths
+            .iter()
+            .filter_map(|source| {
+                Some(TransferItem {
+                    source: source.clone(),
+                    destination: destination_dir.join(source.file_name()?),
+                    direction: direction.clone(),
+                    remove_source,
+                    enabled: true,
+                })
+            })
+            .collect();
+        self.open_transfer_dialog();
+    }
+
+    /// Starts the queue, unless it is big enough to ask first: then its size
+    /// is kept in `large_transfer` until the user confirms. Items that can't
+    /// be sized up front always ask.
+    fn confirm_transfers(&mut self) {
+        let threshold = self.config.confirm_transfer_bytes;
+        // No warning to give, so don't walk the queue to size it
+        if threshold == 0 {
+            self.execute_transfers();
+            return;
+        }
+
+        let mut large = LargeTransfer {
+            bytes: 0,
+            unsized_items: 0,
+        };
+        for item in self.transfer_queue.iter().filter(|item| item.enabled) {
+            match transfer::item_size(self.sftp_client.as_deref(), item, self.config.max_depth) {
+                Some(bytes) => large.bytes += bytes,
+                None => large.unsized_items += 1,
+            }
+        }
+
+        if large.bytes > threshold || large.unsized_items > 0 {
+            self.large_transfer = Some(large);
+        } else {
+            self.execute_transfers();
+        }
+    }
+
+    /// Starts working through the enabled part of the queue on a background
+    /// thread. The dialog stays open and shows progress until `poll_transfers`
+    /// sees it finish.
+    fn execute_transfers(&mut self) {
+        self.transfer_queue.retain(|item| item.enabled);
+
+        let (Some(mut client), Some(params)) = (self.sftp_client.clone(), self.connection.clone())
+        else {
+            self.show_transfer_dialog = false;
+            self.transfer_queue.clear();
+            return;
+        };
+        if self.transfer_queue.is_empty() {
+            self.show_transfer_dialog = false;
+            self.status_message = Some("Nothing to transfer: every item was disabled".to_string());
+            return;
+        }
+
+        let items = self.transfer_queue.clone();
+        let progress = Arc::new(Mutex::new(TransferProgress::new(items.len())));
+        let options = TransferOptions {
+            resume: self.resume_downloads,
+            preserve_times: self.config.preserve_times,
+            max_depth: self.config.max_depth,
+            workers: self.config.transfer_workers,
+            skip_identical: self.config.skip_identical,
+            verify_uploads: self.config.verify_uploads,
+        };
+
+        // Each batch gets its own flag, so a late cancel can't stop the next
+        self.transfer_cancel = Arc::new(AtomicBool::new(false));
+        let cancel = Arc::clone(&self.transfer_cancel);
+        let worker_progress = Arc::clone(&progress);
+        let log = self.config.transfer_log.then(TransferLog::open).flatten();
+        let hook = self
+            .current_host
+            .as_deref()
+            .and_then(|host| self.config.host(host))
+            .and_then(|settings| settings.after_transfer.clone())
+            // Commands on the server may change it
+            .filter(|hook| !(self.readonly && hook.run_on == HookSide::Remote));
+        self.transfer_task = Some(tokio::task::spawn_blocking(move || {
+            let host = params.host.host.clone();
+            let reconnect = || params.connect();
+            let mut log_error = None;
+            let mut write_log = |result: &TransferResult| {
+                if let Some(log) = &log
+                    && let Err(e) = log.record(result)
+                {
+                    log_error.get_or_insert_with(|| e.to_string());
+                }
+            };
+            let mut results = transfer::run_transfers(
+                &mut client,
+                reconnect,
+                &host,
+                &items,
+                &worker_progress,
+                options,
+                &cancel,
+                &mut write_log,
+            );
+            if let Some(hook) = hook {
+                for result in results
+                    .iter_mut()
+                    .filter(|result| result.outcome == TransferOutcome::Done)
+                {
+                    result.hook = Some(hook.run(&result.item, &client));
+                }
+            }
+            FinishedBatch {
+                results,
+                client,
+                log_error,
+            }
+        }));
+        self.transfer_progress = Some(progress);
+    }
+
+    /// Asks the running batch to stop after the chunk it is on.
+    fn cancel_transfers(&mut self) {
+        self.transfer_cancel.store(true, Ordering::Relaxed);
+        if let Some(progress) = &self.transfer_progress
+            && let Ok(mut p) = progress.lock()
+        {
+            p.status = Some("Cancelling…".to_string());
+        }
+    }
+
+    async fn poll_transfers(&mut self) -> Result<()> {
+        let Some(task) = self.transfer_task.take_if(|task| task.is_finished()) else {
+            return Ok(());
+        };
+
+        self.transfer_progress = None;
+        self.show_transfer_dialog = false;
+        self.transfer_queue.clear();
+        self.local_selected.clear();
+        self.remote_selected.clear();
+
+        let FinishedBatch {
+            results,
+            client,
+            log_error,
+        } = task.await?;
+        // The worker may have reconnected along the way
+        self.sftp_client = Some(client);
+        if let Some(e) = log_error {
+            self.status_message = Some(format!("Can't write the transfer log: {e}"));
+        }
+
+        // Whatever was moved is no longer waiting to be pasted
+        if let Some(cut) = &mut self.cut_buffer {
+            cut.paths.retain(|path| {
+                !results.iter().any(|result| {
+                    result.item.remove_source
+                        && result.item.source == *path
+                        && result.outcome == TransferOutcome::Done
+                })
+            });
+            if cut.paths.is_empty() {
+                self.cut_buffer = None;
+            }
+        }
+        let follow = self
+            .config
+            .follow_transfers
+            .then(|| follow_target(&results))
+            .flatten();
+        self.transfer_results = Some(results);
+        self.summary_cursor = 0;
+
+        self.refresh_local_files()?;
+        self.refresh_remote_files().await?;
+        if let Some((direction, dir, first)) = follow {
+            self.follow_transfers(direction, dir, &first).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Shows a finished batch's destination directory in its pane, with the
+    /// cursor on `first`.
+    async fn follow_transfers(
+        &mut self,
+        direction: TransferDirection,
+        dir: PathBuf,
+        first: &Path,
+    ) -> Result<()> {
+        self.active_pane = match direction {
+            TransferDirection::Upload => Pane::Remote,
+            TransferDirection::Download => Pane::Local,
+        };
+        let current = match self.active_pane {
+            Pane::Local => &self.local_path,
+            Pane::Remote => &self.remote_path,
+        };
+        if *current != dir {
+            self.enter_directory(dir).await?;
+        }
+
+        let files = match self.active_pane {
+            Pane::Local => self.get_current_local_files(),
+            Pane::Remote => self.get_current_remote_files(),
+        };
+        if let Some(index) = files.iter().position(|f| f.path == first) {
+            match self.active_pane {
+                Pane::Local => self.local_cursor = index,
+                Pane::Remote => self.remote_cursor = index,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn start_deep_search(&mut self) {
+        if self.sftp_client.is_none() {
+            self.status_message = Some("Find: not connected".to_string());
+            return;
+        }
+
+        self.deep_search = Some(DeepSearch {
+            query: String::new(),
+            editing: true,
+            root: self.remote_path.clone(),
+            results: Arc::new(Mutex::new(SearchResults::default())),
+            cursor: 0,
+            cancel: Arc::new(AtomicBool::new(false)),
+            task: None,
+        });
+    }
+
+    async fn handle_deep_search_event(&mut self, key: KeyCode) -> Result<()> {
+        let Some(deep_search) = self.deep_search.as_mut() else {
+            return Ok(());
+        };
+
+        if deep_search.editing {
+            match key {
+                KeyCode::Esc => self.deep_search = None,
+                KeyCode::Up => {
+                    if let Some(entry) = self.find_history.previous(&deep_search.query) {
+                        deep_search.query = entry.to_string();
+                    }
+                }
+                KeyCode::Down => {
+                    if let Some(entry) = self.find_history.next() {
+                        deep_search.query = entry;
+                    }
+                }
+                KeyCode::Backspace => {
+                    deep_search.query.pop();
+                    self.find_history.reset();
+                }
+                KeyCode::Char(c) => {
+                    deep_search.query.push(c);
+                    self.find_history.reset();
+                }
+                KeyCode::Enter if !deep_search.query.is_empty() => {
+                    self.find_history.record(&deep_search.query);
+                    if let Err(e) = self.find_history.save() {
+                        self.status_message = Some(format!("Can't save find history: {e}"));
+                    }
+                    self.run_deep_search();
+                }
+                _ => {}
+            }
+            return Ok(());
+        }
+
+        let match_count = deep_search
+            .results
+            .lock()
+            .map(|r| r.matches.len())
+            .unwrap_or(0);
+
+        match key {
+            // The first Esc stops a running walk, the next one closes
+            KeyCode::Esc if deep_search.task.is_some() => {
+                deep_search.cancel.store(true, Ordering::Relaxed);
+            }
+            KeyCode::Esc => self.deep_search = None,
+            KeyCode::Up | KeyCode::Char('k') if deep_search.cursor > 0 => {
+                deep_search.cursor -= 1;
+            }
+            KeyCode::Down | KeyCode::Char('j') if deep_search.cursor + 1 < match_count => {
+                deep_search.cursor += 1;
+            }
+            KeyCode::Enter => {
+                let found = deep_search
+                    .results
+                    .lock()
+                    .ok()
+                    .and_then(|r| r.matches.get(deep_search.cursor).cloned());
+                if let Some(found) = found {
+                    deep_search.cancel.store(true, Ordering::Relaxed);
+                    self.deep_search = None;
+                    self.reveal_remote(&found.path).await?;
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Starts walking the remote tree on a background thread.
+    fn run_deep_search(&mut self) {
+        let Some(client) = self.sftp_client.clone() else {
+            self.deep_search = None;
+            return;
+        };
+        let Some(deep_search) = self.deep_search.as_mut() else {
+            return;
+        };
+
+        deep_search.editing = false;
+        let root = deep_search.root.clone();
+        let query = deep_search.query.clone();
+        let cancel = Arc::clone(&deep_search.cancel);
+        let results = Arc::clone(&deep_search.results);
+
+        deep_search.task = Some(tokio::task::spawn_blocking(move || {
+            search::find(
+                |dir| client.list_directory(dir),
+                &root,
+                &query,
+                &cancel,
+                &results,
+            )
+        }));
+    }
+
+    async fn poll_deep_search(&mut self) -> Result<()> {
+        let Some(task) = self
+            .deep_search
+            .as_mut()
+            .and_then(|s| s.task.take_if(|task| task.is_finished()))
+        else {
+            return Ok(());
+        };
+
+        task.await?
+    }
+
+    /// Shows `path`'s directory in the remote pane with the cursor on it.
+    async fn reveal_remote(&mut self, path: &Path) -> Result<()> {
+        let Some(parent) = path.parent() else {
+            return Ok(());
+        };
+
+        self.active_pane = Pane::Remote;
+        self.enter_directory(parent.to_path_buf()).await?;
+        if let Some(index) = self
+            .get_current_remote_files()
+            .iter()
+            .position(|f| f.path == path)
+        {
+            self.remote_cursor = index;
+        }
+
+        Ok(())
+    }
+
+    async fn handle_search_event(&mut self, key: KeyCode) -> Result<()> {
+        match key {
+            KeyCode::Esc => {
+                self.end_search();
+            }
+            KeyCode::Enter if self.search_query.is_empty() => {
+                self.end_search();
+            }
+            KeyCode::Enter => {
+                self.search_editing = false;
+                self.search_history.record(&self.search_query);
+                if let Err(e) = self.search_history.save() {
+                    self.status_message = Some(format!("Can't save search history: {e}"));
+                }
+            }
+            // Both panes are filtered, so either can be browsed
+            KeyCode::Tab => {
+                self.switch_pane();
+            }
+            KeyCode::Up => {
+                if let Some(entry) = self.search_history.previous(&self.search_query) {
+                    self.search_query = entry.to_string();
+                    self.update_filters();
+                }
+            }
+            KeyCode::Down => {
+                if let Some(entry) = self.search_history.next() {
+                    self.search_query = entry;
+                    self.update_filters();
+                }
+            }
+            KeyCode::Backspace => {
+                self.search_query.pop();
+                self.search_history.reset();
+                self.update_filters();
+            }
+            KeyCode::Char(c) => {
+                self.search_query.push(c);
+                self.search_history.reset();
+                self.update_filters();
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn start_search(&mut self) {
+        self.search_mode = true;
+        self.search_editing = true;
+        self.search_query.clear();
+        self.update_filters();
+        self.local_cursor = 0;
+        self.remote_cursor = 0;
+    }
+
+    /// Whether `pane` lists a filtered copy of its entries, because of a `/`
+    /// query, a type filter or the tree view.
+    fn is_filtered(&self, pane: Pane) -> bool {
+        let (type_filter, tree) = match pane {
+            Pane::Local => (self.local_type_filter, &self.local_tree),
+            Pane::Remote => (self.remote_type_filter, &self.remote_tree),
+        };
+        (self.search_mode && !self.search_query.is_empty())
+            || type_filter != TypeFilter::All
+            || tree.is_some()
+    }
+
+    /// Rebuilds the filtered listings from each pane's tree view, the `/`
+    /// query and each pane's type filter.
+    fn update_filters(&mut self) {
+        let query = (self.search_mode && !self.search_query.is_empty())
+            .then(|| self.search_query.to_lowercase());
+        let filter = |files: &[FileInfo],
+                      type_filter: TypeFilter,
+                      tree: Option<&TreeView>|
+         -> Vec<FileInfo> {
+            let rows = tree.map(|tree| tree.flatten(files));
+            rows.as_deref()
+                .unwrap_or(files)
+                .iter()
+                .filter(|file| type_filter.keeps(file))
+                .filter(|file| {
+                    query
+                        .as_ref()
+                        .is_none_or(|query| file.name.to_lowercase().contains(query))
+                })
+                .cloned()
+                .collect()
+        };
+
+        self.filtered_local_files = if self.is_filtered(Pane::Local) {
+            filter(
+                &self.local_files,
+                self.local_type_filter,
+                self.local_tree.as_ref(),
+            )
+        } else {
+            Vec::new()
+        };
+        self.filtered_remote_files = if self.is_filtered(Pane::Remote) {
+            filter(
+                &self.remote_files,
+                self.remote_type_filter,
+                self.remote_tree.as_ref(),
+            )
+        } else {
+            Vec::new()
+        };
+    }
+
+    /// Moves the active pane on to its next type filter, keeping the cursor
+    /// and selection on the same entries where they are still listed.
+    fn cycle_type_filter(&mut self) {
+        let (files, selected, cursor) = match self.active_pane {
+            Pane::Local => (
+                self.get_current_local_files(),
+                &self.local_selected,
+                self.local_cursor,
+            ),
+            Pane::Remote => (
+                self.get_current_remote_files(),
+                &self.remote_selected,
+                self.remote_cursor,
+            ),
+        };
+        let cursor_name = files.get(cursor).map(|f| f.name.clone());
+        let selected_names: HashSet<String> = selected
+            .iter()
+            .filter_map(|&i| files.get(i))
+            .map(|f| f.name.clone())
+            .collect();
+
+        let type_filter = match self.active_pane {
+            Pane::Local => &mut self.local_type_filter,
+            Pane::Remote => &mut self.remote_type_filter,
+        };
+        *type_filter = type_filter.next();
+        let type_filter = *type_filter;
+        if self.active_pane == Pane::Remote
+            && type_filter != TypeFilter::All
+            // Links are only classified once their targets are looked up
+            && let Some(client) = self.sftp_client.clone()
+        {
+            for file in &mut self.remote_files {
+                client.resolve_link(file);
+            }
+        }
+        self.update_filters();
+
+        let files = match self.active_pane {
+            Pane::Local => self.get_current_local_files(),
+            Pane::Remote => self.get_current_remote_files(),
+        };
+        let cursor = restore_cursor(files, cursor_name.as_deref(), 0);
+        let selected: HashSet<usize> = files
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| selected_names.contains(&f.name))
+            .map(|(i, _)| i)
+            .collect();
+        match self.active_pane {
+            Pane::Local => {
+                self.local_cursor = cursor;
+                self.local_selected = selected;
+            }
+            Pane::Remote => {
+                self.remote_cursor = cursor;
+                self.remote_selected = selected;
+            }
+        }
+        self.status_message = Some(
+            match type_filter {
+                TypeFilter::All => "Showing all entries",
+                TypeFilter::Directories => "Showing directories only",
+                TypeFilter::Files => "Showing files only",
+            }
+            .to_string(),
+        );
+    }
+
+    /// Switches the active pane between its flat listing and a tree view.
+    fn toggle_tree(&mut self) {
+        let pane = self.active_pane.clone();
+        self.keeping_marks(|app| {
+            let tree = match pane {
+                Pane::Local => &mut app.local_tree,
+                Pane::Remote => &mut app.remote_tree,
+            };
+            *tree = match tree {
+                Some(_) => None,
+                None => Some(TreeView::default()),
+            };
+            app.status_message = Some(
+                if tree.is_some() {
+                    "Tree view: Enter or Space expands a directory"
+                } else {
+                    "Flat view"
+                }
+                .to_string(),
+            );
+            app.update_filters();
+        });
+    }
+
+    /// Whether Enter and Space expand or collapse the entry under the cursor
+    /// instead of entering or selecting it: the active pane is a tree and
+    /// the entry a directory.
+    fn cursor_on_tree_dir(&self) -> bool {
+        let (tree, file) = match self.active_pane {
+            Pane::Local => (
+                &self.local_tree,
+                self.get_current_local_files().get(self.local_cursor),
+            ),
+            Pane::Remote => (
+                &self.remote_tree,
+                self.get_current_remote_files().get(self.remote_cursor),
+            ),
+        };
+        tree.is_some() && file.is_some_and(|file| file.is_dir && file.name != "..")
+    }
+
+    /// Expands the directory under the cursor in the tree view, listing what
+    /// is in it, or collapses it when it is already expanded.
+    fn toggle_expansion(&mut self) -> Result<()> {
+        let (tree, file) = match self.active_pane {
+            Pane::Local => (
+                &self.local_tree,
+                self.get_current_local_files().get(self.local_cursor),
+            ),
+            Pane::Remote => (
+                &self.remote_tree,
+                self.get_current_remote_files().get(self.remote_cursor),
+            ),
+        };
+        let Some(file) = file.cloned() else {
+            return Ok(());
+        };
+        let expanded = tree
+            .as_ref()
+            .is_some_and(|tree| tree.is_expanded(&file.path));
+
+        let entries = if expanded {
+            None
+        } else {
+            if let Some(target) = &file.symlink_target
+                && !self.follow_symlinks
+            {
+                self.status_message = Some(format!(
+                    "{} -> {} (not following symlinks; L to follow)",
+                    file.name,
+                    target.display()
+                ));
+                return Ok(());
+            }
+            let listing = match self.active_pane {
+                Pane::Local => self
+                    .list_local_dir(&file.path)
+                    .map(|(entries, _)| Some(entries)),
+                Pane::Remote => self
+                    .list_remote_dir(&file.path)
+                    .map(|listing| listing.map(|(entries, _)| entries)),
+            };
+            match listing {
+                Ok(Some(entries)) => Some(entries),
+                Ok(None) => return Ok(()),
+                Err(e) if sftp::is_connection_error(&e) => return Err(e),
+                Err(e) => {
+                    self.status_message = Some(format!("Can't list {}: {e}", file.name));
+                    return Ok(());
+                }
+            }
+        };
+
+        let pane = self.active_pane.clone();
+        self.keeping_marks(|app| {
+            let tree = match pane {
+                Pane::Local => &mut app.local_tree,
+                Pane::Remote => &mut app.remote_tree,
+            }
+            .get_or_insert_with(TreeView::default);
+            match entries {
+                Some(entries) => tree.expand(file.path.clone(), entries),
+                None => tree.collapse(&file.path),
+            }
+            app.update_filters();
+        });
+        Ok(())
+    }
+
+    /// Runs `change`, which relists the active pane, then puts the cursor
+    /// and selection back on the same paths. A cursor whose entry is no
+    /// longer listed goes to the closest directory above it that is.
+    fn keeping_marks(&mut self, change: impl FnOnce(&mut Self)) {
+        let (files, selected, cursor) = match self.active_pane {
+            Pane::Local => (
+                self.get_current_local_files(),
+                &self.local_selected,
+                self.local_cursor,
+            ),
+            Pane::Remote => (
+                self.get_current_remote_files(),
+                &self.remote_selected,
+                self.remote_cursor,
+            ),
+        };
+        let cursor_path = files.get(cursor).map(|f| f.path.clone());
+        let selected_paths: HashSet<PathBuf> = selected
+            .iter()
+            .filter_map(|&i| files.get(i))
+            .map(|f| f.path.clone())
+            .collect();
+
+        change(self);
+
+        let files = match self.active_pane {
+            Pane::Local => self.get_current_local_files(),
+            Pane::Remote => self.get_current_remote_files(),
+        };
+        let cursor = cursor_path
+            .and_then(|path| {
+                path.ancestors()
+                    .find_map(|path| files.iter().position(|f| f.path == path))
+            })
+            .unwrap_or(0);
+        let selected: HashSet<usize> = files
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| selected_paths.contains(&f.path))
+            .map(|(i, _)| i)
+            .collect();
+        match self.active_pane {
+            Pane::Local => {
+                self.local_cursor = cursor;
+                self.local_selected = selected;
+            }
+            Pane::Remote => {
+                self.remote_cursor = cursor;
+                self.remote_selected = selected;
+            }
+        }
+    }
+
+    fn end_search(&mut self) {
+        self.search_mode = false;
+        self.search_editing = false;
+        self.search_query.clear();
+        self.update_filters();
+    }
+
+    /// Drops the filter, keeping both cursors on the entries they were on.
+    /// Selections refer to the filtered listing, so they go too.
+    fn leave_search(&mut self) {
+        let local_name = self
+            .get_current_local_files()
+            .get(self.local_cursor)
+            .map(|f| f.name.clone());
+        let remote_name = self
+            .get_current_remote_files()
+            .get(self.remote_cursor)
+            .map(|f| f.name.clone());
+
+        self.end_search();
+        self.clear_selection();
+        self.local_cursor =
+            restore_cursor(self.get_current_local_files(), local_name.as_deref(), 0);
+        self.remote_cursor =
+            restore_cursor(self.get_current_remote_files(), remote_name.as_deref(), 0);
+    }
+
+    /// Moves the active pane's cursor to the next (or previous) match,
+    /// wrapping around at either end.
+    fn step_match(&mut self, forward: bool) {
+        let count = match self.active_pane {
+            Pane::Local => self.filtered_local_files.len(),
+            Pane::Remote => self.filtered_remote_files.len(),
+        };
+        if count == 0 {
+            return;
+        }
+        let cursor = match self.active_pane {
+            Pane::Local => &mut self.local_cursor,
+            Pane::Remote => &mut self.remote_cursor,
+        };
+        *cursor = if forward {
+            (*cursor + 1) % count
+        } else {
+            (*cursor + count - 1) % count
+        };
+    }
+
+    /// The cursor's match number (from 1) and the number of matches, once a
+    /// `/` query has been entered and is filtering the active pane.
+    pub fn search_position(&self) -> Option<(usize, usize)> {
+        if !self.search_mode || self.search_editing {
+            return None;
+        }
+        let (cursor, count) = match self.active_pane {
+            Pane::Local => (self.local_cursor, self.filtered_local_files.len()),
+            Pane::Remote => (self.remote_cursor, self.filtered_remote_files.len()),
+        };
+        Some((if count == 0 { 0 } else { cursor + 1 }, count))
+    }
+
+    /// How many entries match the query in the local and remote panes,
+    /// while a query is filtering them.
+    pub fn search_counts(&self) -> Option<(usize, usize)> {
+        (self.search_mode && !self.search_query.is_empty()).then_some((
+            self.filtered_local_files.len(),
+            self.filtered_remote_files.len(),
+        ))
+    }
+
+    pub fn get_current_local_files(&self) -> &[FileInfo] {
+        if self.is_filtered(Pane::Local) {
+            &self.filtered_local_files
+        } else {
+            &self.local_files
+        }
+    }
+
+    pub fn get_current_remote_files(&self) -> &[FileInfo] {
+        if self.is_filtered(Pane::Remote) {
+            &self.filtered_remote_files
+        } else {
+            &self.remote_files
+        }
+    }
+
+    fn current_remote_files_mut(&mut self) -> &mut [FileInfo] {
+        if self.is_filtered(Pane::Remote) {
+            &mut self.filtered_remote_files
+        } else {
+            &mut self.remote_files
+        }
+    }
+}
+
+/// Splits directory entries into the readable ones and a count of those that
+/// couldn't be read, so one bad entry doesn't hide the rest.
+fn accessible_entries(
+    entries: impl Iterator<Item = std::io::Result<FileInfo>>,
+) -> (Vec<FileInfo>, usize) {
+    let mut files = Vec::new();
+    let mut inaccessible = 0;
+    for entry in entries {
+        match entry {
+            Ok(file) => files.push(file),
+            Err(_) => inaccessible += 1,
+        }
+    }
+    (files, inaccessible)
+}
+
+/// Builds the pane entry for `path` from its `symlink_metadata`. Symlinks are
+/// described by their targets, as far as those can be read.
+fn local_file_info(path: PathBuf, metadata: &fs::Metadata, names: &OwnerNames) -> FileInfo {
+    let name = path
+        .file_name()
+        .map_or_else(|| "Unknown".to_string(), sftp::display_name);
+
+    #[cfg(unix)]
+    let (uid, gid, permissions) = {
+        use std::os::unix::fs::MetadataExt;
+        (Some(metadata.uid()), Some(metadata.gid()), metadata.mode())
+    };
+    #[cfg(not(unix))]
+    let (uid, gid, permissions) = (None, None, 0);
+
+    let (symlink_target, target) = if metadata.is_symlink() {
+        (fs::read_link(&path).ok(), fs::metadata(&path).ok())
+    } else {
+        (None, None)
+    };
+    let target = target.as_ref().unwrap_or(metadata);
+
+    FileInfo {
+        name,
+        is_dir: target.is_dir(),
+        size: target.len(),
+        permissions,
+        uid,
+        gid,
+        owner: uid.and_then(|uid| names.user(uid)).map(str::to_string),
+        group: gid.and_then(|gid| names.group(gid)).map(str::to_string),
+        symlink_target,
+        link_pending: false,
+        modified: metadata.modified().ok(),
+        depth: 0,
+        path,
+    }
+}
+
+/// Resolves the pending symlinks within `page` entries of `cursor`: the
+/// screenful around it and the next one either way, so they are ready before
+/// they scroll into view.
+fn resolve_links_near(
+    files: &mut [FileInfo],
+    cursor: usize,
+    page: usize,
+    mut resolve: impl FnMut(&mut FileInfo),
+) {
+    let end = cursor.saturating_add(page + 1).min(files.len());
+    let start = cursor.saturating_sub(page).min(end);
+    for file in files[start..end]
+        .iter_mut()
+        .filter(|file| file.link_pending)
+    {
+        resolve(file);
+    }
+}
+
+/// Hosts whose name or hostname contains `filter`, ignoring case.
+pub fn filter_hosts<'a>(hosts: &'a [SshHost], filter: &str) -> Vec<&'a SshHost> {
+    let filter = filter.to_lowercase();
+    hosts
+        .iter()
+        .filter(|host| {
+            host.host.to_lowercase().contains(&filter)
+                || host
+                    .hostname
+                    .as_ref()
+                    .is_some_and(|hostname| hostname.to_lowercase().contains(&filter))
+        })
+        .collect()
+}
+
+/// The command that opens a directory in the desktop's file manager.
+#[cfg(target_os = "macos")]
+const FILE_MANAGER_OPENER: &str = "open";
+#[cfg(windows)]
+const FILE_MANAGER_OPENER: &str = "explorer";
+#[cfg(not(any(target_os = "macos", windows)))]
+const FILE_MANAGER_OPENER: &str = "xdg-open";
+
+/// The directory the local pane starts in: the current one, or when that
+/// can't be read (it may have been deleted) the home directory, or `/`. A
+/// fallback comes with a note saying why.
+fn startup_dir(
+    current: std::io::Result<PathBuf>,
+    home: Option<PathBuf>,
+) -> (PathBuf, Option<String>) {
+    match current {
+        Ok(dir) => (dir, None),
+        Err(e) => {
+            let dir = home
+                .filter(|home| home.is_dir())
+                .unwrap_or_else(|| PathBuf::from("/"));
+            let note = format!(
+                "Current directory unavailable ({e}); started in {}",
+                dir.display()
+            );
+            (dir, Some(note))
+        }
+    }
+}
+
+/// Starts `program` on `path` without a terminal and without waiting for
+/// it. A thread reaps it when it exits.
+fn spawn_detached(program: &str, path: &Path) -> std::io::Result<()> {
+    let mut child = std::process::Command::new(program)
+        .arg(path)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+/// The program and leading arguments to edit a file with. `$EDITOR` may
+/// carry arguments (`code --wait`); unset or blank means `vi`.
+fn editor_command(editor: Option<&str>) -> (String, Vec<String>) {
+    let mut words = editor.unwrap_or_default().split_whitespace();
+    match words.next() {
+        Some(program) => (program.to_string(), words.map(str::to_string).collect()),
+        None => ("vi".to_string(), Vec::new()),
+    }
+}
+
+/// Downloads `remote_file` to `temp_file`, in a directory of its own that is
+/// removed again when the download fails.
+fn download_for_edit(
+    client: &impl TransferClient,
+    remote_file: &Path,
+    temp_file: &Path,
+) -> Result<()> {
+    let Some(temp_dir) = temp_file.parent() else {
+        return Err(anyhow!("no directory for {}", temp_file.display()));
+    };
+    fs::create_dir_all(temp_dir)?;
+    let downloaded = client.download_file(remote_file, temp_file, false, &mut |_| Ok(()));
+    if downloaded.is_err() {
+        let _ = fs::remove_dir_all(temp_dir);
+    }
+    downloaded
+}
+
+/// A fresh directory under the system temp dir for one remote edit.
+fn edit_temp_dir() -> PathBuf {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    env::temp_dir().join(format!("sftui-edit-{}-{nanos}", std::process::id()))
+}
+
+/// Dotfiles are hidden, but the `..` parent entry never is.
+fn is_hidden(name: &str) -> bool {
+    name.starts_with('.') && name != ".."
+}
+
+/// Explains a failed connection attempt with a hint for fixing it. Errors
+/// that aren't about setting up the connection are passed on.
+fn connect_error_message(error: anyhow::Error) -> Result<String> {
+    let Some(sftp_error) = error.downcast_ref::<SftpError>() else {
+        return Err(error);
+    };
+
+    Ok(match sftp_error {
+        SftpError::AuthFailed(_) => {
+            format!("{sftp_error}. Check IdentityFile or that your key is loaded in ssh-agent")
+        }
+        SftpError::ConnectionRefused { .. } => {
+            format!("{sftp_error}. Check HostName and Port, and that sshd is running")
+        }
+        SftpError::HostKeyMismatch(_) => {
+            format!("{sftp_error}. The server may have been reinstalled, or this is an attack")
+        }
+        SftpError::NoUsername(_) => format!("{sftp_error}. Add a User line to its Host entry"),
+        SftpError::UnknownHostKey { .. } => {
+            format!("{sftp_error}. Check the fingerprint before trusting it")
+        }
+        _ => sftp_error.to_string(),
+    })
+}
+
+/// Renames a local entry, refusing to replace one that already exists.
+fn move_local(from: &Path, to: &Path) -> Result<()> {
+    if fs::symlink_metadata(to).is_ok() {
+        return Err(anyhow!("{} already exists", to.display()));
+    }
+    fs::rename(from, to)?;
+    Ok(())
+}
+
+/// Copies a local file, or a directory and everything in it. Symlinks are
+/// copied as links, so one pointing back up the tree can't make the copy go
+/// on forever.
+fn copy_local(from: &Path, to: &Path) -> Result<()> {
+    if to.starts_with(from) {
+        return Err(anyhow!("can't copy {} into itself", from.display()));
+    }
+
+    let metadata = fs::symlink_metadata(from)?;
+    if metadata.is_symlink() {
+        return copy_link(from, to);
+    }
+    if !metadata.is_dir() {
+        fs::copy(from, to)?;
+        return Ok(());
+    }
+
+    fs::create_dir(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        copy_local(&entry.path(), &to.join(entry.file_name()))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_link(from: &Path, to: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(from)?, to)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn copy_link(from: &Path, _to: &Path) -> Result<()> {
+    Err(anyhow!(
+        "{} is a symlink, which can't be copied here",
+        from.display()
+    ))
+}
+
+/// Picks a name for a copy of `name` that `taken` reports as free: `name`
+/// itself, then `a (copy).txt`, `a (copy 2).txt` and so on.
+fn copy_name(name: &OsStr, taken: impl Fn(&OsStr) -> bool) -> OsString {
+    if !taken(name) {
+        return name.to_os_string();
+    }
+
+    // Keep the extension last; `Path` already treats dotfiles like ".env"
+    // as having none
+    let path = Path::new(name);
+    let stem = path.file_stem().unwrap_or(name);
+
+    (1..)
+        .map(|n| {
+            let mut candidate = stem.to_os_string();
+            match n {
+                1 => candidate.push(" (copy)"),
+                n => candidate.push(format!(" (copy {n})")),
+            }
+            if let Some(extension) = path.extension() {
+                candidate.push(".");
+                candidate.push(extension);
+            }
+            candidate
+        })
+        .find(|candidate| !taken(candidate))
+        .expect("some copy name is free")
+}
+
+/// Picks a name for a duplicate of `name` next to it that `taken` reports
+/// as free: `a.copy.txt`, then `a.copy2.txt`, `a.copy3.txt` and so on.
+fn duplicate_name(name: &OsStr, taken: impl Fn(&OsStr) -> bool) -> OsString {
+    let path = Path::new(name);
+    let stem = path.file_stem().unwrap_or(name);
+
+    (1..)
+        .map(|n| {
+            let mut candidate = stem.to_os_string();
+            match n {
+                1 => candidate.push(".copy"),
+                n => candidate.push(format!(".copy{n}")),
+            }
+            if let Some(extension) = path.extension() {
+                candidate.push(".");
+                candidate.push(extension);
+            }
+            candidate
+        })
+        .find(|candidate| !taken(candidate))
+        .expect("some duplicate name is free")
+}
+
+/// Checks that `name` names a single entry in a directory: not empty, not
+/// `.` or `..`, and without path separators.
+pub fn validate_file_name(name: &str) -> Result<()> {
+    if name.trim().is_empty() {
+        return Err(anyhow!("Name can't be empty"));
+    }
+    if name == "." || name == ".." {
+        return Err(anyhow!("Name can't be {name}"));
+    }
+    if name.contains(['/', '\\']) {
+        return Err(anyhow!("Name can't contain / or \\"));
+    }
+    Ok(())
+}
+
+/// The directory every item of a queue went into, if they share one and a
+/// direction.
+fn common_destination(items: &[&TransferItem]) -> Option<(TransferDirection, PathBuf)> {
+    let (first, rest) = items.split_first()?;
+    let dir = first.destination.parent()?;
+    rest.iter()
+        .all(|item| item.direction == first.direction && item.destination.parent() == Some(dir))
+        .then(|| (first.direction.clone(), dir.to_path_buf()))
+}
+
+/// Where to follow a finished batch to: its common destination directory and
+/// the first item that made it there. Nothing when the items went to
+/// different places or none of them was transferred.
+fn follow_target(results: &[TransferResult]) -> Option<(TransferDirection, PathBuf, PathBuf)> {
+    let items: Vec<&TransferItem> = results.iter().map(|result| &result.item).collect();
+    let (direction, dir) = common_destination(&items)?;
+    let first = results
+        .iter()
+        .find(|result| result.outcome == TransferOutcome::Done)?;
+    Some((direction, dir, first.item.destination.clone()))
+}
+
+/// Finds `name` in a freshly refreshed listing, otherwise keeps the previous
+/// index clamped to the new length.
+fn restore_cursor(files: &[FileInfo], name: Option<&str>, previous: usize) -> usize {
+    name.and_then(|name| files.iter().position(|f| f.name == name))
+        .unwrap_or_else(|| previous.min(files.len().saturating_sub(1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::{KeyEvent, KeyModifiers};
+
+    #[test]
+    fn test_pane_toggle() {
+        let pane = Pane::Local;
+        assert_eq!(pane, Pane::Local);
+
+        let pane = Pane::Remote;
+        assert_eq!(pane, Pane::Remote);
+    }
+
+    #[tokio::test]
+    async fn test_file_details() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("notes.txt"), "hello")?;
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.refresh_local_files()?;
+        app.local_cursor = app
+            .local_files
+            .iter()
+            .position(|f| f.name == "notes.txt")
+            .unwrap();
+        // Changed since the listing: the dialog stats it again
+        fs::write(dir.path().join("notes.txt"), "hello, world")?;
+
+        app.handle_event(key('i')).await?;
+        let details = app.file_details.as_ref().unwrap();
+        assert_eq!(details.path, dir.path().join("notes.txt"));
+        assert_eq!(details.size, 12);
+        assert!(details.modified.is_some());
+
+        // Other keys are ignored until it is closed
+        app.handle_event(key('j')).await?;
+        assert!(app.file_details.is_some());
+        app.handle_event(Event::Key(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE)))
+            .await?;
+        assert!(app.file_details.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_custom_key_binding() -> Result<()> {
+        let config: AppConfig = toml::from_str("[keys]\nswitch_pane = [\"ctrl+w\"]")?;
+        let mut app = App::with_config(config, SshConfig::default(), PathBuf::from("/tmp"));
+
+        app.handle_event(Event::Key(KeyEvent::new(KeyCode::Tab, KeyModifiers::NONE)))
+            .await?;
+        assert_eq!(app.active_pane, Pane::Local);
+        app.handle_event(Event::Key(KeyEvent::new(
+            KeyCode::Char('w'),
+            KeyModifiers::CONTROL,
+        )))
+        .await?;
+        assert_eq!(app.active_pane, Pane::Remote);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_select_and_advance() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut app = app_with_files(dir.path(), 5)?;
+        let ctrl_space = Event::Key(KeyEvent::new(KeyCode::Char(' '), KeyModifiers::CONTROL));
+        app.local_cursor = 1;
+
+        app.handle_event(ctrl_space.clone()).await?;
+        app.handle_event(ctrl_space.clone()).await?;
+        assert_eq!(app.local_selected, HashSet::from([1, 2]));
+        assert_eq!(app.local_cursor, 3);
+
+        // Plain Space still selects in place
+        app.handle_event(key(' ')).await?;
+        assert_eq!(app.local_selected, HashSet::from([1, 2, 3]));
+        assert_eq!(app.local_cursor, 3);
+
+        // With a count, the run is marked in one go
+        app.handle_event(key('2')).await?;
+        app.handle_event(ctrl_space).await?;
+        assert_eq!(app.local_selected, HashSet::from([1, 2, 4]));
+        assert_eq!(app.local_cursor, 5);
+
+        app.config.space_advances = true;
+        app.local_cursor = 1;
+        app.handle_event(key(' ')).await?;
+        assert_eq!(app.local_selected, HashSet::from([2, 4]));
+        assert_eq!(app.local_cursor, 2);
+
+        // A count past the end marks each remaining entry once
+        app.local_selected.clear();
+        app.local_cursor = 4;
+        app.handle_event(key('5')).await?;
+        app.handle_event(key(' ')).await?;
+        assert_eq!(app.local_selected, HashSet::from([4, 5]));
+        assert_eq!(app.local_cursor, 5);
+
+        // and doesn't carry on from the top when the cursor wraps
+        app.config.wrap_cursor = true;
+        app.local_selected.clear();
+        app.local_cursor = 4;
+        app.handle_event(key('5')).await?;
+        app.handle_event(key(' ')).await?;
+        assert_eq!(app.local_selected, HashSet::from([4, 5]));
+        assert_eq!(app.local_cursor, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_swap_panes() -> Result<()> {
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            PathBuf::from("/tmp"),
+        );
+        assert!(!app.panes_swapped);
+        assert_eq!(app.active_pane.column(app.panes_swapped), 0);
+
+        app.handle_event(key('W')).await?;
+        assert!(app.panes_swapped);
+        assert_eq!(app.active_pane, Pane::Local);
+        assert_eq!(app.active_pane.column(app.panes_swapped), 1);
+        assert_eq!(Pane::Remote.column(app.panes_swapped), 0);
+
+        app.handle_event(key('W')).await?;
+        assert!(!app.panes_swapped);
+        assert_eq!(app.active_pane, Pane::Local);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_transfer_item_upload() {
+        let item = TransferItem {
+            source: PathBuf::from("/source/file.txt"),
+            destination: PathBuf::from("/dest/file.txt"),
+            direction: TransferDirection::Upload,
+            remove_source: false,
+            enabled: true,
+        };
+
+        assert_eq!(item.source, PathBuf::from("/source/file.txt"));
+        assert_eq!(item.destination, PathBuf::from("/dest/file.txt"));
+        assert!(matches!(item.direction, TransferDirection::Upload));
+    }
+
+    #[test]
+    fn test_transfer_item_download() {
+        let item = TransferItem {
+            source: PathBuf::from("/remote/file.txt"),
+            destination: PathBuf::from("/local/file.txt"),
+            direction: TransferDirection::Download,
+            remove_source: false,
+            enabled: true,
+        };
+
+        assert!(matches!(item.direction, TransferDirection::Download));
+    }
+
+    #[test]
+    fn test_transfer_direction_clone() {
         let upload = TransferDirection::Upload;
         let cloned = upload.clone();
         assert!(matches!(cloned, TransferDirection::Upload));
     }
+
+    #[tokio::test]
+    async fn test_esc_clears_active_pane_selection() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("a.txt"), "a")?;
+        fs::write(dir.path().join("b.txt"), "b")?;
+
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.refresh_local_files()?;
+        app.local_selected.insert(1);
+        app.local_selected.insert(2);
+        app.remote_selected.insert(0);
+
+        let esc = Event::Key(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE));
+        app.handle_event(esc.clone()).await?;
+
+        assert!(app.local_selected.is_empty());
+        assert_eq!(app.remote_selected.len(), 1);
+        assert!(!app.should_quit);
+
+        // A second Esc with nothing selected is a no-op
+        app.handle_event(esc).await?;
+        assert!(!app.should_quit);
+
+        Ok(())
+    }
+
+    fn local_names(app: &App) -> Vec<&str> {
+        app.local_files.iter().map(|f| f.name.as_str()).collect()
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unreadable_directory_keeps_current_listing() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("a.txt"), "a")?;
+        let locked = dir.path().join("locked");
+        fs::create_dir(&locked)?;
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000))?;
+        if fs::read_dir(&locked).is_ok() {
+            // Running as root, which reads it anyway
+            return Ok(());
+        }
+
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.refresh_local_files()?;
+        app.local_cursor = app
+            .local_files
+            .iter()
+            .position(|f| f.name == "locked")
+            .unwrap();
+        let cursor = app.local_cursor;
+
+        app.handle_event(key_code(KeyCode::Enter)).await?;
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755))?;
+
+        assert_eq!(app.local_path, dir.path());
+        assert_eq!(local_names(&app), vec!["..", "locked", "a.txt"]);
+        assert_eq!(app.local_cursor, cursor);
+        assert!(
+            app.status_message
+                .as_deref()
+                .is_some_and(|message| message.starts_with("Can't open directory"))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dotfiles_only_listed_when_toggled_on() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join(".env"), "secret")?;
+        fs::create_dir(dir.path().join(".git"))?;
+        fs::write(dir.path().join("visible.txt"), "hi")?;
+
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.refresh_local_files()?;
+
+        assert!(!app.show_hidden);
+        assert_eq!(local_names(&app), vec!["..", "visible.txt"]);
+
+        app.toggle_hidden().await?;
+        assert_eq!(local_names(&app), vec!["..", ".git", ".env", "visible.txt"]);
+
+        app.toggle_hidden().await?;
+        assert_eq!(local_names(&app), vec!["..", "visible.txt"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_toggle_hidden_keeps_cursor_in_bounds() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join(".a"), "")?;
+        fs::write(dir.path().join(".b"), "")?;
+        fs::write(dir.path().join("c"), "")?;
+
+        let config = AppConfig {
+            show_hidden: true,
+            ..Default::default()
+        };
+        let mut app = App::with_config(config, SshConfig::default(), dir.path().to_path_buf());
+        app.refresh_local_files()?;
+
+        // Cursor on the dotfile ".b", which disappears when hidden
+        app.local_cursor = 2;
+        app.toggle_hidden().await?;
+        assert!(app.local_cursor < app.local_files.len());
+
+        // Cursor follows "c" when the list grows again
+        app.local_cursor = 1;
+        app.toggle_hidden().await?;
+        assert_eq!(app.local_files[app.local_cursor].name, "c");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_host_local_dir_applied() -> Result<()> {
+        let start = tempfile::tempdir()?;
+        let host_dir = tempfile::tempdir()?;
+
+        let mut config = AppConfig::default();
+        config.hosts.insert(
+            "myserver".to_string(),
+            config::HostSettings {
+                local_dir: Some(host_dir.path().to_path_buf()),
+                ..Default::default()
+            },
+        );
+        config.hosts.insert(
+            "broken".to_string(),
+            config::HostSettings {
+                local_dir: Some(start.path().join("missing")),
+                ..Default::default()
+            },
+        );
+
+        let mut app = App::with_config(config, SshConfig::default(), start.path().to_path_buf());
+
+        // Unconfigured hosts keep the current directory
+        app.apply_host_local_dir("other")?;
+        assert_eq!(app.local_path, start.path());
+        assert!(app.status_message.is_none());
+
+        // Missing directories are reported, not switched to
+        app.apply_host_local_dir("broken")?;
+        assert_eq!(app.local_path, start.path());
+        assert!(app.status_message.is_some());
+
+        app.apply_host_local_dir("myserver")?;
+        assert_eq!(app.local_path, host_dir.path());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_host_remote_dir() {
+        let mut config = AppConfig::default();
+        for (host, dir) in [
+            ("web", "/var/www"),
+            ("relative", "site"),
+            ("broken", "/gone"),
+        ] {
+            config.hosts.insert(
+                host.to_string(),
+                config::HostSettings {
+                    remote_dir: Some(PathBuf::from(dir)),
+                    ..Default::default()
+                },
+            );
+        }
+        let mut app = App::with_config(config, SshConfig::default(), PathBuf::from("/"));
+        let home = Path::new("/home/me");
+        let is_dir = |path: &Path| path == Path::new("/var/www") || path == home.join("site");
+
+        assert_eq!(app.host_remote_dir("other", home, is_dir), Path::new("/"));
+        assert!(app.status_message.is_none());
+
+        assert_eq!(
+            app.host_remote_dir("web", home, is_dir),
+            Path::new("/var/www")
+        );
+        assert_eq!(
+            app.host_remote_dir("relative", home, is_dir),
+            Path::new("/home/me/site")
+        );
+        assert!(app.status_message.is_none());
+
+        // A directory the server doesn't have falls back to the root
+        assert_eq!(app.host_remote_dir("broken", home, is_dir), Path::new("/"));
+        assert!(app.status_message.is_some());
+    }
+
+    #[test]
+    fn test_select_all_never_queues_parent_entry() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("a.txt"), "a")?;
+        fs::create_dir(dir.path().join("sub"))?;
+
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.refresh_local_files()?;
+        assert_eq!(app.local_files[0].name, "..");
+
+        // Space on ".." does nothing
+        app.toggle_selection();
+        assert!(app.local_selected.is_empty());
+
+        app.select_all();
+        assert_eq!(app.local_selected.len(), 2);
+        assert!(!app.local_selected.contains(&0));
+
+        // Even a stray ".." index is ignored when building the queue
+        app.local_selected.insert(0);
+        app.prepare_transfer()?;
+        assert_eq!(app.transfer_queue.len(), 2);
+        assert!(
+            app.transfer_queue
+                .iter()
+                .all(|item| item.source != dir.path().parent().unwrap())
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_go_to_parent() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("first"))?;
+        fs::create_dir(dir.path().join("second"))?;
+
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().join("second"),
+        );
+        app.refresh_local_files()?;
+
+        app.go_to_parent().await?;
+        assert_eq!(app.local_path, dir.path());
+        // The cursor lands on the directory we came from
+        assert_eq!(app.local_files[app.local_cursor].name, "second");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_go_home() -> Result<()> {
+        let Some(home) = dirs::home_dir().filter(|home| home.is_dir()) else {
+            return Ok(());
+        };
+        let dir = tempfile::tempdir()?;
+        let mut app = app_with_files(dir.path(), 3)?;
+
+        app.handle_event(key('~')).await?;
+        assert_eq!(app.local_path, home);
+        // The listing is the home directory's, not the one we left
+        assert!(
+            app.local_files
+                .iter()
+                .filter(|f| f.name != "..")
+                .all(|f| f.path.parent() == Some(home.as_path()))
+        );
+
+        // Without a connection the remote pane stays put
+        app.active_pane = Pane::Remote;
+        app.remote_path = PathBuf::from("/srv");
+        app.handle_event(key('~')).await?;
+        assert_eq!(app.remote_path, Path::new("/srv"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_go_to_parent_is_noop_at_root() -> Result<()> {
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            PathBuf::from("/"),
+        );
+        app.local_cursor = 3;
+
+        app.go_to_parent().await?;
+        assert_eq!(app.local_path, Path::new("/"));
+        assert_eq!(app.local_cursor, 3);
+
+        app.active_pane = Pane::Remote;
+        app.go_to_parent().await?;
+        assert_eq!(app.remote_path, Path::new("/"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_descend_into_directory_only() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("sub"))?;
+        fs::write(dir.path().join("file.txt"), "x")?;
+
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.refresh_local_files()?;
+        let right = Event::Key(KeyEvent::new(KeyCode::Right, KeyModifiers::NONE));
+        let l = Event::Key(KeyEvent::new(KeyCode::Char('l'), KeyModifiers::NONE));
+
+        // Files are ignored
+        app.local_cursor = 2;
+        assert_eq!(app.local_files[2].name, "file.txt");
+        app.handle_event(right).await?;
+        assert_eq!(app.local_path, dir.path());
+        assert_eq!(app.local_cursor, 2);
+
+        app.local_cursor = 1;
+        assert_eq!(app.local_files[1].name, "sub");
+        app.handle_event(l).await?;
+        assert_eq!(app.local_path, dir.path().join("sub"));
+        assert_eq!(app.local_cursor, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_connection_errors_disconnect_instead_of_failing() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.current_host = Some("myserver".to_string());
+        app.remote_selected.insert(0);
+
+        let dropped = anyhow::Error::new(std::io::Error::from(std::io::ErrorKind::ConnectionReset));
+        app.handle_error(dropped)?;
+        assert!(app.current_host.is_none());
+        assert!(app.sftp_client.is_none());
+        assert!(app.remote_selected.is_empty());
+        assert!(app.status_message.as_deref().unwrap().contains("myserver"));
+
+        // Anything else still surfaces as an error
+        assert!(app.handle_error(anyhow::anyhow!("disk full")).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cut_and_paste_moves_local_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("a.txt"), "a")?;
+        fs::write(dir.path().join("b.txt"), "b")?;
+        fs::create_dir(dir.path().join("dest"))?;
+
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.refresh_local_files()?;
+        assert_eq!(local_names(&app), vec!["..", "dest", "a.txt", "b.txt"]);
+
+        app.local_selected.insert(2);
+        app.local_selected.insert(3);
+        app.handle_event(Event::Key(KeyEvent::new(
+            KeyCode::Char('X'),
+            KeyModifiers::SHIFT,
+        )))
+        .await?;
+        assert_eq!(app.cut_buffer.as_ref().map(|c| c.paths.len()), Some(2));
+        assert!(app.local_selected.is_empty());
+
+        app.enter_directory(dir.path().join("dest")).await?;
+        app.handle_event(Event::Key(KeyEvent::new(
+            KeyCode::Char('P'),
+            KeyModifiers::SHIFT,
+        )))
+        .await?;
+
+        assert!(app.cut_buffer.is_none());
+        assert_eq!(local_names(&app), vec!["..", "a.txt", "b.txt"]);
+        assert!(!dir.path().join("a.txt").exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_paste_never_overwrites() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("a.txt"), "moved")?;
+        fs::create_dir(dir.path().join("dest"))?;
+        fs::write(dir.path().join("dest/a.txt"), "kept")?;
+
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.refresh_local_files()?;
+        app.local_cursor = 2;
+        app.cut_marked();
+
+        app.enter_directory(dir.path().join("dest")).await?;
+        app.paste_cut().await?;
+
+        assert_eq!(fs::read_to_string(dir.path().join("dest/a.txt"))?, "kept");
+        assert!(dir.path().join("a.txt").exists());
+        // The failed entry stays cut so it can be pasted elsewhere
+        assert!(app.cut_buffer.is_some());
+        assert!(app.status_message.unwrap().contains("1 failed"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cross_pane_move_is_queued_as_transfer() {
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            PathBuf::from("/home/me"),
+        );
+        app.remote_path = PathBuf::from("/srv");
+
+        app.queue_clipboard_transfer(
+            &Clipboard {
+                pane: Pane::Local,
+                paths: vec![PathBuf::from("/home/me/a.txt")],
+            },
+            true,
+        );
+
+        assert!(app.show_transfer_dialog);
+        let item = &app.transfer_queue[0];
+        assert_eq!(item.destination, Path::new("/srv/a.txt"));
+        assert!(matches!(item.direction, TransferDirection::Upload));
+        assert!(item.remove_source);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_skip_self_overwrites() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let home = dir.path().join("home");
+        fs::create_dir(&home)?;
+        fs::write(home.join("notes.txt"), "notes")?;
+        fs::write(home.join("todo.txt"), "todo")?;
+        // The server is this machine and reaches home through a symlink
+        std::os::unix::fs::symlink(&home, dir.path().join("link"))?;
+        let backup = dir.path().join("backup");
+        fs::create_dir(&backup)?;
+
+        let mut app = App::with_config(AppConfig::default(), SshConfig::default(), home.clone());
+        let item = |source: PathBuf, destination: PathBuf, direction| TransferItem {
+            source,
+            destination,
+            direction,
+            remove_source: false,
+            enabled: true,
+        };
+        app.transfer_queue = vec![
+            item(
+                home.join("notes.txt"),
+                dir.path().join("link/notes.txt"),
+                TransferDirection::Upload,
+            ),
+            item(
+                home.join("notes.txt"),
+                backup.join("notes.txt"),
+                TransferDirection::Upload,
+            ),
+            item(
+                dir.path().join("link/todo.txt"),
+                home.join("todo.txt"),
+                TransferDirection::Download,
+            ),
+        ];
+
+        app.skip_self_overwrites(|path| fs::canonicalize(path).ok());
+
+        let destinations: Vec<&Path> = app
+            .transfer_queue
+            .iter()
+            .map(|item| item.destination.as_path())
+            .collect();
+        assert_eq!(destinations, [backup.join("notes.txt")]);
+        assert_eq!(
+            app.status_message.as_deref(),
+            Some("Skipped 2 items whose destination is their own source, like notes.txt")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_server_is_this_machine() -> Result<()> {
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            PathBuf::from("/"),
+        );
+        let mut is_local = |host: SshHost| {
+            app.connection = Some(app.connect_params(host));
+            app.server_is_this_machine()
+        };
+
+        assert!(is_local(SshHost::from_spec("localhost")?));
+        assert!(is_local(SshHost::from_spec("127.0.0.1:22")?));
+        assert!(!is_local(SshHost::from_spec("example.com")?));
+        // Forwarded to a VM or container
+        assert!(!is_local(SshHost::from_spec("127.0.0.1:2222")?));
+        // Resolved by the jump host, so not this machine
+        assert!(!is_local(SshHost {
+            proxy_jump: Some("bastion".to_string()),
+            ..SshHost::from_spec("localhost")?
+        }));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_readonly_blocks_remote_changes() -> Result<()> {
+        let mut app = App::with_config(
+            AppConfig {
+                readonly: true,
+                ..AppConfig::default()
+            },
+            SshConfig::default(),
+            PathBuf::from("/home/me"),
+        );
+        app.remote_path = PathBuf::from("/srv");
+        app.active_pane = Pane::Remote;
+        let remote = Clipboard {
+            pane: Pane::Remote,
+            paths: vec![PathBuf::from("/srv/a.txt")],
+        };
+
+        // A remote move is refused and the cut is kept
+        app.cut_buffer = Some(remote.clone());
+        app.handle_event(key('P')).await?;
+        assert!(app.cut_buffer.is_some());
+        assert!(app.status_message.as_ref().unwrap().contains("Read-only"));
+
+        // So is an upload, and a download that would delete its source
+        app.queue_clipboard_transfer(
+            &Clipboard {
+                pane: Pane::Local,
+                paths: vec![PathBuf::from("/home/me/a.txt")],
+            },
+            false,
+        );
+        assert!(!app.show_transfer_dialog);
+        app.queue_clipboard_transfer(&remote, true);
+        assert!(!app.show_transfer_dialog);
+
+        // Plain downloads only read the server
+        app.queue_clipboard_transfer(&remote, false);
+        assert!(app.show_transfer_dialog);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_large_queue_asks_first() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("small.txt"), "12345")?;
+        fs::write(dir.path().join("big.bin"), vec![0u8; 100])?;
+
+        let mut app = App::with_config(
+            AppConfig {
+                confirm_transfer_bytes: 50,
+                ..AppConfig::default()
+            },
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.refresh_local_files()?;
+        let enter = Event::Key(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE));
+
+        // Under the threshold the queue starts straight away (and, with no
+        // connection, closes)
+        app.transfer_queue = uploads_from(dir.path(), &["small.txt"]);
+        app.show_transfer_dialog = true;
+        app.handle_event(enter.clone()).await?;
+        assert!(app.large_transfer.is_none());
+        assert!(!app.show_transfer_dialog);
+
+        // Over it, the total is shown and nothing starts until confirmed
+        app.transfer_queue = uploads_from(dir.path(), &["small.txt", "big.bin"]);
+        app.show_transfer_dialog = true;
+        app.handle_event(enter.clone()).await?;
+        assert_eq!(
+            app.large_transfer,
+            Some(LargeTransfer {
+                bytes: 105,
+                unsized_items: 0
+            })
+        );
+        assert!(app.show_transfer_dialog);
+
+        app.handle_event(key('n')).await?;
+        assert!(app.large_transfer.is_none());
+        assert_eq!(app.transfer_queue.len(), 2);
+
+        app.handle_event(enter.clone()).await?;
+        // Enter again, as if held down, doesn't confirm it
+        app.handle_event(enter.clone()).await?;
+        assert!(app.large_transfer.is_some());
+        app.handle_event(key('y')).await?;
+        assert!(app.large_transfer.is_none());
+        assert!(!app.show_transfer_dialog);
+
+        // Remote directories can't be sized up front, so they always ask
+        app.transfer_queue = uploads_from(dir.path(), &["small.txt"]);
+        app.transfer_queue.push(TransferItem {
+            source: PathBuf::from("/srv/backups"),
+            destination: dir.path().join("backups"),
+            direction: TransferDirection::Download,
+            remove_source: false,
+            enabled: true,
+        });
+        app.show_transfer_dialog = true;
+        app.handle_event(enter).await?;
+        assert_eq!(
+            app.large_transfer,
+            Some(LargeTransfer {
+                bytes: 5,
+                unsized_items: 1
+            })
+        );
+
+        Ok(())
+    }
+
+    fn uploads_from(dir: &Path, names: &[&str]) -> Vec<TransferItem> {
+        names
+            .iter()
+            .map(|name| TransferItem {
+                source: dir.join(name),
+                destination: Path::new("/srv").join(name),
+                direction: TransferDirection::Upload,
+                remove_source: false,
+                enabled: true,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_validate_file_name() {
+        assert!(validate_file_name("marker").is_ok());
+        assert!(validate_file_name(".lock").is_ok());
+        assert!(validate_file_name("with space.txt").is_ok());
+
+        for name in ["", "   ", ".", "..", "a/b", "/abs", "dir\\file"] {
+            assert!(validate_file_name(name).is_err(), "{name:?} was accepted");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_local_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut app = app_with_files(dir.path(), 3)?;
+
+        for c in "Anew.txt".chars() {
+            app.handle_event(key(c)).await?;
+        }
+        app.handle_event(Event::Key(KeyEvent::new(
+            KeyCode::Enter,
+            KeyModifiers::NONE,
+        )))
+        .await?;
+        assert!(app.new_file_input.is_none());
+        assert_eq!(fs::metadata(dir.path().join("new.txt"))?.len(), 0);
+        assert_eq!(app.local_files[app.local_cursor].name, "new.txt");
+
+        // Existing files are left alone and the prompt stays open
+        fs::write(dir.path().join("00.txt"), "data")?;
+        for c in "A00.txt".chars() {
+            app.handle_event(key(c)).await?;
+        }
+        app.handle_event(Event::Key(KeyEvent::new(
+            KeyCode::Enter,
+            KeyModifiers::NONE,
+        )))
+        .await?;
+        assert!(app.new_file_input.is_some());
+        assert!(app.new_file_error.is_some());
+        assert_eq!(fs::read_to_string(dir.path().join("00.txt"))?, "data");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_and_enter_local_directory() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut app = app_with_files(dir.path(), 3)?;
+
+        // A taken name keeps the prompt open and the pane where it was
+        for c in "A00.txt/".chars() {
+            app.handle_event(key(c)).await?;
+        }
+        app.handle_event(key_code(KeyCode::Enter)).await?;
+        assert!(app.new_file_error.is_some());
+        assert_eq!(app.local_path, dir.path());
+        app.handle_event(key_code(KeyCode::Esc)).await?;
+
+        for c in "Auploads/".chars() {
+            app.handle_event(key(c)).await?;
+        }
+        app.handle_event(key_code(KeyCode::Enter)).await?;
+        assert!(app.new_file_input.is_none());
+        assert!(dir.path().join("uploads").is_dir());
+        assert_eq!(app.local_path, dir.path().join("uploads"));
+        assert!(app.local_files.iter().all(|file| file.name == ".."));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_name() {
+        let taken = ["a.txt", "a (copy).txt", "dir", ".env"];
+        let is_taken = |name: &OsStr| taken.iter().any(|taken| name == *taken);
+        let copy_name = |name: &str| copy_name(OsStr::new(name), is_taken);
+
+        assert_eq!(copy_name("b.txt"), "b.txt");
+        assert_eq!(copy_name("a.txt"), "a (copy 2).txt");
+        assert_eq!(copy_name("dir"), "dir (copy)");
+        assert_eq!(copy_name(".env"), ".env (copy)");
+    }
+
+    #[test]
+    fn test_duplicate_name() {
+        let taken = ["a.copy.txt", "a.copy2.txt", "b.copy"];
+        let is_taken = |name: &OsStr| taken.iter().any(|taken| name == *taken);
+        let duplicate_name = |name: &str| duplicate_name(OsStr::new(name), is_taken);
+
+        assert_eq!(duplicate_name("c.txt"), "c.copy.txt");
+        assert_eq!(duplicate_name("a.txt"), "a.copy3.txt");
+        assert_eq!(duplicate_name("b"), "b.copy2");
+        assert_eq!(duplicate_name(".env"), ".env.copy");
+        assert_eq!(duplicate_name("site.tar.gz"), "site.tar.copy.gz");
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_cursor_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("notes.txt"), "draft")?;
+        fs::write(dir.path().join("notes.copy.txt"), "older")?;
+        fs::create_dir(dir.path().join("sub"))?;
+
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.refresh_local_files()?;
+        app.local_cursor = local_names(&app)
+            .iter()
+            .position(|name| *name == "notes.txt")
+            .unwrap();
+
+        app.handle_event(key('d')).await?;
+        assert_eq!(
+            fs::read_to_string(dir.path().join("notes.copy2.txt"))?,
+            "draft"
+        );
+        assert_eq!(local_names(&app)[app.local_cursor], "notes.copy2.txt");
+
+        // Directories aren't duplicated
+        app.local_cursor = 1;
+        app.handle_event(key('d')).await?;
+        assert!(!dir.path().join("sub.copy").exists());
+        assert_eq!(
+            app.status_message.as_deref(),
+            Some("Duplicate: select a file")
+        );
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_names_that_are_not_utf8() -> Result<()> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::tempdir()?;
+        let latin1 = OsStr::from_bytes(b"caf\xe9.txt");
+        fs::write(dir.path().join(latin1), "latin-1")?;
+        fs::write(dir.path().join(OsStr::from_bytes(b"caf\xe8.txt")), "other")?;
+
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.refresh_local_files()?;
+        assert_eq!(local_names(&app), ["..", "caf\\xE8.txt", "caf\\xE9.txt"]);
+        assert_eq!(app.local_files[2].file_name(), latin1);
+
+        // Queued under its real name
+        app.local_selected.insert(2);
+        app.prepare_transfer()?;
+        assert_eq!(
+            app.transfer_queue[0].destination,
+            app.remote_path.join(latin1)
+        );
+        app.show_transfer_dialog = false;
+
+        // And copied under it, too
+        app.local_cursor = 2;
+        app.local_selected.clear();
+        app.yank_marked();
+        app.paste_copy().await?;
+        assert_eq!(
+            fs::read_to_string(dir.path().join(OsStr::from_bytes(b"caf\xe9 (copy).txt")))?,
+            "latin-1"
+        );
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_local_copies_links_as_links() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let tree = dir.path().join("tree");
+        fs::create_dir(&tree)?;
+        fs::write(tree.join("file.txt"), "contents")?;
+        // Followed, this would copy tree/loop/loop/... until the stack ran out
+        std::os::unix::fs::symlink(".", tree.join("loop"))?;
+
+        copy_local(&tree, &dir.path().join("copy"))?;
+
+        let copy = dir.path().join("copy");
+        assert_eq!(fs::read_to_string(copy.join("file.txt"))?, "contents");
+        assert_eq!(fs::read_link(copy.join("loop"))?, Path::new("."));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_yank_and_paste_copies_local_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("a.txt"), "a")?;
+        fs::create_dir(dir.path().join("sub"))?;
+        fs::write(dir.path().join("sub/inner.txt"), "inner")?;
+
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.refresh_local_files()?;
+        app.select_all();
+
+        let key = |c| Event::Key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+        // A single y does nothing yet
+        app.handle_event(key('y')).await?;
+        assert!(app.copy_buffer.is_none());
+        app.handle_event(key('y')).await?;
+        assert_eq!(app.copy_buffer.as_ref().map(|c| c.paths.len()), Some(2));
+
+        // Pasting in the same directory makes renamed copies
+        app.handle_event(key('p')).await?;
+        assert_eq!(
+            local_names(&app),
+            vec!["..", "sub", "sub (copy)", "a (copy).txt", "a.txt"]
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("sub (copy)/inner.txt"))?,
+            "inner"
+        );
+
+        // The buffer survives directory changes and can be pasted again
+        app.enter_directory(dir.path().join("sub")).await?;
+        app.handle_event(key('p')).await?;
+        assert!(app.copy_buffer.is_some());
+        assert!(dir.path().join("sub/a.txt").exists());
+        // Copying a directory into itself is refused
+        assert!(app.status_message.unwrap().contains("1 failed"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_connect_error_message() {
+        let refused = anyhow::Error::new(SftpError::ConnectionRefused {
+            host: "web".to_string(),
+            source: std::io::Error::from(std::io::ErrorKind::ConnectionRefused),
+        });
+        assert!(connect_error_message(refused).unwrap().contains("sshd"));
+
+        let auth = anyhow::Error::new(SftpError::AuthFailed("web".to_string()));
+        assert!(connect_error_message(auth).unwrap().contains("ssh-agent"));
+
+        // No key to check isn't reported as a changed one
+        let no_key = anyhow::Error::new(SftpError::NoHostKey("web".to_string()));
+        assert_eq!(
+            connect_error_message(no_key).unwrap(),
+            "web sent no host key"
+        );
+
+        // Anything else is not a connection setup problem
+        assert!(connect_error_message(anyhow!("disk full")).is_err());
+    }
+
+    #[test]
+    fn test_tabs_keep_their_own_remote_state() {
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            PathBuf::from("/"),
+        );
+        app.current_host = Some("first".to_string());
+        app.remote_path = PathBuf::from("/srv");
+        app.remote_cursor = 2;
+
+        app.new_tab();
+        assert_eq!(app.tabs.len(), 2);
+        assert_eq!(app.active_tab, 1);
+        assert!(app.show_connection_dialog);
+        assert!(app.current_host.is_none());
+        assert_eq!(app.remote_path, Path::new("/"));
+        assert_eq!(app.tab_titles(), vec!["1: first", "2: Not Connected"]);
+
+        app.show_connection_dialog = false;
+        app.remote_path = PathBuf::from("/var");
+        app.switch_tab(0);
+        assert_eq!(app.current_host.as_deref(), Some("first"));
+        assert_eq!(app.remote_path, Path::new("/srv"));
+        assert_eq!(app.remote_cursor, 2);
+
+        app.switch_tab(1);
+        assert_eq!(app.remote_path, Path::new("/var"));
+
+        // A background tab whose connection dropped reads as disconnected
+        app.tabs[0].disconnect();
+        assert_eq!(
+            app.tab_titles(),
+            vec!["1: Not Connected", "2: Not Connected"]
+        );
+        app.tabs[0].current_host = Some("first".to_string());
+
+        // Closing moves to a neighbour; closing the last tab only disconnects
+        app.close_tab();
+        assert_eq!(app.tabs.len(), 1);
+        assert_eq!(app.current_host.as_deref(), Some("first"));
+        app.close_tab();
+        assert_eq!(app.tabs.len(), 1);
+        assert!(app.current_host.is_none());
+        assert!(app.sftp_client.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_edit_transfer_queue() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            fs::write(dir.path().join(name), name)?;
+        }
+
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.refresh_local_files()?;
+        app.select_all();
+        app.prepare_transfer()?;
+        assert!(app.show_transfer_dialog);
+        assert_eq!(app.transfer_queue.len(), 3);
+
+        let key = |code| Event::Key(KeyEvent::new(code, KeyModifiers::NONE));
+
+        // Disable the second item and remove the third
+        app.handle_event(key(KeyCode::Char('j'))).await?;
+        app.handle_event(key(KeyCode::Char(' '))).await?;
+        app.handle_event(key(KeyCode::Char('j'))).await?;
+        app.handle_event(key(KeyCode::Char('j'))).await?;
+        assert_eq!(app.queue_cursor, 2);
+        app.handle_event(key(KeyCode::Char('d'))).await?;
+
+        assert_eq!(app.queue_cursor, 1);
+        assert_eq!(app.transfer_queue.len(), 2);
+        let enabled: Vec<bool> = app.transfer_queue.iter().map(|i| i.enabled).collect();
+        assert_eq!(enabled, vec![true, false]);
+
+        // Removing every item closes the dialog
+        app.handle_event(key(KeyCode::Char('d'))).await?;
+        app.handle_event(key(KeyCode::Char('d'))).await?;
+        assert!(app.transfer_queue.is_empty());
+        assert!(!app.show_transfer_dialog);
+
+        Ok(())
+    }
+
+    fn key(c: char) -> Event {
+        key_code(KeyCode::Char(c))
+    }
+
+    fn key_code(code: KeyCode) -> Event {
+        Event::Key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    fn app_with_files(dir: &Path, count: usize) -> Result<App> {
+        for i in 0..count {
+            fs::write(dir.join(format!("{i:02}.txt")), "")?;
+        }
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.to_path_buf(),
+        );
+        app.refresh_local_files()?;
+        Ok(app)
+    }
+
+    #[tokio::test]
+    async fn test_motions_without_count() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut app = app_with_files(dir.path(), 5)?;
+
+        app.handle_event(key('j')).await?;
+        app.handle_event(key('j')).await?;
+        assert_eq!(app.local_cursor, 2);
+        app.handle_event(key('k')).await?;
+        assert_eq!(app.local_cursor, 1);
+
+        // G alone goes to the last entry
+        app.handle_event(key('G')).await?;
+        assert_eq!(app.local_cursor, 5);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_motions_with_count() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut app = app_with_files(dir.path(), 20)?;
+
+        for c in "10j".chars() {
+            app.handle_event(key(c)).await?;
+        }
+        assert_eq!(app.local_cursor, 10);
+
+        for c in "3k".chars() {
+            app.handle_event(key(c)).await?;
+        }
+        assert_eq!(app.local_cursor, 7);
+
+        // Counts are clamped to the listing
+        for c in "99j".chars() {
+            app.handle_event(key(c)).await?;
+        }
+        assert_eq!(app.local_cursor, 20);
+        for c in "99k".chars() {
+            app.handle_event(key(c)).await?;
+        }
+        assert_eq!(app.local_cursor, 0);
+
+        for c in "5G".chars() {
+            app.handle_event(key(c)).await?;
+        }
+        assert_eq!(app.local_cursor, 4);
+
+        // Any other key drops the count
+        app.handle_event(key('3')).await?;
+        app.handle_event(key('.')).await?;
+        app.handle_event(key('j')).await?;
+        assert_eq!(app.local_cursor, 5);
+
+        Ok(())
+    }
+
+    /// An app with `count` local files and the same number of remote entries,
+    /// with `wrap_cursor` set as given.
+    fn app_for_wrapping(dir: &Path, count: usize, wrap_cursor: bool) -> Result<App> {
+        let mut app = app_with_files(dir, count)?;
+        app.config.wrap_cursor = wrap_cursor;
+        app.remote_files = (0..count)
+            .map(|i| FileInfo {
+                name: format!("{i:02}.txt"),
+                path: PathBuf::from("/srv").join(format!("{i:02}.txt")),
+                is_dir: false,
+                size: 0,
+                permissions: 0o644,
+                ..FileInfo::default()
+            })
+            .collect();
+        Ok(app)
+    }
+
+    #[tokio::test]
+    async fn test_bulk_rename() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        for name in ["IMG_1.jpg", "IMG_2.jpg", "photo-2.jpg"] {
+            fs::write(dir.path().join(name), "")?;
+        }
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.refresh_local_files()?;
+        app.select_all();
+
+        for event in "R^IMG_"
+            .chars()
+            .map(key)
+            .chain([Event::Key(KeyEvent::new(KeyCode::Tab, KeyModifiers::NONE))])
+        {
+            app.handle_event(event).await?;
+        }
+        for c in "photo-".chars() {
+            app.handle_event(key(c)).await?;
+        }
+        let rename = app.bulk_rename.as_ref().unwrap();
+        let statuses: Vec<_> = rename.plan.iter().map(|e| e.status.clone()).collect();
+        assert_eq!(
+            statuses,
+            [
+                RenameStatus::Rename,
+                RenameStatus::Collision,
+                RenameStatus::Unchanged
+            ]
+        );
+
+        app.handle_event(Event::Key(KeyEvent::new(
+            KeyCode::Enter,
+            KeyModifiers::NONE,
+        )))
+        .await?;
+        assert!(app.bulk_rename.is_none());
+        assert!(dir.path().join("photo-1.jpg").exists());
+        // The collision was left alone
+        assert!(dir.path().join("IMG_2.jpg").exists());
+        assert_eq!(app.status_message.as_deref(), Some("Renamed 1 item(s)"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bulk_rename_keeps_hidden_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("draft.txt"), "new")?;
+        fs::write(dir.path().join(".draft.txt"), "keep")?;
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.refresh_local_files()?;
+        app.select_all();
+
+        for event in "R^"
+            .chars()
+            .map(key)
+            .chain([key_code(KeyCode::Tab), key('.')])
+        {
+            app.handle_event(event).await?;
+        }
+        // The dotfile isn't listed, so the preview can't see the clash
+        let rename = app.bulk_rename.as_ref().unwrap();
+        assert_eq!(rename.plan[0].status, RenameStatus::Rename);
+
+        app.handle_event(key_code(KeyCode::Enter)).await?;
+        assert_eq!(fs::read_to_string(dir.path().join(".draft.txt"))?, "keep");
+        assert_eq!(fs::read_to_string(dir.path().join("draft.txt"))?, "new");
+        assert!(app.status_message.unwrap().contains("1 failed"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bulk_rename_in_tree_view() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("docs"))?;
+        for name in ["a.md", "docs/a.txt", "docs/b.md", "docs/b.txt"] {
+            fs::write(dir.path().join(name), "")?;
+        }
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.refresh_local_files()?;
+        app.handle_event(key('z')).await?;
+        app.local_cursor = 1;
+        app.handle_event(key_code(KeyCode::Enter)).await?;
+        // .., docs, a.txt, b.md, b.txt, a.md
+        app.local_selected.extend([2, 4]);
+
+        for event in "R\\.txt$"
+            .chars()
+            .map(key)
+            .chain([key_code(KeyCode::Tab)])
+            .chain(".md".chars().map(key))
+        {
+            app.handle_event(event).await?;
+        }
+        // Each name is checked against its own directory: the top-level a.md
+        // doesn't stand in the way, docs/b.md does
+        let rename = app.bulk_rename.as_ref().unwrap();
+        let statuses: Vec<_> = rename.plan.iter().map(|e| e.status.clone()).collect();
+        assert_eq!(statuses, [RenameStatus::Rename, RenameStatus::Collision]);
+
+        app.handle_event(key_code(KeyCode::Enter)).await?;
+        assert!(dir.path().join("docs/a.md").exists());
+        assert!(!dir.path().join("docs/a.txt").exists());
+        assert!(dir.path().join("docs/b.txt").exists());
+        assert_eq!(app.status_message.as_deref(), Some("Renamed 1 item(s)"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_compare_paths_in_tree_view() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("docs"))?;
+        fs::write(dir.path().join("docs/notes.txt"), "")?;
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.remote_path = PathBuf::from("/srv");
+        app.refresh_local_files()?;
+        app.handle_event(key('z')).await?;
+        app.local_cursor = 1;
+        app.handle_event(key_code(KeyCode::Enter)).await?;
+
+        // The nested file is compared with the one at the same place remotely
+        let file = app.get_current_local_files()[2].clone();
+        assert_eq!(file.name, "notes.txt");
+        assert_eq!(
+            app.compare_paths(&file),
+            (
+                dir.path().join("docs/notes.txt"),
+                PathBuf::from("/srv/docs/notes.txt")
+            )
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_search_counts_per_pane() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        for name in ["notes.md", "report-a.txt", "report-b.txt"] {
+            fs::write(dir.path().join(name), "")?;
+        }
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.refresh_local_files()?;
+        app.remote_files = [
+            "..",
+            "report-2023.pdf",
+            "report-2024.pdf",
+            "README",
+            "reports",
+        ]
+        .iter()
+        .map(|name| FileInfo {
+            name: name.to_string(),
+            path: Path::new("/srv").join(name),
+            ..FileInfo::default()
+        })
+        .collect();
+        assert_eq!(app.search_counts(), None);
+
+        for c in "/report".chars() {
+            app.handle_event(key(c)).await?;
+        }
+        assert_eq!(app.search_counts(), Some((2, 3)));
+
+        // Tab moves to the other pane without leaving the query
+        let tab = Event::Key(KeyEvent::new(KeyCode::Tab, KeyModifiers::NONE));
+        app.handle_event(tab).await?;
+        assert_eq!(app.active_pane, Pane::Remote);
+        assert!(app.search_editing);
+        assert_eq!(app.search_query, "report");
+
+        // Both panes follow the query as it changes
+        app.handle_event(key('-')).await?;
+        assert_eq!(app.search_counts(), Some((2, 2)));
+        app.handle_event(key('a')).await?;
+        assert_eq!(app.search_counts(), Some((1, 0)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_edit_transfer_destination() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("incoming"))?;
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        let item = |source: &str, destination: PathBuf, direction| TransferItem {
+            source: PathBuf::from(source),
+            destination,
+            direction,
+            remove_source: false,
+            enabled: true,
+        };
+        app.transfer_queue = vec![
+            item(
+                "/srv/a.log",
+                dir.path().join("a.log"),
+                TransferDirection::Download,
+            ),
+            item(
+                "/srv/b.log",
+                dir.path().join("b.log"),
+                TransferDirection::Download,
+            ),
+            item(
+                "/home/me/notes.txt",
+                PathBuf::from("/srv/notes.txt"),
+                TransferDirection::Upload,
+            ),
+        ];
+        app.open_transfer_dialog();
+        let type_text = |app: &mut App, text: &str| {
+            for c in text.chars() {
+                app.destination_edit.as_mut().unwrap().input.push(c);
+            }
+        };
+
+        // One item, prefilled with where it would go, renamed on the way
+        app.handle_event(key('j')).await?;
+        app.handle_event(key('e')).await?;
+        let edit = app.destination_edit.as_mut().unwrap();
+        assert_eq!(edit.scope, DestinationScope::Item(1));
+        assert_eq!(edit.input, dir.path().join("a.log").display().to_string());
+        edit.input.clear();
+        type_text(&mut app, "incoming/today.log");
+        app.handle_event(key_code(KeyCode::Enter)).await?;
+        assert!(app.destination_edit.is_none());
+        assert_eq!(
+            app.transfer_queue[1].destination,
+            dir.path().join("incoming/today.log")
+        );
+
+        // A directory that doesn't exist is refused, and nothing changes
+        app.handle_event(key('E')).await?;
+        assert_eq!(
+            app.destination_edit.as_ref().unwrap().input,
+            dir.path().join("incoming").display().to_string()
+        );
+        type_text(&mut app, "/missing");
+        app.handle_event(key_code(KeyCode::Enter)).await?;
+        let error = app.destination_edit.as_ref().unwrap().error.as_deref();
+        assert!(error.unwrap().ends_with("is not a directory"));
+        assert_eq!(app.transfer_queue[2].destination, dir.path().join("b.log"));
+
+        // Every download goes to the new directory, keeping its name
+        app.destination_edit.as_mut().unwrap().input = "incoming".to_string();
+        app.handle_event(key_code(KeyCode::Enter)).await?;
+        let destinations: Vec<&Path> = app
+            .transfer_queue
+            .iter()
+            .map(|item| item.destination.as_path())
+            .collect();
+        assert_eq!(
+            destinations,
+            [
+                Path::new("/srv/notes.txt"),
+                &dir.path().join("incoming/today.log"),
+                &dir.path().join("incoming/b.log"),
+            ]
+        );
+
+        // Esc leaves the prompt without leaving the dialog
+        app.handle_event(key('e')).await?;
+        app.handle_event(key_code(KeyCode::Esc)).await?;
+        assert!(app.destination_edit.is_none());
+        assert!(app.show_transfer_dialog);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tree_view() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir_all(dir.path().join("src/bin"))?;
+        fs::write(dir.path().join("src/bin/main.rs"), "")?;
+        fs::write(dir.path().join("src/lib.rs"), "")?;
+        fs::write(dir.path().join("README"), "")?;
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.refresh_local_files()?;
+        let listed = |app: &App| -> Vec<(usize, String)> {
+            app.get_current_local_files()
+                .iter()
+                .map(|f| (f.depth, f.name.clone()))
+                .collect()
+        };
+        let rows = |rows: &[(usize, &str)]| -> Vec<(usize, String)> {
+            rows.iter()
+                .map(|&(depth, name)| (depth, name.to_string()))
+                .collect()
+        };
+
+        // In the tree, Enter expands a directory in place instead of
+        // entering it
+        app.handle_event(key('z')).await?;
+        assert!(app.local_tree.is_some());
+        app.local_cursor = 1;
+        app.handle_event(key_code(KeyCode::Enter)).await?;
+        assert_eq!(app.local_path, dir.path());
+        assert_eq!(
+            listed(&app),
+            rows(&[
+                (0, ".."),
+                (0, "src"),
+                (1, "bin"),
+                (1, "lib.rs"),
+                (0, "README")
+            ])
+        );
+
+        // Space expands too, and the selection stays on its entry
+        app.local_selected.insert(3);
+        app.local_cursor = 2;
+        app.handle_event(key(' ')).await?;
+        assert_eq!(
+            listed(&app),
+            rows(&[
+                (0, ".."),
+                (0, "src"),
+                (1, "bin"),
+                (2, "main.rs"),
+                (1, "lib.rs"),
+                (0, "README"),
+            ])
+        );
+        assert_eq!(app.local_selected, HashSet::from([4]));
+
+        // Expansions survive a relist, and see what changed
+        fs::write(dir.path().join("src/bin/tool.rs"), "")?;
+        app.reload_local_files()?;
+        assert_eq!(listed(&app).len(), 7);
+
+        // Collapsing src hides everything below it
+        app.local_cursor = 1;
+        app.handle_event(key_code(KeyCode::Enter)).await?;
+        assert_eq!(listed(&app), rows(&[(0, ".."), (0, "src"), (0, "README")]));
+        assert!(app.local_selected.is_empty());
+
+        // Back to the flat list, with the cursor on the closest entry still
+        // listed
+        app.handle_event(key_code(KeyCode::Enter)).await?;
+        app.local_cursor = 3;
+        app.handle_event(key('z')).await?;
+        assert!(app.local_tree.is_none());
+        assert_eq!(app.local_cursor, 1);
+        assert_eq!(listed(&app), rows(&[(0, ".."), (0, "src"), (0, "README")]));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_type_filter() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        for name in ["docs", "src"] {
+            fs::create_dir(dir.path().join(name))?;
+        }
+        for name in ["Cargo.toml", "docs.md"] {
+            fs::write(dir.path().join(name), "")?;
+        }
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.refresh_local_files()?;
+        let listed = |app: &App| -> Vec<String> {
+            app.get_current_local_files()
+                .iter()
+                .map(|f| f.name.clone())
+                .collect()
+        };
+        assert_eq!(listed(&app), ["..", "docs", "src", "Cargo.toml", "docs.md"]);
+
+        // The cursor stays on its entry while that is still listed
+        app.local_cursor = 2;
+        app.handle_event(key('f')).await?;
+        assert_eq!(app.local_type_filter, TypeFilter::Directories);
+        assert_eq!(listed(&app), ["..", "docs", "src"]);
+        assert_eq!(app.local_cursor, 2);
+
+        app.handle_event(key('f')).await?;
+        assert_eq!(app.local_type_filter, TypeFilter::Files);
+        assert_eq!(listed(&app), ["..", "Cargo.toml", "docs.md"]);
+        assert_eq!(app.local_cursor, 0);
+
+        // A search narrows the filtered listing further, and ending it
+        // leaves the type filter on
+        for c in "/docs".chars() {
+            app.handle_event(key(c)).await?;
+        }
+        assert_eq!(listed(&app), ["docs.md"]);
+        app.handle_event(key_code(KeyCode::Esc)).await?;
+        assert_eq!(listed(&app), ["..", "Cargo.toml", "docs.md"]);
+
+        // Selections count within the filtered listing, and follow it
+        app.handle_event(key('a')).await?;
+        app.handle_event(key('f')).await?;
+        assert_eq!(app.local_type_filter, TypeFilter::All);
+        assert_eq!(listed(&app), ["..", "docs", "src", "Cargo.toml", "docs.md"]);
+        assert_eq!(app.local_selected, HashSet::from([3, 4]));
+        // The other pane was never filtered
+        assert_eq!(app.remote_type_filter, TypeFilter::All);
+
+        // Entries filtered out drop out of the selection
+        app.handle_event(key('f')).await?;
+        app.handle_event(key('f')).await?;
+        assert!(app.local_selected.is_empty());
+        app.handle_event(key('a')).await?;
+        app.prepare_transfer()?;
+        let mut sources: Vec<&Path> = app
+            .transfer_queue
+            .iter()
+            .map(|item| item.source.as_path())
+            .collect();
+        sources.sort();
+        assert_eq!(
+            sources,
+            [dir.path().join("Cargo.toml"), dir.path().join("docs.md")]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remote_type_filter_keeps_parent() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        let entry = |name: &str, is_dir: bool| FileInfo {
+            name: name.to_string(),
+            path: PathBuf::from("/srv").join(name),
+            is_dir,
+            ..FileInfo::default()
+        };
+        app.remote_files = vec![
+            FileInfo {
+                path: PathBuf::from("/"),
+                ..entry("..", true)
+            },
+            entry("logs", true),
+            entry("app.toml", false),
+        ];
+        app.active_pane = Pane::Remote;
+        let listed = |app: &App| -> Vec<String> {
+            app.get_current_remote_files()
+                .iter()
+                .map(|f| f.name.clone())
+                .collect()
+        };
+
+        // `..` stays listed under either filter, so the pane can still be left
+        app.handle_event(key('f')).await?;
+        assert_eq!(listed(&app), ["..", "logs"]);
+        app.handle_event(key('f')).await?;
+        assert_eq!(listed(&app), ["..", "app.toml"]);
+        assert_eq!(app.local_type_filter, TypeFilter::All);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_search_history() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut app = app_with_files(dir.path(), 3)?;
+        for query in ["00", "01"] {
+            app.handle_event(key('/')).await?;
+            for c in query.chars() {
+                app.handle_event(key(c)).await?;
+            }
+            app.handle_event(key_code(KeyCode::Enter)).await?;
+            app.handle_event(key_code(KeyCode::Esc)).await?;
+        }
+
+        app.handle_event(key('/')).await?;
+        app.handle_event(key('0')).await?;
+        app.handle_event(key_code(KeyCode::Up)).await?;
+        assert_eq!(app.search_query, "01");
+        // The recalled query filters like a typed one
+        assert_eq!(app.search_counts(), Some((1, 0)));
+        app.handle_event(key_code(KeyCode::Up)).await?;
+        assert_eq!(app.search_query, "00");
+        app.handle_event(key_code(KeyCode::Down)).await?;
+        app.handle_event(key_code(KeyCode::Down)).await?;
+        assert_eq!(app.search_query, "0");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_step_through_search_matches() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        for name in ["notes.md", "report-a.txt", "report-b.txt", "report-c.txt"] {
+            fs::write(dir.path().join(name), "")?;
+        }
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.refresh_local_files()?;
+
+        for c in "/report".chars() {
+            app.handle_event(key(c)).await?;
+        }
+        // Still typing: n is part of the query
+        assert_eq!(app.search_position(), None);
+        app.handle_event(Event::Key(KeyEvent::new(
+            KeyCode::Enter,
+            KeyModifiers::NONE,
+        )))
+        .await?;
+        assert_eq!(app.search_position(), Some((1, 3)));
+
+        app.handle_event(key('n')).await?;
+        app.handle_event(key('n')).await?;
+        assert_eq!(app.search_position(), Some((3, 3)));
+        assert_eq!(
+            app.get_current_local_files()[app.local_cursor].name,
+            "report-c.txt"
+        );
+
+        app.handle_event(key('n')).await?;
+        assert_eq!(app.search_position(), Some((1, 3)));
+        app.handle_event(key('N')).await?;
+        assert_eq!(app.search_position(), Some((3, 3)));
+
+        // Esc drops the filter but stays on the same entry
+        app.handle_event(Event::Key(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE)))
+            .await?;
+        assert!(!app.search_mode);
+        assert_eq!(app.local_files[app.local_cursor].name, "report-c.txt");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_search_without_matches() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut app = app_with_files(dir.path(), 3)?;
+        app.handle_event(key('G')).await?;
+
+        for c in "/zzz".chars() {
+            app.handle_event(key(c)).await?;
+        }
+        assert!(app.get_current_local_files().is_empty());
+        assert_eq!(app.local_cursor, 0);
+
+        app.handle_event(Event::Key(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE)))
+            .await?;
+        app.handle_event(key('j')).await?;
+        assert_eq!(app.local_cursor, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_toggle_split() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut app = app_with_files(dir.path(), 3)?;
+        assert!(!app.vertical_split);
+
+        app.handle_event(key('S')).await?;
+        assert!(app.vertical_split);
+        // Moving works the same stacked
+        app.handle_event(key('j')).await?;
+        assert_eq!(app.local_cursor, 1);
+        app.handle_event(key('S')).await?;
+        assert!(!app.vertical_split);
+
+        let config = AppConfig {
+            vertical_split: true,
+            ..AppConfig::default()
+        };
+        let app = App::with_config(config, SshConfig::default(), dir.path().to_path_buf());
+        assert!(app.vertical_split);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_grid_view_movement() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        // ".." and 00.txt..09.txt: 11 entries, 6 characters at most
+        let mut app = app_with_files(dir.path(), 10)?;
+        app.handle_event(key('v')).await?;
+        assert!(app.grid_view);
+        // 28 columns inside the borders hold three 8-wide cells with their gaps
+        app.pane_areas = [Rect::new(0, 0, 30, 24); 2];
+        assert_eq!(app.grid_columns(), 3);
+
+        app.handle_event(key('l')).await?;
+        assert_eq!(app.local_cursor, 1);
+        app.handle_event(key('j')).await?;
+        assert_eq!(app.local_cursor, 4);
+        app.handle_event(key('h')).await?;
+        assert_eq!(app.local_cursor, 3);
+        app.handle_event(key('k')).await?;
+        assert_eq!(app.local_cursor, 0);
+        // Sideways keys don't change directory in the grid
+        assert_eq!(app.local_path, dir.path());
+
+        // Down from the last full row stops at the last entry
+        for c in "3j".chars() {
+            app.handle_event(key(c)).await?;
+        }
+        assert_eq!(app.local_cursor, 9);
+        app.handle_event(key('j')).await?;
+        assert_eq!(app.local_cursor, 10);
+
+        // Back in the list view, j moves one entry
+        app.handle_event(key('v')).await?;
+        app.local_cursor = 0;
+        app.handle_event(key('j')).await?;
+        assert_eq!(app.local_cursor, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_wrap_cursor() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut app = app_for_wrapping(dir.path(), 4, true)?;
+
+        // The local listing has ".." above the four files
+        app.handle_event(key('k')).await?;
+        assert_eq!(app.local_cursor, 4);
+        app.handle_event(key('j')).await?;
+        assert_eq!(app.local_cursor, 0);
+
+        app.active_pane = Pane::Remote;
+        app.handle_event(key('k')).await?;
+        assert_eq!(app.remote_cursor, 3);
+        app.handle_event(key('j')).await?;
+        assert_eq!(app.remote_cursor, 0);
+
+        // A count stops at the end before wrapping on the next move
+        for c in "9j".chars() {
+            app.handle_event(key(c)).await?;
+        }
+        assert_eq!(app.remote_cursor, 3);
+        app.handle_event(key('j')).await?;
+        assert_eq!(app.remote_cursor, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_no_wrap_by_default() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut app = app_for_wrapping(dir.path(), 4, false)?;
+
+        app.handle_event(key('k')).await?;
+        assert_eq!(app.local_cursor, 0);
+        app.handle_event(key('G')).await?;
+        app.handle_event(key('j')).await?;
+        assert_eq!(app.local_cursor, 4);
+
+        app.active_pane = Pane::Remote;
+        app.handle_event(key('k')).await?;
+        assert_eq!(app.remote_cursor, 0);
+        app.handle_event(key('G')).await?;
+        app.handle_event(key('j')).await?;
+        assert_eq!(app.remote_cursor, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancel_transfers_flags_the_batch() {
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            PathBuf::from("/"),
+        );
+        let progress = Arc::new(Mutex::new(TransferProgress::new(2)));
+        app.transfer_progress = Some(Arc::clone(&progress));
+
+        app.cancel_transfers();
+        assert!(app.transfer_cancel.load(Ordering::Relaxed));
+        assert_eq!(
+            progress.lock().unwrap().status.as_deref(),
+            Some("Cancelling…")
+        );
+    }
+
+    #[test]
+    fn test_retry_requeues_unfinished_items() {
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            PathBuf::from("/"),
+        );
+        let result = |name: &str, outcome| TransferResult {
+            item: TransferItem {
+                source: PathBuf::from(name),
+                destination: PathBuf::from("/srv").join(name),
+                direction: TransferDirection::Upload,
+                remove_source: false,
+                enabled: true,
+            },
+            outcome,
+            bytes: None,
+            hook: None,
+        };
+        app.transfer_results = Some(vec![
+            result("a", TransferOutcome::Done),
+            result(
+                "b",
+                TransferOutcome::Failed("permission denied".to_string()),
+            ),
+            result("c", TransferOutcome::Skipped("connection lost".to_string())),
+        ]);
+
+        // The cursor scrolls through the results, but not past them
+        for _ in 0..3 {
+            app.handle_transfer_summary_event(KeyCode::Down);
+        }
+        assert_eq!(app.summary_cursor, 2);
+        app.handle_transfer_summary_event(KeyCode::Char('k'));
+        assert_eq!(app.summary_cursor, 1);
+
+        app.handle_transfer_summary_event(KeyCode::Char('r'));
+
+        assert!(app.transfer_results.is_none());
+        assert!(app.show_transfer_dialog);
+        let sources: Vec<_> = app.transfer_queue.iter().map(|i| &i.source).collect();
+        assert_eq!(sources, vec![Path::new("b"), Path::new("c")]);
+    }
+
+    #[test]
+    fn test_reload_keeps_cursor_and_selection_by_name() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("b.txt"), "")?;
+        fs::write(dir.path().join("d.txt"), "")?;
+
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.refresh_local_files()?;
+        app.local_cursor = 2;
+        app.local_selected.insert(1);
+        app.local_selected.insert(2);
+
+        // New files shift everything down
+        fs::write(dir.path().join("a.txt"), "")?;
+        fs::write(dir.path().join("c.txt"), "")?;
+        app.reload_local_files()?;
+
+        assert_eq!(app.local_files[app.local_cursor].name, "d.txt");
+        let mut selected: Vec<&str> = app
+            .local_selected
+            .iter()
+            .map(|&i| app.local_files[i].name.as_str())
+            .collect();
+        selected.sort();
+        assert_eq!(selected, vec!["b.txt", "d.txt"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_watched_directory_removed_moves_to_ancestor() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let watched = dir.path().join("a/b");
+        fs::create_dir_all(&watched)?;
+
+        let mut app = App::with_config(AppConfig::default(), SshConfig::default(), watched.clone());
+        app.refresh_local_files()?;
+        app.local_watcher = Some(LocalWatcher::new()?);
+        app.poll_local_watcher();
+
+        fs::remove_dir_all(dir.path().join("a"))?;
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while app.local_path == watched && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            app.poll_local_watcher();
+        }
+
+        // Still running, in the nearest directory left
+        assert!(!app.should_quit);
+        assert_eq!(app.local_path, dir.path());
+        assert_eq!(
+            app.status_message.as_deref(),
+            Some(
+                format!(
+                    "{} is gone; moved to {}",
+                    watched.display(),
+                    dir.path().display()
+                )
+                .as_str()
+            )
+        );
+        assert_eq!(app.local_files.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cursor_transfer_queues_one_item() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("a.txt"), "a")?;
+        fs::write(dir.path().join("b.txt"), "b")?;
+
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.refresh_local_files()?;
+        app.remote_path = PathBuf::from("/srv");
+        // The selection is left out of it
+        app.local_selected.insert(1);
+        app.local_cursor = 2;
+
+        app.handle_event(key('t')).await?;
+
+        assert!(app.show_transfer_dialog);
+        assert_eq!(app.transfer_queue.len(), 1);
+        let item = &app.transfer_queue[0];
+        assert_eq!(item.source, dir.path().join("b.txt"));
+        assert_eq!(item.destination, Path::new("/srv/b.txt"));
+        assert!(matches!(item.direction, TransferDirection::Upload));
+
+        // Nothing to queue on ".."
+        app.show_transfer_dialog = false;
+        app.transfer_queue.clear();
+        app.local_cursor = 0;
+        app.handle_event(key('t')).await?;
+        assert!(!app.show_transfer_dialog);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_common_destination() {
+        let item = |destination: &str, direction: TransferDirection| TransferItem {
+            source: PathBuf::from("/src").join(Path::new(destination).file_name().unwrap()),
+            destination: PathBuf::from(destination),
+            direction,
+            remove_source: false,
+            enabled: true,
+        };
+        let uploads = [
+            item("/srv/www/index.html", TransferDirection::Upload),
+            item("/srv/www/assets", TransferDirection::Upload),
+        ];
+        assert_eq!(
+            common_destination(&uploads.iter().collect::<Vec<_>>()),
+            Some((TransferDirection::Upload, PathBuf::from("/srv/www")))
+        );
+
+        let elsewhere = item("/srv/backup/index.html", TransferDirection::Upload);
+        assert_eq!(
+            common_destination(&[&uploads[0], &elsewhere, &uploads[1]]),
+            None
+        );
+        // A download landing in a same-named local directory is still elsewhere
+        let download = item("/srv/www/notes.txt", TransferDirection::Download);
+        assert_eq!(common_destination(&[&uploads[0], &download]), None);
+        assert_eq!(common_destination(&[]), None);
+
+        // The cursor goes to the first item that was transferred
+        let results: Vec<TransferResult> = uploads
+            .iter()
+            .zip([
+                TransferOutcome::Failed("denied".to_string()),
+                TransferOutcome::Done,
+            ])
+            .map(|(item, outcome)| TransferResult {
+                item: item.clone(),
+                outcome,
+                bytes: None,
+                hook: None,
+            })
+            .collect();
+        assert_eq!(
+            follow_target(&results),
+            Some((
+                TransferDirection::Upload,
+                PathBuf::from("/srv/www"),
+                PathBuf::from("/srv/www/assets")
+            ))
+        );
+        assert_eq!(follow_target(&results[..1]), None);
+    }
+
+    #[test]
+    fn test_startup_dir_fallback() -> Result<()> {
+        let cwd = tempfile::tempdir()?;
+        let home = tempfile::tempdir()?;
+        let unavailable = || Err(std::io::Error::from(std::io::ErrorKind::NotFound));
+
+        let (dir, note) = startup_dir(Ok(cwd.path().to_path_buf()), None);
+        assert_eq!(dir, cwd.path());
+        assert_eq!(note, None);
+
+        let (dir, note) = startup_dir(unavailable(), Some(home.path().to_path_buf()));
+        assert_eq!(dir, home.path());
+        let note = note.unwrap();
+        assert!(note.starts_with("Current directory unavailable"), "{note}");
+        assert!(note.ends_with(&home.path().display().to_string()), "{note}");
+
+        // A home directory that is gone too leaves the root
+        let missing_home = home.path().join("missing");
+        assert_eq!(
+            startup_dir(unavailable(), Some(missing_home)).0,
+            Path::new("/")
+        );
+        assert_eq!(startup_dir(unavailable(), None).0, Path::new("/"));
+
+        // The pane can list wherever it ends up
+        let (dir, _) = startup_dir(unavailable(), Some(home.path().to_path_buf()));
+        let mut app = App::with_config(AppConfig::default(), SshConfig::default(), dir);
+        app.refresh_local_files()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reveal_local() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut app = app_with_files(dir.path(), 1)?;
+
+        app.active_pane = Pane::Remote;
+        app.reveal_local();
+        assert_eq!(
+            app.status_message.as_deref(),
+            Some("Reveal: only for local files")
+        );
+
+        assert!(spawn_detached("sftui-no-such-opener", dir.path()).is_err());
+        // `true` ignores its argument, standing in for the opener
+        #[cfg(unix)]
+        spawn_detached("true", dir.path())?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_editor_command() {
+        assert_eq!(editor_command(None), ("vi".to_string(), vec![]));
+        assert_eq!(editor_command(Some("  ")), ("vi".to_string(), vec![]));
+        assert_eq!(editor_command(Some("nano")), ("nano".to_string(), vec![]));
+        assert_eq!(
+            editor_command(Some("code --wait")),
+            ("code".to_string(), vec!["--wait".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_edit_only_local_regular_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("sub"))?;
+        fs::write(dir.path().join("notes.txt"), "")?;
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.refresh_local_files()?;
+        let message = |app: &App| app.status_message.clone().unwrap_or_default();
+
+        // ".." and "sub" are directories
+        app.handle_event(key('e')).await?;
+        assert!(message(&app).contains("regular file"));
+        app.local_cursor = 1;
+        app.handle_event(key('e')).await?;
+        assert!(message(&app).contains("regular file"));
+
+        // Without a terminal the editor isn't started
+        app.active_pane = Pane::Local;
+        app.local_cursor = 2;
+        app.handle_event(key('e')).await?;
+        assert!(message(&app).contains("no terminal"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_edit_remote_guards() -> Result<()> {
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            PathBuf::from("/home/me"),
+        );
+        let file = |name: &str, is_dir: bool, size: u64| FileInfo {
+            name: name.to_string(),
+            path: PathBuf::from("/srv").join(name),
+            is_dir,
+            size,
+            ..FileInfo::default()
+        };
+        app.remote_files = vec![
+            file("logs", true, 4096),
+            file("huge.sql", false, MAX_EDIT_BYTES + 1),
+            file("app.conf", false, 120),
+        ];
+        app.active_pane = Pane::Remote;
+        let message = |app: &App| app.status_message.clone().unwrap_or_default();
+
+        app.handle_event(key('e')).await?;
+        assert!(message(&app).contains("regular file"));
+
+        app.remote_cursor = 1;
+        app.handle_event(key('e')).await?;
+        assert!(message(&app).contains("too large"));
+
+        app.remote_cursor = 2;
+        app.handle_event(key('e')).await?;
+        assert!(message(&app).contains("not connected"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_local_file_info_has_owner() -> Result<()> {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("notes.txt");
+        fs::write(&path, "hello")?;
+        let metadata = fs::metadata(&path)?;
+        let names = OwnerNames::parse(
+            &format!(
+                "me:x:{}:{}::/home/me:/bin/sh\n",
+                metadata.uid(),
+                metadata.gid()
+            ),
+            "",
+        );
+
+        let info = local_file_info(path, &metadata, &names);
+        assert_eq!(info.name, "notes.txt");
+        assert_eq!(info.size, 5);
+        assert_eq!(info.uid, Some(metadata.uid()));
+        assert_eq!(info.gid, Some(metadata.gid()));
+        // The group isn't listed, so it stays numeric
+        assert_eq!(info.owner.as_deref(), Some("me"));
+        assert_eq!(info.group, None);
+        assert_eq!(info.owner_label(), format!("me:{}", metadata.gid()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_links_are_resolved_only_near_the_cursor() {
+        let mut files: Vec<FileInfo> = (0..50_000)
+            .map(|i| FileInfo {
+                name: format!("link{i:05}"),
+                link_pending: true,
+                ..FileInfo::default()
+            })
+            .collect();
+        let resolved = std::cell::Cell::new(0);
+        let resolve = |file: &mut FileInfo| {
+            file.link_pending = false;
+            resolved.set(resolved.get() + 1);
+        };
+
+        // The first screen and the one after it
+        resolve_links_near(&mut files, 0, 20, resolve);
+        assert_eq!(resolved.get(), 21);
+        assert!(files[21..].iter().all(|file| file.link_pending));
+
+        // Scrolling further only looks up what's newly near
+        resolve_links_near(&mut files, 10, 20, resolve);
+        assert_eq!(resolved.get(), 31);
+
+        // Jumping deep in leaves everything in between alone
+        resolve_links_near(&mut files, 30_000, 20, resolve);
+        assert_eq!(resolved.get(), 72);
+        assert!(files[31..29_980].iter().all(|file| file.link_pending));
+        assert!(!files[30_020].link_pending);
+        assert!(files[30_021].link_pending);
+
+        resolve_links_near(&mut files, 49_999, 20, resolve);
+        assert_eq!(resolved.get(), 93);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_sort_between_directories_and_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("zdir"))?;
+        fs::write(dir.path().join("b.txt"), "b")?;
+        std::os::unix::fs::symlink(dir.path().join("zdir"), dir.path().join("alink"))?;
+        std::os::unix::fs::symlink(dir.path().join("b.txt"), dir.path().join("clink"))?;
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+
+        app.refresh_local_files()?;
+        assert_eq!(
+            local_names(&app),
+            vec!["..", "zdir", "alink", "clink", "b.txt"]
+        );
+
+        // Without their own tier, links sort with what they point to
+        app.config.sort_symlinks_apart = false;
+        app.refresh_local_files()?;
+        assert_eq!(
+            local_names(&app),
+            vec!["..", "alink", "zdir", "b.txt", "clink"]
+        );
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_follow_symlinks() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("real"))?;
+        std::os::unix::fs::symlink(dir.path().join("real"), dir.path().join("link"))?;
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            dir.path().to_path_buf(),
+        );
+        app.refresh_local_files()?;
+        let link = app
+            .local_files
+            .iter()
+            .position(|f| f.name == "link")
+            .unwrap();
+        assert!(app.local_files[link].is_dir);
+        assert_eq!(
+            app.local_files[link].symlink_target.as_deref(),
+            Some(dir.path().join("real").as_path())
+        );
+
+        // Not following: the target is shown and we stay put
+        app.handle_event(key('L')).await?;
+        assert!(!app.follow_symlinks);
+        app.local_cursor = link;
+        app.change_directory().await?;
+        assert_eq!(app.local_path, dir.path());
+        let message = app.status_message.clone().unwrap_or_default();
+        assert!(message.contains(&dir.path().join("real").display().to_string()));
+
+        // Following: the link is entered like any directory
+        app.handle_event(key('L')).await?;
+        app.local_cursor = link;
+        app.change_directory().await?;
+        assert_eq!(app.local_path, dir.path().join("link"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unreadable_entries_are_skipped() {
+        let file = |name: &str| FileInfo {
+            name: name.to_string(),
+            path: PathBuf::from("/data").join(name),
+            is_dir: false,
+            size: 1,
+            permissions: 0o644,
+            ..FileInfo::default()
+        };
+        let denied = || std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+
+        let (files, inaccessible) = accessible_entries(
+            vec![
+                Ok(file("a.txt")),
+                Err(denied()),
+                Ok(file("b.txt")),
+                Err(denied()),
+            ]
+            .into_iter(),
+        );
+
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["a.txt", "b.txt"]);
+        assert_eq!(inaccessible, 2);
+    }
+
+    #[test]
+    fn test_unreadable_remote_entries_are_reported() -> Result<()> {
+        let stat = |perm| ssh2::FileStat {
+            size: Some(1),
+            uid: None,
+            gid: None,
+            perm,
+            atime: None,
+            mtime: None,
+        };
+        // What a server sends for a name it couldn't stat: no attributes
+        let listing = [
+            ("/data/a.txt", Some(0o100644)),
+            ("/data/secret", None),
+            ("/data/b.txt", Some(0o100644)),
+        ]
+        .map(|(path, perm)| FileInfo::from_listing(PathBuf::from(path), &stat(perm)));
+
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            PathBuf::from("/"),
+        );
+        app.remote_path = PathBuf::from("/data");
+        let (files, inaccessible) = accessible_entries(listing.into_iter());
+        app.show_remote_listing(files, inaccessible)?;
+
+        let names: Vec<&str> = app.remote_files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["..", "a.txt", "b.txt"]);
+        assert_eq!(
+            app.status_message.as_deref(),
+            Some("1 items not accessible")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_hosts() {
+        let host = |name: &str, hostname: &str| SshHost {
+            host: name.to_string(),
+            hostname: Some(hostname.to_string()),
+            ..Default::default()
+        };
+        let hosts = vec![
+            host("db", "10.0.0.5"),
+            host("prod-web", "web.example.com"),
+            host("staging", "staging.example.com"),
+        ];
+        let names = |filter: &str| -> Vec<&str> {
+            filter_hosts(&hosts, filter)
+                .iter()
+                .map(|h| h.host.as_str())
+                .collect()
+        };
+
+        assert_eq!(names(""), vec!["db", "prod-web", "staging"]);
+        assert_eq!(names("PROD"), vec!["prod-web"]);
+        // The hostname matches too
+        assert_eq!(names("example"), vec!["prod-web", "staging"]);
+        assert_eq!(names("10.0"), vec!["db"]);
+        assert!(names("nothing").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_connection_filter_keeps_cursor_in_bounds() -> Result<()> {
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            PathBuf::from("/"),
+        );
+        app.available_hosts = vec![
+            SshHost {
+                host: "alpha".to_string(),
+                ..Default::default()
+            },
+            SshHost {
+                host: "beta".to_string(),
+                ..Default::default()
+            },
+        ];
+        app.show_connection_dialog = true;
+        app.connection_cursor = 2;
+
+        app.handle_event(key('b')).await?;
+        assert_eq!(app.connection_filter, "b");
+        assert_eq!(app.connection_cursor, 1);
+
+        app.handle_event(key('z')).await?;
+        assert_eq!(app.connection_cursor, 0);
+
+        app.handle_event(Event::Key(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE)))
+            .await?;
+        assert!(!app.show_connection_dialog);
+        assert!(app.connection_filter.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_recent_hosts_come_first() {
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            PathBuf::from("/"),
+        );
+        app.available_hosts = ["alpha", "beta", "gamma", "delta"]
+            .iter()
+            .map(|name| SshHost {
+                host: name.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        app.recent_hosts.record("gamma");
+        app.recent_hosts.record("beta");
+
+        let names = |app: &App| -> Vec<String> {
+            app.connection_hosts()
+                .iter()
+                .map(|h| h.host.clone())
+                .collect()
+        };
+        assert_eq!(names(&app), vec!["beta", "gamma", "alpha", "delta"]);
+
+        app.connection_filter = "a".to_string();
+        assert_eq!(names(&app), vec!["beta", "gamma", "alpha", "delta"]);
+        app.connection_filter = "ta".to_string();
+        assert_eq!(names(&app), vec!["beta", "delta"]);
+    }
+
+    #[tokio::test]
+    async fn test_custom_host_settings_prompt() -> Result<()> {
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            PathBuf::from("/"),
+        );
+        app.show_connection_dialog = true;
+        app.custom_host_input = Some("127.0.0.1:1".to_string());
+
+        // The resolved settings are shown before anything connects
+        app.handle_event(key_code(KeyCode::Enter)).await?;
+        let pending = app.pending_host_settings.clone().unwrap();
+        assert_eq!(pending.host.port, Some(1));
+        assert!(pending.matched.is_empty());
+
+        // Backing out returns to the input to fix it
+        app.handle_event(key_code(KeyCode::Esc)).await?;
+        assert!(app.pending_host_settings.is_none());
+        assert_eq!(app.custom_host_input.as_deref(), Some("127.0.0.1:1"));
+
+        // Nothing listens on port 1, so confirming fails fast at connect
+        app.handle_event(key_code(KeyCode::Enter)).await?;
+        app.handle_event(key('y')).await?;
+        assert!(app.pending_host_settings.is_none());
+        finish_connecting(&mut app).await?;
+        assert!(app.custom_host_error.is_some());
+
+        // Turned off, Enter connects straight away
+        app.config.confirm_custom_hosts = false;
+        app.custom_host_error = None;
+        app.handle_event(key_code(KeyCode::Enter)).await?;
+        assert!(app.pending_host_settings.is_none());
+        assert!(app.connecting.is_some());
+        finish_connecting(&mut app).await?;
+        assert!(app.custom_host_error.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_host_key_prompt() -> Result<()> {
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            PathBuf::from("/"),
+        );
+        let pending = PendingHostKey {
+            // Nothing listens on port 1, so trusting fails fast at connect
+            host: SshHost::from_spec("127.0.0.1:1")?,
+            address: "127.0.0.1:1".to_string(),
+            fingerprint: "SHA256:abc".to_string(),
+        };
+
+        // Other keys are ignored while the prompt is up
+        app.pending_host_key = Some(pending.clone());
+        app.handle_event(key('j')).await?;
+        assert!(app.pending_host_key.is_some());
+
+        app.handle_event(key('n')).await?;
+        assert!(app.pending_host_key.is_none());
+        assert!(app.trusted_hosts.get("127.0.0.1:1").is_none());
+
+        app.pending_host_key = Some(pending);
+        app.handle_event(key('y')).await?;
+        assert!(app.pending_host_key.is_none());
+        assert_eq!(app.trusted_hosts.get("127.0.0.1:1"), Some("SHA256:abc"));
+        finish_connecting(&mut app).await?;
+        assert!(app.sftp_client.is_none());
+        assert!(app.status_message.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_background_tasks_keep_the_shell_closed() -> Result<()> {
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            PathBuf::from("/"),
+        );
+        assert_eq!(app.session_in_use(), None);
+
+        app.comparing = Some(Comparing {
+            name: "data".to_string(),
+            size: 0,
+            task: tokio::task::spawn_blocking(|| {
+                Ok(ContentCheck {
+                    identical: true,
+                    server_hash_error: None,
+                })
+            }),
+        });
+        assert_eq!(app.session_in_use(), Some("the comparison"));
+
+        // Only once it has been collected is the session free again
+        while app.comparing.is_some() {
+            tokio::task::yield_now().await;
+            app.poll_compare().await?;
+        }
+        assert_eq!(app.session_in_use(), None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cancel_connecting() -> Result<()> {
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            PathBuf::from("/"),
+        );
+        // Accepts the connection but never answers, and no ConnectTimeout is
+        // set, so only cancelling ends the handshake
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let host = SshHost::from_spec(&listener.local_addr()?.to_string())?;
+        app.show_connection_dialog = true;
+
+        app.start_connect(host);
+        let (mut server, _) = listener.accept()?;
+        // Other keys wait for the attempt
+        app.handle_event(key('q')).await?;
+        assert!(app.connecting.is_some());
+        assert!(!app.should_quit);
+
+        app.handle_event(key_code(KeyCode::Esc)).await?;
+        assert!(app.connecting.is_none());
+        assert!(app.sftp_client.is_none());
+        assert!(app.current_host.is_none());
+        assert!(app.show_connection_dialog);
+        assert!(
+            app.status_message
+                .as_deref()
+                .is_some_and(|message| message.starts_with("Cancelled connecting"))
+        );
+
+        // The abandoned handshake's socket is closed, so its thread doesn't
+        // hold up the runtime shutting down
+        server.set_read_timeout(Some(std::time::Duration::from_secs(5)))?;
+        let mut received = Vec::new();
+        std::io::Read::read_to_end(&mut server, &mut received)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cancel_connecting_keeps_current_connection() -> Result<()> {
+        let mut app = App::with_config(
+            AppConfig::default(),
+            SshConfig::default(),
+            PathBuf::from("/"),
+        );
+        let current = SshHost::from_spec("current.example.com")?;
+        app.connection = Some(app.connect_params(current));
+        app.current_host = Some("current.example.com".to_string());
+        app.remote_path = PathBuf::from("/srv");
+        app.remote_files = vec![FileInfo {
+            name: "data".to_string(),
+            path: PathBuf::from("/srv/data"),
+            ..FileInfo::default()
+        }];
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        app.start_connect(SshHost::from_spec(&listener.local_addr()?.to_string())?);
+        let _server = listener.accept()?;
+        app.handle_event(key_code(KeyCode::Esc)).await?;
+
+        // The connection already open is still the one in use
+        assert!(app.connecting.is_none());
+        assert_eq!(app.current_host.as_deref(), Some("current.example.com"));
+        assert_eq!(
+            app.connection
+                .as_ref()
+                .map(|params| params.host.host.as_str()),
+            Some("current.example.com")
+        );
+        assert_eq!(app.remote_path, Path::new("/srv"));
+        assert_eq!(app.remote_files.len(), 1);
+
+        Ok(())
+    }
+
+    /// Lets a background connection finish, as the event loop would.
+    /// A server where nothing exists: every remote file operation fails the
+    /// way SFTP reports a missing file.
+    struct EmptyServer;
+
+    impl EmptyServer {
+        fn no_such_file() -> anyhow::Error {
+            ssh2::Error::new(ssh2::ErrorCode::SFTP(2), "no such file").into()
+        }
+    }
+
+    impl TransferClient for EmptyServer {
+        fn file_size(&self, _remote_path: &Path) -> Option<u64> {
+            None
+        }
+
+        fn upload_file(
+            &self,
+            _local_path: &Path,
+            _remote_path: &Path,
+            _progress: &mut dyn FnMut(u64) -> Result<()>,
+        ) -> Result<()> {
+            Err(EmptyServer::no_such_file())
+        }
+
+        fn download_file(
+            &self,
+            _remote_path: &Path,
+            _local_path: &Path,
+            _resume: bool,
+            _progress: &mut dyn FnMut(u64) -> Result<()>,
+        ) -> Result<()> {
+            Err(EmptyServer::no_such_file())
+        }
+
+        fn remove(&self, _remote_path: &Path) -> Result<()> {
+            Err(EmptyServer::no_such_file())
+        }
+
+        fn sha256(&self, _remote_path: &Path) -> Result<String> {
+            Err(EmptyServer::no_such_file())
+        }
+
+        fn server_sha256(&self, _remote_path: &Path) -> Result<String> {
+            Err(EmptyServer::no_such_file())
+        }
+
+        fn is_dir(&self, _remote_path: &Path) -> bool {
+            false
+        }
+
+        fn create_directory(&self, _remote_path: &Path) -> Result<()> {
+            Err(EmptyServer::no_such_file())
+        }
+
+        fn times(&self, _remote_path: &Path) -> Result<sftp::Timestamps> {
+            Err(EmptyServer::no_such_file())
+        }
+
+        fn set_times(&self, _remote_path: &Path, _times: sftp::Timestamps) -> Result<()> {
+            Err(EmptyServer::no_such_file())
+        }
+    }
+
+    #[test]
+    fn test_edit_download_of_missing_file() {
+        let temp_dir = edit_temp_dir();
+        let error = download_for_edit(
+            &EmptyServer,
+            Path::new("/srv/missing.txt"),
+            &temp_dir.join("missing.txt"),
+        )
+        .unwrap_err();
+
+        // Not a dropped connection, so the edit reports it in the status
+        // line and the app carries on
+        assert!(!sftp::is_connection_error(&error));
+        assert!(!temp_dir.exists());
+    }
+
+    async fn finish_connecting(app: &mut App) -> Result<()> {
+        while app.connecting.is_some() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            app.poll_connect().await?;
+        }
+        Ok(())
+    }
 }
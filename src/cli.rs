@@ -0,0 +1,275 @@
+use anyhow::{Result, anyhow};
+use clap::Subcommand;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::config::AppConfig;
+use crate::sftp::{ConnectCancel, SftpClient, SftpError};
+use crate::ssh_config::SshConfig;
+use crate::transfer::{self, TransferProgress, format_speed};
+use crate::trusted_hosts::TrustedHosts;
+
+/// One-shot transfers that run without the TUI.
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Upload a local file or directory
+    Put {
+        local: PathBuf,
+        #[arg(value_name = "HOST:REMOTE")]
+        remote: String,
+    },
+    /// Download a remote file
+    Get {
+        #[arg(value_name = "HOST:REMOTE")]
+        remote: String,
+        local: PathBuf,
+    },
+}
+
+pub fn run(
+    command: Command,
+    ssh_config_path: Option<PathBuf>,
+    resume: bool,
+    readonly: bool,
+    compress: bool,
+) -> Result<()> {
+    let mut config = AppConfig::load()?;
+    config.compression |= compress;
+    if matches!(command, Command::Put { .. }) && (readonly || config.readonly) {
+        return Err(anyhow!("Read-only mode: uploads are disabled"));
+    }
+    let ssh_config = match ssh_config_path {
+        Some(path) => SshConfig::from_path(&path)?,
+        None => SshConfig::new()?,
+    };
+    if let Some(warning) = ssh_config.warning() {
+        eprintln!("Warning: {warning}");
+    }
+
+    match command {
+        Command::Put { local, remote } => {
+            let (host, remote_path) = parse_remote(&remote)?;
+            let client = connect(&ssh_config, &host, &config)?;
+
+            // Like scp, uploading into a directory keeps the local name
+            let remote_path = match local.file_name() {
+                Some(name) if client.is_dir(&remote_path) => remote_path.join(name),
+                _ => remote_path,
+            };
+
+            transfer::create_remote_parents(&client, &remote_path)?;
+            let mut reporter = ProgressReporter::new(&local, local_size(&local, config.max_depth));
+            if local.is_dir() {
+                transfer::upload_directory(
+                    &client,
+                    &local,
+                    &remote_path,
+                    config.max_depth,
+                    config.verify_uploads,
+                    &mut |n| {
+                        reporter.advance(n);
+                        Ok(())
+                    },
+                    &mut || {},
+                )?;
+            } else {
+                client.upload_file(&local, &remote_path, &mut |n| {
+                    reporter.advance(n);
+                    Ok(())
+                })?;
+                if config.verify_uploads {
+                    transfer::verify_upload(&client, &local, &remote_path)?;
+                }
+                if config.preserve_times {
+                    client.set_times(&remote_path, transfer::local_times(&local)?)?;
+                }
+            }
+            reporter.finish();
+        }
+        Command::Get { remote, local } => {
+            let (host, remote_path) = parse_remote(&remote)?;
+            let client = connect(&ssh_config, &host, &config)?;
+
+            let local = match remote_path.file_name() {
+                Some(name) if local.is_dir() => local.join(name),
+                _ => local,
+            };
+
+            let mut reporter = ProgressReporter::new(&remote_path, client.file_size(&remote_path));
+            client.download_file(&remote_path, &local, resume, &mut |n| {
+                reporter.advance(n);
+                Ok(())
+            })?;
+            if config.preserve_times {
+                transfer::set_local_times(&local, client.times(&remote_path)?)?;
+            }
+            reporter.finish();
+        }
+    }
+
+    Ok(())
+}
+
+/// Splits an scp-style `host:path` argument. The host may be an ssh_config
+/// name or `[user@]hostname`, with IPv6 addresses in brackets. An empty path
+/// means the remote home directory.
+fn parse_remote(arg: &str) -> Result<(String, PathBuf)> {
+    let (host, path) = match arg.find('[') {
+        Some(open) if !arg[..open].contains(':') => {
+            let close = arg[open..]
+                .find("]:")
+                .map(|i| open + i + 1)
+                .ok_or_else(|| anyhow!("Expected HOST:PATH, got '{}'", arg))?;
+            (&arg[..close], &arg[close + 1..])
+        }
+        _ => arg
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Expected HOST:PATH, got '{}'", arg))?,
+    };
+
+    if host.is_empty() {
+        return Err(anyhow!("Host cannot be empty in '{}'", arg));
+    }
+    let path = if path.is_empty() { "." } else { path };
+
+    Ok((host.to_string(), PathBuf::from(path)))
+}
+
+fn connect(ssh_config: &SshConfig, host: &str, config: &AppConfig) -> Result<SftpClient> {
+    let host_config = ssh_config.host_for_spec(host)?;
+    eprintln!("Connecting to {}...", host_config.host);
+
+    let mut trusted_hosts = TrustedHosts::load();
+    loop {
+        match SftpClient::connect(
+            &host_config,
+            ssh_config,
+            config.keepalive_interval,
+            config.compression,
+            &trusted_hosts,
+            &ConnectCancel::default(),
+        ) {
+            Ok(mut client) => {
+                client.set_retries(config.sftp_retries);
+                if host_config.compression(config.compression) && !client.compressed() {
+                    eprintln!("Server declined compression; continuing uncompressed");
+                }
+                return Ok(client);
+            }
+            // Each unknown key (the target's or a jump host's) is confirmed
+            // on the terminal, like ssh does
+            Err(SftpError::UnknownHostKey {
+                address,
+                fingerprint,
+            }) => {
+                if !confirm_host_key(&address, &fingerprint)? {
+                    return Err(anyhow!("Host key for {} not trusted", address));
+                }
+                trusted_hosts.trust(&address, &fingerprint);
+                trusted_hosts.save()?;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn confirm_host_key(address: &str, fingerprint: &str) -> Result<bool> {
+    eprintln!("The authenticity of host '{address}' can't be established.");
+    eprintln!("Key fingerprint is {fingerprint}.");
+    eprint!("Trust this host and continue connecting (yes/no)? ");
+    std::io::stderr().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "yes"))
+}
+
+/// Size of a local file, or of everything in a local directory.
+fn local_size(path: &Path, max_depth: usize) -> Option<u64> {
+    let metadata = std::fs::metadata(path).ok()?;
+    if metadata.is_dir() {
+        transfer::local_tree_size(path, max_depth)
+            .ok()
+            .map(|tree| tree.bytes)
+    } else {
+        Some(metadata.len())
+    }
+}
+
+/// Prints a one-line progress report to stderr, redrawn in place.
+struct ProgressReporter {
+    progress: TransferProgress,
+}
+
+impl ProgressReporter {
+    fn new(source: &Path, size: Option<u64>) -> Self {
+        let name = source
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| source.display().to_string());
+        let mut progress = TransferProgress::new(1);
+        progress.start_item(0, name, size);
+        ProgressReporter { progress }
+    }
+
+    fn advance(&mut self, bytes: u64) {
+        self.progress.advance(0, bytes);
+        self.print();
+    }
+
+    fn print(&self) {
+        let Some(item) = self.progress.current() else {
+            return;
+        };
+        let done = match item.ratio() {
+            Some(ratio) => format!("{:.0}%", ratio * 100.0),
+            None => format!("{} bytes", item.bytes_done),
+        };
+        eprint!(
+            "\r{}: {} {}    ",
+            item.name,
+            done,
+            format_speed(item.speed())
+        );
+        let _ = std::io::stderr().flush();
+    }
+
+    fn finish(&self) {
+        self.print();
+        eprintln!();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_remote() -> Result<()> {
+        assert_eq!(
+            parse_remote("myserver:/var/log/syslog")?,
+            ("myserver".to_string(), PathBuf::from("/var/log/syslog"))
+        );
+        assert_eq!(
+            parse_remote("deploy@example.com:releases")?,
+            ("deploy@example.com".to_string(), PathBuf::from("releases"))
+        );
+        assert_eq!(
+            parse_remote("myserver:")?,
+            ("myserver".to_string(), PathBuf::from("."))
+        );
+        assert_eq!(
+            parse_remote("root@[::1]:/tmp/x")?,
+            ("root@[::1]".to_string(), PathBuf::from("/tmp/x"))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_remote_errors() {
+        assert!(parse_remote("no-colon").is_err());
+        assert!(parse_remote(":/tmp").is_err());
+        assert!(parse_remote("[::1]/tmp").is_err());
+    }
+}
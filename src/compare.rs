@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+
+use crate::sftp::FileInfo;
+
+/// How an entry in one pane relates to the same-named entry in the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Presence {
+    /// Listed on both sides with the same size (or as directories on both).
+    Both,
+    LocalOnly,
+    RemoteOnly,
+    /// Listed on both sides, but the sizes differ or only one is a directory.
+    Different,
+}
+
+/// Cross-references two directory listings by name. Every name from either
+/// side gets a status; `..` is left out.
+pub fn compare_listings(local: &[FileInfo], remote: &[FileInfo]) -> HashMap<String, Presence> {
+    let remote_by_name: HashMap<&str, &FileInfo> = remote
+        .iter()
+        .filter(|file| file.name != "..")
+        .map(|file| (file.name.as_str(), file))
+        .collect();
+
+    let mut statuses = HashMap::new();
+    for file in local.iter().filter(|file| file.name != "..") {
+        let status = match remote_by_name.get(file.name.as_str()) {
+            None => Presence::LocalOnly,
+            Some(other) if file.is_dir && other.is_dir => Presence::Both,
+            Some(other) if file.is_dir == other.is_dir && file.size == other.size => Presence::Both,
+            Some(_) => Presence::Different,
+        };
+        statuses.insert(file.name.clone(), status);
+    }
+    for name in remote_by_name.keys() {
+        statuses
+            .entry(name.to_string())
+            .or_insert(Presence::RemoteOnly);
+    }
+    statuses
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, size: u64, is_dir: bool) -> FileInfo {
+        FileInfo {
+            name: name.to_string(),
+            size,
+            is_dir,
+            ..FileInfo::default()
+        }
+    }
+
+    #[test]
+    fn test_compare_listings() {
+        let local = [
+            entry("..", 0, true),
+            entry("same.txt", 10, false),
+            entry("changed.txt", 10, false),
+            entry("src", 4096, true),
+            entry("build", 0, false),
+            entry("notes.md", 3, false),
+        ];
+        let remote = [
+            entry("..", 0, true),
+            entry("same.txt", 10, false),
+            entry("changed.txt", 12, false),
+            entry("src", 512, true),
+            entry("build", 0, true),
+            entry("deploy.sh", 7, false),
+        ];
+
+        let statuses = compare_listings(&local, &remote);
+        assert_eq!(statuses.len(), 6);
+        assert_eq!(statuses["same.txt"], Presence::Both);
+        assert_eq!(statuses["changed.txt"], Presence::Different);
+        // Directory sizes mean nothing across filesystems
+        assert_eq!(statuses["src"], Presence::Both);
+        assert_eq!(statuses["build"], Presence::Different);
+        assert_eq!(statuses["notes.md"], Presence::LocalOnly);
+        assert_eq!(statuses["deploy.sh"], Presence::RemoteOnly);
+        assert!(!statuses.contains_key(".."));
+    }
+
+    #[test]
+    fn test_compare_against_empty_listing() {
+        let local = [entry("a", 1, false)];
+
+        let statuses = compare_listings(&local, &[]);
+        assert_eq!(statuses["a"], Presence::LocalOnly);
+
+        let statuses = compare_listings(&[], &local);
+        assert_eq!(statuses["a"], Presence::RemoteOnly);
+    }
+}
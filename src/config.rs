@@ -0,0 +1,278 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::hook::TransferHook;
+use crate::keymap::{self, Action, KeyBinding};
+use crate::sftp;
+use crate::transfer::{self, SkipIdentical};
+
+/// User preferences read from `<config dir>/sftui/config.toml`.
+///
+/// Every field is optional in the file; anything missing keeps its default.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AppConfig {
+    /// List dotfiles in both panes on startup.
+    pub show_hidden: bool,
+    /// Seconds between SSH keepalives on idle connections; 0 disables them.
+    pub keepalive_interval: u32,
+    /// Ask servers to compress the connection, unless a host's ssh_config
+    /// entry says otherwise. Also set by `--compress`.
+    pub compression: bool,
+    /// How many times an SFTP request that only reads is sent again when it
+    /// timed out or its reply didn't arrive, waiting longer each time.
+    pub sftp_retries: u32,
+    /// Relist the local pane when its directory changes on disk. Off by
+    /// default, since watching can be expensive on network filesystems.
+    pub watch_local: bool,
+    /// Browse without changing anything on the server: uploads, remote moves
+    /// and copies are refused. Also set by `--readonly`.
+    pub readonly: bool,
+    /// Queues larger than this many bytes ask for confirmation before they
+    /// start; 0 never asks.
+    pub confirm_transfer_bytes: u64,
+    /// Give transferred files their source's modification and access times
+    /// instead of the time of the transfer.
+    pub preserve_times: bool,
+    /// Moving up from the first entry goes to the last, and down from the
+    /// last to the first.
+    pub wrap_cursor: bool,
+    /// Mark file types with plain ASCII characters instead of Unicode symbols,
+    /// for terminals and fonts that can't show them.
+    pub ascii_icons: bool,
+    /// Stack the local pane above the remote one instead of side by side,
+    /// for narrow terminals.
+    pub vertical_split: bool,
+    /// Deepest directory nesting a directory upload follows. Deeper trees
+    /// fail instead of being walked without end.
+    pub max_depth: usize,
+    /// Append a JSON line for every finished transfer to `transfers.jsonl`
+    /// in the config dir.
+    pub transfer_log: bool,
+    /// How many items of a batch are transferred at once. Each extra one
+    /// opens another connection to the server.
+    pub transfer_workers: usize,
+    /// Leave out files whose destination already matches: `off`,
+    /// `size_and_time` or `checksum`.
+    pub skip_identical: SkipIdentical,
+    /// After each upload, compare the remote file's SHA-256, from the
+    /// server's `sha256sum`, with the local one. Servers without it get
+    /// their size compared only.
+    pub verify_uploads: bool,
+    /// Enter symlinked directories. When off, Enter on one shows where it
+    /// points instead.
+    pub follow_symlinks: bool,
+    /// Show pane titles as `~/…/dir` rather than the full path.
+    pub abbreviate_paths: bool,
+    /// List symlinks after directories and before files, instead of with
+    /// whatever they point to.
+    pub sort_symlinks_apart: bool,
+    /// Space moves down after toggling the selection, as Ctrl-Space always
+    /// does.
+    pub space_advances: bool,
+    /// Show the settings a typed-in host resolves to, and which ssh_config
+    /// blocks they came from, before connecting to it.
+    pub confirm_custom_hosts: bool,
+    /// After a batch, take its destination pane to the directory everything
+    /// went into, with the cursor on the first file transferred.
+    pub follow_transfers: bool,
+    /// Keep what was entered at the `/` and `F` prompts between sessions,
+    /// in `search_history` and `find_history` in the config dir.
+    pub save_input_history: bool,
+    /// Keys for main-view actions, replacing the defaults of each action
+    /// listed, e.g. `quit = ["ctrl+q"]` under `[keys]`.
+    #[serde(deserialize_with = "keymap::deserialize_overrides")]
+    pub keys: HashMap<Action, Vec<KeyBinding>>,
+    /// Per-host settings keyed by the ssh_config host name, e.g. `[hosts.myserver]`.
+    pub hosts: HashMap<String, HostSettings>,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        AppConfig {
+            show_hidden: false,
+            keepalive_interval: 30,
+            compression: false,
+            sftp_retries: sftp::DEFAULT_RETRIES,
+            watch_local: false,
+            readonly: false,
+            confirm_transfer_bytes: 1024 * 1024 * 1024,
+            preserve_times: false,
+            wrap_cursor: false,
+            ascii_icons: false,
+            vertical_split: false,
+            max_depth: transfer::DEFAULT_MAX_DEPTH,
+            transfer_log: false,
+            transfer_workers: 3,
+            skip_identical: SkipIdentical::Off,
+            verify_uploads: false,
+            follow_symlinks: true,
+            abbreviate_paths: false,
+            sort_symlinks_apart: true,
+            space_advances: false,
+            confirm_custom_hosts: true,
+            follow_transfers: false,
+            save_input_history: false,
+            keys: HashMap::new(),
+            hosts: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct HostSettings {
+    /// Local directory to switch to after connecting to this host.
+    pub local_dir: Option<PathBuf>,
+    /// Remote directory to open after connecting, instead of `/`. Relative
+    /// paths start from the login directory.
+    pub remote_dir: Option<PathBuf>,
+    /// Command to run after each item transferred to or from this host.
+    pub after_transfer: Option<TransferHook>,
+}
+
+impl AppConfig {
+    /// Directory holding sftui's config and state files.
+    pub fn dir() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("sftui"))
+    }
+
+    /// Loads the default config file, falling back to defaults when it doesn't exist.
+    pub fn load() -> Result<Self> {
+        match Self::dir().map(|dir| dir.join("config.toml")) {
+            Some(path) if path.exists() => Self::from_path(&path),
+            _ => Ok(AppConfig::default()),
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        toml::from_str(&content).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    pub fn host(&self, name: &str) -> Option<&HostSettings> {
+        self.hosts.get(name)
+    }
+}
+
+/// Expands a leading `~` to the user's home directory.
+pub fn expand_tilde(path: &Path) -> PathBuf {
+    match path.strip_prefix("~") {
+        Ok(rest) => match dirs::home_dir() {
+            Some(home) => home.join(rest),
+            None => path.to_path_buf(),
+        },
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_defaults_when_empty() -> Result<()> {
+        let file = NamedTempFile::new()?;
+        let config = AppConfig::from_path(file.path())?;
+        assert!(!config.show_hidden);
+        assert_eq!(config.keepalive_interval, 30);
+        assert!(!config.compression);
+        assert!(!config.watch_local);
+        assert!(!config.readonly);
+        assert_eq!(config.confirm_transfer_bytes, 1024 * 1024 * 1024);
+        assert!(!config.preserve_times);
+        assert!(!config.wrap_cursor);
+        assert!(!config.ascii_icons);
+        assert!(!config.vertical_split);
+        assert_eq!(config.max_depth, 64);
+        assert!(!config.transfer_log);
+        assert!(config.follow_symlinks);
+        assert_eq!(config.transfer_workers, 3);
+        assert_eq!(config.skip_identical, SkipIdentical::Off);
+        assert!(!config.verify_uploads);
+        assert!(config.confirm_custom_hosts);
+        assert!(!config.follow_transfers);
+        assert!(!config.save_input_history);
+        assert!(config.keys.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_level_settings() -> Result<()> {
+        let mut file = NamedTempFile::new()?;
+        writeln!(file, "show_hidden = true")?;
+        writeln!(file, "keepalive_interval = 0")?;
+        writeln!(file, "watch_local = true")?;
+        writeln!(file, "readonly = true")?;
+        writeln!(file, "compression = true")?;
+        writeln!(file, "skip_identical = \"checksum\"")?;
+        writeln!(file, "verify_uploads = true")?;
+
+        let config = AppConfig::from_path(file.path())?;
+        assert!(config.show_hidden);
+        assert_eq!(config.keepalive_interval, 0);
+        assert!(config.watch_local);
+        assert!(config.readonly);
+        assert!(config.compression);
+        assert_eq!(config.skip_identical, SkipIdentical::Checksum);
+        assert!(config.verify_uploads);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_config_is_an_error() -> Result<()> {
+        let mut file = NamedTempFile::new()?;
+        writeln!(file, "show_hidden = \"sometimes\"")?;
+
+        assert!(AppConfig::from_path(file.path()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_host_local_dir() -> Result<()> {
+        let mut file = NamedTempFile::new()?;
+        write!(
+            file,
+            r#"
+[hosts.myserver]
+local_dir = "/srv/myserver"
+remote_dir = "/var/www"
+
+[hosts."web.example.com"]
+"#
+        )?;
+
+        let config = AppConfig::from_path(file.path())?;
+        assert_eq!(
+            config.host("myserver").and_then(|h| h.local_dir.clone()),
+            Some(PathBuf::from("/srv/myserver"))
+        );
+        assert_eq!(
+            config.host("myserver").and_then(|h| h.remote_dir.clone()),
+            Some(PathBuf::from("/var/www"))
+        );
+        assert!(config.host("web.example.com").unwrap().local_dir.is_none());
+        assert!(config.host("web.example.com").unwrap().remote_dir.is_none());
+        assert!(config.host("other").is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand_tilde() {
+        if let Some(home) = dirs::home_dir() {
+            assert_eq!(expand_tilde(Path::new("~/projects")), home.join("projects"));
+            assert_eq!(expand_tilde(Path::new("~")), home);
+        }
+        assert_eq!(expand_tilde(Path::new("/tmp/~x")), PathBuf::from("/tmp/~x"));
+        assert_eq!(expand_tilde(Path::new("~user")), PathBuf::from("~user"));
+    }
+}
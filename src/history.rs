@@ -0,0 +1,178 @@
+use anyhow::Result;
+
+use crate::store::{Persist, Stored};
+
+/// How many entries each input's history keeps.
+pub const MAX_HISTORY: usize = 100;
+
+/// What was entered into one text input, oldest first, browsed with Up and
+/// Down like a shell's history. Stored one entry per line in
+/// `<config dir>/sftui/<name>` when `save_input_history` is on.
+#[derive(Debug, Clone, Default)]
+pub struct InputHistory {
+    entries: Vec<String>,
+    /// The entry being shown while browsing, and what was typed before
+    /// browsing started, to come back to past the newest entry.
+    browsing: Option<(usize, String)>,
+}
+
+impl InputHistory {
+    /// Loads the history called `name` from the config dir.
+    pub fn load(name: &str) -> Stored<Self> {
+        Stored::load(name)
+    }
+
+    /// Adds `entry` as the newest, unless it is blank or repeats the newest,
+    /// dropping the oldest once the history is full. Ends any browsing.
+    pub fn record(&mut self, entry: &str) {
+        self.browsing = None;
+        if entry.trim().is_empty() || self.entries.last().is_some_and(|last| last == entry) {
+            return;
+        }
+        self.entries.push(entry.to_string());
+        if self.entries.len() > MAX_HISTORY {
+            self.entries.remove(0);
+        }
+    }
+
+    /// The entry before the one shown, starting from the newest; `current`
+    /// is what the input holds, kept for `next` to return to. Stays on the
+    /// oldest entry once there.
+    pub fn previous(&mut self, current: &str) -> Option<&str> {
+        let index = match &self.browsing {
+            Some((index, _)) => index.saturating_sub(1),
+            None => self.entries.len().checked_sub(1)?,
+        };
+        let draft = match self.browsing.take() {
+            Some((_, draft)) => draft,
+            None => current.to_string(),
+        };
+        self.browsing = Some((index, draft));
+        Some(&self.entries[index])
+    }
+
+    /// The entry after the one shown, or past the newest, what was typed
+    /// before browsing. None when not browsing.
+    pub fn next(&mut self) -> Option<String> {
+        let (index, draft) = self.browsing.take()?;
+        if index + 1 < self.entries.len() {
+            self.browsing = Some((index + 1, draft));
+            Some(self.entries[index + 1].clone())
+        } else {
+            Some(draft)
+        }
+    }
+
+    /// Stops browsing, so the next Up starts from the newest entry again.
+    /// Called when the input is edited.
+    pub fn reset(&mut self) {
+        self.browsing = None;
+    }
+}
+
+impl Persist for InputHistory {
+    fn parse(content: &str) -> Self {
+        let mut entries: Vec<String> = content
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect();
+        let excess = entries.len().saturating_sub(MAX_HISTORY);
+        entries.drain(..excess);
+
+        InputHistory {
+            entries,
+            browsing: None,
+        }
+    }
+
+    fn serialize(&self) -> Result<String> {
+        let mut content = self.entries.join("\n");
+        content.push('\n');
+        Ok(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(entries: &[&str]) -> InputHistory {
+        let mut history = InputHistory::default();
+        for entry in entries {
+            history.record(entry);
+        }
+        history
+    }
+
+    #[test]
+    fn test_browse_back_and_forth() {
+        let mut history = history(&["alpha", "beta", "gamma"]);
+
+        assert_eq!(history.previous("draft"), Some("gamma"));
+        assert_eq!(history.previous("gamma"), Some("beta"));
+        assert_eq!(history.previous("beta"), Some("alpha"));
+        // The oldest entry is as far back as it goes
+        assert_eq!(history.previous("alpha"), Some("alpha"));
+
+        assert_eq!(history.next().as_deref(), Some("beta"));
+        assert_eq!(history.next().as_deref(), Some("gamma"));
+        // Past the newest comes back what was being typed
+        assert_eq!(history.next().as_deref(), Some("draft"));
+        assert_eq!(history.next(), None);
+
+        // Editing starts the next Up from the newest again
+        history.previous("");
+        history.previous("");
+        history.reset();
+        assert_eq!(history.previous("x"), Some("gamma"));
+    }
+
+    #[test]
+    fn test_empty_history() {
+        let mut history = InputHistory::default();
+        assert_eq!(history.previous("typed"), None);
+        assert_eq!(history.next(), None);
+    }
+
+    #[test]
+    fn test_record_skips_repeats_and_blanks() {
+        let mut history = history(&["alpha", "alpha", "beta", "", "  ", "alpha"]);
+        assert_eq!(history.entries, ["alpha", "beta", "alpha"]);
+
+        // Recording ends browsing
+        history.previous("");
+        history.record("gamma");
+        assert_eq!(history.previous(""), Some("gamma"));
+    }
+
+    #[test]
+    fn test_record_caps_the_history() {
+        let mut history = InputHistory::default();
+        for i in 0..MAX_HISTORY + 3 {
+            history.record(&format!("query{i}"));
+        }
+
+        assert_eq!(history.entries.len(), MAX_HISTORY);
+        assert_eq!(history.entries[0], "query3");
+    }
+
+    #[test]
+    fn test_save_and_load() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("sftui").join("search_history");
+
+        let mut history = Stored::<InputHistory>::from_path(&path);
+        assert!(history.entries.is_empty());
+        history.record("report");
+        history.record("*.log");
+        history.save()?;
+
+        assert_eq!(
+            Stored::<InputHistory>::from_path(&path).entries,
+            ["report", "*.log"]
+        );
+
+        Ok(())
+    }
+}
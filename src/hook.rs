@@ -0,0 +1,161 @@
+use anyhow::Result;
+use regex::{Captures, Regex};
+use serde::Deserialize;
+use std::process::{Command, Stdio};
+use std::sync::LazyLock;
+
+use crate::app::TransferItem;
+use crate::sftp::{self, SftpClient};
+
+/// The placeholders a hook command can contain.
+static PLACEHOLDER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{(source|dest|name)\}").expect("valid regex"));
+
+/// A command run after each item transferred to or from a host, set per
+/// host as `after_transfer` under `[hosts.<name>]`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TransferHook {
+    /// Run by `sh -c`, with `{source}`, `{dest}` and `{name}` replaced by the
+    /// item's source path, destination path and destination file name, each
+    /// quoted as one word.
+    pub command: String,
+    /// Where the command runs: on the server (the default) or here.
+    #[serde(default)]
+    pub run_on: HookSide,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookSide {
+    #[default]
+    Remote,
+    Local,
+}
+
+/// How a hook run ended, as shown next to its item in the summary.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HookStatus {
+    Exited(i32),
+    /// The command couldn't be started, or ended without an exit status.
+    Failed(String),
+}
+
+impl HookStatus {
+    pub fn describe(&self) -> String {
+        match self {
+            HookStatus::Exited(0) => "hook ok".to_string(),
+            HookStatus::Exited(code) => format!("hook exited with {code}"),
+            HookStatus::Failed(error) => format!("hook failed: {error}"),
+        }
+    }
+}
+
+impl TransferHook {
+    /// The command for `item`, placeholders filled in. Paths that happen to
+    /// contain a placeholder are left alone.
+    pub fn command_for(&self, item: &TransferItem) -> String {
+        PLACEHOLDER
+            .replace_all(&self.command, |captures: &Captures| {
+                let value = match &captures[1] {
+                    "source" => item.source.as_os_str(),
+                    "dest" => item.destination.as_os_str(),
+                    _ => item.destination.file_name().unwrap_or_default(),
+                };
+                sftp::shell_quote(&value.to_string_lossy())
+            })
+            .into_owned()
+    }
+
+    /// Runs the hook for `item` and waits for it. Its output is discarded.
+    pub fn run(&self, item: &TransferItem, client: &SftpClient) -> HookStatus {
+        let command = self.command_for(item);
+        let status = match self.run_on {
+            HookSide::Remote => client.exec(&command),
+            HookSide::Local => run_local(&command),
+        };
+        match status {
+            Ok(code) => HookStatus::Exited(code),
+            Err(e) => HookStatus::Failed(e.to_string()),
+        }
+    }
+}
+
+fn run_local(command: &str) -> Result<i32> {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()?;
+    status
+        .code()
+        .ok_or_else(|| anyhow::anyhow!("killed by a signal"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::TransferDirection;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_command_for() {
+        let hook = TransferHook {
+            command: "install -m 644 {dest} /etc/nginx/ && echo {name} from {source}".to_string(),
+            run_on: HookSide::Remote,
+        };
+        let item = TransferItem {
+            source: PathBuf::from("/home/me/site's.conf"),
+            destination: PathBuf::from("/tmp/upload/site's.conf"),
+            direction: TransferDirection::Upload,
+            remove_source: false,
+            enabled: true,
+        };
+
+        assert_eq!(
+            hook.command_for(&item),
+            "install -m 644 '/tmp/upload/site'\\''s.conf' /etc/nginx/ \
+             && echo 'site'\\''s.conf' from '/home/me/site'\\''s.conf'"
+        );
+
+        let plain = TransferHook {
+            command: "systemctl reload nginx".to_string(),
+            run_on: HookSide::Remote,
+        };
+        assert_eq!(plain.command_for(&item), "systemctl reload nginx");
+
+        // A placeholder spelled out in a path is just part of the path
+        let odd = TransferItem {
+            source: PathBuf::from("/home/me/{dest}"),
+            ..item
+        };
+        assert_eq!(
+            hook.command_for(&odd),
+            "install -m 644 '/tmp/upload/site'\\''s.conf' /etc/nginx/ \
+             && echo 'site'\\''s.conf' from '/home/me/{dest}'"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_local_exit_status() -> Result<()> {
+        assert_eq!(run_local("true")?, 0);
+        assert_eq!(run_local("exit 3")?, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hook_from_config() -> Result<()> {
+        let hook: TransferHook = toml::from_str(r#"command = "make reload""#)?;
+        assert_eq!(hook.run_on, HookSide::Remote);
+        let hook: TransferHook = toml::from_str(
+            r#"
+command = "notify-send {name}"
+run_on = "local"
+"#,
+        )?;
+        assert_eq!(hook.run_on, HookSide::Local);
+        Ok(())
+    }
+}
@@ -0,0 +1,488 @@
+use anyhow::{Result, anyhow};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Everything a key can do in the main view. Config files name these in
+/// snake_case, e.g. `switch_pane`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Quit,
+    SwitchPane,
+    Up,
+    Down,
+    /// The previous column in the grid view, otherwise the parent directory.
+    Left,
+    /// The next column in the grid view, otherwise into the directory.
+    Right,
+    Home,
+    ToggleGrid,
+    ToggleSplit,
+    SwapPanes,
+    NewFile,
+    CompareDirs,
+    Rename,
+    FollowSymlinks,
+    /// Switches pane titles between full and shortened paths.
+    AbbreviatePaths,
+    GoToEntry,
+    FindInSubdirs,
+    Open,
+    Parent,
+    ToggleSelection,
+    /// Toggles the selection, then moves down, to mark a run of entries.
+    SelectAndDown,
+    SelectAll,
+    /// Clears an entered search, otherwise the selection.
+    Cancel,
+    NextMatch,
+    PreviousMatch,
+    Connect,
+    Transfer,
+    TransferCursor,
+    Search,
+    ToggleHidden,
+    CompareFile,
+    /// Full metadata for the entry under the cursor.
+    Info,
+    Edit,
+    Cut,
+    PasteCut,
+    /// Takes two presses, like vi's `yy`.
+    Yank,
+    PasteCopy,
+    /// Copies the file under the cursor next to itself, as `name.copy.ext`.
+    Duplicate,
+    /// An interactive shell on the connected host.
+    Shell,
+    /// Opens the local directory in the desktop's file manager.
+    RevealLocal,
+    /// Copies the `ssh` command for the connected host to the clipboard.
+    CopySshCommand,
+    /// Cycles the active pane between all entries, directories only and
+    /// files only.
+    CycleTypeFilter,
+    /// Switches the active pane between a flat list and a tree whose
+    /// directories expand in place.
+    ToggleTree,
+    NewTab,
+    NextTab,
+    PreviousTab,
+    CloseTab,
+}
+
+/// The bindings sftui ships with.
+const DEFAULT_BINDINGS: &[(Action, &[&str])] = &[
+    (Action::Quit, &["q", "Q"]),
+    (Action::SwitchPane, &["tab"]),
+    (Action::Up, &["up", "k", "K"]),
+    (Action::Down, &["down", "j", "J"]),
+    (Action::Left, &["left", "h"]),
+    (Action::Right, &["right", "l"]),
+    (Action::Home, &["~"]),
+    (Action::ToggleGrid, &["v"]),
+    (Action::ToggleSplit, &["S"]),
+    (Action::SwapPanes, &["W"]),
+    (Action::NewFile, &["A"]),
+    (Action::CompareDirs, &["D"]),
+    (Action::Rename, &["R"]),
+    (Action::FollowSymlinks, &["L"]),
+    (Action::AbbreviatePaths, &["B"]),
+    (Action::GoToEntry, &["G"]),
+    (Action::FindInSubdirs, &["F"]),
+    (Action::Open, &["enter"]),
+    (Action::Parent, &["backspace"]),
+    (Action::ToggleSelection, &["space"]),
+    (Action::SelectAndDown, &["ctrl+space"]),
+    (Action::SelectAll, &["a"]),
+    (Action::Cancel, &["esc"]),
+    (Action::NextMatch, &["n"]),
+    (Action::PreviousMatch, &["N"]),
+    (Action::Connect, &["c", "C"]),
+    (Action::Transfer, &["T"]),
+    (Action::TransferCursor, &["t"]),
+    (Action::Search, &["/"]),
+    (Action::ToggleHidden, &["."]),
+    (Action::CompareFile, &["="]),
+    (Action::Info, &["i"]),
+    (Action::Edit, &["e"]),
+    (Action::Cut, &["X"]),
+    (Action::PasteCut, &["P"]),
+    (Action::Yank, &["y"]),
+    (Action::PasteCopy, &["p"]),
+    (Action::Duplicate, &["d"]),
+    (Action::Shell, &["!"]),
+    (Action::RevealLocal, &["O"]),
+    (Action::CopySshCommand, &["Y"]),
+    (Action::CycleTypeFilter, &["f"]),
+    (Action::ToggleTree, &["z"]),
+    (Action::NewTab, &["+"]),
+    (Action::NextTab, &["]"]),
+    (Action::PreviousTab, &["["]),
+    (Action::CloseTab, &["-"]),
+];
+
+/// A key with its modifiers, written in config files as e.g. `"x"`,
+/// `"enter"` or `"ctrl+r"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct KeyBinding {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl KeyBinding {
+    /// Shift is part of the character itself (`A` rather than `shift+a`), so
+    /// it is dropped for character keys, whatever the terminal reports.
+    fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        match code {
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::SHIFT) => KeyBinding {
+                code: KeyCode::Char(c.to_ascii_uppercase()),
+                modifiers: modifiers - KeyModifiers::SHIFT,
+            },
+            code => KeyBinding { code, modifiers },
+        }
+    }
+
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = spec;
+        while let Some((modifier, tail)) = rest.split_once('+')
+            && !tail.is_empty()
+        {
+            modifiers |= match modifier.to_lowercase().as_str() {
+                "ctrl" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return Err(anyhow!("Unknown modifier '{modifier}' in '{spec}'")),
+            };
+            rest = tail;
+        }
+
+        let mut chars = rest.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match rest.to_lowercase().as_str() {
+                "tab" => KeyCode::Tab,
+                "enter" => KeyCode::Enter,
+                "esc" => KeyCode::Esc,
+                "backspace" => KeyCode::Backspace,
+                "space" => KeyCode::Char(' '),
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                "delete" => KeyCode::Delete,
+                "insert" => KeyCode::Insert,
+                name => match name.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                    Some(n @ 1..=12) => KeyCode::F(n),
+                    _ => return Err(anyhow!("Unknown key '{spec}'")),
+                },
+            },
+        };
+        Ok(KeyBinding::new(code, modifiers))
+    }
+}
+
+/// The key as the footer shows it, e.g. `a`, `Enter`, `←` or `ctrl+r`.
+impl fmt::Display for KeyBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (modifier, name) in [
+            (KeyModifiers::CONTROL, "ctrl+"),
+            (KeyModifiers::ALT, "alt+"),
+            (KeyModifiers::SHIFT, "shift+"),
+        ] {
+            if self.modifiers.contains(modifier) {
+                f.write_str(name)?;
+            }
+        }
+        match self.code {
+            KeyCode::Char(' ') => f.write_str("Space"),
+            KeyCode::Char(c) => write!(f, "{c}"),
+            KeyCode::F(n) => write!(f, "F{n}"),
+            KeyCode::Left => f.write_str("←"),
+            KeyCode::Right => f.write_str("→"),
+            KeyCode::Up => f.write_str("↑"),
+            KeyCode::Down => f.write_str("↓"),
+            KeyCode::PageUp => f.write_str("PgUp"),
+            KeyCode::PageDown => f.write_str("PgDn"),
+            KeyCode::Delete => f.write_str("Del"),
+            KeyCode::Insert => f.write_str("Ins"),
+            code => write!(f, "{code:?}"),
+        }
+    }
+}
+
+impl TryFrom<String> for KeyBinding {
+    type Error = anyhow::Error;
+
+    fn try_from(spec: String) -> Result<Self> {
+        KeyBinding::parse(&spec)
+    }
+}
+
+/// Which action each key triggers in the main view.
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    actions: HashMap<KeyBinding, Action>,
+    /// The keys each action was given, in order, including any an override
+    /// has since taken away.
+    keys: HashMap<Action, Vec<KeyBinding>>,
+}
+
+impl Keymap {
+    /// The default bindings, with the keys of every action in `overrides`
+    /// replaced by the ones given there. A key bound by an override is taken
+    /// away from whatever default action had it.
+    pub fn new(overrides: &HashMap<Action, Vec<KeyBinding>>) -> Self {
+        let mut keys: HashMap<Action, Vec<KeyBinding>> = DEFAULT_BINDINGS
+            .iter()
+            .filter(|(action, _)| !overrides.contains_key(action))
+            .map(|(action, specs)| {
+                let bindings = specs
+                    .iter()
+                    .filter_map(|spec| KeyBinding::parse(spec).ok())
+                    .collect();
+                (*action, bindings)
+            })
+            .collect();
+        // In the order listed, so the same bindings always give the same map
+        let mut actions = HashMap::new();
+        for (action, _) in DEFAULT_BINDINGS {
+            for binding in keys.get(action).into_iter().flatten() {
+                actions.insert(*binding, *action);
+            }
+        }
+        // `deserialize_overrides` has made sure no two of these share a key
+        for (action, bindings) in overrides {
+            for binding in bindings {
+                actions.insert(*binding, *action);
+            }
+            keys.insert(*action, bindings.clone());
+        }
+        Keymap { actions, keys }
+    }
+
+    /// The keys that trigger `action`, in the order they were given.
+    pub fn keys(&self, action: Action) -> Vec<KeyBinding> {
+        self.keys
+            .get(&action)
+            .into_iter()
+            .flatten()
+            .filter(|key| self.actions.get(key) == Some(&action))
+            .copied()
+            .collect()
+    }
+
+    pub fn action(&self, key: &KeyEvent) -> Option<Action> {
+        self.actions
+            .get(&KeyBinding::new(key.code, key.modifiers))
+            .copied()
+    }
+}
+
+/// Reads the `[keys]` table of the config file. A key given to two actions
+/// is an error naming both, since which one it triggered would be down to
+/// chance.
+pub fn deserialize_overrides<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<Action, Vec<KeyBinding>>, D::Error> {
+    let overrides = HashMap::<Action, Vec<KeyBinding>>::deserialize(deserializer)?;
+
+    let mut owners: HashMap<KeyBinding, Action> = HashMap::new();
+    for (action, bindings) in &overrides {
+        for binding in bindings {
+            match owners.insert(*binding, *action) {
+                Some(other) if other != *action => {
+                    let mut names = [config_name(other), config_name(*action)];
+                    names.sort();
+                    return Err(D::Error::custom(format!(
+                        "key '{binding}' is bound to both {} and {}",
+                        names[0], names[1]
+                    )));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(overrides)
+}
+
+/// The snake_case name an action goes by in the config file.
+fn config_name(action: Action) -> String {
+    let mut name = String::new();
+    for c in format!("{action:?}").chars() {
+        if c.is_ascii_uppercase() && !name.is_empty() {
+            name.push('_');
+        }
+        name.push(c.to_ascii_lowercase());
+    }
+    name
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::new(&HashMap::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+
+    fn press(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn test_parse_key_binding() -> Result<()> {
+        assert_eq!(
+            KeyBinding::parse("x")?,
+            KeyBinding::new(KeyCode::Char('x'), KeyModifiers::NONE)
+        );
+        assert_eq!(
+            KeyBinding::parse("Ctrl+r")?,
+            KeyBinding::new(KeyCode::Char('r'), KeyModifiers::CONTROL)
+        );
+        assert_eq!(KeyBinding::parse("shift+a")?, KeyBinding::parse("A")?);
+        assert_eq!(
+            KeyBinding::parse("alt+enter")?,
+            KeyBinding::new(KeyCode::Enter, KeyModifiers::ALT)
+        );
+        assert_eq!(
+            KeyBinding::parse("+")?,
+            KeyBinding::new(KeyCode::Char('+'), KeyModifiers::NONE)
+        );
+        assert_eq!(
+            KeyBinding::parse("ctrl++")?,
+            KeyBinding::new(KeyCode::Char('+'), KeyModifiers::CONTROL)
+        );
+        assert_eq!(
+            KeyBinding::parse("F5")?,
+            KeyBinding::new(KeyCode::F(5), KeyModifiers::NONE)
+        );
+
+        assert!(KeyBinding::parse("hyper+x").is_err());
+        assert!(KeyBinding::parse("escape-hatch").is_err());
+        assert!(KeyBinding::parse("f13").is_err());
+        assert!(KeyBinding::parse("").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_keymap() {
+        let keymap = Keymap::default();
+        assert_eq!(
+            keymap.action(&press(KeyCode::Char('q'), KeyModifiers::NONE)),
+            Some(Action::Quit)
+        );
+        // Terminals may report shift along with an uppercase letter
+        assert_eq!(
+            keymap.action(&press(KeyCode::Char('T'), KeyModifiers::SHIFT)),
+            Some(Action::Transfer)
+        );
+        assert_eq!(
+            keymap.action(&press(KeyCode::Tab, KeyModifiers::NONE)),
+            Some(Action::SwitchPane)
+        );
+        assert_eq!(
+            keymap.action(&press(KeyCode::Char('q'), KeyModifiers::CONTROL)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_config_overrides_default_binding() -> Result<()> {
+        let config: AppConfig = toml::from_str(
+            r#"
+[keys]
+quit = ["ctrl+q"]
+transfer_cursor = ["x"]
+"#,
+        )?;
+        let keymap = Keymap::new(&config.keys);
+
+        assert_eq!(
+            keymap.action(&press(KeyCode::Char('q'), KeyModifiers::CONTROL)),
+            Some(Action::Quit)
+        );
+        // The defaults for an overridden action are gone
+        assert_eq!(
+            keymap.action(&press(KeyCode::Char('q'), KeyModifiers::NONE)),
+            None
+        );
+        assert_eq!(
+            keymap.action(&press(KeyCode::Char('x'), KeyModifiers::NONE)),
+            Some(Action::TransferCursor)
+        );
+        assert_eq!(
+            keymap.action(&press(KeyCode::Char('t'), KeyModifiers::NONE)),
+            None
+        );
+        // Everything else keeps its keys
+        assert_eq!(
+            keymap.action(&press(KeyCode::Char('T'), KeyModifiers::NONE)),
+            Some(Action::Transfer)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_keys_of_an_action() -> Result<()> {
+        let config: AppConfig = toml::from_str(
+            r#"
+[keys]
+quit = ["ctrl+q", "X"]
+"#,
+        )?;
+        let keymap = Keymap::new(&config.keys);
+
+        let names = |action| -> Vec<String> {
+            keymap
+                .keys(action)
+                .iter()
+                .map(|key| key.to_string())
+                .collect()
+        };
+        assert_eq!(names(Action::Quit), ["ctrl+q", "X"]);
+        assert_eq!(names(Action::Left), ["←", "h"]);
+        assert_eq!(names(Action::ToggleSelection), ["Space"]);
+        // The override took the only key of cut
+        assert!(names(Action::Cut).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_binding_in_config() {
+        assert!(toml::from_str::<AppConfig>("[keys]\nquit = [\"hyper+q\"]").is_err());
+        assert!(toml::from_str::<AppConfig>("[keys]\nlaunch = [\"x\"]").is_err());
+    }
+
+    #[test]
+    fn test_key_bound_to_two_actions_in_config() {
+        let error =
+            toml::from_str::<AppConfig>("[keys]\nquit = [\"x\"]\ntransfer_cursor = [\"x\"]")
+                .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("key 'x' is bound to both quit and transfer_cursor"),
+            "{error}"
+        );
+
+        // Listing a key twice for one action is harmless
+        assert!(toml::from_str::<AppConfig>("[keys]\nquit = [\"x\", \"x\"]").is_ok());
+    }
+}
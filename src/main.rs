@@ -1,10 +1,30 @@
 use anyhow::Result;
 use clap::Parser;
+use std::path::PathBuf;
 
 mod app;
+mod cli;
+mod compare;
+mod config;
+mod history;
+mod hook;
+mod keymap;
+mod osc52;
+mod owners;
+mod recent;
+mod rename;
+mod search;
 mod sftp;
+mod shell;
 mod ssh_config;
+mod store;
+mod transfer;
+mod transfer_log;
+mod tree;
+mod trusted_hosts;
 mod ui;
+mod view_state;
+mod watcher;
 
 use app::App;
 
@@ -14,13 +34,50 @@ use app::App;
 struct Args {
     #[arg(short = 'H', long, help = "SSH host to connect to")]
     host: Option<String>,
+
+    #[arg(long, help = "Resume interrupted downloads instead of starting over")]
+    resume: bool,
+
+    #[arg(long, help = "Browse without changing anything on the server")]
+    readonly: bool,
+
+    #[arg(short = 'C', long, help = "Ask the server to compress the connection")]
+    compress: bool,
+
+    #[arg(
+        short = 'F',
+        long,
+        value_name = "PATH",
+        help = "SSH config file to use instead of ~/.ssh/config"
+    )]
+    config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<cli::Command>,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
 
-    let mut app = App::new(args.host).await?;
+    if let Some(command) = args.command {
+        return cli::run(
+            command,
+            args.config,
+            args.resume,
+            args.readonly,
+            args.compress,
+        );
+    }
+
+    let mut app = App::new(
+        args.host,
+        args.resume,
+        args.readonly,
+        args.compress,
+        args.config,
+    )
+    .await?;
     app.run().await?;
 
     Ok(())
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+
+/// Local user and group names by numeric id, read from `/etc/passwd` and
+/// `/etc/group`. Lookups are best-effort: ids that aren't listed (for example
+/// users that only exist in LDAP) are shown as numbers.
+#[derive(Debug, Clone, Default)]
+pub struct OwnerNames {
+    users: HashMap<u32, String>,
+    groups: HashMap<u32, String>,
+}
+
+impl OwnerNames {
+    pub fn load() -> Self {
+        let read = |path| std::fs::read_to_string(path).unwrap_or_default();
+        Self::parse(&read("/etc/passwd"), &read("/etc/group"))
+    }
+
+    /// Builds the tables from passwd- and group-format text, where the name is
+    /// the first field and the id the third.
+    pub fn parse(passwd: &str, group: &str) -> Self {
+        OwnerNames {
+            users: parse_ids(passwd),
+            groups: parse_ids(group),
+        }
+    }
+
+    pub fn user(&self, uid: u32) -> Option<&str> {
+        self.users.get(&uid).map(String::as_str)
+    }
+
+    pub fn group(&self, gid: u32) -> Option<&str> {
+        self.groups.get(&gid).map(String::as_str)
+    }
+}
+
+fn parse_ids(content: &str) -> HashMap<u32, String> {
+    content
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            let id = fields.nth(1)?.parse().ok()?;
+            // The first entry for an id wins, as with getpwuid
+            Some((id, name.to_string()))
+        })
+        .fold(HashMap::new(), |mut ids, (id, name)| {
+            ids.entry(id).or_insert(name);
+            ids
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let names = OwnerNames::parse(
+            "# comment\nroot:x:0:0:root:/root:/bin/sh\nalice:x:1000:1000::/home/alice:/bin/sh\n\
+             toor:x:0:0::/root:/bin/sh\nbroken line\n",
+            "root:x:0:\nstaff:x:50:alice,bob\n",
+        );
+
+        assert_eq!(names.user(0), Some("root"));
+        assert_eq!(names.user(1000), Some("alice"));
+        assert_eq!(names.user(1001), None);
+        assert_eq!(names.group(50), Some("staff"));
+        assert_eq!(names.group(1000), None);
+    }
+}
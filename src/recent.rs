@@ -0,0 +1,98 @@
+use anyhow::Result;
+
+use crate::store::{Persist, Stored};
+
+/// How many hosts the recently connected list keeps.
+pub const MAX_RECENT: usize = 10;
+
+/// Hosts connected to most recently, newest first, stored one per line in
+/// `<config dir>/sftui/recent_hosts`.
+#[derive(Debug, Clone, Default)]
+pub struct RecentHosts {
+    hosts: Vec<String>,
+}
+
+impl RecentHosts {
+    pub fn load() -> Stored<Self> {
+        Stored::load("recent_hosts")
+    }
+
+    pub fn hosts(&self) -> &[String] {
+        &self.hosts
+    }
+
+    /// Moves `host` to the front of the list, dropping the oldest entry once
+    /// the list is full.
+    pub fn record(&mut self, host: &str) {
+        self.hosts.retain(|h| h != host);
+        self.hosts.insert(0, host.to_string());
+        self.hosts.truncate(MAX_RECENT);
+    }
+}
+
+impl Persist for RecentHosts {
+    fn parse(content: &str) -> Self {
+        let hosts = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .take(MAX_RECENT)
+            .map(str::to_string)
+            .collect();
+        RecentHosts { hosts }
+    }
+
+    fn serialize(&self) -> Result<String> {
+        let mut content = self.hosts.join("\n");
+        content.push('\n');
+        Ok(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_moves_host_to_front() {
+        let mut recent = RecentHosts::default();
+        recent.record("alpha");
+        recent.record("beta");
+        recent.record("gamma");
+        assert_eq!(recent.hosts(), ["gamma", "beta", "alpha"]);
+
+        // Reconnecting to a listed host moves it rather than duplicating it
+        recent.record("alpha");
+        assert_eq!(recent.hosts(), ["alpha", "gamma", "beta"]);
+    }
+
+    #[test]
+    fn test_record_caps_the_list() {
+        let mut recent = RecentHosts::default();
+        for i in 0..MAX_RECENT + 3 {
+            recent.record(&format!("host{i}"));
+        }
+
+        assert_eq!(recent.hosts().len(), MAX_RECENT);
+        assert_eq!(recent.hosts()[0], format!("host{}", MAX_RECENT + 2));
+    }
+
+    #[test]
+    fn test_save_and_load() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("sftui").join("recent_hosts");
+
+        let mut recent = Stored::<RecentHosts>::from_path(&path);
+        assert!(recent.hosts().is_empty());
+        recent.record("alpha");
+        recent.record("beta");
+        recent.save()?;
+
+        assert_eq!(
+            Stored::<RecentHosts>::from_path(&path).hosts(),
+            ["beta", "alpha"]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+
+use crate::app::validate_file_name;
+
+/// What a bulk rename will do with one entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RenameStatus {
+    Rename,
+    /// The pattern doesn't change the name.
+    Unchanged,
+    /// The new name is already taken, in the directory or by another entry
+    /// of the batch. Skipped rather than overwriting anything.
+    Collision,
+    /// The new name isn't usable as a file name; the reason is given.
+    Invalid(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedRename {
+    pub from: String,
+    pub to: String,
+    pub status: RenameStatus,
+}
+
+/// Works out the new name for each of `names` by replacing every match of
+/// `pattern` with `replacement` (which may refer to groups as `$1`).
+/// `existing` is everything listed in the directory, so names that would
+/// overwrite an entry are flagged rather than renamed.
+pub fn plan_renames(
+    names: &[String],
+    pattern: &Regex,
+    replacement: &str,
+    existing: &[String],
+) -> Vec<PlannedRename> {
+    let existing: HashSet<&str> = existing.iter().map(String::as_str).collect();
+    let renamed: Vec<String> = names
+        .iter()
+        .map(|name| pattern.replace_all(name, replacement).into_owned())
+        .collect();
+
+    let mut targets: HashMap<&str, usize> = HashMap::new();
+    for (name, to) in names.iter().zip(&renamed) {
+        if name != to {
+            *targets.entry(to.as_str()).or_default() += 1;
+        }
+    }
+
+    names
+        .iter()
+        .zip(&renamed)
+        .map(|(from, to)| {
+            let status = if from == to {
+                RenameStatus::Unchanged
+            } else if let Err(e) = validate_file_name(to) {
+                RenameStatus::Invalid(e.to_string())
+            } else if existing.contains(to.as_str()) || targets[to.as_str()] > 1 {
+                RenameStatus::Collision
+            } else {
+                RenameStatus::Rename
+            };
+            PlannedRename {
+                from: from.clone(),
+                to: to.clone(),
+                status,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_plan_renames() {
+        let selected = names(&["IMG_001.jpg", "IMG_002.jpg", "notes.txt"]);
+        let existing = names(&["IMG_001.jpg", "IMG_002.jpg", "notes.txt", "other"]);
+        let pattern = Regex::new(r"^IMG_(\d+)").unwrap();
+
+        let plan = plan_renames(&selected, &pattern, "photo-$1", &existing);
+        assert_eq!(
+            plan,
+            vec![
+                PlannedRename {
+                    from: "IMG_001.jpg".to_string(),
+                    to: "photo-001.jpg".to_string(),
+                    status: RenameStatus::Rename,
+                },
+                PlannedRename {
+                    from: "IMG_002.jpg".to_string(),
+                    to: "photo-002.jpg".to_string(),
+                    status: RenameStatus::Rename,
+                },
+                PlannedRename {
+                    from: "notes.txt".to_string(),
+                    to: "notes.txt".to_string(),
+                    status: RenameStatus::Unchanged,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_renames_collisions() {
+        let selected = names(&["a-1.txt", "a-2.txt", "b.txt"]);
+        let existing = names(&["a-1.txt", "a-2.txt", "b.txt", "c.txt"]);
+
+        // Both map to the same name
+        let pattern = Regex::new(r"-\d").unwrap();
+        let plan = plan_renames(&selected, &pattern, "", &existing);
+        assert_eq!(plan[0].to, "a.txt");
+        assert_eq!(plan[0].status, RenameStatus::Collision);
+        assert_eq!(plan[1].status, RenameStatus::Collision);
+        assert_eq!(plan[2].status, RenameStatus::Unchanged);
+
+        // The new name is already in the directory
+        let pattern = Regex::new(r"^b").unwrap();
+        let plan = plan_renames(&selected, &pattern, "c", &existing);
+        assert_eq!(plan[2].to, "c.txt");
+        assert_eq!(plan[2].status, RenameStatus::Collision);
+    }
+
+    #[test]
+    fn test_plan_renames_invalid_names() {
+        let selected = names(&["report.txt"]);
+        let pattern = Regex::new(r"^report").unwrap();
+
+        let plan = plan_renames(&selected, &pattern, "2024/report", &selected);
+        assert!(matches!(plan[0].status, RenameStatus::Invalid(_)));
+
+        let pattern = Regex::new(r".*").unwrap();
+        let plan = plan_renames(&selected, &pattern, "", &selected);
+        assert!(matches!(plan[0].status, RenameStatus::Invalid(_)));
+    }
+}
@@ -0,0 +1,195 @@
+use anyhow::Result;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::sftp::FileInfo;
+
+/// How many directory levels below the starting point a search descends.
+pub const MAX_DEPTH: usize = 16;
+/// A search stops once it has found this many matches.
+pub const MAX_RESULTS: usize = 500;
+
+/// Matches found so far by a running search, shared with the UI.
+#[derive(Debug, Clone, Default)]
+pub struct SearchResults {
+    pub matches: Vec<FileInfo>,
+    pub dirs_scanned: usize,
+    pub done: bool,
+    /// Why the search ended early, if it did.
+    pub stopped: Option<String>,
+}
+
+/// Walks the tree under `root` breadth-first, collecting entries whose name
+/// contains `query` (ignoring case) into `results`.
+///
+/// Directories that can't be listed are skipped, so one unreadable directory
+/// doesn't end the search; a dropped connection does. Setting `cancel` stops
+/// the walk at the next directory.
+pub fn find(
+    mut list_directory: impl FnMut(&Path) -> Result<Vec<FileInfo>>,
+    root: &Path,
+    query: &str,
+    cancel: &AtomicBool,
+    results: &Mutex<SearchResults>,
+) -> Result<()> {
+    let query = query.to_lowercase();
+    let mut pending = VecDeque::from([(root.to_path_buf(), 0)]);
+
+    let outcome = loop {
+        let Some((dir, depth)) = pending.pop_front() else {
+            break Ok(());
+        };
+        if cancel.load(Ordering::Relaxed) {
+            break Ok(());
+        }
+
+        let entries = match list_directory(&dir) {
+            Ok(entries) => entries,
+            Err(e) if crate::sftp::is_connection_error(&e) => break Err(e),
+            Err(_) => continue,
+        };
+
+        let Ok(mut shared) = results.lock() else {
+            break Ok(());
+        };
+        shared.dirs_scanned += 1;
+
+        for entry in entries {
+            if entry.name == "." || entry.name == ".." {
+                continue;
+            }
+            if entry.is_dir && depth < MAX_DEPTH {
+                pending.push_back((entry.path.clone(), depth + 1));
+            }
+            if entry.name.to_lowercase().contains(&query) {
+                shared.matches.push(entry);
+            }
+        }
+
+        if shared.matches.len() >= MAX_RESULTS {
+            shared.matches.truncate(MAX_RESULTS);
+            shared.stopped = Some(format!("stopped at {MAX_RESULTS} matches"));
+            break Ok(());
+        }
+    };
+
+    if let Ok(mut shared) = results.lock() {
+        shared.done = true;
+        if cancel.load(Ordering::Relaxed) {
+            shared.stopped = Some("cancelled".to_string());
+        }
+    }
+
+    outcome
+}
+
+/// `path` relative to the directory a search started from.
+pub fn relative_path(root: &Path, path: &Path) -> PathBuf {
+    path.strip_prefix(root).unwrap_or(path).to_path_buf()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    fn entry(path: &str, is_dir: bool) -> FileInfo {
+        let path = PathBuf::from(path);
+        FileInfo {
+            name: path.file_name().unwrap().to_string_lossy().into_owned(),
+            path,
+            is_dir,
+            size: 0,
+            permissions: 0o644,
+            ..FileInfo::default()
+        }
+    }
+
+    /// A small tree: /srv/{app/{config.toml, logs/app.log}, locked/, Config.bak}
+    fn list_tree(dir: &Path) -> Result<Vec<FileInfo>> {
+        match dir.to_str().unwrap() {
+            "/srv" => Ok(vec![
+                entry("/srv/app", true),
+                entry("/srv/locked", true),
+                entry("/srv/Config.bak", false),
+            ]),
+            "/srv/app" => Ok(vec![
+                entry("/srv/app/config.toml", false),
+                entry("/srv/app/logs", true),
+            ]),
+            "/srv/app/logs" => Ok(vec![entry("/srv/app/logs/app.log", false)]),
+            _ => Err(anyhow!("permission denied")),
+        }
+    }
+
+    fn names(results: &Mutex<SearchResults>) -> Vec<PathBuf> {
+        let results = results.lock().unwrap();
+        results.matches.iter().map(|m| m.path.clone()).collect()
+    }
+
+    #[test]
+    fn test_find_walks_the_tree() -> Result<()> {
+        let results = Mutex::new(SearchResults::default());
+        let cancel = AtomicBool::new(false);
+
+        find(list_tree, Path::new("/srv"), "CONFIG", &cancel, &results)?;
+
+        // Breadth-first, case-insensitive, unreadable "locked" skipped
+        assert_eq!(
+            names(&results),
+            vec![
+                PathBuf::from("/srv/Config.bak"),
+                PathBuf::from("/srv/app/config.toml")
+            ]
+        );
+        let results = results.lock().unwrap();
+        assert!(results.done);
+        assert!(results.stopped.is_none());
+        assert_eq!(results.dirs_scanned, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_matches_directories_too() -> Result<()> {
+        let results = Mutex::new(SearchResults::default());
+        let cancel = AtomicBool::new(false);
+
+        find(list_tree, Path::new("/srv"), "app", &cancel, &results)?;
+
+        assert_eq!(
+            names(&results),
+            vec![
+                PathBuf::from("/srv/app"),
+                PathBuf::from("/srv/app/logs/app.log")
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_can_be_cancelled() -> Result<()> {
+        let results = Mutex::new(SearchResults::default());
+        let cancel = AtomicBool::new(true);
+
+        find(list_tree, Path::new("/srv"), "app", &cancel, &results)?;
+
+        let results = results.lock().unwrap();
+        assert!(results.matches.is_empty());
+        assert!(results.done);
+        assert_eq!(results.stopped.as_deref(), Some("cancelled"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(
+            relative_path(Path::new("/srv"), Path::new("/srv/app/config.toml")),
+            PathBuf::from("app/config.toml")
+        );
+    }
+}
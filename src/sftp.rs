@@ -1,21 +1,32 @@
 use crate::ssh_config::{SshConfig, SshHost};
+use crate::trusted_hosts::TrustedHosts;
 use anyhow::{Result, anyhow};
-use ssh2::{Channel, Session, Sftp};
+use sha2::{Digest, Sha256};
+use ssh2::{
+    Channel, ErrorCode, ExtendedData, FileStat, HashType, MethodType, OpenFlags, OpenType, Session,
+    Sftp,
+};
+use std::ffi::OsStr;
 use std::fs;
 use std::io::prelude::*;
-use std::net::TcpStream;
+use std::io::{self, ErrorKind, SeekFrom};
+use std::net::Shutdown;
+use std::net::{TcpStream, ToSocketAddrs};
 #[cfg(unix)]
 use std::os::unix::net::UnixStream;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 #[cfg(unix)]
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 #[cfg(unix)]
 use std::thread;
-#[cfg(unix)]
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct FileInfo {
+    /// The name as shown, with bytes that aren't UTF-8 escaped as `\xNN`.
+    /// Anything touching the file goes by `file_name` instead.
     pub name: String,
     pub path: PathBuf,
     pub is_dir: bool,
@@ -23,213 +34,546 @@ pub struct FileInfo {
     pub size: u64,
     #[allow(dead_code)]
     pub permissions: u32,
+    /// Numeric owner and group, when the listing reports them.
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// Owner and group names, resolved for local files only; the server's
+    /// accounts aren't known here.
+    pub owner: Option<String>,
+    pub group: Option<String>,
+    /// Where the entry points, when it is a symlink. `is_dir` and `size`
+    /// then describe the target.
+    pub symlink_target: Option<PathBuf>,
+    /// A remote symlink whose target hasn't been looked up yet. Until
+    /// `SftpClient::resolve_link` does, `is_dir` and `size` describe the link.
+    pub link_pending: bool,
+    /// Last modification, of the link itself for symlinks.
+    pub modified: Option<SystemTime>,
+    /// How many expanded directories down the tree view lists the entry;
+    /// 0 in a flat listing.
+    pub depth: usize,
 }
 
-pub struct SftpClient {
-    _session: Session,
-    #[cfg(unix)]
-    _bastion_session: Option<Session>,
-    #[cfg(unix)]
-    _proxy_threads: Option<ProxyThreads>,
-    sftp: Sftp,
-}
-
-#[cfg(unix)]
-struct ProxyThreads {
-    #[allow(dead_code)]
-    handles: Vec<thread::JoinHandle<()>>,
-}
-
-impl SftpClient {
-    pub fn connect(host_config: &SshHost) -> Result<Self> {
-        // Check if we need to use ProxyJump
-        #[cfg(unix)]
-        if let Some(proxy_jump) = &host_config.proxy_jump {
-            // Get SSH config to look up bastion host details
-            let ssh_config = SshConfig::new()?;
-            let bastion_config = ssh_config.get_host(proxy_jump).ok_or_else(|| {
-                anyhow!("ProxyJump host '{}' not found in SSH config", proxy_jump)
-            })?;
+impl FileInfo {
+    /// Builds the entry for `path` from an SFTP stat.
+    pub fn from_stat(path: PathBuf, stat: &FileStat) -> Self {
+        let name = path
+            .file_name()
+            .map_or_else(|| "Unknown".to_string(), display_name);
+
+        FileInfo {
+            name,
+            is_dir: stat.is_dir(),
+            size: stat.size.unwrap_or(0),
+            permissions: stat.perm.unwrap_or(0),
+            uid: stat.uid,
+            gid: stat.gid,
+            owner: None,
+            group: None,
+            symlink_target: None,
+            link_pending: stat.file_type().is_symlink(),
+            modified: stat
+                .mtime
+                .map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+            depth: 0,
+            path,
+        }
+    }
 
-            return Self::connect_via_proxy(host_config, &bastion_config);
+    /// Builds the entry for a name in a directory listing. A name the server
+    /// sent without its permissions is one it couldn't read, and fails.
+    pub fn from_listing(path: PathBuf, stat: &FileStat) -> io::Result<Self> {
+        if stat.perm.is_none() {
+            return Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                format!("{}: attributes not readable", path.display()),
+            ));
         }
+        Ok(Self::from_stat(path, stat))
+    }
 
-        #[cfg(not(unix))]
-        if host_config.proxy_jump.is_some() {
-            return Err(anyhow!("ProxyJump is not supported on Windows"));
+    /// The owner column: `owner:group`, falling back to the numeric ids.
+    /// Empty when neither is known.
+    pub fn owner_label(&self) -> String {
+        let user = self
+            .owner
+            .clone()
+            .or_else(|| self.uid.map(|uid| uid.to_string()));
+        let group = self
+            .group
+            .clone()
+            .or_else(|| self.gid.map(|gid| gid.to_string()));
+        match (user, group) {
+            (Some(user), Some(group)) => format!("{user}:{group}"),
+            (Some(user), None) => user,
+            (None, Some(group)) => format!(":{group}"),
+            (None, None) => String::new(),
         }
+    }
 
-        // Direct connection
-        Self::connect_direct(host_config)
+    /// The entry's name exactly as stored, for joining onto other
+    /// directories; `name` may have been altered to display it.
+    pub fn file_name(&self) -> &OsStr {
+        self.path
+            .file_name()
+            .unwrap_or_else(|| OsStr::new(&self.name))
     }
 
-    fn connect_direct(host_config: &SshHost) -> Result<Self> {
-        let hostname = host_config.hostname.as_ref().unwrap_or(&host_config.host);
-        let port = host_config.port.unwrap_or(22);
-        let user = host_config
-            .user
-            .as_ref()
-            .ok_or_else(|| anyhow!("No username specified"))?;
+    pub fn is_symlink(&self) -> bool {
+        self.symlink_target.is_some() || self.link_pending
+    }
 
-        let tcp = TcpStream::connect(format!("{hostname}:{port}"))?;
-        let mut session = Session::new()?;
-        session.set_tcp_stream(tcp);
-        session.handshake()?;
-
-        // Try authentication methods
-        let auth_result = if let Some(identity_file) = &host_config.identity_file {
-            // Try public key authentication with the identity file
-            // Note: For PEM files and standard SSH keys, we don't need the .pub file
-            // The public key can be derived from the private key
-            session.userauth_pubkey_file(user, None, identity_file, None)
+    /// Where the entry goes in a listing: `..`, then directories, then
+    /// files. With `symlinks_apart`, symlinks come between directories and
+    /// files whatever they point to.
+    fn sort_tier(&self, symlinks_apart: bool) -> u8 {
+        if self.name == ".." {
+            0
+        } else if symlinks_apart && self.is_symlink() {
+            2
+        } else if self.is_dir {
+            1
         } else {
-            // No identity file specified, use ssh-agent
-            session.userauth_agent(user)
-        };
-
-        // If identity file auth failed, try ssh-agent as fallback
-        if auth_result.is_err() {
-            session.userauth_agent(user)?;
+            3
         }
+    }
+}
 
-        if !session.authenticated() {
-            return Err(anyhow!("Authentication failed"));
+/// `name` as text, with any bytes that aren't UTF-8 written as `\xNN` so
+/// that names differing only in those bytes still look different.
+pub fn display_name(name: &OsStr) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut display = String::new();
+        for chunk in name.as_bytes().utf8_chunks() {
+            display.push_str(chunk.valid());
+            for byte in chunk.invalid() {
+                display.push_str(&format!("\\x{byte:02X}"));
+            }
         }
+        display
+    }
+    #[cfg(not(unix))]
+    {
+        name.to_string_lossy().into_owned()
+    }
+}
 
-        let sftp = session.sftp()?;
+/// Listing order: by `FileInfo::sort_tier`, then by name.
+pub fn compare_entries(a: &FileInfo, b: &FileInfo, symlinks_apart: bool) -> std::cmp::Ordering {
+    a.sort_tier(symlinks_apart)
+        .cmp(&b.sort_tier(symlinks_apart))
+        .then_with(|| a.name.cmp(&b.name))
+}
 
-        Ok(SftpClient {
-            _session: session,
-            #[cfg(unix)]
-            _bastion_session: None,
-            #[cfg(unix)]
-            _proxy_threads: None,
-            sftp,
-        })
+/// An interactive shell channel from `SftpClient::open_shell`. Reads and
+/// writes return `WouldBlock` rather than waiting.
+pub struct Shell<'a> {
+    session: &'a Session,
+    pub channel: Channel,
+}
+
+impl Drop for Shell<'_> {
+    fn drop(&mut self) {
+        // Closing without blocking can't hang on a dead connection
+        let _ = self.channel.close();
+        self.session.set_blocking(true);
     }
+}
 
-    #[cfg(unix)]
-    fn connect_via_proxy(host_config: &SshHost, bastion_config: &SshHost) -> Result<Self> {
-        // First, connect to bastion host
-        let bastion_hostname = bastion_config
-            .hostname
-            .as_ref()
-            .unwrap_or(&bastion_config.host);
-        let bastion_port = bastion_config.port.unwrap_or(22);
-
-        // Validate port number
-        if bastion_port == 0 {
-            return Err(anyhow!(
-                "Invalid port number for bastion host: {}",
-                bastion_port
-            ));
-        }
+/// Space on a remote filesystem, in bytes. `free` is what an unprivileged
+/// user can still write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskSpace {
+    pub free: u64,
+    pub total: u64,
+}
+
+/// Access and modification times of a file, kept when preserving timestamps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timestamps {
+    pub accessed: SystemTime,
+    pub modified: SystemTime,
+}
 
-        let bastion_user = bastion_config
-            .user
-            .as_ref()
-            .ok_or_else(|| anyhow!("No username specified for bastion host"))?;
+/// Why a connection couldn't be set up, so callers can tell a rejected key
+/// from an unreachable host.
+#[derive(Debug, Error)]
+pub enum SftpError {
+    #[error("Authentication failed for {0}")]
+    AuthFailed(String),
+    #[error("Connection to {host} refused")]
+    ConnectionRefused {
+        host: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Host key for {0} doesn't match the one you trusted")]
+    HostKeyMismatch(String),
+    /// The server's key couldn't be read, so there is nothing to check.
+    #[error("{0} sent no host key")]
+    NoHostKey(String),
+    /// The server's key hasn't been accepted yet. Nothing has been sent to
+    /// it, so it is safe to ask the user and connect again.
+    #[error("Host key for {address} is not trusted yet ({fingerprint})")]
+    UnknownHostKey {
+        address: String,
+        fingerprint: String,
+    },
+    #[error("No username specified for {0}")]
+    NoUsername(String),
+    #[error("ProxyJump host '{0}' not found in SSH config")]
+    ProxyJumpNotFound(String),
+    #[cfg(not(unix))]
+    #[error("ProxyJump is not supported on Windows")]
+    ProxyJumpUnsupported,
+    /// `seconds` is the host's `ConnectTimeout`. Without one, the system's
+    /// own limit ran out, which isn't known here.
+    #[error(
+        "Connection to {host} timed out{}",
+        seconds.map(|s| format!(" after {s}s")).unwrap_or_default()
+    )]
+    TimedOut { host: String, seconds: Option<u64> },
+    #[error("Invalid port number for {0}: 0")]
+    InvalidPort(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Ssh(#[from] ssh2::Error),
+}
 
-        let bastion_tcp = TcpStream::connect(format!("{bastion_hostname}:{bastion_port}"))?;
-        let mut bastion_session = Session::new()?;
-        bastion_session.set_tcp_stream(bastion_tcp);
-        bastion_session.handshake()?;
+/// Everything needed to open, or re-open, a connection to one host.
+#[derive(Clone)]
+pub struct ConnectParams {
+    pub host: SshHost,
+    pub ssh_config: SshConfig,
+    pub keepalive_interval: u32,
+    /// Whether to ask for compression when the host doesn't say.
+    pub compression: bool,
+    pub trusted_hosts: TrustedHosts,
+    /// How many times a request that timed out or went unanswered is retried.
+    pub retries: u32,
+}
 
-        // Authenticate to bastion
-        let auth_result = if let Some(identity_file) = &bastion_config.identity_file {
-            // Try public key authentication with the identity file
-            // Note: For PEM files and standard SSH keys, we don't need the .pub file
-            // The public key can be derived from the private key
-            bastion_session.userauth_pubkey_file(bastion_user, None, identity_file, None)
-        } else {
-            bastion_session.userauth_agent(bastion_user)
+impl ConnectParams {
+    pub fn connect(&self) -> Result<SftpClient> {
+        self.connect_cancellable(&ConnectCancel::default())
+    }
+
+    /// Connects like `connect`, but gives up as soon as `cancel` is used.
+    pub fn connect_cancellable(&self, cancel: &ConnectCancel) -> Result<SftpClient> {
+        Ok(SftpClient::connect(
+            &self.host,
+            &self.ssh_config,
+            self.keepalive_interval,
+            self.compression,
+            &self.trusted_hosts,
+            cancel,
+        )
+        .map(|mut client| {
+            client.set_retries(self.retries);
+            client
+        })?)
+    }
+}
+
+/// Aborts a connection being opened on another thread. The handshake and
+/// login can't be interrupted, so the sockets they wait on are shut down
+/// instead, which makes them fail straight away.
+#[derive(Clone, Default)]
+pub struct ConnectCancel {
+    state: Arc<Mutex<CancelState>>,
+}
+
+#[derive(Default)]
+struct CancelState {
+    cancelled: bool,
+    sockets: Vec<CancelSocket>,
+}
+
+/// A clone of a socket a connection is opened over.
+enum CancelSocket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl CancelSocket {
+    fn shutdown(&self) {
+        // Already closed is as good as shut down
+        let _ = match self {
+            CancelSocket::Tcp(stream) => stream.shutdown(Shutdown::Both),
+            #[cfg(unix)]
+            CancelSocket::Unix(stream) => stream.shutdown(Shutdown::Both),
         };
+    }
+}
 
-        if auth_result.is_err() {
-            bastion_session.userauth_agent(bastion_user)?;
+impl ConnectCancel {
+    /// Shuts down every socket of the connection, and any it opens later.
+    pub fn cancel(&self) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        state.cancelled = true;
+        for socket in state.sockets.drain(..) {
+            socket.shutdown();
         }
+    }
 
-        if !bastion_session.authenticated() {
-            return Err(anyhow!("Authentication failed for bastion host"));
+    fn register(&self, socket: CancelSocket) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        if state.cancelled {
+            socket.shutdown();
+        } else {
+            state.sockets.push(socket);
         }
+    }
+}
 
-        // Set bastion session to non-blocking mode
-        bastion_session.set_blocking(false);
+/// What the server accepted as proof of who is logging in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthMethod {
+    /// The private key named by `IdentityFile`.
+    IdentityFile,
+    /// A key held by ssh-agent.
+    Agent,
+}
+
+impl std::fmt::Display for AuthMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            AuthMethod::IdentityFile => "publickey",
+            AuthMethod::Agent => "agent",
+        })
+    }
+}
 
-        // Create a direct-tcpip channel to the target host through bastion
-        let target_hostname = host_config.hostname.as_ref().unwrap_or(&host_config.host);
-        let target_port = host_config.port.unwrap_or(22);
+pub struct SftpClient {
+    session: Session,
+    // Sessions to each ProxyJump hop, kept alive for the tunnels through them
+    #[cfg(unix)]
+    _jump_sessions: Vec<Session>,
+    #[cfg(unix)]
+    _proxy_threads: Vec<ProxyThreads>,
+    sftp: Sftp,
+    /// The login directory, resolved once when connecting.
+    home: PathBuf,
+    /// Whether the server agreed to compress the connection.
+    compressed: bool,
+    /// How the target host (not any jump host) accepted the login.
+    auth_method: AuthMethod,
+    /// How many times an unanswered request is sent again before giving up.
+    retries: u32,
+}
 
-        // Validate target port number
-        if target_port == 0 {
-            return Err(anyhow!(
-                "Invalid port number for target host: {}",
-                target_port
-            ));
+/// The two threads carrying a tunnel's data between its socket and its
+/// channel. Dropping them, as the client does when it goes, stops and
+/// joins both, so reconnecting doesn't leave any behind.
+#[cfg(unix)]
+struct ProxyThreads {
+    /// Checked by both threads between reads.
+    shutdown: Arc<AtomicBool>,
+    handles: Vec<thread::JoinHandle<()>>,
+}
+
+#[cfg(unix)]
+impl Drop for ProxyThreads {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
         }
+    }
+}
 
-        let channel = bastion_session.channel_direct_tcpip(target_hostname, target_port, None)?;
+impl SftpClient {
+    pub fn connect(
+        host_config: &SshHost,
+        #[cfg_attr(not(unix), allow(unused_variables))] ssh_config: &SshConfig,
+        keepalive_interval: u32,
+        compression: bool,
+        trusted_hosts: &TrustedHosts,
+        cancel: &ConnectCancel,
+    ) -> Result<Self, SftpError> {
+        let hops = host_config.proxy_jumps();
 
-        // Create a socketpair for the proxy
-        let (local_sock, remote_sock) = UnixStream::pair()?;
-        local_sock.set_nonblocking(true)?;
-        remote_sock.set_nonblocking(true)?;
+        // Check if we need to use ProxyJump
+        #[cfg(unix)]
+        if !hops.is_empty() {
+            // Look up each jump host's details in the same SSH config
+            let jump_configs = hops
+                .iter()
+                .map(|hop| {
+                    ssh_config
+                        .get_host(hop)
+                        .ok_or_else(|| SftpError::ProxyJumpNotFound(hop.clone()))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            return Self::connect_via_proxy(
+                host_config,
+                &jump_configs,
+                keepalive_interval,
+                compression,
+                trusted_hosts,
+                cancel,
+            );
+        }
+
+        #[cfg(not(unix))]
+        if !hops.is_empty() {
+            return Err(SftpError::ProxyJumpUnsupported);
+        }
 
-        // Create Arc<Mutex<Channel>> for thread sharing
-        let channel = Arc::new(Mutex::new(channel));
+        // Direct connection
+        Self::connect_direct(
+            host_config,
+            keepalive_interval,
+            compression,
+            trusted_hosts,
+            cancel,
+        )
+    }
 
-        // Start proxy threads
-        let proxy_threads = Self::start_proxy_threads(channel, remote_sock)?;
+    fn connect_direct(
+        host_config: &SshHost,
+        keepalive_interval: u32,
+        compression: bool,
+        trusted_hosts: &TrustedHosts,
+        cancel: &ConnectCancel,
+    ) -> Result<Self, SftpError> {
+        let tcp = connect_tcp(host_config)?;
+        cancel.register(CancelSocket::Tcp(tcp.try_clone()?));
+        let mut session = Session::new()?;
+        session.set_tcp_stream(tcp);
+        session.set_compress(host_config.compression(compression));
+        handshake(&mut session, host_config)?;
+        verify_host_key(&session, host_config, trusted_hosts)?;
+        session.set_keepalive(true, host_config.keepalive_interval(keepalive_interval));
+        let auth_method = authenticate(&session, host_config)?;
 
-        // Create session for target host using the local socket
-        let mut target_session = Session::new()?;
-        target_session.set_tcp_stream(local_sock);
-        target_session.handshake()?;
+        let sftp = session.sftp()?;
+        let home = login_directory(&sftp);
+        let compressed = is_compressed(&session);
 
-        // Authenticate to target host
-        let target_user = host_config
-            .user
-            .as_ref()
-            .ok_or_else(|| anyhow!("No username specified for target host"))?;
+        Ok(SftpClient {
+            session,
+            #[cfg(unix)]
+            _jump_sessions: Vec::new(),
+            #[cfg(unix)]
+            _proxy_threads: Vec::new(),
+            sftp,
+            home,
+            compressed,
+            auth_method,
+            retries: DEFAULT_RETRIES,
+        })
+    }
 
-        let auth_result = if let Some(identity_file) = &host_config.identity_file {
-            // Try public key authentication with the identity file
-            // Note: For PEM files and standard SSH keys, we don't need the .pub file
-            // The public key can be derived from the private key
-            target_session.userauth_pubkey_file(target_user, None, identity_file, None)
-        } else {
-            target_session.userauth_agent(target_user)
+    /// Connects through a chain of jump hosts, each hop tunneled through the
+    /// session to the one before it.
+    #[cfg(unix)]
+    fn connect_via_proxy(
+        host_config: &SshHost,
+        jump_configs: &[SshHost],
+        keepalive_interval: u32,
+        compression: bool,
+        trusted_hosts: &TrustedHosts,
+        cancel: &ConnectCancel,
+    ) -> Result<Self, SftpError> {
+        let Some((first_jump, later_jumps)) = jump_configs.split_first() else {
+            return Self::connect_direct(
+                host_config,
+                keepalive_interval,
+                compression,
+                trusted_hosts,
+                cancel,
+            );
         };
 
-        if auth_result.is_err() {
-            target_session.userauth_agent(target_user)?;
+        // First, connect to the first jump host directly
+        let tcp = connect_tcp(first_jump)?;
+        cancel.register(CancelSocket::Tcp(tcp.try_clone()?));
+        let mut session = Session::new()?;
+        session.set_tcp_stream(tcp);
+        // The default only applies to the target; hops need their own setting
+        session.set_compress(first_jump.compression(false));
+        handshake(&mut session, first_jump)?;
+        verify_host_key(&session, first_jump, trusted_hosts)?;
+        session.set_keepalive(true, first_jump.keepalive_interval(keepalive_interval));
+        let mut auth_method = authenticate(&session, first_jump)?;
+
+        let mut jump_sessions = Vec::new();
+        let mut proxy_threads = Vec::new();
+
+        let hops = later_jumps.iter().map(|hop| (hop, false));
+        for (next, default_compression) in hops.chain([(host_config, compression)]) {
+            // Set the previous hop's session to non-blocking mode
+            session.set_blocking(false);
+
+            // Create a direct-tcpip channel to the next hop through it
+            let (hostname, port) = target_address(next)?;
+            let channel = session.channel_direct_tcpip(hostname, port, None)?;
+
+            // Create a socketpair for the proxy
+            let (local_sock, remote_sock) = UnixStream::pair()?;
+            local_sock.set_nonblocking(true)?;
+            remote_sock.set_nonblocking(true)?;
+            cancel.register(CancelSocket::Unix(local_sock.try_clone()?));
+
+            // Create Arc<Mutex<Channel>> for thread sharing
+            let channel = Arc::new(Mutex::new(channel));
+            proxy_threads.push(Self::start_proxy_threads(channel, remote_sock)?);
+            jump_sessions.push(session);
+
+            // Create session for the next hop using the local socket
+            session = Session::new()?;
+            session.set_tcp_stream(local_sock);
+            session.set_compress(next.compression(default_compression));
+            handshake(&mut session, next)?;
+            verify_host_key(&session, next, trusted_hosts)?;
+            session.set_keepalive(true, next.keepalive_interval(keepalive_interval));
+            auth_method = authenticate(&session, next)?;
         }
 
-        if !target_session.authenticated() {
-            return Err(anyhow!("Authentication failed for target host"));
-        }
+        let sftp = session.sftp()?;
 
-        let sftp = target_session.sftp()?;
+        let home = login_directory(&sftp);
+        let compressed = is_compressed(&session);
 
         Ok(SftpClient {
-            _session: target_session,
-            _bastion_session: Some(bastion_session),
-            _proxy_threads: Some(proxy_threads),
+            session,
+            _jump_sessions: jump_sessions,
+            _proxy_threads: proxy_threads,
             sftp,
+            home,
+            compressed,
+            auth_method,
+            retries: DEFAULT_RETRIES,
         })
     }
 
     #[cfg(unix)]
-    fn start_proxy_threads(channel: Arc<Mutex<Channel>>, sock: UnixStream) -> Result<ProxyThreads> {
+    fn start_proxy_threads<C: Read + Write + Send + 'static>(
+        channel: Arc<Mutex<C>>,
+        sock: UnixStream,
+    ) -> std::io::Result<ProxyThreads> {
         let sock_clone = sock.try_clone()?;
         let channel_clone = Arc::clone(&channel);
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let shutdown_clone = Arc::clone(&shutdown);
 
         // Thread 1: Read from socket and write to channel
         let handle1 = thread::spawn(move || {
             let mut buffer = [0u8; 8192];
             let mut sock = sock_clone;
 
-            loop {
+            while !shutdown_clone.load(Ordering::Relaxed) {
                 match sock.read(&mut buffer) {
                     Ok(0) => break, // EOF
                     Ok(n) => {
@@ -255,11 +599,14 @@ impl SftpClient {
         });
 
         // Thread 2: Read from channel and write to socket
+        let thread_shutdown = Arc::clone(&shutdown);
         let handle2 = thread::spawn(move || {
             let mut buffer = [0u8; 8192];
             let mut sock = sock;
 
-            while let Ok(mut chan) = channel.lock() {
+            while !thread_shutdown.load(Ordering::Relaxed)
+                && let Ok(mut chan) = channel.lock()
+            {
                 match chan.read(&mut buffer) {
                     Ok(0) => break, // EOF
                     Ok(n) => {
@@ -282,109 +629,963 @@ impl SftpClient {
         });
 
         Ok(ProxyThreads {
+            shutdown,
             handles: vec![handle1, handle2],
         })
     }
 
+    /// Sends a keepalive if one is due. Fails once the connection is gone.
+    pub fn send_keepalive(&self) -> Result<()> {
+        self.session.keepalive_send()?;
+        Ok(())
+    }
+
     pub fn list_directory(&self, path: &Path) -> Result<Vec<FileInfo>> {
-        let mut files = Vec::new();
-
-        for (path_buf, stat) in self.sftp.readdir(path)? {
-            let name = path_buf
-                .file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("Unknown")
-                .to_string();
-
-            let is_dir = stat.is_dir();
-            let size = stat.size.unwrap_or(0);
-            let permissions = stat.perm.unwrap_or(0);
-
-            files.push(FileInfo {
-                name,
-                path: path_buf,
-                is_dir,
-                size,
-                permissions,
-            });
+        let mut files: Vec<FileInfo> = self
+            .list_directory_lazy(path)?
+            .into_iter()
+            .flatten()
+            .collect();
+        for file in &mut files {
+            self.resolve_link(file);
         }
 
-        files.sort_by(|a, b| match (a.is_dir, b.is_dir) {
-            (true, false) => std::cmp::Ordering::Less,
-            (false, true) => std::cmp::Ordering::Greater,
-            _ => a.name.cmp(&b.name),
-        });
+        files.sort_by(|a, b| compare_entries(a, b, false));
 
         Ok(files)
     }
 
-    pub fn download_file(&self, remote_path: &Path, local_path: &Path) -> Result<()> {
-        let mut remote_file = self.sftp.open(remote_path)?;
-        let mut local_file = fs::File::create(local_path)?;
+    /// Lists `path` without following symlinks, in the server's order. The
+    /// server sends names and attributes together a page at a time, so this
+    /// costs the same however the entries are used; only symlinks need a
+    /// round trip of their own, left to `resolve_link` for the ones shown.
+    /// Entries the server couldn't read come back as errors, to be skipped.
+    pub fn list_directory_lazy(&self, path: &Path) -> Result<Vec<io::Result<FileInfo>>> {
+        Ok(self
+            .retry(|sftp| sftp.readdir(path))?
+            .into_iter()
+            .map(|(path_buf, stat)| FileInfo::from_listing(path_buf, &stat))
+            .collect())
+    }
 
-        let mut buffer = [0; 8192];
-        loop {
-            let bytes_read = remote_file.read(&mut buffer)?;
-            if bytes_read == 0 {
-                break;
-            }
-            local_file.write_all(&buffer[..bytes_read])?;
+    /// Fills in where a pending symlink points and what it leads to. Links
+    /// that can't be followed keep describing themselves.
+    pub fn resolve_link(&self, file: &mut FileInfo) {
+        if !file.link_pending {
+            return;
+        }
+        file.link_pending = false;
+        file.symlink_target = self.retry(|sftp| sftp.readlink(&file.path)).ok();
+        if let Ok(target) = self.retry(|sftp| sftp.stat(&file.path)) {
+            file.is_dir = target.is_dir();
+            file.size = target.size.unwrap_or(0);
         }
+    }
+
+    /// A fresh entry for `path`, as a listing would show it, following a
+    /// symlink to describe what it leads to.
+    pub fn file_info(&self, path: &Path) -> Result<FileInfo> {
+        let stat = self.retry(|sftp| sftp.lstat(path))?;
+        let mut file = FileInfo::from_stat(path.to_path_buf(), &stat);
+        self.resolve_link(&mut file);
+        Ok(file)
+    }
+
+    /// Downloads `remote_path` to `local_path`.
+    ///
+    /// With `resume` set, an existing local file smaller than the remote one is
+    /// treated as an interrupted download and only the remainder is appended.
+    pub fn download_file(
+        &self,
+        remote_path: &Path,
+        local_path: &Path,
+        resume: bool,
+        progress: &mut dyn FnMut(u64) -> Result<()>,
+    ) -> Result<()> {
+        let offset = if resume {
+            let remote_size = self.retry(|sftp| sftp.stat(remote_path))?.size.unwrap_or(0);
+            resume_offset(local_path, remote_size)
+        } else {
+            0
+        };
+
+        let mut remote_file = self.retry(|sftp| sftp.open(remote_path))?;
+        let mut local_file = if offset > 0 {
+            fs::OpenOptions::new().append(true).open(local_path)?
+        } else {
+            fs::File::create(local_path)?
+        };
+
+        copy_from_offset(&mut remote_file, &mut local_file, offset, progress)?;
 
         Ok(())
     }
 
-    pub fn upload_file(&self, local_path: &Path, remote_path: &Path) -> Result<()> {
+    pub fn upload_file(
+        &self,
+        local_path: &Path,
+        remote_path: &Path,
+        progress: &mut dyn FnMut(u64) -> Result<()>,
+    ) -> Result<()> {
         let mut local_file = fs::File::open(local_path)?;
-        let mut remote_file = self.sftp.create(remote_path)?;
+        let mut remote_file = self.retry_change(|sftp| sftp.create(remote_path))?;
+
+        copy_from_offset(&mut local_file, &mut remote_file, 0, progress)?;
+
+        Ok(())
+    }
+
+    /// Size of a remote file, if the server reports one.
+    pub fn file_size(&self, remote_path: &Path) -> Option<u64> {
+        self.retry(|sftp| sftp.stat(remote_path))
+            .ok()
+            .filter(|stat| !stat.is_dir())
+            .and_then(|stat| stat.size)
+    }
+
+    /// SHA-256 of a remote file, streamed without buffering it in memory.
+    pub fn sha256(&self, remote_path: &Path) -> Result<String> {
+        let mut remote_file = self.retry(|sftp| sftp.open(remote_path))?;
+        sha256_reader(&mut remote_file)
+    }
+
+    /// SHA-256 of a remote file from the server's own `sha256sum`, without
+    /// sending the file back. Fails with what the server printed when it
+    /// won't run commands or has no `sha256sum`, and on paths that aren't
+    /// valid UTF-8.
+    pub fn server_sha256(&self, remote_path: &Path) -> Result<String> {
+        let path = remote_path
+            .to_str()
+            .ok_or_else(|| anyhow!("{} isn't valid UTF-8", remote_path.display()))?;
+        let mut channel = self.session.channel_session()?;
+        channel.exec(&format!("sha256sum -- {}", shell_quote(path)))?;
+        let mut output = String::new();
+        channel.read_to_string(&mut output)?;
+        let mut errors = String::new();
+        channel.stderr().read_to_string(&mut errors)?;
+        channel.wait_close()?;
+
+        match channel.exit_status()? {
+            0 => parse_sha256sum(&output)
+                .ok_or_else(|| anyhow!("unexpected sha256sum output: {}", output.trim())),
+            status if errors.trim().is_empty() => {
+                Err(anyhow!("sha256sum exited with status {status}"))
+            }
+            _ => Err(anyhow!("{}", errors.trim())),
+        }
+    }
+
+    /// Runs `command` on the server and waits for its exit status. Its output
+    /// is read and dropped. Stderr is merged into stdout, so a command that
+    /// writes a lot of it can't stall waiting for it to be read.
+    pub fn exec(&self, command: &str) -> Result<i32> {
+        let mut channel = self.session.channel_session()?;
+        channel.handle_extended_data(ExtendedData::Merge)?;
+        channel.exec(command)?;
+        io::copy(&mut channel, &mut io::sink())?;
+        channel.wait_close()?;
+        Ok(channel.exit_status()?)
+    }
+
+    pub fn auth_method(&self) -> AuthMethod {
+        self.auth_method
+    }
+
+    /// How many times an SFTP request that timed out or went unanswered is
+    /// sent again, with the delay doubling each time.
+    pub fn set_retries(&mut self, retries: u32) {
+        self.retries = retries;
+    }
+
+    /// Runs a request that only reads from the server, sending it again
+    /// while it fails in a way that may pass.
+    fn retry<T>(
+        &self,
+        mut op: impl FnMut(&Sftp) -> Result<T, ssh2::Error>,
+    ) -> Result<T, ssh2::Error> {
+        with_retry(self.retries, RETRY_DELAY, || op(&self.sftp))
+    }
+
+    /// Runs a request that changes the server. It is only sent again when
+    /// the session couldn't take it; a lost reply goes up as a dropped
+    /// connection instead, since the change may already have been made.
+    fn retry_change<T>(
+        &self,
+        mut op: impl FnMut(&Sftp) -> Result<T, ssh2::Error>,
+    ) -> Result<T, ssh2::Error> {
+        with_change_retry(self.retries, RETRY_DELAY, || op(&self.sftp))
+    }
+
+    pub fn exists(&self, remote_path: &Path) -> bool {
+        self.retry(|sftp| sftp.lstat(remote_path)).is_ok()
+    }
+
+    /// The directory the server starts sessions in, usually the user's home.
+    pub fn home(&self) -> &Path {
+        &self.home
+    }
+
+    /// Whether data to the server is compressed. A server can turn it down
+    /// even when asked, in which case the connection simply goes uncompressed.
+    pub fn compressed(&self) -> bool {
+        self.compressed
+    }
+
+    /// Starts a login shell on a `cols` x `rows` pseudo-terminal over this
+    /// connection. Until the returned shell is dropped the connection doesn't
+    /// block, so nothing else should use it in the meantime.
+    pub fn open_shell(&self, cols: u16, rows: u16) -> Result<Shell<'_>> {
+        let mut channel = self.session.channel_session()?;
+        channel.request_pty("xterm", None, Some((cols.into(), rows.into(), 0, 0)))?;
+        channel.shell()?;
+        self.session.set_blocking(false);
+        Ok(Shell {
+            session: &self.session,
+            channel,
+        })
+    }
+
+    /// The canonical absolute path the server resolves `remote_path` to, with
+    /// symlinks and `..` resolved.
+    pub fn realpath(&self, remote_path: &Path) -> Result<PathBuf> {
+        Ok(self.retry(|sftp| sftp.realpath(remote_path))?)
+    }
+
+    /// Free and total space on the filesystem holding `remote_path`. Needs the
+    /// server's `fstatvfs@openssh.com` extension; others return an error.
+    pub fn disk_space(&self, remote_path: &Path) -> Result<DiskSpace> {
+        let mut dir = self.retry(|sftp| sftp.opendir(remote_path))?;
+        let stats = dir.statvfs()?;
+        // The fragment size is the unit for block counts, when it is set
+        let block = if stats.f_frsize > 0 {
+            stats.f_frsize
+        } else {
+            stats.f_bsize
+        };
+        Ok(DiskSpace {
+            free: stats.f_bavail.saturating_mul(block),
+            total: stats.f_blocks.saturating_mul(block),
+        })
+    }
+
+    pub fn times(&self, remote_path: &Path) -> Result<Timestamps> {
+        let stat = self.retry(|sftp| sftp.stat(remote_path))?;
+        let from_secs = |secs: Option<u64>| UNIX_EPOCH + Duration::from_secs(secs.unwrap_or(0));
+        Ok(Timestamps {
+            accessed: from_secs(stat.atime),
+            modified: from_secs(stat.mtime),
+        })
+    }
 
-        let mut buffer = [0; 8192];
-        loop {
-            let bytes_read = local_file.read(&mut buffer)?;
-            if bytes_read == 0 {
-                break;
+    /// Sets a remote file's times. SFTP only keeps whole seconds.
+    pub fn set_times(&self, remote_path: &Path, times: Timestamps) -> Result<()> {
+        let to_secs = |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        };
+        let stat = FileStat {
+            size: None,
+            uid: None,
+            gid: None,
+            perm: None,
+            atime: Some(to_secs(times.accessed)),
+            mtime: Some(to_secs(times.modified)),
+        };
+        self.retry_change(|sftp| sftp.setstat(remote_path, stat.clone()))?;
+        Ok(())
+    }
+
+    pub fn is_dir(&self, remote_path: &Path) -> bool {
+        self.retry(|sftp| sftp.stat(remote_path))
+            .is_ok_and(|stat| stat.is_dir())
+    }
+
+    /// Moves `from` to `to` on the server. Refuses to replace an existing entry.
+    pub fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        if self.exists(to) {
+            return Err(anyhow!("{} already exists", to.display()));
+        }
+        self.retry_change(|sftp| sftp.rename(from, to, None))?;
+        Ok(())
+    }
+
+    /// Copies a remote file, or a directory and everything in it, to `to` on
+    /// the same server. SFTP has no copy operation, so the data is streamed
+    /// through this client. Symlinks are copied as links, so one pointing
+    /// back up the tree can't make the copy go on forever.
+    pub fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        if to.starts_with(from) {
+            return Err(anyhow!("can't copy {} into itself", from.display()));
+        }
+
+        let stat = self.retry(|sftp| sftp.lstat(from))?;
+        if stat.file_type().is_symlink() {
+            let target = self.retry(|sftp| sftp.readlink(from))?;
+            self.retry_change(|sftp| sftp.symlink(&target, to))?;
+            return Ok(());
+        }
+        if !stat.is_dir() {
+            let mut source = self.retry(|sftp| sftp.open(from))?;
+            let mut destination = self.retry_change(|sftp| sftp.create(to))?;
+            std::io::copy(&mut source, &mut destination)?;
+            return Ok(());
+        }
+
+        self.create_directory(to)?;
+        for (path, _) in self.retry(|sftp| sftp.readdir(from))? {
+            if let Some(name) = path.file_name() {
+                self.copy(&path, &to.join(name))?;
             }
-            remote_file.write_all(&buffer[..bytes_read])?;
         }
 
         Ok(())
     }
 
+    /// Deletes a remote file, or a directory and everything in it.
+    pub fn remove(&self, remote_path: &Path) -> Result<()> {
+        if !self.retry(|sftp| sftp.lstat(remote_path))?.is_dir() {
+            self.retry_change(|sftp| sftp.unlink(remote_path))?;
+            return Ok(());
+        }
+
+        for (path, _) in self.retry(|sftp| sftp.readdir(remote_path))? {
+            self.remove(&path)?;
+        }
+        self.retry_change(|sftp| sftp.rmdir(remote_path))?;
+
+        Ok(())
+    }
+
+    /// Creates an empty file. Fails if `remote_path` already exists, rather
+    /// than truncating it.
+    pub fn create_file(&self, remote_path: &Path) -> Result<()> {
+        self.retry_change(|sftp| {
+            sftp.open_mode(
+                remote_path,
+                OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::EXCLUSIVE,
+                0o644,
+                OpenType::File,
+            )
+        })?;
+        Ok(())
+    }
+
     pub fn create_directory(&self, remote_path: &Path) -> Result<()> {
-        self.sftp.mkdir(remote_path, 0o755)?;
+        self.retry_change(|sftp| sftp.mkdir(remote_path, 0o755))?;
         Ok(())
     }
+}
 
-    pub fn upload_directory(&self, local_path: &Path, remote_path: &Path) -> Result<()> {
-        // Create the remote directory
-        self.create_directory(remote_path)?;
+/// A directory nested deeper than a walk's `max_depth`.
+#[derive(Debug, Error)]
+#[error("{} is nested too deeply; raise max_depth to include it", path.display())]
+pub struct TooDeep {
+    pub path: PathBuf,
+}
 
-        // Read local directory contents
-        let entries = fs::read_dir(local_path)?;
+/// An entry found by `walk_local_tree`, relative to the directory walked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LocalEntry<'a> {
+    Directory(&'a Path),
+    File(&'a Path),
+}
 
-        for entry in entries {
-            let entry = entry?;
-            let file_type = entry.file_type()?;
-            let file_name = entry.file_name();
-            let local_file_path = entry.path();
-            let remote_file_path = remote_path.join(&file_name);
+/// Visits everything below `root`, each directory before its contents.
+/// Symlinks aren't followed into, so they are visited as files. Going more
+/// than `max_depth` directories down fails with `TooDeep` before anything
+/// that deep is visited.
+pub fn walk_local_tree(
+    root: &Path,
+    max_depth: usize,
+    visit: &mut dyn FnMut(LocalEntry) -> Result<()>,
+) -> Result<()> {
+    walk_from(root, Path::new(""), max_depth, visit)
+}
 
-            if file_type.is_dir() {
-                // Recursively upload subdirectory
-                self.upload_directory(&local_file_path, &remote_file_path)?;
-            } else {
-                // Upload file
-                self.upload_file(&local_file_path, &remote_file_path)?;
+fn walk_from(
+    root: &Path,
+    relative: &Path,
+    depth_left: usize,
+    visit: &mut dyn FnMut(LocalEntry) -> Result<()>,
+) -> Result<()> {
+    for entry in fs::read_dir(root.join(relative))? {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            let Some(depth_left) = depth_left.checked_sub(1) else {
+                return Err(TooDeep {
+                    path: root.join(&path),
+                }
+                .into());
+            };
+            visit(LocalEntry::Directory(&path))?;
+            walk_from(root, &path, depth_left, visit)?;
+        } else {
+            visit(LocalEntry::File(&path))?;
+        }
+    }
+    Ok(())
+}
+
+/// Hostname and port to connect to for `host`.
+fn target_address(host: &SshHost) -> Result<(&str, u16), SftpError> {
+    let hostname = host.hostname.as_deref().unwrap_or(&host.host);
+    let port = host.port.unwrap_or(22);
+
+    // Validate port number
+    if port == 0 {
+        return Err(SftpError::InvalidPort(host.host.clone()));
+    }
+
+    Ok((hostname, port))
+}
+
+/// Resolves the session's starting directory, or `/` on servers that don't
+/// support realpath.
+fn login_directory(sftp: &Sftp) -> PathBuf {
+    sftp.realpath(Path::new("."))
+        .unwrap_or_else(|_| PathBuf::from("/"))
+}
+
+fn connect_tcp(host: &SshHost) -> Result<TcpStream, SftpError> {
+    let (hostname, port) = target_address(host)?;
+    let address = format!("{hostname}:{port}");
+    let result = match host.connect_timeout() {
+        Some(timeout) => connect_with_timeout(&address, timeout),
+        None => TcpStream::connect(address),
+    };
+    result.map_err(|e| match e.kind() {
+        ErrorKind::ConnectionRefused => SftpError::ConnectionRefused {
+            host: host.host.clone(),
+            source: e,
+        },
+        ErrorKind::TimedOut => timed_out(host),
+        _ => SftpError::Io(e),
+    })
+}
+
+/// Tries each address `address` resolves to in turn, giving each `timeout`.
+fn connect_with_timeout(address: &str, timeout: Duration) -> std::io::Result<TcpStream> {
+    let mut last_error = None;
+    for addr in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        std::io::Error::new(ErrorKind::NotFound, format!("{address} has no addresses"))
+    }))
+}
+
+/// Whether the handshake settled on a compression method other than "none".
+fn is_compressed(session: &Session) -> bool {
+    session
+        .methods(MethodType::CompCs)
+        .is_some_and(|method| method != "none")
+}
+
+/// Runs the SSH handshake, giving up after the host's `ConnectTimeout`.
+fn handshake(session: &mut Session, host: &SshHost) -> Result<(), SftpError> {
+    let Some(timeout) = host.connect_timeout() else {
+        return Ok(session.handshake()?);
+    };
+
+    session.set_timeout(u32::try_from(timeout.as_millis()).unwrap_or(u32::MAX));
+    let result = session.handshake();
+    // Later operations, like long transfers, aren't bound by it
+    session.set_timeout(0);
+    result.map_err(|e| match e.code() {
+        ErrorCode::Session(LIBSSH2_ERROR_TIMEOUT) => timed_out(host),
+        _ => e.into(),
+    })
+}
+
+fn timed_out(host: &SshHost) -> SftpError {
+    SftpError::TimedOut {
+        host: host.host.clone(),
+        seconds: host.connect_timeout,
+    }
+}
+
+/// What a host's key is trusted under: its `HostKeyAlias` as written, like
+/// OpenSSH, or else `hostname:port`.
+pub fn host_key_address(host: &SshHost) -> Result<String, SftpError> {
+    let (hostname, port) = target_address(host)?;
+    Ok(match &host.host_key_alias {
+        Some(alias) => alias.clone(),
+        None => format!("{hostname}:{port}"),
+    })
+}
+
+/// Checks the key the server presented against the one the user trusted,
+/// before any credentials are sent.
+fn verify_host_key(
+    session: &Session,
+    host: &SshHost,
+    trusted_hosts: &TrustedHosts,
+) -> Result<(), SftpError> {
+    let address = host_key_address(host)?;
+    let fingerprint = session
+        .host_key_hash(HashType::Sha256)
+        .map(host_key_fingerprint)
+        .ok_or_else(|| SftpError::NoHostKey(host.host.clone()))?;
+
+    match trusted_hosts.get(&address) {
+        Some(trusted) if trusted == fingerprint => Ok(()),
+        Some(_) => Err(SftpError::HostKeyMismatch(host.host.clone())),
+        None => Err(SftpError::UnknownHostKey {
+            address,
+            fingerprint,
+        }),
+    }
+}
+
+/// Formats a SHA-256 key hash the way OpenSSH prints it: `SHA256:` followed
+/// by unpadded base64.
+fn host_key_fingerprint(hash: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::from("SHA256:");
+    for chunk in hash.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let group = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        // One character per 6 bits actually present in the chunk
+        for i in 0..=chunk.len() {
+            let index = (group >> (18 - 6 * i)) & 0x3f;
+            encoded.push(ALPHABET[index as usize] as char);
+        }
+    }
+    encoded
+}
+
+/// Authenticates with the host's identity file, falling back to ssh-agent.
+fn authenticate(session: &Session, host: &SshHost) -> Result<AuthMethod, SftpError> {
+    let user = host
+        .user
+        .as_ref()
+        .ok_or_else(|| SftpError::NoUsername(host.host.clone()))?;
+
+    // For PEM files and standard SSH keys the .pub file isn't needed; the
+    // public key is derived from the private key
+    let method = try_auth_methods(
+        host,
+        |identity_file| session.userauth_pubkey_file(user, None, identity_file, None),
+        || session.userauth_agent(user),
+    )?;
+
+    if !session.authenticated() {
+        return Err(SftpError::AuthFailed(host.host.clone()));
+    }
+
+    Ok(method)
+}
+
+/// Tries the host's identity file, if it has one, then the agent, and says
+/// which was accepted.
+fn try_auth_methods(
+    host: &SshHost,
+    identity_file: impl FnOnce(&Path) -> Result<(), ssh2::Error>,
+    agent: impl FnOnce() -> Result<(), ssh2::Error>,
+) -> Result<AuthMethod, SftpError> {
+    if let Some(path) = &host.identity_file
+        && identity_file(path).is_ok()
+    {
+        return Ok(AuthMethod::IdentityFile);
+    }
+    agent()
+        .map(|()| AuthMethod::Agent)
+        .map_err(|_| SftpError::AuthFailed(host.host.clone()))
+}
+
+/// Returns how many bytes of `local_path` can be kept when resuming a download
+/// of a `remote_size` byte file, or 0 if it has to start from scratch.
+fn resume_offset(local_path: &Path, remote_size: u64) -> u64 {
+    match fs::metadata(local_path) {
+        Ok(metadata) if metadata.is_file() && metadata.len() < remote_size => metadata.len(),
+        _ => 0,
+    }
+}
+
+/// Copies everything from `offset` onwards in `reader` to `writer`, reporting
+/// each chunk's size to `progress`. An error from `progress` stops the copy
+/// after the chunk it was reported for, which is how transfers are cancelled.
+fn copy_from_offset<R: Read + Seek, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    offset: u64,
+    progress: &mut dyn FnMut(u64) -> Result<()>,
+) -> Result<u64> {
+    if offset > 0 {
+        reader.seek(SeekFrom::Start(offset))?;
+    }
+
+    let mut copied = 0;
+    let mut buffer = [0; 8192];
+    loop {
+        let bytes_read = reader.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        writer.write_all(&buffer[..bytes_read])?;
+        copied += bytes_read as u64;
+        progress(bytes_read as u64)?;
+    }
+
+    Ok(copied)
+}
+
+// libssh2 session and SFTP status codes that mean the connection is gone
+const LIBSSH2_ERROR_SOCKET_SEND: i32 = -7;
+const LIBSSH2_ERROR_TIMEOUT: i32 = -9;
+const LIBSSH2_ERROR_SOCKET_DISCONNECT: i32 = -13;
+const LIBSSH2_ERROR_CHANNEL_CLOSED: i32 = -26;
+const LIBSSH2_ERROR_SOCKET_TIMEOUT: i32 = -30;
+const LIBSSH2_ERROR_EAGAIN: i32 = -37;
+const LIBSSH2_ERROR_SOCKET_RECV: i32 = -43;
+const LIBSSH2_FX_PERMISSION_DENIED: i32 = 3;
+const LIBSSH2_FX_NO_CONNECTION: i32 = 6;
+const LIBSSH2_FX_CONNECTION_LOST: i32 = 7;
+
+/// Whether `err` means the SSH connection has dropped, as opposed to an
+/// ordinary failure such as a missing file or denied permission.
+pub fn is_connection_error(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        if let Some(ssh_err) = cause.downcast_ref::<ssh2::Error>() {
+            return is_dropped_ssh(ssh_err);
+        }
+        if let Some(io_err) = cause.downcast_ref::<std::io::Error>() {
+            return is_dropped_io(io_err);
+        }
+        match cause.downcast_ref::<SftpError>() {
+            Some(SftpError::Ssh(ssh_err)) => is_dropped_ssh(ssh_err),
+            Some(SftpError::Io(io_err)) => is_dropped_io(io_err),
+            _ => false,
+        }
+    })
+}
+
+/// True if `err` was caused by the server or OS refusing access.
+pub fn is_permission_denied(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        if let Some(ssh_err) = cause.downcast_ref::<ssh2::Error>() {
+            return ssh_err.code() == ErrorCode::SFTP(LIBSSH2_FX_PERMISSION_DENIED);
+        }
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|io_err| io_err.kind() == ErrorKind::PermissionDenied)
+    })
+}
+
+fn is_dropped_ssh(err: &ssh2::Error) -> bool {
+    matches!(
+        err.code(),
+        ErrorCode::Session(
+            LIBSSH2_ERROR_SOCKET_SEND
+                | LIBSSH2_ERROR_TIMEOUT
+                | LIBSSH2_ERROR_SOCKET_DISCONNECT
+                | LIBSSH2_ERROR_CHANNEL_CLOSED
+                | LIBSSH2_ERROR_SOCKET_TIMEOUT
+                | LIBSSH2_ERROR_SOCKET_RECV
+        ) | ErrorCode::SFTP(LIBSSH2_FX_NO_CONNECTION | LIBSSH2_FX_CONNECTION_LOST)
+    )
+}
+
+fn is_dropped_io(err: &std::io::Error) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::BrokenPipe
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::UnexpectedEof
+            | ErrorKind::TimedOut
+    )
+}
+
+/// How many times a request is retried unless configured otherwise.
+pub const DEFAULT_RETRIES: u32 = 2;
+
+/// The wait before the first retry; each one after waits twice as long.
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Runs a request that only reads, like a stat or a listing, and runs it
+/// again up to `retries` times while it fails with an error that may go away
+/// by itself, waiting `delay`, then twice that, and so on in between. Any
+/// other error, like a missing file or a refused permission, is returned
+/// straight away.
+pub fn with_retry<T>(
+    retries: u32,
+    delay: Duration,
+    op: impl FnMut() -> Result<T, ssh2::Error>,
+) -> Result<T, ssh2::Error> {
+    retry_while(retries, delay, is_transient, op)
+}
+
+/// Like `with_retry`, for a request that changes the server, like a rename
+/// or a mkdir. Only a request the session was too busy to take is run again:
+/// one whose reply was lost may have been carried out, and sending it again
+/// would report a change that worked as a failure.
+pub fn with_change_retry<T>(
+    retries: u32,
+    delay: Duration,
+    op: impl FnMut() -> Result<T, ssh2::Error>,
+) -> Result<T, ssh2::Error> {
+    retry_while(retries, delay, is_busy, op)
+}
+
+fn retry_while<T>(
+    retries: u32,
+    delay: Duration,
+    retryable: fn(&ssh2::Error) -> bool,
+    mut op: impl FnMut() -> Result<T, ssh2::Error>,
+) -> Result<T, ssh2::Error> {
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(e) if attempt < retries && retryable(&e) => {
+                std::thread::sleep(delay * 2u32.pow(attempt));
+                attempt += 1;
             }
+            result => return result,
         }
+    }
+}
 
-        Ok(())
+/// Whether `err` may go away if the request is sent again: the session
+/// couldn't take it right now, it timed out, or its reply didn't arrive.
+/// When these outlast the retries, they count as a dropped connection.
+fn is_transient(err: &ssh2::Error) -> bool {
+    is_busy(err)
+        || matches!(
+            err.code(),
+            ErrorCode::Session(
+                LIBSSH2_ERROR_TIMEOUT | LIBSSH2_ERROR_SOCKET_TIMEOUT | LIBSSH2_ERROR_SOCKET_RECV
+            )
+        )
+}
+
+/// Whether the session couldn't take the request right now, so nothing
+/// reached the server yet.
+fn is_busy(err: &ssh2::Error) -> bool {
+    err.code() == ErrorCode::Session(LIBSSH2_ERROR_EAGAIN)
+}
+
+/// Runs `op` against `client`. If it fails because the connection dropped,
+/// `reconnect` is called once to replace `client` and `op` is retried.
+///
+/// When reconnecting fails, the original connection error is returned with
+/// the reconnect error attached, so it is still seen as a connection error.
+pub fn with_reconnect<C, T>(
+    client: &mut Arc<C>,
+    reconnect: impl FnOnce() -> Result<C>,
+    mut op: impl FnMut(&C) -> Result<T>,
+) -> Result<T> {
+    match op(client) {
+        Err(e) if is_connection_error(&e) => match reconnect() {
+            Ok(new_client) => {
+                *client = Arc::new(new_client);
+                op(client)
+            }
+            Err(reconnect_err) => Err(e.context(format!("reconnect failed: {reconnect_err}"))),
+        },
+        result => result,
     }
 }
 
+/// Cleans up a remote path without asking the server: `.` components go,
+/// `..` takes away the component before it (and does nothing at `/`), and
+/// trailing or doubled slashes disappear. A relative path keeps the `..`s
+/// it starts with, and an empty result is `.`.
+pub fn normalize_remote_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::RootDir | Component::Prefix(_) => normalized.push("/"),
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                Some(Component::RootDir) => {}
+                _ => normalized.push(".."),
+            },
+            Component::Normal(name) => normalized.push(name),
+        }
+    }
+    if normalized.as_os_str().is_empty() {
+        normalized.push(".");
+    }
+    normalized
+}
+
+/// The directory above a remote path, normalized, or None at `/`.
+pub fn remote_parent(path: &Path) -> Option<PathBuf> {
+    let path = normalize_remote_path(path);
+    match path.components().next_back()? {
+        Component::RootDir => None,
+        Component::Normal(_) => Some(normalize_remote_path(path.parent()?)),
+        // Only a relative path can end up here, at `.` or above it
+        _ => Some(normalize_remote_path(&path.join(".."))),
+    }
+}
+
+/// Quotes a string for a POSIX shell.
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// The hash at the start of `sha256sum` output, lowercased. A leading `\`
+/// only marks an escaped file name.
+fn parse_sha256sum(output: &str) -> Option<String> {
+    let hash = output.split_whitespace().next()?.trim_start_matches('\\');
+    (hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()))
+        .then(|| hash.to_ascii_lowercase())
+}
+
+/// Hex-encoded SHA-256 of everything `reader` yields.
+pub fn sha256_reader<R: Read>(reader: &mut R) -> Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 8192];
+    loop {
+        let bytes_read = reader.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+    }
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn test_host_key_fingerprint() {
+        assert_eq!(host_key_fingerprint(b"foobar"), "SHA256:Zm9vYmFy");
+        assert_eq!(host_key_fingerprint(b"fo"), "SHA256:Zm8");
+        assert_eq!(host_key_fingerprint(b"f"), "SHA256:Zg");
+        // A real SHA-256 is 32 bytes, which is 43 characters unpadded
+        let hash = [0xffu8; 32];
+        assert_eq!(host_key_fingerprint(&hash).len(), "SHA256:".len() + 43);
+    }
+
+    #[test]
+    fn test_host_key_alias_changes_trust_key() -> Result<()> {
+        let mut host = SshHost {
+            host: "db-replica".to_string(),
+            hostname: Some("db.internal".to_string()),
+            port: Some(2222),
+            ..SshHost::default()
+        };
+        assert_eq!(host_key_address(&host)?, "db.internal:2222");
+
+        // A key trusted under the alias is found for every host using it
+        let mut trusted = TrustedHosts::default();
+        trusted.trust("db-cluster", "SHA256:abc");
+        host.host_key_alias = Some("db-cluster".to_string());
+        let address = host_key_address(&host)?;
+        assert_eq!(address, "db-cluster");
+        assert_eq!(trusted.get(&address), Some("SHA256:abc"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_timed_out_message() {
+        let mut host = SshHost::from_spec("web").unwrap();
+        assert_eq!(timed_out(&host).to_string(), "Connection to web timed out");
+
+        host.connect_timeout = Some(5);
+        assert_eq!(
+            timed_out(&host).to_string(),
+            "Connection to web timed out after 5s"
+        );
+    }
+
+    #[test]
+    fn test_connect_tcp_with_timeout() -> Result<()> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let host = SshHost {
+            host: "local".to_string(),
+            hostname: Some("127.0.0.1".to_string()),
+            port: Some(port),
+            connect_timeout: Some(5),
+            ..SshHost::default()
+        };
+
+        let stream = connect_tcp(&host)?;
+        assert_eq!(stream.peer_addr()?.port(), port);
+
+        // Failures keep their meaning when a timeout is set
+        drop((stream, listener));
+        assert!(matches!(
+            connect_tcp(&host),
+            Err(SftpError::ConnectionRefused { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_local_tree() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir_all(dir.path().join("a/b"))?;
+        fs::write(dir.path().join("a/b/c.txt"), "")?;
+
+        let mut visited = Vec::new();
+        walk_local_tree(dir.path(), 2, &mut |entry| {
+            visited.push(match entry {
+                LocalEntry::Directory(path) => format!("{}/", path.display()),
+                LocalEntry::File(path) => path.display().to_string(),
+            });
+            Ok(())
+        })?;
+        assert_eq!(visited, ["a/", "a/b/", "a/b/c.txt"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_stops_at_max_depth() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let deepest: PathBuf = (1..=10).map(|level| format!("d{level}")).collect();
+        fs::create_dir_all(dir.path().join(&deepest))?;
+
+        // Ten levels fit in a limit of ten
+        let mut count = 0;
+        walk_local_tree(dir.path(), 10, &mut |_| {
+            count += 1;
+            Ok(())
+        })?;
+        assert_eq!(count, 10);
+
+        // With nine, the tenth directory is refused before it is visited
+        let mut visited = Vec::new();
+        let error = walk_local_tree(dir.path(), 9, &mut |entry| {
+            visited.push(format!("{entry:?}"));
+            Ok(())
+        })
+        .unwrap_err();
+        let too_deep = error.downcast_ref::<TooDeep>().expect("a TooDeep error");
+        assert_eq!(too_deep.path, dir.path().join(&deepest));
+        assert_eq!(visited.len(), 9);
+
+        Ok(())
+    }
 
     #[test]
     fn test_file_info_creation() {
@@ -394,6 +1595,7 @@ mod tests {
             is_dir: false,
             size: 1024,
             permissions: 0o644,
+            ..FileInfo::default()
         };
 
         assert_eq!(file_info.name, "test.txt");
@@ -411,6 +1613,7 @@ mod tests {
             is_dir: true,
             size: 4096,
             permissions: 0o755,
+            ..FileInfo::default()
         };
 
         assert!(dir_info.is_dir);
@@ -425,6 +1628,7 @@ mod tests {
             is_dir: false,
             size: 2048,
             permissions: 0o644,
+            ..FileInfo::default()
         };
 
         let cloned = original.clone();
@@ -435,6 +1639,58 @@ mod tests {
         assert_eq!(original.permissions, cloned.permissions);
     }
 
+    #[test]
+    fn test_file_info_from_stat() {
+        let stat = FileStat {
+            size: Some(512),
+            uid: Some(1000),
+            gid: Some(50),
+            perm: Some(0o100640),
+            atime: None,
+            mtime: None,
+        };
+        let info = FileInfo::from_stat(PathBuf::from("/srv/app.log"), &stat);
+
+        assert_eq!(info.name, "app.log");
+        assert!(!info.is_dir);
+        assert_eq!(info.size, 512);
+        assert_eq!(info.uid, Some(1000));
+        assert_eq!(info.gid, Some(50));
+        // Remote ids aren't resolved to names
+        assert_eq!(info.owner, None);
+        assert_eq!(info.owner_label(), "1000:50");
+
+        let bare = FileStat {
+            size: None,
+            uid: None,
+            gid: None,
+            perm: Some(0o040755),
+            atime: None,
+            mtime: None,
+        };
+        let info = FileInfo::from_stat(PathBuf::from("/srv/logs"), &bare);
+        assert!(info.is_dir);
+        assert_eq!(info.owner_label(), "");
+
+        // A listed name without permissions is one the server couldn't read
+        let unreadable = FileStat { perm: None, ..bare };
+        let err = FileInfo::from_listing(PathBuf::from("/srv/secret"), &unreadable).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert!(FileInfo::from_listing(PathBuf::from("/srv/app.log"), &stat).is_ok());
+    }
+
+    #[test]
+    fn test_owner_label_prefers_names() {
+        let info = FileInfo {
+            uid: Some(1000),
+            gid: Some(50),
+            owner: Some("alice".to_string()),
+            group: None,
+            ..FileInfo::default()
+        };
+        assert_eq!(info.owner_label(), "alice:50");
+    }
+
     #[test]
     #[cfg(unix)]
     fn test_proxy_jump_config() {
@@ -446,6 +1702,10 @@ mod tests {
             port: Some(22),
             identity_file: None,
             proxy_jump: Some("bastion-host".to_string()),
+            host_key_alias: None,
+            server_alive_interval: None,
+            connect_timeout: None,
+            compression: None,
         };
 
         assert!(host_with_proxy.proxy_jump.is_some());
@@ -462,9 +1722,475 @@ mod tests {
             port: Some(0),
             identity_file: None,
             proxy_jump: None,
+            host_key_alias: None,
+            server_alive_interval: None,
+            connect_timeout: None,
+            compression: None,
         };
 
         // Port 0 is invalid
         assert_eq!(host_config.port.unwrap_or(22), 0);
     }
+
+    #[test]
+    fn test_resume_partial_download() -> Result<()> {
+        let full: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+        let dir = tempfile::tempdir()?;
+        let local_path = dir.path().join("partial.bin");
+        fs::write(&local_path, &full[..5_000])?;
+
+        let offset = resume_offset(&local_path, full.len() as u64);
+        assert_eq!(offset, 5_000);
+
+        let mut remote = std::io::Cursor::new(full.clone());
+        let mut local = fs::OpenOptions::new().append(true).open(&local_path)?;
+        let mut reported = 0;
+        let copied = copy_from_offset(&mut remote, &mut local, offset, &mut |n| {
+            reported += n;
+            Ok(())
+        })?;
+        drop(local);
+
+        assert_eq!(copied, 15_000);
+        assert_eq!(reported, 15_000);
+        assert_eq!(fs::read(&local_path)?, full);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_progress_error_stops_copy() -> Result<()> {
+        let full = vec![7u8; 20_000];
+        let mut reader = std::io::Cursor::new(full);
+        let mut writer = Vec::new();
+        let cancel = AtomicBool::new(false);
+
+        let result = copy_from_offset(&mut reader, &mut writer, 0, &mut |_| {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(anyhow!("cancelled"));
+            }
+            // Cancel as soon as the first chunk is through
+            cancel.store(true, Ordering::Relaxed);
+            Ok(())
+        });
+
+        assert!(result.is_err());
+        // The first chunk is reported and the second stops the copy
+        assert_eq!(writer.len(), 2 * 8192);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resume_offset_requires_smaller_partial() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let local_path = dir.path().join("file.bin");
+
+        // Nothing downloaded yet
+        assert_eq!(resume_offset(&local_path, 100), 0);
+
+        // Local file already complete or larger than the remote
+        fs::write(&local_path, [0u8; 100])?;
+        assert_eq!(resume_offset(&local_path, 100), 0);
+        assert_eq!(resume_offset(&local_path, 50), 0);
+
+        // Directories are never resumed
+        assert_eq!(resume_offset(dir.path(), 100), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sha256_reader() -> Result<()> {
+        assert_eq!(
+            sha256_reader(&mut "abc".as_bytes())?,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        // Larger than one read buffer
+        let data = vec![b'x'; 20_000];
+        let first = sha256_reader(&mut data.as_slice())?;
+        let second = sha256_reader(&mut std::io::Cursor::new(data))?;
+        assert_eq!(first, second);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_connection_error() {
+        let dropped = anyhow::Error::new(ssh2::Error::new(
+            ErrorCode::Session(LIBSSH2_ERROR_SOCKET_RECV),
+            "Failure while reading from socket",
+        ));
+        assert!(is_connection_error(&dropped));
+
+        let lost = anyhow::Error::new(ssh2::Error::new(
+            ErrorCode::SFTP(LIBSSH2_FX_CONNECTION_LOST),
+            "connection lost",
+        ))
+        .context("Listing /home");
+        assert!(is_connection_error(&lost));
+
+        let broken_pipe = anyhow::Error::new(std::io::Error::from(ErrorKind::BrokenPipe));
+        assert!(is_connection_error(&broken_pipe));
+
+        let denied = anyhow::Error::new(ssh2::Error::new(
+            ErrorCode::SFTP(LIBSSH2_FX_PERMISSION_DENIED),
+            "permission denied",
+        ));
+        assert!(!is_connection_error(&denied));
+
+        let missing = anyhow::Error::new(std::io::Error::from(ErrorKind::NotFound));
+        assert!(!is_connection_error(&missing));
+        assert!(!is_connection_error(&anyhow!("Authentication failed")));
+    }
+
+    #[test]
+    fn test_is_permission_denied() {
+        let remote = anyhow::Error::new(ssh2::Error::new(
+            ErrorCode::SFTP(LIBSSH2_FX_PERMISSION_DENIED),
+            "permission denied",
+        ))
+        .context("Listing /root");
+        assert!(is_permission_denied(&remote));
+
+        let local = anyhow::Error::new(std::io::Error::from(ErrorKind::PermissionDenied));
+        assert!(is_permission_denied(&local));
+
+        let missing = anyhow::Error::new(std::io::Error::from(ErrorKind::NotFound));
+        assert!(!is_permission_denied(&missing));
+        assert!(!is_permission_denied(&dropped_connection()));
+    }
+
+    fn dropped_connection() -> anyhow::Error {
+        anyhow::Error::new(std::io::Error::from(ErrorKind::ConnectionReset))
+    }
+
+    #[test]
+    fn test_auth_method_follows_what_was_accepted() {
+        let rejected = || ssh2::Error::new(ErrorCode::Session(-18), "rejected");
+        let with_key = SshHost {
+            host: "web".to_string(),
+            identity_file: Some(PathBuf::from("/keys/web")),
+            ..Default::default()
+        };
+        let without_key = SshHost {
+            identity_file: None,
+            ..with_key.clone()
+        };
+
+        let agent_tried = std::cell::Cell::new(false);
+        let method = try_auth_methods(
+            &with_key,
+            |path| {
+                assert_eq!(path, Path::new("/keys/web"));
+                Ok(())
+            },
+            || {
+                agent_tried.set(true);
+                Ok(())
+            },
+        );
+        assert_eq!(method.unwrap(), AuthMethod::IdentityFile);
+        assert!(!agent_tried.get());
+
+        // A refused key falls back to the agent
+        let method = try_auth_methods(&with_key, |_| Err(rejected()), || Ok(()));
+        assert_eq!(method.unwrap(), AuthMethod::Agent);
+
+        let method = try_auth_methods(
+            &without_key,
+            |_| panic!("no identity file to try"),
+            || Ok(()),
+        );
+        assert_eq!(method.unwrap(), AuthMethod::Agent);
+        assert_eq!(AuthMethod::Agent.to_string(), "agent");
+        assert_eq!(AuthMethod::IdentityFile.to_string(), "publickey");
+
+        let method = try_auth_methods(&with_key, |_| Err(rejected()), || Err(rejected()));
+        assert!(matches!(method, Err(SftpError::AuthFailed(host)) if host == "web"));
+    }
+
+    #[test]
+    fn test_transient_errors() {
+        let session_error = |code| ssh2::Error::new(ErrorCode::Session(code), "");
+        for code in [
+            LIBSSH2_ERROR_EAGAIN,
+            LIBSSH2_ERROR_TIMEOUT,
+            LIBSSH2_ERROR_SOCKET_TIMEOUT,
+            LIBSSH2_ERROR_SOCKET_RECV,
+        ] {
+            assert!(is_transient(&session_error(code)), "{code}");
+        }
+        assert!(!is_transient(&session_error(
+            LIBSSH2_ERROR_SOCKET_DISCONNECT
+        )));
+        assert!(!is_transient(&ssh2::Error::new(
+            ErrorCode::SFTP(LIBSSH2_FX_PERMISSION_DENIED),
+            "permission denied",
+        )));
+    }
+
+    #[test]
+    fn test_with_retry_outlasts_a_flaky_link() -> Result<()> {
+        let busy = || {
+            ssh2::Error::new(
+                ErrorCode::Session(LIBSSH2_ERROR_SOCKET_RECV),
+                "failure while receiving",
+            )
+        };
+        // A server whose replies don't arrive for the first two requests
+        let mut calls = 0;
+        let started = std::time::Instant::now();
+        let result = with_retry(2, Duration::from_millis(5), || {
+            calls += 1;
+            if calls <= 2 {
+                Err(busy())
+            } else {
+                Ok("opened")
+            }
+        })?;
+
+        assert_eq!(result, "opened");
+        assert_eq!(calls, 3);
+        // 5 ms, then twice that
+        assert!(started.elapsed() >= Duration::from_millis(15));
+
+        // One retry too few and the last error comes back
+        let mut calls = 0;
+        let result = with_retry(1, Duration::ZERO, || {
+            calls += 1;
+            if calls <= 2 {
+                Err(busy())
+            } else {
+                Ok("opened")
+            }
+        });
+        assert_eq!(
+            result.unwrap_err().code(),
+            ErrorCode::Session(LIBSSH2_ERROR_SOCKET_RECV)
+        );
+        assert_eq!(calls, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_change_retry_leaves_lost_replies_to_reconnect() {
+        let lost_reply = || {
+            ssh2::Error::new(
+                ErrorCode::Session(LIBSSH2_ERROR_SOCKET_RECV),
+                "failure while receiving",
+            )
+        };
+        // The rename may have gone through, so it isn't sent again
+        let mut calls = 0;
+        let result: Result<(), ssh2::Error> = with_change_retry(3, Duration::ZERO, || {
+            calls += 1;
+            Err(lost_reply())
+        });
+        assert_eq!(calls, 1);
+        let error = anyhow::Error::from(result.unwrap_err());
+        assert!(is_connection_error(&error));
+
+        // A request the session was too busy to take is sent again
+        let mut calls = 0;
+        let result = with_change_retry(3, Duration::ZERO, || {
+            calls += 1;
+            if calls == 1 {
+                Err(ssh2::Error::new(
+                    ErrorCode::Session(LIBSSH2_ERROR_EAGAIN),
+                    "",
+                ))
+            } else {
+                Ok("renamed")
+            }
+        });
+        assert_eq!(result.ok(), Some("renamed"));
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn test_with_retry_gives_up_on_permanent_errors() {
+        let mut calls = 0;
+        let result: Result<(), ssh2::Error> = with_retry(3, Duration::ZERO, || {
+            calls += 1;
+            Err(ssh2::Error::new(
+                ErrorCode::SFTP(LIBSSH2_FX_PERMISSION_DENIED),
+                "permission denied",
+            ))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_with_reconnect_retries_once() -> Result<()> {
+        // Stand-in for a client: its generation number
+        let mut client = Arc::new(1);
+        let mut reconnects = 0;
+
+        let result = with_reconnect(
+            &mut client,
+            || {
+                reconnects += 1;
+                Ok(2)
+            },
+            |generation| {
+                if *generation == 1 {
+                    Err(dropped_connection())
+                } else {
+                    Ok("listed")
+                }
+            },
+        )?;
+
+        assert_eq!(result, "listed");
+        assert_eq!(reconnects, 1);
+        assert_eq!(*client, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_reconnect_ignores_other_errors() {
+        let mut client = Arc::new(1);
+        let mut reconnects = 0;
+
+        let result: Result<()> = with_reconnect(
+            &mut client,
+            || {
+                reconnects += 1;
+                Ok(2)
+            },
+            |_| Err(anyhow!("permission denied")),
+        );
+
+        assert!(result.is_err());
+        assert_eq!(reconnects, 0);
+        assert_eq!(*client, 1);
+    }
+
+    #[test]
+    fn test_with_reconnect_failure_stays_a_connection_error() {
+        let mut client = Arc::new(1);
+        let mut attempts = 0;
+
+        let result: Result<()> = with_reconnect(
+            &mut client,
+            || Err(anyhow!("connection refused")),
+            |_| {
+                attempts += 1;
+                Err(dropped_connection())
+            },
+        );
+
+        let err = result.unwrap_err();
+        assert!(is_connection_error(&err));
+        assert!(err.to_string().contains("connection refused"));
+        // No retry without a new connection
+        assert_eq!(attempts, 1);
+        assert_eq!(*client, 1);
+    }
+
+    #[test]
+    fn test_parse_sha256sum() {
+        let hash = "9F86D081884C7D659A2FEAA0C55AD015A3BF4F1B2B0B822CD15D6C15B0F00A08";
+        assert_eq!(
+            parse_sha256sum(&format!("{hash}  /srv/test.txt\n")),
+            Some(hash.to_ascii_lowercase())
+        );
+        // Names with a backslash or newline are escaped, and the line marked
+        assert_eq!(
+            parse_sha256sum(&format!("\\{hash}  /srv/a\\nb\n")),
+            Some(hash.to_ascii_lowercase())
+        );
+        assert_eq!(parse_sha256sum("sha256sum: not found\n"), None);
+        assert_eq!(parse_sha256sum(""), None);
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn test_remote_parent() {
+        assert_eq!(remote_parent(Path::new("/")), None);
+        assert_eq!(remote_parent(Path::new("/foo")), Some(PathBuf::from("/")));
+        assert_eq!(
+            remote_parent(Path::new("/foo/bar")),
+            Some(PathBuf::from("/foo"))
+        );
+        assert_eq!(
+            remote_parent(Path::new("/foo/bar/")),
+            Some(PathBuf::from("/foo"))
+        );
+        assert_eq!(remote_parent(Path::new("/foo/..")), None);
+        assert_eq!(remote_parent(Path::new("/..")), None);
+        assert_eq!(remote_parent(Path::new("foo")), Some(PathBuf::from(".")));
+        assert_eq!(remote_parent(Path::new(".")), Some(PathBuf::from("..")));
+    }
+
+    #[test]
+    fn test_normalize_remote_path() {
+        assert_eq!(normalize_remote_path(Path::new("/")), Path::new("/"));
+        assert_eq!(
+            normalize_remote_path(Path::new("//srv/./www//")),
+            Path::new("/srv/www")
+        );
+        assert_eq!(
+            normalize_remote_path(Path::new("/srv/www/../log")),
+            Path::new("/srv/log")
+        );
+        // Nothing goes above the root
+        assert_eq!(
+            normalize_remote_path(Path::new("/../../etc")),
+            Path::new("/etc")
+        );
+        assert_eq!(normalize_remote_path(Path::new("a/..")), Path::new("."));
+        assert_eq!(
+            normalize_remote_path(Path::new("../a/../b")),
+            Path::new("../b")
+        );
+    }
+
+    /// A tunnel channel that never has anything to read, like a quiet
+    /// connection.
+    #[cfg(unix)]
+    struct IdleChannel;
+
+    #[cfg(unix)]
+    impl Read for IdleChannel {
+        fn read(&mut self, _buffer: &mut [u8]) -> io::Result<usize> {
+            Err(ErrorKind::WouldBlock.into())
+        }
+    }
+
+    #[cfg(unix)]
+    impl Write for IdleChannel {
+        fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+            Ok(buffer.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_proxy_threads_stop_when_dropped() -> Result<()> {
+        let (sock, peer) = UnixStream::pair()?;
+        sock.set_nonblocking(true)?;
+        let threads = SftpClient::start_proxy_threads(Arc::new(Mutex::new(IdleChannel)), sock)?;
+
+        // With both ends still open, nothing but the drop can end them
+        thread::sleep(Duration::from_millis(50));
+        assert!(threads.handles.iter().all(|handle| !handle.is_finished()));
+
+        let started = std::time::Instant::now();
+        drop(threads);
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        drop(peer);
+        Ok(())
+    }
 }
@@ -0,0 +1,200 @@
+use anyhow::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+use std::io::{self, ErrorKind, Read, Write};
+use std::thread;
+use std::time::Duration;
+
+use crate::sftp::SftpClient;
+
+/// How long to wait for a keypress before checking the shell for output.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Runs an interactive shell on the server in the current terminal until it
+/// exits. The terminal must be out of the interface already; it is put in
+/// raw mode here so every key reaches the remote side, and taken out of it
+/// again however the shell ends.
+pub fn run(client: &SftpClient) -> Result<()> {
+    let (cols, rows) = terminal::size()?;
+    terminal::enable_raw_mode()?;
+    let result = client
+        .open_shell(cols, rows)
+        .and_then(|mut shell| proxy(&mut shell.channel));
+    let restored = terminal::disable_raw_mode();
+    result?;
+    restored?;
+    Ok(())
+}
+
+/// Copies the shell's output to stdout and keypresses to the shell until the
+/// remote side closes the channel.
+fn proxy(channel: &mut ssh2::Channel) -> Result<()> {
+    let mut stdout = io::stdout();
+    let mut buffer = [0; 8192];
+    loop {
+        loop {
+            match channel.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => stdout.write_all(&buffer[..n])?,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        stdout.flush()?;
+        if channel.eof() {
+            return Ok(());
+        }
+
+        if !event::poll(POLL_INTERVAL)? {
+            continue;
+        }
+        match event::read()? {
+            Event::Key(key) if key.kind != KeyEventKind::Release => {
+                if let Some(bytes) = key_bytes(&key) {
+                    write_all(channel, &bytes)?;
+                }
+            }
+            Event::Paste(text) => write_all(channel, text.as_bytes())?,
+            Event::Resize(cols, rows) => {
+                // Best effort: a missed resize only leaves lines wrapped oddly
+                let _ = channel.request_pty_size(cols.into(), rows.into(), None, None);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Writes all of `bytes` to a non-blocking channel.
+fn write_all(channel: &mut ssh2::Channel, mut bytes: &[u8]) -> Result<()> {
+    while !bytes.is_empty() {
+        match channel.write(bytes) {
+            Ok(n) => bytes = &bytes[n..],
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+/// What a terminal would send for `key`, as an xterm does. Keys without an
+/// encoding give None.
+fn key_bytes(key: &KeyEvent) -> Option<Vec<u8>> {
+    let bytes: Vec<u8> = match key.code {
+        KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            match c.to_ascii_lowercase() {
+                c @ 'a'..='z' => vec![c as u8 & 0x1f],
+                ' ' | '@' | '2' => vec![0],
+                '[' | '3' => vec![0x1b],
+                '\\' | '4' => vec![0x1c],
+                ']' | '5' => vec![0x1d],
+                '^' | '6' => vec![0x1e],
+                '_' | '-' | '7' => vec![0x1f],
+                _ => return None,
+            }
+        }
+        KeyCode::Char(c) => c.to_string().into_bytes(),
+        KeyCode::Enter => b"\r".to_vec(),
+        KeyCode::Tab => b"\t".to_vec(),
+        KeyCode::BackTab => b"\x1b[Z".to_vec(),
+        KeyCode::Backspace => vec![0x7f],
+        KeyCode::Esc => vec![0x1b],
+        KeyCode::Up => b"\x1b[A".to_vec(),
+        KeyCode::Down => b"\x1b[B".to_vec(),
+        KeyCode::Right => b"\x1b[C".to_vec(),
+        KeyCode::Left => b"\x1b[D".to_vec(),
+        KeyCode::Home => b"\x1b[H".to_vec(),
+        KeyCode::End => b"\x1b[F".to_vec(),
+        KeyCode::Insert => b"\x1b[2~".to_vec(),
+        KeyCode::Delete => b"\x1b[3~".to_vec(),
+        KeyCode::PageUp => b"\x1b[5~".to_vec(),
+        KeyCode::PageDown => b"\x1b[6~".to_vec(),
+        KeyCode::F(n @ 1..=4) => vec![0x1b, b'O', b'P' + n - 1],
+        KeyCode::F(n @ 5..=12) => {
+            let code = [15, 17, 18, 19, 20, 21, 23, 24][usize::from(n - 5)];
+            format!("\x1b[{code}~").into_bytes()
+        }
+        _ => return None,
+    };
+
+    // Alt sends the key prefixed with Esc
+    if key.modifiers.contains(KeyModifiers::ALT) {
+        let mut prefixed = vec![0x1b];
+        prefixed.extend(bytes);
+        return Some(prefixed);
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(code: KeyCode, modifiers: KeyModifiers) -> Option<Vec<u8>> {
+        key_bytes(&KeyEvent::new(code, modifiers))
+    }
+
+    #[test]
+    fn test_key_bytes() {
+        assert_eq!(
+            bytes(KeyCode::Char('a'), KeyModifiers::NONE),
+            Some(b"a".to_vec())
+        );
+        assert_eq!(
+            bytes(KeyCode::Char('é'), KeyModifiers::NONE),
+            Some("é".as_bytes().to_vec())
+        );
+        assert_eq!(
+            bytes(KeyCode::Enter, KeyModifiers::NONE),
+            Some(b"\r".to_vec())
+        );
+        assert_eq!(
+            bytes(KeyCode::Backspace, KeyModifiers::NONE),
+            Some(vec![0x7f])
+        );
+        assert_eq!(
+            bytes(KeyCode::Up, KeyModifiers::NONE),
+            Some(b"\x1b[A".to_vec())
+        );
+        assert_eq!(
+            bytes(KeyCode::F(1), KeyModifiers::NONE),
+            Some(b"\x1bOP".to_vec())
+        );
+        assert_eq!(
+            bytes(KeyCode::F(5), KeyModifiers::NONE),
+            Some(b"\x1b[15~".to_vec())
+        );
+        assert_eq!(
+            bytes(KeyCode::F(12), KeyModifiers::NONE),
+            Some(b"\x1b[24~".to_vec())
+        );
+        assert_eq!(bytes(KeyCode::Null, KeyModifiers::NONE), None);
+    }
+
+    #[test]
+    fn test_key_bytes_with_modifiers() {
+        assert_eq!(
+            bytes(KeyCode::Char('c'), KeyModifiers::CONTROL),
+            Some(vec![3])
+        );
+        assert_eq!(
+            bytes(KeyCode::Char('D'), KeyModifiers::CONTROL),
+            Some(vec![4])
+        );
+        assert_eq!(
+            bytes(KeyCode::Char('['), KeyModifiers::CONTROL),
+            Some(vec![0x1b])
+        );
+        assert_eq!(bytes(KeyCode::Char('1'), KeyModifiers::CONTROL), None);
+        assert_eq!(
+            bytes(KeyCode::Char('b'), KeyModifiers::ALT),
+            Some(b"\x1bb".to_vec())
+        );
+        assert_eq!(
+            bytes(
+                KeyCode::Char('x'),
+                KeyModifiers::CONTROL | KeyModifiers::ALT
+            ),
+            Some(vec![0x1b, 0x18])
+        );
+    }
+}
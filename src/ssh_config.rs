@@ -1,10 +1,13 @@
 use anyhow::{Result, anyhow};
 use regex::Regex;
 use std::collections::HashSet;
+use std::env;
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct SshHost {
     pub host: String,
     pub hostname: Option<String>,
@@ -12,39 +15,381 @@ pub struct SshHost {
     pub port: Option<u16>,
     pub identity_file: Option<PathBuf>,
     pub proxy_jump: Option<String>,
+    /// Name the host key is trusted under instead of `hostname:port`, so
+    /// hosts sharing a key (or one host behind several names) match.
+    pub host_key_alias: Option<String>,
+    /// Seconds between keepalives (`ServerAliveInterval`), overriding the
+    /// app's `keepalive_interval`; 0 disables them.
+    pub server_alive_interval: Option<u32>,
+    /// Seconds to wait for the TCP connection and SSH handshake
+    /// (`ConnectTimeout`).
+    pub connect_timeout: Option<u64>,
+    /// Whether to ask the server for compression (`Compression`).
+    pub compression: Option<bool>,
+}
+
+impl SshHost {
+    /// The `ProxyJump` hops in the order they are connected through. A
+    /// comma-separated list chains several jump hosts; `none` means no hops.
+    pub fn proxy_jumps(&self) -> Vec<String> {
+        match self.proxy_jump.as_deref().map(str::trim) {
+            None | Some("none") => Vec::new(),
+            Some(value) => value
+                .split(',')
+                .map(str::trim)
+                .filter(|hop| !hop.is_empty())
+                .map(String::from)
+                .collect(),
+        }
+    }
+
+    /// Seconds between keepalives: `ServerAliveInterval` if set, else
+    /// `default`.
+    pub fn keepalive_interval(&self, default: u32) -> u32 {
+        self.server_alive_interval.unwrap_or(default)
+    }
+
+    /// Whether to ask for compression: `Compression` if set, else `default`.
+    pub fn compression(&self, default: bool) -> bool {
+        self.compression.unwrap_or(default)
+    }
+
+    /// How long to wait for the server to connect and complete the handshake.
+    /// `None` waits as long as the OS does; so does `ConnectTimeout 0`.
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout
+            .filter(|&seconds| seconds > 0)
+            .map(Duration::from_secs)
+    }
+
+    /// The `ssh` command that reaches this host as resolved, for when it's
+    /// needed outside sftui. The default port is left out.
+    pub fn ssh_command(&self) -> String {
+        let mut args = vec!["ssh".to_string()];
+        if let Some(port) = self.port.filter(|&port| port != 22) {
+            args.extend(["-p".to_string(), port.to_string()]);
+        }
+        if let Some(identity) = &self.identity_file {
+            args.extend(["-i".to_string(), shell_word(&identity.to_string_lossy())]);
+        }
+        let jumps = self.proxy_jumps();
+        if !jumps.is_empty() {
+            args.extend(["-J".to_string(), shell_word(&jumps.join(","))]);
+        }
+        let hostname = self.hostname.as_deref().unwrap_or(&self.host);
+        args.push(shell_word(&match &self.user {
+            Some(user) => format!("{user}@{hostname}"),
+            None => hostname.to_string(),
+        }));
+        args.join(" ")
+    }
+
+    /// Parses a free-form `[user@]hostname[:port]` spec into an ad-hoc host entry.
+    ///
+    /// The user defaults to the OS username and the port to 22. IPv6 addresses
+    /// must be bracketed when a port is given, e.g. `user@[::1]:2222`.
+    pub fn from_spec(spec: &str) -> Result<Self> {
+        let spec = spec.trim();
+        if spec.is_empty() {
+            return Err(anyhow!("Host cannot be empty"));
+        }
+
+        let (user, rest) = split_user(spec)?;
+        let user = user.map(str::to_string).or_else(default_username);
+        let (hostname, port) = split_port(rest)?;
+
+        Ok(SshHost {
+            host: hostname.to_string(),
+            hostname: Some(hostname.to_string()),
+            user,
+            port: Some(port.unwrap_or(22)),
+            ..Default::default()
+        })
+    }
+}
+
+/// `word` as a single shell word, quoted only if it has to be. A leading `~`
+/// stays unquoted so the shell still expands it.
+fn shell_word(word: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "@%+=:,./_~-".contains(c);
+    if !word.is_empty() && word.chars().all(plain) {
+        word.to_string()
+    } else if let Some(rest) = word.strip_prefix("~/") {
+        format!("~/{}", shell_word(rest))
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+/// Splits an optional `user@` prefix off `[user@]host`. Neither side of the
+/// `@` may be empty.
+pub fn split_user(spec: &str) -> Result<(Option<&str>, &str)> {
+    match spec.rsplit_once('@') {
+        Some(("", _)) => Err(anyhow!("User cannot be empty before '@'")),
+        Some((_, "")) => Err(anyhow!("Host cannot be empty after '@'")),
+        Some((user, host)) => Ok((Some(user), host)),
+        None => Ok((None, spec)),
+    }
+}
+
+/// Splits an optional `:port` suffix off `hostname[:port]`. IPv6 addresses
+/// must be bracketed when a port is given, e.g. `[::1]:2222`.
+pub fn split_port(spec: &str) -> Result<(&str, Option<u16>)> {
+    let (hostname, port) = if let Some(bracketed) = spec.strip_prefix('[') {
+        let (host, after) = bracketed
+            .split_once(']')
+            .ok_or_else(|| anyhow!("Missing closing ']' in '{}'", spec))?;
+        match after {
+            "" => (host, None),
+            _ => match after.strip_prefix(':') {
+                Some(port) => (host, Some(port)),
+                None => return Err(anyhow!("Unexpected '{}' after ']'", after)),
+            },
+        }
+    } else if spec.matches(':').count() > 1 {
+        // Bare IPv6 address without a port
+        (spec, None)
+    } else {
+        match spec.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (spec, None),
+        }
+    };
+
+    if hostname.is_empty() {
+        return Err(anyhow!("Hostname cannot be empty"));
+    }
+
+    let port = match port {
+        Some(port) => match port.parse::<u16>() {
+            Ok(port) if port != 0 => Some(port),
+            _ => return Err(anyhow!("Invalid port '{}'", port)),
+        },
+        None => None,
+    };
+
+    Ok((hostname, port))
+}
+
+/// Parses the criteria of a `Match` line. Arguments may be quoted, as with
+/// `exec "test -f ~/.vpn"`.
+fn parse_match_criteria(value: &str) -> Vec<MatchCriterion> {
+    let mut words = split_quoted(value).into_iter();
+    let mut criteria = Vec::new();
+    while let Some(keyword) = words.next() {
+        let keyword = keyword.to_lowercase();
+        let criterion = match keyword.as_str() {
+            "all" => MatchCriterion::All,
+            // These take no argument
+            "canonical" | "final" => MatchCriterion::Unsupported(keyword),
+            _ => {
+                let argument = words.next().unwrap_or_default();
+                match keyword.as_str() {
+                    "host" => MatchCriterion::Host(argument),
+                    "originalhost" => MatchCriterion::OriginalHost(argument),
+                    "user" => MatchCriterion::User(argument),
+                    _ => MatchCriterion::Unsupported(keyword),
+                }
+            }
+        };
+        criteria.push(criterion);
+    }
+    criteria
+}
+
+/// Splits on whitespace, keeping double-quoted runs together.
+fn split_quoted(value: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_quotes = false;
+    for c in value.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            c if c.is_whitespace() && !in_quotes => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// Expands OpenSSH's `%` tokens in the settings that take them, as OpenSSH
+/// does once the config has been read. `HostName` only knows `%h`, the name
+/// that was looked up; `IdentityFile` also gets `%p` and `%r`, with `%h`
+/// meaning the expanded `HostName`.
+fn expand_host_tokens(mut host: SshHost) -> SshHost {
+    let original = host.host.clone();
+    host.hostname = host
+        .hostname
+        .map(|hostname| expand_tokens(&hostname, &[('h', &original)]));
+
+    let hostname = host.hostname.clone().unwrap_or_else(|| original.clone());
+    let port = host.port.unwrap_or(22).to_string();
+    let mut tokens = vec![('h', hostname.as_str()), ('p', port.as_str())];
+    if let Some(user) = &host.user {
+        tokens.push(('r', user));
+    }
+    host.identity_file = host
+        .identity_file
+        .map(|path| PathBuf::from(expand_tokens(&path.to_string_lossy(), &tokens)));
+
+    host
+}
+
+/// Replaces `%x` for each `(x, value)` in `tokens`, and `%%` with `%`.
+/// Unknown tokens are left as written.
+fn expand_tokens(value: &str, tokens: &[(char, &str)]) -> String {
+    let mut expanded = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => expanded.push('%'),
+            Some(token) => match tokens.iter().find(|(name, _)| *name == token) {
+                Some((_, replacement)) => expanded.push_str(replacement),
+                None => {
+                    expanded.push('%');
+                    expanded.push(token);
+                }
+            },
+            None => expanded.push('%'),
+        }
+    }
+    expanded
+}
+
+fn default_username() -> Option<String> {
+    env::var("USER")
+        .or_else(|_| env::var("USERNAME"))
+        .ok()
+        .filter(|user| !user.is_empty())
 }
 
 #[derive(Debug)]
 struct SshConfigEntry {
-    patterns: Vec<String>,
+    /// One per pattern of a `Host` line, or the single `Match` condition.
+    conditions: Vec<BlockCondition>,
     hostname: Option<String>,
     user: Option<String>,
     port: Option<u16>,
     identity_file: Option<PathBuf>,
     proxy_jump: Option<String>,
+    host_key_alias: Option<String>,
+    server_alive_interval: Option<u32>,
+    connect_timeout: Option<u64>,
+    compression: Option<bool>,
+}
+
+/// One condition of a `Match` line. Pattern lists are comma-separated and
+/// may contain negated patterns, as in ssh_config(5).
+#[derive(Debug, Clone, PartialEq)]
+enum MatchCriterion {
+    All,
+    /// The hostname being connected to, after `HostName` is applied.
+    Host(String),
+    /// The name given on the command line.
+    OriginalHost(String),
+    /// The remote user.
+    User(String),
+    /// A criterion sftui can't evaluate, such as `exec`. It is left out.
+    Unsupported(String),
+}
+
+/// A `Host` pattern or `Match` block with its settings, in file order.
+#[derive(Debug, Clone)]
+struct ConfigBlock {
+    condition: BlockCondition,
+    settings: SshHost,
+}
+
+#[derive(Debug, Clone)]
+enum BlockCondition {
+    /// One of a `Host` line's patterns.
+    Host(String),
+    /// A `Match` line's criteria, all of which must hold.
+    Match(Vec<MatchCriterion>),
 }
 
+impl fmt::Display for BlockCondition {
+    /// The block's header as it would appear in the file, e.g. `Host *.dev`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockCondition::Host(pattern) => write!(f, "Host {pattern}"),
+            BlockCondition::Match(criteria) => {
+                write!(f, "Match")?;
+                for criterion in criteria {
+                    match criterion {
+                        MatchCriterion::All => write!(f, " all")?,
+                        MatchCriterion::Host(patterns) => write!(f, " host {patterns}")?,
+                        MatchCriterion::OriginalHost(patterns) => {
+                            write!(f, " originalhost {patterns}")?
+                        }
+                        MatchCriterion::User(patterns) => write!(f, " user {patterns}")?,
+                        MatchCriterion::Unsupported(keyword) => write!(f, " {keyword}")?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Clone, Default)]
 pub struct SshConfig {
-    hosts: Vec<SshHost>,
+    blocks: Vec<ConfigBlock>,
+    /// Why the config couldn't be loaded, when it was left empty instead of
+    /// failing.
+    warning: Option<String>,
 }
 
 impl SshConfig {
+    /// Loads `~/.ssh/config`, or an empty config if it doesn't exist.
     pub fn new() -> Result<Self> {
-        let config_path = dirs::home_dir()
-            .ok_or_else(|| anyhow!("Cannot find home directory"))?
-            .join(".ssh")
-            .join("config");
+        Self::in_home(dirs::home_dir().as_deref())
+    }
 
-        let mut ssh_config = SshConfig { hosts: Vec::new() };
+    /// Loads `.ssh/config` under `home`. Without a home directory there is
+    /// nothing to load, so the config is empty and `warning` says why;
+    /// custom hosts still work.
+    fn in_home(home: Option<&Path>) -> Result<Self> {
+        let Some(home) = home else {
+            return Ok(SshConfig {
+                warning: Some(
+                    "Can't find your home directory, so ~/.ssh/config wasn't read".to_string(),
+                ),
+                ..SshConfig::default()
+            });
+        };
+        let config_path = home.join(".ssh").join("config");
 
         if config_path.exists() {
-            ssh_config.parse_config(&config_path)?;
+            Self::from_path(&config_path)
+        } else {
+            Ok(SshConfig::default())
         }
+    }
+
+    pub fn warning(&self) -> Option<&str> {
+        self.warning.as_deref()
+    }
 
+    /// Loads an explicit config file, which must exist.
+    pub fn from_path(config_path: &Path) -> Result<Self> {
+        let mut ssh_config = SshConfig::default();
+        ssh_config.parse_config(config_path)?;
         Ok(ssh_config)
     }
 
-    pub(crate) fn parse_config(&mut self, config_path: &PathBuf) -> Result<()> {
+    pub(crate) fn parse_config(&mut self, config_path: &Path) -> Result<()> {
         let content = fs::read_to_string(config_path)?;
         let mut current_entry: Option<SshConfigEntry> = None;
 
@@ -62,32 +407,38 @@ impl SshConfig {
             let key = parts[0].to_lowercase();
             let value = parts[1..].join(" ");
 
-            match key.as_str() {
-                "host" => {
-                    if let Some(entry) = current_entry.take() {
-                        // Convert entry to hosts
-                        for pattern in entry.patterns {
-                            self.hosts.push(SshHost {
-                                host: pattern,
-                                hostname: entry.hostname.clone(),
-                                user: entry.user.clone(),
-                                port: entry.port,
-                                identity_file: entry.identity_file.clone(),
-                                proxy_jump: entry.proxy_jump.clone(),
-                            });
-                        }
-                    }
-                    let patterns: Vec<String> =
-                        value.split_whitespace().map(|s| s.to_string()).collect();
-                    current_entry = Some(SshConfigEntry {
-                        patterns,
-                        hostname: None,
-                        user: None,
-                        port: None,
-                        identity_file: None,
-                        proxy_jump: None,
-                    });
+            let conditions = match key.as_str() {
+                "host" => Some(
+                    value
+                        .split_whitespace()
+                        .map(|pattern| BlockCondition::Host(pattern.to_string()))
+                        .collect(),
+                ),
+                "match" => Some(vec![BlockCondition::Match(parse_match_criteria(
+                    line[parts[0].len()..].trim(),
+                ))]),
+                _ => None,
+            };
+            if let Some(conditions) = conditions {
+                if let Some(entry) = current_entry.take() {
+                    self.push_entry(entry);
                 }
+                current_entry = Some(SshConfigEntry {
+                    conditions,
+                    hostname: None,
+                    user: None,
+                    port: None,
+                    identity_file: None,
+                    proxy_jump: None,
+                    host_key_alias: None,
+                    server_alive_interval: None,
+                    connect_timeout: None,
+                    compression: None,
+                });
+                continue;
+            }
+
+            match key.as_str() {
                 "hostname" => {
                     if let Some(ref mut entry) = current_entry {
                         entry.hostname = Some(value);
@@ -115,65 +466,220 @@ impl SshConfig {
                         entry.proxy_jump = Some(value);
                     }
                 }
+                "hostkeyalias" => {
+                    if let Some(ref mut entry) = current_entry {
+                        entry.host_key_alias = Some(value);
+                    }
+                }
+                "serveraliveinterval" => {
+                    if let Some(ref mut entry) = current_entry
+                        && let Ok(seconds) = value.parse::<u32>()
+                    {
+                        entry.server_alive_interval = Some(seconds);
+                    }
+                }
+                "connecttimeout" => {
+                    if let Some(ref mut entry) = current_entry
+                        && let Ok(seconds) = value.parse::<u64>()
+                    {
+                        entry.connect_timeout = Some(seconds);
+                    }
+                }
+                "compression" => {
+                    if let Some(ref mut entry) = current_entry {
+                        match value.to_lowercase().as_str() {
+                            "yes" => entry.compression = Some(true),
+                            "no" => entry.compression = Some(false),
+                            _ => {}
+                        }
+                    }
+                }
                 _ => {}
             }
         }
 
         if let Some(entry) = current_entry {
-            // Convert entry to hosts
-            for pattern in entry.patterns {
-                self.hosts.push(SshHost {
-                    host: pattern,
-                    hostname: entry.hostname.clone(),
-                    user: entry.user.clone(),
-                    port: entry.port,
-                    identity_file: entry.identity_file.clone(),
-                    proxy_jump: entry.proxy_jump.clone(),
-                });
-            }
+            self.push_entry(entry);
         }
 
         Ok(())
     }
 
+    /// Adds a parsed entry: one block per `Host` pattern, or a single block
+    /// for a `Match` line.
+    fn push_entry(&mut self, entry: SshConfigEntry) {
+        let settings = |host: &str| SshHost {
+            host: host.to_string(),
+            hostname: entry.hostname.clone(),
+            user: entry.user.clone(),
+            port: entry.port,
+            identity_file: entry.identity_file.clone(),
+            proxy_jump: entry.proxy_jump.clone(),
+            host_key_alias: entry.host_key_alias.clone(),
+            server_alive_interval: entry.server_alive_interval,
+            connect_timeout: entry.connect_timeout,
+            compression: entry.compression,
+        };
+
+        for condition in &entry.conditions {
+            let host = match condition {
+                BlockCondition::Host(pattern) => pattern.as_str(),
+                BlockCondition::Match(_) => "",
+            };
+            self.blocks.push(ConfigBlock {
+                condition: condition.clone(),
+                settings: settings(host),
+            });
+        }
+    }
+
+    /// Resolves a `[user@]host[:port]` spec typed on the command line or in
+    /// the connection dialog. `host` picks up its ssh_config settings as usual;
+    /// a user or port given inline overrides them.
+    pub fn host_for_spec(&self, spec: &str) -> Result<SshHost> {
+        let spec = spec.trim();
+        let (user, rest) = split_user(spec)?;
+        let (name, port) = split_port(rest)?;
+
+        let Some(mut host) = self.get_host(name) else {
+            return SshHost::from_spec(spec);
+        };
+        if let Some(user) = user {
+            host.user = Some(user.to_string());
+        }
+        if port.is_some() {
+            host.port = port;
+        }
+        // Like ssh, log in as the local user when no block names one
+        if host.user.is_none() {
+            host.user = default_username();
+        }
+        Ok(host)
+    }
+
     pub fn get_host(&self, name: &str) -> Option<SshHost> {
+        self.resolve(name).0
+    }
+
+    /// The headers of the blocks whose settings `name` picks up, in file
+    /// order, e.g. `["Host web*", "Host *"]`.
+    pub fn matched_blocks(&self, name: &str) -> Vec<String> {
+        self.resolve(name).1
+    }
+
+    fn resolve(&self, name: &str) -> (Option<SshHost>, Vec<String>) {
         // SSH config merges all matching patterns, with first-match-wins for each property
-        let mut merged = None;
-
-        for host in &self.hosts {
-            if self.pattern_matches(&host.host, name) {
-                if merged.is_none() {
-                    // First matching pattern, use it as base
-                    merged = Some(SshHost {
-                        host: name.to_string(), // Use the actual hostname, not the pattern
-                        hostname: host.hostname.clone(),
-                        user: host.user.clone(),
-                        port: host.port,
-                        identity_file: host.identity_file.clone(),
-                        proxy_jump: host.proxy_jump.clone(),
-                    });
-                } else if let Some(ref mut m) = merged {
-                    // Merge subsequent matches, only filling in missing values
-                    if m.hostname.is_none() && host.hostname.is_some() {
-                        m.hostname = host.hostname.clone();
-                    }
-                    if m.user.is_none() && host.user.is_some() {
-                        m.user = host.user.clone();
-                    }
-                    if m.port.is_none() && host.port.is_some() {
-                        m.port = host.port;
-                    }
-                    if m.identity_file.is_none() && host.identity_file.is_some() {
-                        m.identity_file = host.identity_file.clone();
-                    }
-                    if m.proxy_jump.is_none() && host.proxy_jump.is_some() {
-                        m.proxy_jump = host.proxy_jump.clone();
-                    }
+        let mut merged: Option<SshHost> = None;
+        let mut matched = Vec::new();
+
+        for block in &self.blocks {
+            let applies = match &block.condition {
+                BlockCondition::Host(pattern) => self.pattern_matches(pattern, name),
+                BlockCondition::Match(criteria) => {
+                    self.match_applies(criteria, name, merged.as_ref())
+                }
+            };
+            if !applies {
+                continue;
+            }
+
+            matched.push(block.condition.to_string());
+            let host = &block.settings;
+            if merged.is_none() {
+                // First matching block, use it as base
+                merged = Some(SshHost {
+                    host: name.to_string(), // Use the actual hostname, not the pattern
+                    hostname: host.hostname.clone(),
+                    user: host.user.clone(),
+                    port: host.port,
+                    identity_file: host.identity_file.clone(),
+                    proxy_jump: host.proxy_jump.clone(),
+                    host_key_alias: host.host_key_alias.clone(),
+                    server_alive_interval: host.server_alive_interval,
+                    connect_timeout: host.connect_timeout,
+                    compression: host.compression,
+                });
+            } else if let Some(ref mut m) = merged {
+                // Merge subsequent matches, only filling in missing values
+                if m.hostname.is_none() && host.hostname.is_some() {
+                    m.hostname = host.hostname.clone();
+                }
+                if m.user.is_none() && host.user.is_some() {
+                    m.user = host.user.clone();
+                }
+                if m.port.is_none() && host.port.is_some() {
+                    m.port = host.port;
+                }
+                if m.identity_file.is_none() && host.identity_file.is_some() {
+                    m.identity_file = host.identity_file.clone();
+                }
+                if m.proxy_jump.is_none() && host.proxy_jump.is_some() {
+                    m.proxy_jump = host.proxy_jump.clone();
+                }
+                if m.host_key_alias.is_none() && host.host_key_alias.is_some() {
+                    m.host_key_alias = host.host_key_alias.clone();
+                }
+                if m.server_alive_interval.is_none() && host.server_alive_interval.is_some() {
+                    m.server_alive_interval = host.server_alive_interval;
+                }
+                if m.connect_timeout.is_none() && host.connect_timeout.is_some() {
+                    m.connect_timeout = host.connect_timeout;
+                }
+                if m.compression.is_none() && host.compression.is_some() {
+                    m.compression = host.compression;
                 }
             }
         }
 
-        merged
+        (merged.map(expand_host_tokens), matched)
+    }
+
+    /// Whether a `Match` block applies to `name`, given the settings gathered
+    /// from the blocks above it. Unsupported criteria are skipped, but a block
+    /// with nothing else to go on never applies.
+    fn match_applies(
+        &self,
+        criteria: &[MatchCriterion],
+        name: &str,
+        merged: Option<&SshHost>,
+    ) -> bool {
+        let mut supported = criteria
+            .iter()
+            .filter(|criterion| !matches!(criterion, MatchCriterion::Unsupported(_)))
+            .peekable();
+        if supported.peek().is_none() {
+            return false;
+        }
+
+        supported.all(|criterion| match criterion {
+            MatchCriterion::All => true,
+            MatchCriterion::Host(patterns) => {
+                let hostname = merged
+                    .and_then(|m| m.hostname.as_deref())
+                    .map_or_else(|| name.to_string(), |h| expand_tokens(h, &[('h', name)]));
+                self.pattern_list_matches(patterns, &hostname)
+            }
+            MatchCriterion::OriginalHost(patterns) => self.pattern_list_matches(patterns, name),
+            MatchCriterion::User(patterns) => merged
+                .and_then(|m| m.user.clone())
+                .or_else(default_username)
+                .is_some_and(|user| self.pattern_list_matches(patterns, &user)),
+            MatchCriterion::Unsupported(_) => true,
+        })
+    }
+
+    /// Matches a comma-separated pattern list: any negated pattern that
+    /// matches rules the value out, otherwise one plain pattern must match.
+    fn pattern_list_matches(&self, patterns: &str, value: &str) -> bool {
+        let mut matched = false;
+        for pattern in patterns.split(',').map(str::trim) {
+            match pattern.strip_prefix('!') {
+                Some(negated) if self.pattern_matches(negated, value) => return false,
+                Some(_) => {}
+                None => matched |= self.pattern_matches(pattern, value),
+            }
+        }
+        matched
     }
 
     pub fn get_all_hosts(&self) -> Vec<SshHost> {
@@ -181,7 +687,14 @@ impl SshConfig {
         let mut seen = HashSet::new();
 
         // First, collect all concrete (non-wildcard) host names
-        for host in &self.hosts {
+        for host in self
+            .blocks
+            .iter()
+            .filter_map(|block| match &block.condition {
+                BlockCondition::Host(_) => Some(&block.settings),
+                BlockCondition::Match(_) => None,
+            })
+        {
             if !host.host.contains('*')
                 && !host.host.contains('?')
                 && !host.host.contains('!')
@@ -243,12 +756,35 @@ mod tests {
         let mut file = NamedTempFile::new()?;
         write!(file, "{content}")?;
 
-        let mut config = SshConfig { hosts: Vec::new() };
-        config.parse_config(&file.path().to_path_buf())?;
+        let mut config = SshConfig::default();
+        config.parse_config(file.path())?;
 
         Ok(config)
     }
 
+    #[test]
+    fn test_from_path() -> Result<()> {
+        let mut file = NamedTempFile::new()?;
+        write!(
+            file,
+            r#"
+Host staging
+    HostName staging.example.com
+    User deploy
+"#
+        )?;
+
+        let config = SshConfig::from_path(file.path())?;
+        let host = config.get_host("staging").unwrap();
+        assert_eq!(host.hostname, Some("staging.example.com".to_string()));
+        assert_eq!(host.user, Some("deploy".to_string()));
+
+        // An explicitly requested file has to exist
+        assert!(SshConfig::from_path(&file.path().with_extension("missing")).is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn test_exact_match() -> Result<()> {
         let config = create_test_config(
@@ -319,6 +855,42 @@ Host *.example.com
         Ok(())
     }
 
+    #[test]
+    fn test_matched_blocks() -> Result<()> {
+        let config = create_test_config(
+            r#"
+Host prod-*
+    User deploy
+
+Host *.internal
+    ProxyJump bastion
+
+Match originalhost prod-* user deploy
+    Port 2222
+
+Host *
+    IdentityFile ~/.ssh/id_ed25519
+"#,
+        )?;
+
+        assert_eq!(
+            config.matched_blocks("prod-db.internal"),
+            [
+                "Host prod-*",
+                "Host *.internal",
+                "Match originalhost prod-* user deploy",
+                "Host *"
+            ]
+        );
+        assert_eq!(
+            config.matched_blocks("dev.internal"),
+            ["Host *.internal", "Host *"]
+        );
+        assert!(SshConfig::default().matched_blocks("anything").is_empty());
+
+        Ok(())
+    }
+
     #[test]
     fn test_wildcard_question_mark() -> Result<()> {
         let config = create_test_config(
@@ -335,12 +907,12 @@ Host server??
 
         // Single character wildcard
         let host = config.get_host("server1").unwrap();
-        assert_eq!(host.hostname, Some("10.0.0.%h".to_string()));
+        assert_eq!(host.hostname, Some("10.0.0.server1".to_string()));
         assert_eq!(host.user, Some("admin".to_string()));
 
         // Two character wildcard
         let host = config.get_host("server10").unwrap();
-        assert_eq!(host.hostname, Some("10.1.0.%h".to_string()));
+        assert_eq!(host.hostname, Some("10.1.0.server10".to_string()));
         assert_eq!(host.user, Some("superadmin".to_string()));
 
         // No match - too many characters
@@ -462,13 +1034,16 @@ Host *-db-*
 
         // Should match most specific pattern first
         let host = config.get_host("prod-db-master").unwrap();
-        assert_eq!(host.hostname, Some("%h.database.internal".to_string()));
+        assert_eq!(
+            host.hostname,
+            Some("prod-db-master.database.internal".to_string())
+        );
         assert_eq!(host.user, Some("dbadmin".to_string()));
         assert_eq!(host.port, Some(5432));
 
         // Should match prod-* pattern
         let host = config.get_host("prod-web").unwrap();
-        assert_eq!(host.hostname, Some("%h.prod.internal".to_string()));
+        assert_eq!(host.hostname, Some("prod-web.prod.internal".to_string()));
         assert_eq!(host.user, Some("produser".to_string()));
         assert_eq!(host.port, Some(22));
 
@@ -593,4 +1168,476 @@ Host env-app
 
         Ok(())
     }
+
+    #[test]
+    fn test_proxy_jump_chain() -> Result<()> {
+        let config = create_test_config(
+            r#"
+Host inner
+    HostName 10.0.0.2
+    ProxyJump a, b
+
+Host direct
+    HostName 10.0.0.3
+    ProxyJump none
+"#,
+        )?;
+
+        let inner = config.get_host("inner").unwrap();
+        assert_eq!(inner.proxy_jumps(), vec!["a", "b"]);
+        assert!(config.get_host("direct").unwrap().proxy_jumps().is_empty());
+        assert!(SshHost::default().proxy_jumps().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_host_spec_full() -> Result<()> {
+        let host = SshHost::from_spec("deploy@example.com:2222")?;
+        assert_eq!(host.host, "example.com");
+        assert_eq!(host.hostname, Some("example.com".to_string()));
+        assert_eq!(host.user, Some("deploy".to_string()));
+        assert_eq!(host.port, Some(2222));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_host_spec_defaults() -> Result<()> {
+        let host = SshHost::from_spec("  example.com ")?;
+        assert_eq!(host.hostname, Some("example.com".to_string()));
+        assert_eq!(host.user, default_username());
+        assert_eq!(host.port, Some(22));
+
+        let host = SshHost::from_spec("root@10.0.0.1")?;
+        assert_eq!(host.user, Some("root".to_string()));
+        assert_eq!(host.port, Some(22));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_host_spec_ipv6() -> Result<()> {
+        let host = SshHost::from_spec("admin@[::1]:2200")?;
+        assert_eq!(host.hostname, Some("::1".to_string()));
+        assert_eq!(host.port, Some(2200));
+
+        let host = SshHost::from_spec("fe80::1")?;
+        assert_eq!(host.hostname, Some("fe80::1".to_string()));
+        assert_eq!(host.port, Some(22));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_host_spec_errors() {
+        assert!(SshHost::from_spec("").is_err());
+        assert!(SshHost::from_spec("@example.com").is_err());
+        assert!(SshHost::from_spec("user@").is_err());
+        assert!(SshHost::from_spec("example.com:").is_err());
+        assert!(SshHost::from_spec("example.com:abc").is_err());
+        assert!(SshHost::from_spec("example.com:0").is_err());
+        assert!(SshHost::from_spec("example.com:70000").is_err());
+        assert!(SshHost::from_spec("[::1").is_err());
+    }
+
+    #[test]
+    fn test_split_user() -> Result<()> {
+        assert_eq!(split_user("deploy@1.2.3.4")?, (Some("deploy"), "1.2.3.4"));
+        assert_eq!(split_user("1.2.3.4")?, (None, "1.2.3.4"));
+        // Only the last '@' separates the host
+        assert_eq!(split_user("me@corp@bastion")?, (Some("me@corp"), "bastion"));
+        assert!(split_user("@1.2.3.4").is_err());
+        assert!(split_user("deploy@").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_host_for_spec() -> Result<()> {
+        let config = create_test_config(
+            r#"
+Host web
+    HostName 10.0.0.5
+    User www
+    Port 2200
+    IdentityFile ~/.ssh/web
+"#,
+        )?;
+
+        // The inline user wins; everything else still comes from the config
+        let host = config.host_for_spec("admin@web")?;
+        assert_eq!(host.user, Some("admin".to_string()));
+        assert_eq!(host.hostname, Some("10.0.0.5".to_string()));
+        assert_eq!(host.port, Some(2200));
+        assert!(host.identity_file.is_some());
+
+        let host = config.host_for_spec("web:2222")?;
+        assert_eq!(host.user, Some("www".to_string()));
+        assert_eq!(host.port, Some(2222));
+
+        // Hosts the config doesn't know are taken as written
+        let host = config.host_for_spec("root@1.2.3.4")?;
+        assert_eq!(host.hostname, Some("1.2.3.4".to_string()));
+        assert_eq!(host.user, Some("root".to_string()));
+        assert_eq!(host.port, Some(22));
+
+        assert!(config.host_for_spec("@web").is_err());
+        assert!(config.host_for_spec("admin@").is_err());
+
+        // Only a wildcard block matches, and it names no user
+        let config = create_test_config(
+            r#"
+Host *
+    ServerAliveInterval 15
+"#,
+        )?;
+        let host = config.host_for_spec("example.com")?;
+        assert_eq!(host.user, default_username());
+        assert_eq!(host.server_alive_interval, Some(15));
+        let host = config.host_for_spec("admin@example.com")?;
+        assert_eq!(host.user, Some("admin".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_port() -> Result<()> {
+        assert_eq!(split_port("myhost")?, ("myhost", None));
+        assert_eq!(split_port("myhost:22")?, ("myhost", Some(22)));
+        assert_eq!(split_port("[::1]:2022")?, ("::1", Some(2022)));
+        assert_eq!(split_port("fe80::1")?, ("fe80::1", None));
+
+        assert!(split_port("myhost:abc").is_err());
+        assert!(split_port("myhost:").is_err());
+        assert!(split_port(":22").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_match_host_block() -> Result<()> {
+        let config = create_test_config(
+            r#"
+Host db1
+    HostName db1.prod
+
+Host web1
+    HostName web1.staging
+
+Match host *.prod
+    User produser
+    Port 2222
+
+Host *
+    User default_user
+"#,
+        )?;
+
+        // Match host sees the HostName set above it
+        let host = config.get_host("db1").unwrap();
+        assert_eq!(host.user, Some("produser".to_string()));
+        assert_eq!(host.port, Some(2222));
+
+        let host = config.get_host("web1").unwrap();
+        assert_eq!(host.user, Some("default_user".to_string()));
+        assert_eq!(host.port, None);
+
+        // A name with no Host block of its own still picks the block up
+        let host = config.get_host("cache.prod").unwrap();
+        assert_eq!(host.user, Some("produser".to_string()));
+
+        // Match blocks don't add entries to the host list
+        let names: Vec<String> = config.get_all_hosts().into_iter().map(|h| h.host).collect();
+        assert_eq!(names, ["db1", "web1"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_match_user_and_pattern_lists() -> Result<()> {
+        let config = create_test_config(
+            r#"
+Host app1 app2 build
+    User deploy
+
+Match user deploy originalhost app*,!app2
+    IdentityFile ~/.ssh/deploy_key
+
+Match user root
+    Port 2200
+"#,
+        )?;
+
+        let host = config.get_host("app1").unwrap();
+        assert_eq!(host.identity_file, Some(PathBuf::from("~/.ssh/deploy_key")));
+        assert_eq!(host.port, None);
+
+        // Negated in the pattern list
+        assert!(config.get_host("app2").unwrap().identity_file.is_none());
+        // Not matched by app*
+        assert!(config.get_host("build").unwrap().identity_file.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_match_unsupported_criteria() -> Result<()> {
+        let config = create_test_config(
+            r#"
+Match host *.prod exec "test -f ~/.vpn-up"
+    User produser
+
+Match exec "true"
+    Port 2200
+
+Match all
+    IdentityFile ~/.ssh/default_key
+"#,
+        )?;
+
+        // exec is skipped, so the host condition alone decides
+        let host = config.get_host("db.prod").unwrap();
+        assert_eq!(host.user, Some("produser".to_string()));
+        // A block with only unsupported criteria never applies
+        assert_eq!(host.port, None);
+        assert_eq!(
+            host.identity_file,
+            Some(PathBuf::from("~/.ssh/default_key"))
+        );
+
+        let host = config.get_host("db.staging").unwrap();
+        assert_eq!(host.user, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_host_key_alias_and_add_keys_to_agent() -> Result<()> {
+        let config = create_test_config(
+            r#"
+Host db-primary db-replica
+    HostName db.internal
+    AddKeysToAgent yes
+    HostKeyAlias db-cluster
+
+Host *
+    AddKeysToAgent yes
+    User admin
+"#,
+        )?;
+
+        // AddKeysToAgent is skipped without losing the settings around it
+        let host = config.get_host("db-replica").unwrap();
+        assert_eq!(host.host_key_alias, Some("db-cluster".to_string()));
+        assert_eq!(host.user, Some("admin".to_string()));
+
+        let host = config.get_host("other").unwrap();
+        assert_eq!(host.host_key_alias, None);
+        assert_eq!(host.user, Some("admin".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_server_alive_interval_and_connect_timeout() -> Result<()> {
+        let config = create_test_config(
+            r#"
+Host slow
+    ServerAliveInterval 15
+    ConnectTimeout 5
+
+Host nokeepalive
+    ServerAliveInterval 0
+    ConnectTimeout 0
+
+Host broken
+    ServerAliveInterval soon
+    ConnectTimeout -1
+
+Host *
+    ConnectTimeout 30
+"#,
+        )?;
+
+        let host = config.get_host("slow").unwrap();
+        assert_eq!(host.server_alive_interval, Some(15));
+        assert_eq!(host.connect_timeout, Some(5));
+        assert_eq!(host.keepalive_interval(30), 15);
+        assert_eq!(host.connect_timeout(), Some(Duration::from_secs(5)));
+
+        let host = config.get_host("nokeepalive").unwrap();
+        assert_eq!(host.keepalive_interval(30), 0);
+        assert_eq!(host.connect_timeout(), None);
+
+        // Unparseable values are ignored, leaving later blocks to fill them in
+        let host = config.get_host("broken").unwrap();
+        assert_eq!(host.server_alive_interval, None);
+        assert_eq!(host.keepalive_interval(30), 30);
+        assert_eq!(host.connect_timeout, Some(30));
+
+        let host = config.get_host("other").unwrap();
+        assert_eq!(host.connect_timeout(), Some(Duration::from_secs(30)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compression() -> Result<()> {
+        let config = create_test_config(
+            r#"
+Host slow
+    Compression yes
+
+Host fast
+    Compression no
+
+Host odd
+    Compression maybe
+"#,
+        )?;
+
+        assert!(config.get_host("slow").unwrap().compression(false));
+        assert!(!config.get_host("fast").unwrap().compression(true));
+        // Unknown values leave the app's default in charge
+        let host = config.get_host("odd").unwrap();
+        assert_eq!(host.compression, None);
+        assert!(host.compression(true));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ssh_command() {
+        let host = SshHost {
+            host: "web".to_string(),
+            hostname: Some("web.example.com".to_string()),
+            user: Some("deploy".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(host.ssh_command(), "ssh deploy@web.example.com");
+
+        let with_port = SshHost {
+            port: Some(2222),
+            ..host.clone()
+        };
+        assert_eq!(
+            with_port.ssh_command(),
+            "ssh -p 2222 deploy@web.example.com"
+        );
+        let default_port = SshHost {
+            port: Some(22),
+            ..host.clone()
+        };
+        assert_eq!(default_port.ssh_command(), "ssh deploy@web.example.com");
+
+        let everything = SshHost {
+            port: Some(2222),
+            identity_file: Some(PathBuf::from("~/.ssh/id_ed25519")),
+            proxy_jump: Some("bastion, admin@gate:2200".to_string()),
+            ..host.clone()
+        };
+        assert_eq!(
+            everything.ssh_command(),
+            "ssh -p 2222 -i ~/.ssh/id_ed25519 -J bastion,admin@gate:2200 deploy@web.example.com"
+        );
+
+        let awkward = SshHost {
+            hostname: None,
+            user: None,
+            identity_file: Some(PathBuf::from("/keys/my key's")),
+            proxy_jump: Some("none".to_string()),
+            ..host
+        };
+        assert_eq!(awkward.ssh_command(), "ssh -i '/keys/my key'\\''s' web");
+    }
+
+    #[test]
+    fn test_without_home_directory() -> Result<()> {
+        let config = SshConfig::in_home(None)?;
+        assert!(config.get_all_hosts().is_empty());
+        assert!(config.get_host("anything").is_none());
+        assert!(config.warning().is_some());
+
+        // A home without a config is quietly empty
+        let home = tempfile::tempdir()?;
+        let config = SshConfig::in_home(Some(home.path()))?;
+        assert!(config.get_all_hosts().is_empty());
+        assert!(config.warning().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_percent_expansion() -> Result<()> {
+        let config = create_test_config(
+            r#"
+Host web db
+    HostName %h.internal
+    User deploy
+    Port 2222
+    IdentityFile /keys/id_%r@%h:%p
+
+Host plain
+    IdentityFile /keys/100%%_%x
+"#,
+        )?;
+
+        let host = config.get_host("web").unwrap();
+        assert_eq!(host.hostname, Some("web.internal".to_string()));
+        assert_eq!(
+            host.identity_file,
+            Some(PathBuf::from("/keys/id_deploy@web.internal:2222"))
+        );
+        let host = config.get_host("db").unwrap();
+        assert_eq!(host.hostname, Some("db.internal".to_string()));
+
+        // %% is a literal %, and unknown tokens are left alone
+        let host = config.get_host("plain").unwrap();
+        assert_eq!(host.identity_file, Some(PathBuf::from("/keys/100%_%x")));
+
+        // Match host sees the expanded HostName
+        let config = create_test_config(
+            r#"
+Host web
+    HostName %h.internal
+
+Match host *.internal
+    User deploy
+"#,
+        )?;
+        assert_eq!(
+            config.get_host("web").unwrap().user.as_deref(),
+            Some("deploy")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand_tokens() {
+        let tokens = [('h', "example.com"), ('p', "22")];
+        assert_eq!(
+            expand_tokens("%h.internal", &tokens),
+            "example.com.internal"
+        );
+        assert_eq!(expand_tokens("%h:%p", &tokens), "example.com:22");
+        assert_eq!(expand_tokens("100%%", &tokens), "100%");
+        assert_eq!(expand_tokens("%r@%h", &tokens), "%r@example.com");
+        assert_eq!(expand_tokens("trailing%", &tokens), "trailing%");
+        assert_eq!(expand_tokens("no tokens", &tokens), "no tokens");
+    }
+
+    #[test]
+    fn test_parse_match_criteria() {
+        assert_eq!(
+            parse_match_criteria(r#"host *.prod exec "nc -z vpn 22" final"#),
+            vec![
+                MatchCriterion::Host("*.prod".to_string()),
+                MatchCriterion::Unsupported("exec".to_string()),
+                MatchCriterion::Unsupported("final".to_string()),
+            ]
+        );
+        assert_eq!(parse_match_criteria("All"), vec![MatchCriterion::All]);
+    }
 }
@@ -0,0 +1,107 @@
+use anyhow::Result;
+use std::fs;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+
+use crate::config::AppConfig;
+
+/// State that is saved to a file of its own between sessions.
+pub trait Persist: Default {
+    /// Reads the state back from a file's content. Whatever can't be made
+    /// sense of is left out rather than failing the load.
+    fn parse(content: &str) -> Self;
+
+    /// The file content `parse` reads back.
+    fn serialize(&self) -> Result<String>;
+}
+
+/// A `T` together with the file it is loaded from and saved to. The default
+/// has no file and keeps the state in memory only.
+#[derive(Debug, Clone, Default)]
+pub struct Stored<T> {
+    path: Option<PathBuf>,
+    value: T,
+}
+
+impl<T: Persist> Stored<T> {
+    /// Loads `<config dir>/sftui/<name>`. A missing or unreadable file just
+    /// means nothing has been saved yet.
+    pub fn load(name: &str) -> Self {
+        match AppConfig::dir() {
+            Some(dir) => Self::from_path(&dir.join(name)),
+            None => Stored::default(),
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Self {
+        let value = fs::read_to_string(path)
+            .map(|content| T::parse(&content))
+            .unwrap_or_default();
+
+        Stored {
+            path: Some(path.to_path_buf()),
+            value,
+        }
+    }
+
+    /// Writes the state to its file, creating the config dir if needed.
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        fs::write(path, self.value.serialize()?)?;
+        Ok(())
+    }
+}
+
+impl<T> Deref for Stored<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for Stored<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq)]
+    struct Note(String);
+
+    impl Persist for Note {
+        fn parse(content: &str) -> Self {
+            Note(content.to_string())
+        }
+
+        fn serialize(&self) -> Result<String> {
+            Ok(self.0.clone())
+        }
+    }
+
+    #[test]
+    fn test_save_and_load() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("sftui").join("note");
+
+        // Nothing saved yet
+        let mut note = Stored::<Note>::from_path(&path);
+        assert_eq!(*note, Note::default());
+
+        note.0 = "hello".to_string();
+        note.save()?;
+        assert_eq!(*Stored::<Note>::from_path(&path), Note("hello".to_string()));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1869 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+use crate::app::{TransferDirection, TransferItem};
+use crate::hook::HookStatus;
+use crate::sftp::{self, LocalEntry, SftpClient, Timestamps};
+
+/// How far back the smoothed speed looks.
+const SPEED_WINDOW: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone)]
+pub struct TransferProgress {
+    pub total_items: usize,
+    pub status: Option<String>,
+    /// Items processed so far, whatever their outcome.
+    pub items_finished: usize,
+    /// Items whose size couldn't be determined up front. They are left out of
+    /// the overall ratio.
+    pub unsized_items: usize,
+    /// Items being transferred, oldest first. There is one per worker that
+    /// is busy.
+    pub active: Vec<ActiveItem>,
+    queue_bytes_total: u64,
+    queue_bytes_finished: u64,
+}
+
+/// An item a worker is transferring.
+#[derive(Debug, Clone)]
+pub struct ActiveItem {
+    pub index: usize,
+    pub name: String,
+    pub bytes_done: u64,
+    pub bytes_total: Option<u64>,
+    /// Files uploaded so far and in total, when the item is a directory
+    /// upload.
+    pub dir_files: Option<(usize, usize)>,
+    started_at: Instant,
+    // (timestamp, bytes_done) pairs used for the moving average
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl ActiveItem {
+    fn new(index: usize, name: String, bytes_total: Option<u64>, now: Instant) -> Self {
+        ActiveItem {
+            index,
+            name,
+            bytes_done: 0,
+            bytes_total,
+            dir_files: None,
+            started_at: now,
+            samples: VecDeque::from([(now, 0)]),
+        }
+    }
+
+    fn advance_at(&mut self, bytes: u64, now: Instant) {
+        self.bytes_done += bytes;
+        self.samples.push_back((now, self.bytes_done));
+
+        // Keep the oldest sample that still covers the window
+        while self.samples.len() > 2 && now.duration_since(self.samples[1].0) >= SPEED_WINDOW {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Bytes per second over the last couple of seconds.
+    pub fn speed(&self) -> f64 {
+        match (self.samples.front(), self.samples.back()) {
+            (Some(&(t0, b0)), Some(&(t1, b1))) => {
+                let elapsed = t1.duration_since(t0).as_secs_f64();
+                if elapsed > 0.0 {
+                    (b1 - b0) as f64 / elapsed
+                } else {
+                    0.0
+                }
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Bytes per second since the item started.
+    pub fn average_speed(&self) -> f64 {
+        let last = self.samples.back().map_or(self.started_at, |&(t, _)| t);
+        let elapsed = last.duration_since(self.started_at).as_secs_f64();
+        if elapsed > 0.0 {
+            self.bytes_done as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    pub fn eta(&self) -> Option<Duration> {
+        let total = self.bytes_total?;
+        let speed = self.speed();
+        if speed <= 0.0 {
+            return None;
+        }
+        let remaining = total.saturating_sub(self.bytes_done);
+        Some(Duration::from_secs_f64(remaining as f64 / speed))
+    }
+
+    pub fn ratio(&self) -> Option<f64> {
+        match self.bytes_total {
+            Some(0) => Some(1.0),
+            Some(total) => Some((self.bytes_done as f64 / total as f64).min(1.0)),
+            None => None,
+        }
+    }
+}
+
+impl TransferProgress {
+    pub fn new(total_items: usize) -> Self {
+        TransferProgress {
+            total_items,
+            status: None,
+            items_finished: 0,
+            unsized_items: 0,
+            active: Vec::new(),
+            queue_bytes_total: 0,
+            queue_bytes_finished: 0,
+        }
+    }
+
+    /// Records the sizes of every queued item, for the overall ratio.
+    pub fn set_queue_sizes(&mut self, sizes: &[Option<u64>]) {
+        self.queue_bytes_total = sizes.iter().flatten().sum();
+        self.unsized_items = sizes.iter().filter(|size| size.is_none()).count();
+    }
+
+    /// The item shown in detail: the one that has been running longest.
+    pub fn current(&self) -> Option<&ActiveItem> {
+        self.active.first()
+    }
+
+    fn item_mut(&mut self, index: usize) -> Option<&mut ActiveItem> {
+        self.active.iter_mut().find(|item| item.index == index)
+    }
+
+    /// Marks item `index` as processed. `bytes` is its size if it was
+    /// transferred, so failed and skipped items don't count towards the total.
+    pub fn finish_item(&mut self, index: usize, bytes: Option<u64>) {
+        self.items_finished += 1;
+        self.queue_bytes_finished += bytes.unwrap_or(0);
+        // Its bytes are now part of the finished total
+        self.active.retain(|item| item.index != index);
+    }
+
+    pub fn start_item(&mut self, index: usize, name: String, bytes_total: Option<u64>) {
+        self.start_item_at(index, name, bytes_total, Instant::now());
+    }
+
+    fn start_item_at(
+        &mut self,
+        index: usize,
+        name: String,
+        bytes_total: Option<u64>,
+        now: Instant,
+    ) {
+        let item = ActiveItem::new(index, name, bytes_total, now);
+        match self.item_mut(index) {
+            // A retry starts over in the same place
+            Some(active) => *active = item,
+            None => self.active.push(item),
+        }
+        self.status = None;
+    }
+
+    /// Marks item `index` as a directory of `files` files.
+    pub fn start_directory(&mut self, index: usize, files: usize) {
+        if let Some(item) = self.item_mut(index) {
+            item.dir_files = Some((0, files));
+        }
+    }
+
+    /// Counts one more file of directory `index` as uploaded.
+    pub fn finish_dir_file(&mut self, index: usize) {
+        if let Some((done, _)) = self
+            .item_mut(index)
+            .and_then(|item| item.dir_files.as_mut())
+        {
+            *done += 1;
+        }
+    }
+
+    pub fn advance(&mut self, index: usize, bytes: u64) {
+        self.advance_at(index, bytes, Instant::now());
+    }
+
+    fn advance_at(&mut self, index: usize, bytes: u64, now: Instant) {
+        if let Some(item) = self.item_mut(index) {
+            item.advance_at(bytes, now);
+        }
+    }
+
+    /// Bytes per second across every running item.
+    pub fn speed(&self) -> f64 {
+        self.active.iter().map(ActiveItem::speed).sum()
+    }
+
+    /// Fraction of the queue's known bytes transferred so far, or None if no
+    /// item's size is known.
+    pub fn overall_ratio(&self) -> Option<f64> {
+        if self.queue_bytes_total == 0 {
+            return (self.unsized_items == 0).then_some(1.0);
+        }
+        // Only a sized item contributes to the total, so only count its bytes
+        let running: u64 = self
+            .active
+            .iter()
+            .map(|item| {
+                item.bytes_total
+                    .map_or(0, |total| item.bytes_done.min(total))
+            })
+            .sum();
+        let done = self.queue_bytes_finished + running;
+        Some((done as f64 / self.queue_bytes_total as f64).min(1.0))
+    }
+}
+
+/// Formats a throughput such as `1.3 MB/s`.
+pub fn format_speed(bytes_per_sec: f64) -> String {
+    format_scaled(bytes_per_sec, &["B/s", "KB/s", "MB/s", "GB/s"])
+}
+
+/// Formats a byte count such as `4.2 GB`.
+pub fn format_size(bytes: u64) -> String {
+    format_scaled(bytes as f64, &["B", "KB", "MB", "GB", "TB"])
+}
+
+fn format_scaled(value: f64, units: &[&str]) -> String {
+    let mut value = if value.is_finite() {
+        value.max(0.0)
+    } else {
+        0.0
+    };
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{value:.0} {}", units[unit])
+    } else {
+        format!("{value:.1} {}", units[unit])
+    }
+}
+
+/// Formats a duration as `MM:SS`, or `HH:MM:SS` once it exceeds an hour.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, minutes, seconds) = (secs / 3600, (secs % 3600) / 60, secs % 60);
+    if hours > 0 {
+        format!("{hours:02}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes:02}:{seconds:02}")
+    }
+}
+
+/// What a transfer batch needs from a connection. Implemented by
+/// `SftpClient`, and by stand-ins in tests.
+pub trait TransferClient {
+    fn file_size(&self, remote_path: &Path) -> Option<u64>;
+    fn upload_file(
+        &self,
+        local_path: &Path,
+        remote_path: &Path,
+        progress: &mut dyn FnMut(u64) -> Result<()>,
+    ) -> Result<()>;
+    fn download_file(
+        &self,
+        remote_path: &Path,
+        local_path: &Path,
+        resume: bool,
+        progress: &mut dyn FnMut(u64) -> Result<()>,
+    ) -> Result<()>;
+    fn remove(&self, remote_path: &Path) -> Result<()>;
+    fn sha256(&self, remote_path: &Path) -> Result<String>;
+    /// SHA-256 of a remote file computed by the server itself. Fails, with
+    /// the reason, when it can't compute one.
+    fn server_sha256(&self, remote_path: &Path) -> Result<String>;
+    fn is_dir(&self, remote_path: &Path) -> bool;
+    fn create_directory(&self, remote_path: &Path) -> Result<()>;
+    fn times(&self, remote_path: &Path) -> Result<Timestamps>;
+    fn set_times(&self, remote_path: &Path, times: Timestamps) -> Result<()>;
+}
+
+impl TransferClient for SftpClient {
+    fn file_size(&self, remote_path: &Path) -> Option<u64> {
+        SftpClient::file_size(self, remote_path)
+    }
+
+    fn upload_file(
+        &self,
+        local_path: &Path,
+        remote_path: &Path,
+        progress: &mut dyn FnMut(u64) -> Result<()>,
+    ) -> Result<()> {
+        SftpClient::upload_file(self, local_path, remote_path, progress)
+    }
+
+    fn download_file(
+        &self,
+        remote_path: &Path,
+        local_path: &Path,
+        resume: bool,
+        progress: &mut dyn FnMut(u64) -> Result<()>,
+    ) -> Result<()> {
+        SftpClient::download_file(self, remote_path, local_path, resume, progress)
+    }
+
+    fn remove(&self, remote_path: &Path) -> Result<()> {
+        SftpClient::remove(self, remote_path)
+    }
+
+    fn sha256(&self, remote_path: &Path) -> Result<String> {
+        SftpClient::sha256(self, remote_path)
+    }
+
+    fn server_sha256(&self, remote_path: &Path) -> Result<String> {
+        SftpClient::server_sha256(self, remote_path)
+    }
+
+    fn is_dir(&self, remote_path: &Path) -> bool {
+        SftpClient::is_dir(self, remote_path)
+    }
+
+    fn create_directory(&self, remote_path: &Path) -> Result<()> {
+        SftpClient::create_directory(self, remote_path)
+    }
+
+    fn times(&self, remote_path: &Path) -> Result<Timestamps> {
+        SftpClient::times(self, remote_path)
+    }
+
+    fn set_times(&self, remote_path: &Path, times: Timestamps) -> Result<()> {
+        SftpClient::set_times(self, remote_path, times)
+    }
+}
+
+/// How many directories deep a directory upload goes unless configured
+/// otherwise.
+pub const DEFAULT_MAX_DEPTH: usize = 64;
+
+/// How a batch treats the files it transfers.
+#[derive(Debug, Clone, Copy)]
+pub struct TransferOptions {
+    /// Continue partial downloads instead of starting over.
+    pub resume: bool,
+    /// Give each transferred file its source's access and modification times.
+    pub preserve_times: bool,
+    /// Deepest directory nesting a directory upload follows.
+    pub max_depth: usize,
+    /// How many items may be transferred at once, each over its own
+    /// connection.
+    pub workers: usize,
+    /// Leave out files the destination already has.
+    pub skip_identical: SkipIdentical,
+    /// Check each uploaded file against its source once it's written.
+    pub verify_uploads: bool,
+}
+
+/// When a file counts as already transferred. Only files are compared;
+/// directories are always transferred.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipIdentical {
+    #[default]
+    Off,
+    /// Same size and modification time, to the second.
+    SizeAndTime,
+    /// Same size and SHA-256, whatever the times. Reads both files in full.
+    Checksum,
+}
+
+impl Default for TransferOptions {
+    fn default() -> Self {
+        TransferOptions {
+            resume: false,
+            preserve_times: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            workers: 1,
+            skip_identical: SkipIdentical::Off,
+            verify_uploads: false,
+        }
+    }
+}
+
+/// How one queued item ended up.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransferOutcome {
+    Done,
+    Skipped(String),
+    Failed(String),
+    /// Stopped by the user. `partial` is set when the item had started, so
+    /// its destination holds an incomplete copy.
+    Cancelled {
+        partial: bool,
+    },
+}
+
+/// Returned through the progress callback to stop a transfer early.
+#[derive(Debug, Error)]
+#[error("transfer cancelled")]
+pub struct Cancelled;
+
+#[derive(Debug, Clone)]
+pub struct TransferResult {
+    pub item: TransferItem,
+    pub outcome: TransferOutcome,
+    /// Size of the item as measured before the batch, if it could be.
+    pub bytes: Option<u64>,
+    /// How the host's `after_transfer` hook went, if it ran for this item.
+    pub hook: Option<HookStatus>,
+}
+
+/// Runs the queue, publishing progress as it goes, and reports how each
+/// item went. Up to `options.workers` items run at once: the first worker
+/// uses `client` and each of the others opens its own connection with
+/// `connect`, since one ssh session can only do one thing at a time. A
+/// worker that can't connect leaves its share to the others.
+///
+/// A failed item doesn't stop the rest, but once the connection is lost for
+/// good the remaining items are skipped. A dropped connection to `host` is
+/// re-established once per item with `connect`, replacing the worker's
+/// client (`client` itself for the first worker). Setting `cancel` stops the
+/// running items after their next chunk and cancels the rest. `on_result`
+/// sees each result as its item ends; the returned results are in queue
+/// order.
+#[allow(clippy::too_many_arguments)]
+pub fn run_transfers<C: TransferClient + Send + Sync>(
+    client: &mut Arc<C>,
+    connect: impl Fn() -> Result<C> + Sync,
+    host: &str,
+    items: &[TransferItem],
+    progress: &Mutex<TransferProgress>,
+    options: TransferOptions,
+    cancel: &AtomicBool,
+    on_result: &mut (dyn FnMut(&TransferResult) + Send),
+) -> Vec<TransferResult> {
+    let sizes: Vec<ItemSize> = items
+        .iter()
+        .map(|item| measure_item(Some(&**client), item, options.max_depth))
+        .collect();
+    if let Ok(mut p) = progress.lock() {
+        let bytes: Vec<Option<u64>> = sizes.iter().map(|size| size.bytes).collect();
+        p.set_queue_sizes(&bytes);
+    }
+
+    let batch = Batch {
+        host,
+        items,
+        sizes: &sizes,
+        next: AtomicUsize::new(0),
+        connection_lost: AtomicBool::new(false),
+        results: Mutex::new(vec![None; items.len()]),
+        on_result: Mutex::new(on_result),
+        progress,
+        options,
+        cancel,
+    };
+    let workers = options.workers.clamp(1, items.len().max(1));
+    thread::scope(|scope| {
+        for _ in 1..workers {
+            scope.spawn(|| {
+                if let Ok(own_client) = connect() {
+                    batch.work(&mut Arc::new(own_client), &connect);
+                }
+            });
+        }
+        batch.work(client, &connect);
+    });
+
+    batch
+        .results
+        .into_inner()
+        .unwrap_or_else(|e| e.into_inner())
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+/// What the workers of one `run_transfers` call share.
+struct Batch<'a> {
+    host: &'a str,
+    items: &'a [TransferItem],
+    sizes: &'a [ItemSize],
+    /// The first item no worker has taken yet.
+    next: AtomicUsize,
+    connection_lost: AtomicBool,
+    results: Mutex<Vec<Option<TransferResult>>>,
+    on_result: Mutex<&'a mut (dyn FnMut(&TransferResult) + Send)>,
+    progress: &'a Mutex<TransferProgress>,
+    options: TransferOptions,
+    cancel: &'a AtomicBool,
+}
+
+impl Batch<'_> {
+    /// Takes items off the queue until none are left.
+    fn work<C: TransferClient>(&self, client: &mut Arc<C>, connect: &impl Fn() -> Result<C>) {
+        loop {
+            let index = self.next.fetch_add(1, Ordering::Relaxed);
+            let (Some(item), Some(&size)) = (self.items.get(index), self.sizes.get(index)) else {
+                return;
+            };
+
+            let outcome = if self.cancel.load(Ordering::Relaxed) {
+                TransferOutcome::Cancelled { partial: false }
+            } else if self.connection_lost.load(Ordering::Relaxed) {
+                TransferOutcome::Skipped("connection lost".to_string())
+            } else if is_identical(&**client, item, self.options.skip_identical) {
+                if item.remove_source {
+                    // The destination already has it, so the move only has
+                    // the source left to remove
+                    match remove_source(client, connect, item) {
+                        Ok(()) => TransferOutcome::Done,
+                        Err(e) => {
+                            if sftp::is_connection_error(&e) {
+                                self.connection_lost.store(true, Ordering::Relaxed);
+                            }
+                            TransferOutcome::Failed(format!(
+                                "identical to the destination, but removing the source failed: {e:#}"
+                            ))
+                        }
+                    }
+                } else {
+                    TransferOutcome::Skipped("identical to the destination".to_string())
+                }
+            } else {
+                match run_item(
+                    client,
+                    connect,
+                    self.host,
+                    index,
+                    item,
+                    size,
+                    self.progress,
+                    self.options,
+                    self.cancel,
+                ) {
+                    Ok(()) => TransferOutcome::Done,
+                    Err(e) if e.is::<Cancelled>() => TransferOutcome::Cancelled { partial: true },
+                    Err(e) => {
+                        if sftp::is_connection_error(&e) {
+                            self.connection_lost.store(true, Ordering::Relaxed);
+                        }
+                        TransferOutcome::Failed(format!("{e:#}"))
+                    }
+                }
+            };
+
+            if let Ok(mut p) = self.progress.lock() {
+                p.finish_item(
+                    index,
+                    size.bytes.filter(|_| outcome == TransferOutcome::Done),
+                );
+            }
+
+            let result = TransferResult {
+                item: item.clone(),
+                outcome,
+                bytes: size.bytes,
+                hook: None,
+            };
+            if let Ok(mut on_result) = self.on_result.lock() {
+                on_result(&result);
+            }
+            if let Ok(mut results) = self.results.lock() {
+                results[index] = Some(result);
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_item<C: TransferClient>(
+    client: &mut Arc<C>,
+    reconnect: &impl Fn() -> Result<C>,
+    host: &str,
+    index: usize,
+    item: &TransferItem,
+    size: ItemSize,
+    progress: &Mutex<TransferProgress>,
+    options: TransferOptions,
+    cancel: &AtomicBool,
+) -> Result<()> {
+    let name = item
+        .source
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| item.source.display().to_string());
+
+    let attempts = Cell::new(0);
+    let announce_reconnect = || {
+        attempts.set(attempts.get() + 1);
+        if let Ok(mut p) = progress.lock() {
+            p.status = Some(format!(
+                "Reconnecting to {host} (attempt {})...",
+                attempts.get()
+            ));
+        }
+        reconnect()
+    };
+
+    sftp::with_reconnect(client, announce_reconnect, |client| {
+        // A retry starts the item over from zero
+        if let Ok(mut p) = progress.lock() {
+            p.start_item(index, name.clone(), size.bytes);
+            if let Some(files) = size.files {
+                p.start_directory(index, files);
+            }
+        }
+        transfer_item(client, index, item, progress, options, cancel)
+    })?;
+
+    // Directories keep the times they were created with
+    let is_file = match item.direction {
+        TransferDirection::Upload => item.source.is_file(),
+        TransferDirection::Download => true,
+    };
+    if options.preserve_times && is_file {
+        sftp::with_reconnect(client, announce_reconnect, |client| {
+            copy_times(client, item)
+        })
+        .map_err(|e| e.context("transferred, but setting its timestamps failed"))?;
+    }
+
+    if item.remove_source {
+        remove_source(client, announce_reconnect, item)
+            .map_err(|e| e.context("transferred, but removing the source failed"))?;
+    }
+
+    Ok(())
+}
+
+/// Removes what a move was transferring from, once the destination has it.
+fn remove_source<C: TransferClient>(
+    client: &mut Arc<C>,
+    reconnect: impl FnOnce() -> Result<C>,
+    item: &TransferItem,
+) -> Result<()> {
+    match item.direction {
+        TransferDirection::Upload => remove_local(&item.source),
+        TransferDirection::Download => {
+            sftp::with_reconnect(client, reconnect, |client| client.remove(&item.source))
+        }
+    }
+}
+
+/// How much a queued item holds, as far as it can be told up front.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct ItemSize {
+    bytes: Option<u64>,
+    /// Number of files, for directory uploads.
+    files: Option<usize>,
+}
+
+/// Size in bytes of an item to transfer: a file, or all the files in a local
+/// directory being uploaded. None for remote directories and anything that
+/// can't be stat'ed, or directories deeper than `max_depth`. Downloads need a
+/// `client` to be sized.
+pub fn item_size<C: TransferClient>(
+    client: Option<&C>,
+    item: &TransferItem,
+    max_depth: usize,
+) -> Option<u64> {
+    measure_item(client, item, max_depth).bytes
+}
+
+fn measure_item<C: TransferClient>(
+    client: Option<&C>,
+    item: &TransferItem,
+    max_depth: usize,
+) -> ItemSize {
+    match item.direction {
+        TransferDirection::Upload => match fs::metadata(&item.source) {
+            Ok(metadata) if metadata.is_dir() => match local_tree_size(&item.source, max_depth) {
+                Ok(tree) => ItemSize {
+                    bytes: Some(tree.bytes),
+                    files: Some(tree.files),
+                },
+                Err(_) => ItemSize::default(),
+            },
+            Ok(metadata) => ItemSize {
+                bytes: Some(metadata.len()),
+                files: None,
+            },
+            Err(_) => ItemSize::default(),
+        },
+        TransferDirection::Download => ItemSize {
+            bytes: client.and_then(|client| client.file_size(&item.source)),
+            files: None,
+        },
+    }
+}
+
+/// Number of files in a local directory tree and their total size.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TreeSize {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Walks a local directory the way a directory upload does, reading only
+/// metadata, to count what it will send.
+pub fn local_tree_size(path: &Path, max_depth: usize) -> Result<TreeSize> {
+    let mut size = TreeSize::default();
+    sftp::walk_local_tree(path, max_depth, &mut |entry| {
+        if let LocalEntry::File(relative) = entry {
+            // Uploads follow symlinks, so count what they point to
+            size.files += 1;
+            size.bytes += fs::metadata(path.join(relative))?.len();
+        }
+        Ok(())
+    })?;
+    Ok(size)
+}
+
+/// Creates whatever is missing of the directories above `remote_path`,
+/// like `mkdir -p`. A directory that turns up between the check and the
+/// mkdir, e.g. made by another transfer, counts as created.
+pub fn create_remote_parents(client: &impl TransferClient, remote_path: &Path) -> Result<()> {
+    let Some(parent) = remote_path.parent() else {
+        return Ok(());
+    };
+    if parent.as_os_str().is_empty() || client.is_dir(parent) {
+        return Ok(());
+    }
+    create_remote_parents(client, parent)?;
+    ensure_remote_directory(client, parent)
+}
+
+/// Creates a remote directory unless it is already there.
+fn ensure_remote_directory(client: &impl TransferClient, remote_path: &Path) -> Result<()> {
+    match client.create_directory(remote_path) {
+        Err(_) if client.is_dir(remote_path) => Ok(()),
+        result => result.with_context(|| format!("Failed to create {}", remote_path.display())),
+    }
+}
+
+/// Uploads a directory tree, calling `on_file` after each file. A
+/// directory that already exists on the server is merged into: what's in
+/// it stays, and files of the same name are replaced. Stops with `TooDeep`
+/// rather than go more than `max_depth` directories below `local_path`.
+pub fn upload_directory(
+    client: &impl TransferClient,
+    local_path: &Path,
+    remote_path: &Path,
+    max_depth: usize,
+    verify: bool,
+    progress: &mut dyn FnMut(u64) -> Result<()>,
+    on_file: &mut dyn FnMut(),
+) -> Result<()> {
+    ensure_remote_directory(client, remote_path)?;
+
+    sftp::walk_local_tree(local_path, max_depth, &mut |entry| match entry {
+        LocalEntry::Directory(relative) => {
+            ensure_remote_directory(client, &remote_path.join(relative))
+        }
+        LocalEntry::File(relative) => {
+            let (local, remote) = (local_path.join(relative), remote_path.join(relative));
+            client.upload_file(&local, &remote, progress)?;
+            if verify {
+                verify_upload(client, &local, &remote)?;
+            }
+            on_file();
+            Ok(())
+        }
+    })
+}
+
+fn transfer_item(
+    client: &impl TransferClient,
+    index: usize,
+    item: &TransferItem,
+    progress: &Mutex<TransferProgress>,
+    options: TransferOptions,
+    cancel: &AtomicBool,
+) -> Result<()> {
+    let mut on_progress = |bytes: u64| {
+        if let Ok(mut p) = progress.lock() {
+            p.advance(index, bytes);
+        }
+        if cancel.load(Ordering::Relaxed) {
+            return Err(Cancelled.into());
+        }
+        Ok(())
+    };
+    let mut on_file = || {
+        if let Ok(mut p) = progress.lock() {
+            p.finish_dir_file(index);
+        }
+    };
+
+    match item.direction {
+        TransferDirection::Upload => {
+            create_remote_parents(client, &item.destination)?;
+            // Check if source is a directory
+            if item.source.is_dir() {
+                upload_directory(
+                    client,
+                    &item.source,
+                    &item.destination,
+                    options.max_depth,
+                    options.verify_uploads,
+                    &mut on_progress,
+                    &mut on_file,
+                )
+            } else {
+                client.upload_file(&item.source, &item.destination, &mut on_progress)?;
+                if options.verify_uploads {
+                    verify_upload(client, &item.source, &item.destination)?;
+                }
+                Ok(())
+            }
+        }
+        TransferDirection::Download => client.download_file(
+            &item.source,
+            &item.destination,
+            options.resume,
+            &mut on_progress,
+        ),
+    }
+}
+
+/// Checks an uploaded file against its local source: by size, and by SHA-256
+/// when the server can compute one. Without that, a same-size file passes.
+pub fn verify_upload(client: &impl TransferClient, local: &Path, remote: &Path) -> Result<()> {
+    let local_size = fs::metadata(local)?.len();
+    match client.file_size(remote) {
+        Some(size) if size == local_size => {}
+        Some(size) => anyhow::bail!(
+            "verification failed: {} has {size} bytes instead of {local_size}",
+            remote.display()
+        ),
+        None => anyhow::bail!("verification failed: can't read back {}", remote.display()),
+    }
+
+    if let Ok(remote_hash) = client.server_sha256(remote) {
+        let local_hash = sftp::sha256_reader(&mut fs::File::open(local)?)?;
+        if local_hash != remote_hash {
+            anyhow::bail!(
+                "verification failed: checksum of {} doesn't match",
+                remote.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Whether two files of the same size have the same contents.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContentCheck {
+    pub identical: bool,
+    /// Why the server couldn't hash its copy, when it had to be streamed
+    /// back to be hashed here instead.
+    pub server_hash_error: Option<String>,
+}
+
+/// Compares a local file with a remote one by SHA-256. The server hashes its
+/// copy when it can, which saves sending the file back.
+pub fn same_content(
+    client: &impl TransferClient,
+    local: &Path,
+    remote: &Path,
+) -> Result<ContentCheck> {
+    let local_hash = sftp::sha256_reader(&mut fs::File::open(local)?)?;
+    let (remote_hash, server_hash_error) = match client.server_sha256(remote) {
+        Ok(hash) => (hash, None),
+        Err(e) => (client.sha256(remote)?, Some(e.to_string())),
+    };
+    Ok(ContentCheck {
+        identical: local_hash == remote_hash,
+        server_hash_error,
+    })
+}
+
+/// Whether the destination of a file transfer already matches its source
+/// under `policy`. Anything that can't be compared counts as different, so
+/// it gets transferred.
+fn is_identical(client: &impl TransferClient, item: &TransferItem, policy: SkipIdentical) -> bool {
+    if policy == SkipIdentical::Off {
+        return false;
+    }
+    let (local, remote) = match item.direction {
+        TransferDirection::Upload => (&item.source, &item.destination),
+        TransferDirection::Download => (&item.destination, &item.source),
+    };
+    let Ok(metadata) = fs::metadata(local) else {
+        return false;
+    };
+    if !metadata.is_file() || client.file_size(remote) != Some(metadata.len()) {
+        return false;
+    }
+
+    match policy {
+        SkipIdentical::Off => false,
+        SkipIdentical::SizeAndTime => {
+            // SFTP only keeps whole seconds
+            let seconds =
+                |time: SystemTime| time.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs());
+            let local_modified = metadata.modified().ok().and_then(seconds);
+            let remote_modified = client.times(remote).ok().and_then(|t| seconds(t.modified));
+            local_modified.is_some() && local_modified == remote_modified
+        }
+        SkipIdentical::Checksum => {
+            let local_hash = fs::File::open(local)
+                .ok()
+                .and_then(|mut file| sftp::sha256_reader(&mut file).ok());
+            local_hash.is_some() && local_hash == client.sha256(remote).ok()
+        }
+    }
+}
+
+/// Gives the destination of a transferred file its source's times.
+fn copy_times(client: &impl TransferClient, item: &TransferItem) -> Result<()> {
+    match item.direction {
+        TransferDirection::Upload => {
+            client.set_times(&item.destination, local_times(&item.source)?)
+        }
+        TransferDirection::Download => {
+            set_local_times(&item.destination, client.times(&item.source)?)
+        }
+    }
+}
+
+pub fn local_times(path: &Path) -> Result<Timestamps> {
+    let metadata = fs::metadata(path)?;
+    Ok(Timestamps {
+        accessed: metadata.accessed()?,
+        modified: metadata.modified()?,
+    })
+}
+
+pub fn set_local_times(path: &Path, times: Timestamps) -> Result<()> {
+    let file = fs::File::options().write(true).open(path)?;
+    file.set_times(
+        fs::FileTimes::new()
+            .set_accessed(times.accessed)
+            .set_modified(times.modified),
+    )?;
+    Ok(())
+}
+
+/// Deletes a local file, or a directory and everything in it.
+pub fn remove_local(path: &Path) -> Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::ErrorKind;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_format_speed() {
+        assert_eq!(format_speed(0.0), "0 B/s");
+        assert_eq!(format_speed(512.0), "512 B/s");
+        assert_eq!(format_speed(1536.0), "1.5 KB/s");
+        assert_eq!(format_speed(1.3 * 1024.0 * 1024.0), "1.3 MB/s");
+        assert_eq!(format_speed(f64::NAN), "0 B/s");
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KB");
+        assert_eq!(format_size(50 * 1024 * 1024 * 1024), "50.0 GB");
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::ZERO), "00:00");
+        assert_eq!(format_duration(Duration::from_secs(42)), "00:42");
+        assert_eq!(format_duration(Duration::from_secs(754)), "12:34");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "01:02:03");
+    }
+
+    #[test]
+    fn test_progress_zero_elapsed() {
+        let item = ActiveItem::new(0, "file".to_string(), Some(10), Instant::now());
+        assert_eq!(item.speed(), 0.0);
+        assert_eq!(item.average_speed(), 0.0);
+        assert!(item.eta().is_none());
+    }
+
+    #[test]
+    fn test_progress_zero_bytes() {
+        let start = Instant::now();
+        let mut item = ActiveItem::new(0, "empty".to_string(), Some(0), start);
+        item.advance_at(0, start + Duration::from_secs(1));
+
+        assert_eq!(item.speed(), 0.0);
+        assert!(item.eta().is_none());
+        assert_eq!(item.ratio(), Some(1.0));
+    }
+
+    #[test]
+    fn test_progress_speed_and_eta() {
+        let start = Instant::now();
+        let mut item = ActiveItem::new(0, "file".to_string(), Some(10_000), start);
+        item.advance_at(1_000, start + Duration::from_secs(1));
+        item.advance_at(1_000, start + Duration::from_secs(2));
+
+        assert_eq!(item.speed(), 1_000.0);
+        assert_eq!(item.average_speed(), 1_000.0);
+        assert_eq!(item.eta(), Some(Duration::from_secs(8)));
+    }
+
+    #[test]
+    fn test_progress_speed_is_smoothed_over_window() {
+        let start = Instant::now();
+        let mut item = ActiveItem::new(0, "file".to_string(), None, start);
+
+        // A slow start followed by a steady faster rate
+        item.advance_at(100, start + Duration::from_secs(1));
+        for second in 2..=6 {
+            item.advance_at(2_000, start + Duration::from_secs(second));
+        }
+
+        // Old samples fall out of the window, so the slow start no longer counts
+        assert_eq!(item.speed(), 2_000.0);
+        assert!(item.average_speed() < item.speed());
+        assert!(item.eta().is_none());
+    }
+
+    #[test]
+    fn test_overall_ratio_skips_unsized_items() {
+        let mut progress = TransferProgress::new(3);
+        progress.set_queue_sizes(&[Some(100), None, Some(300)]);
+        assert_eq!(progress.unsized_items, 1);
+        assert_eq!(progress.overall_ratio(), Some(0.0));
+
+        progress.start_item(0, "a".to_string(), Some(100));
+        progress.advance(0, 100);
+        progress.finish_item(0, Some(100));
+        assert_eq!(progress.overall_ratio(), Some(0.25));
+
+        // The unsized directory's bytes don't move the overall gauge
+        progress.start_item(1, "dir".to_string(), None);
+        progress.advance(1, 5_000);
+        progress.finish_item(1, None);
+        assert_eq!(progress.overall_ratio(), Some(0.25));
+
+        progress.start_item(2, "c".to_string(), Some(300));
+        progress.advance(2, 150);
+        assert_eq!(progress.overall_ratio(), Some(0.625));
+        assert_eq!(progress.items_finished, 2);
+    }
+
+    #[test]
+    fn test_local_tree_size() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("a.txt"), "12345")?;
+        fs::create_dir_all(dir.path().join("sub/deeper"))?;
+        fs::create_dir(dir.path().join("empty"))?;
+        fs::write(dir.path().join("sub/b.txt"), "123")?;
+        fs::write(dir.path().join("sub/deeper/c.txt"), "")?;
+        fs::write(dir.path().join("sub/deeper/d.txt"), "12")?;
+
+        assert_eq!(
+            local_tree_size(dir.path(), DEFAULT_MAX_DEPTH)?,
+            TreeSize {
+                files: 4,
+                bytes: 10
+            }
+        );
+        assert_eq!(
+            local_tree_size(&dir.path().join("empty"), DEFAULT_MAX_DEPTH)?,
+            TreeSize::default()
+        );
+        // sub/deeper is two levels down
+        assert!(local_tree_size(dir.path(), 1).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_directory_upload_counts_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            fs::write(dir.path().join(name), "1234")?;
+        }
+        let mut client = Arc::new(MockClient::new(vec![]));
+        let items = vec![TransferItem {
+            source: dir.path().to_path_buf(),
+            destination: PathBuf::from("/remote/dir"),
+            direction: TransferDirection::Upload,
+            remove_source: false,
+            enabled: true,
+        }];
+        let progress = Mutex::new(TransferProgress::new(items.len()));
+
+        run_transfers(
+            &mut client,
+            || Err(anyhow::anyhow!("no reconnect expected")),
+            "server",
+            &items,
+            &progress,
+            TransferOptions::default(),
+            &AtomicBool::new(false),
+            &mut |_| {},
+        );
+
+        let progress = progress.lock().unwrap();
+        assert_eq!(client.uploaded.lock().unwrap().len(), 3);
+        // The directory was sized up front, so it counts towards the total
+        assert_eq!(progress.unsized_items, 0);
+        assert_eq!(progress.overall_ratio(), Some(1.0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_files_progress() {
+        let mut progress = TransferProgress::new(2);
+        progress.start_item(0, "dir".to_string(), Some(10));
+        progress.start_directory(0, 40);
+        progress.finish_dir_file(0);
+        progress.finish_dir_file(0);
+        assert_eq!(progress.current().unwrap().dir_files, Some((2, 40)));
+
+        // The next item isn't a directory
+        progress.finish_item(0, Some(10));
+        progress.start_item(1, "file".to_string(), Some(10));
+        progress.finish_dir_file(1);
+        assert_eq!(progress.current().unwrap().dir_files, None);
+    }
+
+    #[test]
+    fn test_overall_ratio_without_known_sizes() {
+        let mut progress = TransferProgress::new(1);
+        progress.set_queue_sizes(&[None]);
+        assert_eq!(progress.overall_ratio(), None);
+    }
+
+    /// Records every upload and fails the ones listed in `failing`. Clones
+    /// stand for further connections to the same server, so they share
+    /// what they record.
+    #[derive(Clone)]
+    struct MockClient {
+        uploaded: Arc<Mutex<Vec<PathBuf>>>,
+        /// Chunks reported across all uploads. Each upload sends three.
+        chunks: Arc<Mutex<usize>>,
+        /// How long each chunk takes.
+        chunk_delay: Duration,
+        /// Uploads in progress now, and the most there have been at once.
+        running: Arc<AtomicUsize>,
+        peak_running: Arc<AtomicUsize>,
+        /// Sets the flag partway through uploading the given file.
+        cancel_during: Option<(PathBuf, Arc<AtomicBool>)>,
+        failing: Vec<(PathBuf, ErrorKind)>,
+        /// Times set on remote files, and reported for every remote file.
+        times_set: Arc<Mutex<Vec<(PathBuf, Timestamps)>>>,
+        remote_times: Timestamps,
+        /// Contents of remote files, for sizes and checksums.
+        remote_files: HashMap<PathBuf, Vec<u8>>,
+        /// Whether the server can hash files itself.
+        server_checksums: bool,
+        /// Remote directories, in the order they came to exist. Uploading
+        /// into a directory not listed here fails, as on a real server.
+        directories: Arc<Mutex<Vec<PathBuf>>>,
+    }
+
+    impl MockClient {
+        fn new(failing: Vec<(&str, ErrorKind)>) -> Self {
+            MockClient {
+                uploaded: Arc::new(Mutex::new(Vec::new())),
+                chunks: Arc::new(Mutex::new(0)),
+                chunk_delay: Duration::ZERO,
+                running: Arc::new(AtomicUsize::new(0)),
+                peak_running: Arc::new(AtomicUsize::new(0)),
+                cancel_during: None,
+                failing: failing
+                    .into_iter()
+                    .map(|(path, kind)| (PathBuf::from(path), kind))
+                    .collect(),
+                times_set: Arc::new(Mutex::new(Vec::new())),
+                remote_times: Timestamps {
+                    accessed: UNIX_EPOCH + Duration::from_secs(1_500_000_000),
+                    modified: UNIX_EPOCH + Duration::from_secs(1_400_000_000),
+                },
+                remote_files: HashMap::new(),
+                server_checksums: true,
+                directories: Arc::new(Mutex::new(vec![PathBuf::from("/")])),
+            }
+        }
+
+        fn send_chunks(
+            &self,
+            local_path: &Path,
+            progress: &mut dyn FnMut(u64) -> Result<()>,
+        ) -> Result<()> {
+            for _ in 0..3 {
+                thread::sleep(self.chunk_delay);
+                *self.chunks.lock().unwrap() += 1;
+                if let Some((path, cancel)) = &self.cancel_during
+                    && path == local_path
+                {
+                    cancel.store(true, Ordering::Relaxed);
+                }
+                progress(1)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl TransferClient for MockClient {
+        fn file_size(&self, remote_path: &Path) -> Option<u64> {
+            let contents = self.remote_files.get(remote_path)?;
+            Some(contents.len() as u64)
+        }
+
+        fn upload_file(
+            &self,
+            local_path: &Path,
+            remote_path: &Path,
+            progress: &mut dyn FnMut(u64) -> Result<()>,
+        ) -> Result<()> {
+            self.uploaded.lock().unwrap().push(local_path.to_path_buf());
+            if let Some((_, kind)) = self.failing.iter().find(|(path, _)| path == local_path) {
+                return Err(std::io::Error::from(*kind).into());
+            }
+            if !remote_path
+                .parent()
+                .is_some_and(|parent| self.is_dir(parent))
+            {
+                return Err(std::io::Error::from(ErrorKind::NotFound).into());
+            }
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak_running.fetch_max(running, Ordering::SeqCst);
+            let result = self.send_chunks(local_path, progress);
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            result
+        }
+
+        fn download_file(
+            &self,
+            _remote_path: &Path,
+            _local_path: &Path,
+            _resume: bool,
+            _progress: &mut dyn FnMut(u64) -> Result<()>,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        fn remove(&self, _remote_path: &Path) -> Result<()> {
+            Ok(())
+        }
+
+        fn sha256(&self, remote_path: &Path) -> Result<String> {
+            let contents = self
+                .remote_files
+                .get(remote_path)
+                .ok_or_else(|| anyhow::anyhow!("no such file"))?;
+            sftp::sha256_reader(&mut contents.as_slice())
+        }
+
+        fn server_sha256(&self, remote_path: &Path) -> Result<String> {
+            if !self.server_checksums {
+                return Err(anyhow::anyhow!("sha256sum: command not found"));
+            }
+            self.sha256(remote_path)
+        }
+
+        fn is_dir(&self, remote_path: &Path) -> bool {
+            self.directories
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|dir| dir == remote_path)
+        }
+
+        fn create_directory(&self, remote_path: &Path) -> Result<()> {
+            if self.is_dir(remote_path) {
+                return Err(anyhow::anyhow!("{} already exists", remote_path.display()));
+            }
+            if !remote_path
+                .parent()
+                .is_some_and(|parent| self.is_dir(parent))
+            {
+                return Err(std::io::Error::from(ErrorKind::NotFound).into());
+            }
+            self.directories
+                .lock()
+                .unwrap()
+                .push(remote_path.to_path_buf());
+            Ok(())
+        }
+
+        fn times(&self, _remote_path: &Path) -> Result<Timestamps> {
+            Ok(self.remote_times)
+        }
+
+        fn set_times(&self, remote_path: &Path, times: Timestamps) -> Result<()> {
+            self.times_set
+                .lock()
+                .unwrap()
+                .push((remote_path.to_path_buf(), times));
+            Ok(())
+        }
+    }
+
+    fn uploads(names: &[&str]) -> Vec<TransferItem> {
+        names
+            .iter()
+            .map(|name| TransferItem {
+                source: PathBuf::from(name),
+                destination: Path::new("/remote").join(name),
+                direction: TransferDirection::Upload,
+                remove_source: false,
+                enabled: true,
+            })
+            .collect()
+    }
+
+    fn outcomes(results: &[TransferResult]) -> Vec<&TransferOutcome> {
+        results.iter().map(|r| &r.outcome).collect()
+    }
+
+    #[test]
+    fn test_failed_item_does_not_stop_the_batch() {
+        let mut client = Arc::new(MockClient::new(vec![(
+            "b.txt",
+            ErrorKind::PermissionDenied,
+        )]));
+        let items = uploads(&["a.txt", "b.txt", "c.txt"]);
+        let progress = Mutex::new(TransferProgress::new(items.len()));
+
+        let results = run_transfers(
+            &mut client,
+            || Err(anyhow::anyhow!("no reconnect expected")),
+            "server",
+            &items,
+            &progress,
+            TransferOptions::default(),
+            &AtomicBool::new(false),
+            &mut |_| {},
+        );
+
+        assert_eq!(
+            *client.uploaded.lock().unwrap(),
+            vec![
+                PathBuf::from("a.txt"),
+                PathBuf::from("b.txt"),
+                PathBuf::from("c.txt")
+            ]
+        );
+        let outcomes = outcomes(&results);
+        assert_eq!(outcomes[0], &TransferOutcome::Done);
+        assert!(matches!(outcomes[1], TransferOutcome::Failed(_)));
+        assert_eq!(outcomes[2], &TransferOutcome::Done);
+    }
+
+    #[test]
+    fn test_cancel_stops_the_batch() {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let mut mock = MockClient::new(vec![]);
+        mock.cancel_during = Some((PathBuf::from("b.txt"), Arc::clone(&cancel)));
+        let mut client = Arc::new(mock);
+        let items = uploads(&["a.txt", "b.txt", "c.txt"]);
+        let progress = Mutex::new(TransferProgress::new(items.len()));
+
+        // Each result is reported as its item ends, with the chunks sent so far
+        let observer = Arc::clone(&client);
+        let mut reported = Vec::new();
+        let results = run_transfers(
+            &mut client,
+            || Err(anyhow::anyhow!("no reconnect expected")),
+            "server",
+            &items,
+            &progress,
+            TransferOptions::default(),
+            &cancel,
+            &mut |result| {
+                reported.push((result.outcome.clone(), *observer.chunks.lock().unwrap()));
+            },
+        );
+
+        // b.txt stopped after its first chunk, and c.txt never started
+        assert_eq!(*client.chunks.lock().unwrap(), 4);
+        assert_eq!(
+            reported,
+            [
+                (TransferOutcome::Done, 3),
+                (TransferOutcome::Cancelled { partial: true }, 4),
+                (TransferOutcome::Cancelled { partial: false }, 4),
+            ]
+        );
+        assert_eq!(client.uploaded.lock().unwrap().len(), 2);
+        let outcomes = outcomes(&results);
+        assert_eq!(outcomes[0], &TransferOutcome::Done);
+        assert_eq!(outcomes[1], &TransferOutcome::Cancelled { partial: true });
+        assert_eq!(outcomes[2], &TransferOutcome::Cancelled { partial: false });
+    }
+
+    #[test]
+    fn test_lost_connection_skips_the_rest() {
+        let mut client = Arc::new(MockClient::new(vec![("b.txt", ErrorKind::ConnectionReset)]));
+        let items = uploads(&["a.txt", "b.txt", "c.txt"]);
+        let progress = Mutex::new(TransferProgress::new(items.len()));
+
+        let results = run_transfers(
+            &mut client,
+            || Err(anyhow::anyhow!("connection refused")),
+            "server",
+            &items,
+            &progress,
+            TransferOptions::default(),
+            &AtomicBool::new(false),
+            &mut |_| {},
+        );
+
+        assert_eq!(client.uploaded.lock().unwrap().len(), 2);
+        let outcomes = outcomes(&results);
+        assert_eq!(outcomes[0], &TransferOutcome::Done);
+        assert!(matches!(outcomes[1], TransferOutcome::Failed(_)));
+        assert!(matches!(outcomes[2], TransferOutcome::Skipped(_)));
+        assert_eq!(
+            progress.lock().unwrap().status.as_deref(),
+            Some("Reconnecting to server (attempt 1)...")
+        );
+    }
+
+    fn seconds_apart(a: SystemTime, b: SystemTime) -> u64 {
+        a.duration_since(b)
+            .or_else(|_| b.duration_since(a))
+            .unwrap()
+            .as_secs()
+    }
+
+    #[test]
+    fn test_preserve_times() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let source = dir.path().join("build.log");
+        fs::write(&source, "log")?;
+        let old = UNIX_EPOCH + Duration::from_secs(1_300_000_000);
+        set_local_times(
+            &source,
+            Timestamps {
+                accessed: old,
+                modified: old,
+            },
+        )?;
+        // The mock doesn't write downloads, so give it a file to stamp
+        let downloaded = dir.path().join("remote.log");
+        fs::write(&downloaded, "remote")?;
+
+        let mut client = Arc::new(MockClient::new(vec![]));
+        let items = vec![
+            TransferItem {
+                source: source.clone(),
+                destination: PathBuf::from("/remote/build.log"),
+                direction: TransferDirection::Upload,
+                remove_source: false,
+                enabled: true,
+            },
+            TransferItem {
+                source: PathBuf::from("/remote/remote.log"),
+                destination: downloaded.clone(),
+                direction: TransferDirection::Download,
+                remove_source: false,
+                enabled: true,
+            },
+        ];
+        let progress = Mutex::new(TransferProgress::new(items.len()));
+        let options = TransferOptions {
+            preserve_times: true,
+            ..TransferOptions::default()
+        };
+
+        let results = run_transfers(
+            &mut client,
+            || Err(anyhow::anyhow!("no reconnect expected")),
+            "server",
+            &items,
+            &progress,
+            options,
+            &AtomicBool::new(false),
+            &mut |_| {},
+        );
+        assert!(results.iter().all(|r| r.outcome == TransferOutcome::Done));
+
+        let times_set = client.times_set.lock().unwrap();
+        assert_eq!(times_set[0].0, Path::new("/remote/build.log"));
+        assert!(seconds_apart(times_set[0].1.modified, old) < 1);
+
+        let local = local_times(&downloaded)?;
+        assert!(seconds_apart(local.modified, client.remote_times.modified) < 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_upload_creates_missing_parents() {
+        let mut client = Arc::new(MockClient::new(Vec::new()));
+        let items = vec![TransferItem {
+            source: PathBuf::from("c.txt"),
+            destination: PathBuf::from("/a/b/c.txt"),
+            direction: TransferDirection::Upload,
+            remove_source: false,
+            enabled: true,
+        }];
+        let progress = Mutex::new(TransferProgress::new(items.len()));
+
+        let results = run_transfers(
+            &mut client,
+            || Err(anyhow::anyhow!("no reconnect expected")),
+            "server",
+            &items,
+            &progress,
+            TransferOptions::default(),
+            &AtomicBool::new(false),
+            &mut |_| {},
+        );
+
+        // The mock refuses uploads into a missing directory, so the upload
+        // succeeding means a and a/b were made first.
+        assert_eq!(outcomes(&results), vec![&TransferOutcome::Done]);
+        assert_eq!(
+            *client.directories.lock().unwrap(),
+            vec![
+                PathBuf::from("/"),
+                PathBuf::from("/a"),
+                PathBuf::from("/a/b")
+            ]
+        );
+        assert_eq!(
+            *client.uploaded.lock().unwrap(),
+            vec![PathBuf::from("c.txt")]
+        );
+    }
+
+    #[test]
+    fn test_parallel_workers_respect_the_limit() {
+        let mock = MockClient {
+            chunk_delay: Duration::from_millis(5),
+            ..MockClient::new(Vec::new())
+        };
+        let connections = AtomicUsize::new(1);
+        let mut client = Arc::new(mock.clone());
+        let names: Vec<String> = (0..12).map(|i| format!("{i}.txt")).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let items = uploads(&names);
+        let progress = Mutex::new(TransferProgress::new(items.len()));
+        let options = TransferOptions {
+            workers: 3,
+            ..TransferOptions::default()
+        };
+
+        let mut reported = 0;
+        let results = run_transfers(
+            &mut client,
+            || {
+                connections.fetch_add(1, Ordering::SeqCst);
+                Ok(mock.clone())
+            },
+            "server",
+            &items,
+            &progress,
+            options,
+            &AtomicBool::new(false),
+            &mut |_| reported += 1,
+        );
+
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+        // The workers ran side by side, but never more of them than allowed
+        let peak = mock.peak_running.load(Ordering::SeqCst);
+        assert!((2..=3).contains(&peak), "peak of {peak} at once");
+        assert_eq!(reported, items.len());
+        // Results come back in queue order, whichever worker finished first
+        let finished: Vec<&Path> = results.iter().map(|r| r.item.source.as_path()).collect();
+        assert_eq!(finished, names.iter().map(Path::new).collect::<Vec<_>>());
+        assert!(results.iter().all(|r| r.outcome == TransferOutcome::Done));
+        assert_eq!(mock.uploaded.lock().unwrap().len(), items.len());
+
+        let progress = progress.lock().unwrap();
+        assert_eq!(progress.items_finished, items.len());
+        assert!(progress.active.is_empty());
+    }
+
+    #[test]
+    fn test_overall_ratio_counts_every_running_item() {
+        let mut progress = TransferProgress::new(2);
+        progress.set_queue_sizes(&[Some(100), Some(100)]);
+        progress.start_item(0, "a".to_string(), Some(100));
+        progress.start_item(1, "b".to_string(), Some(100));
+        progress.advance(0, 50);
+        progress.advance(1, 30);
+        assert_eq!(progress.overall_ratio(), Some(0.4));
+
+        // The oldest running item is the one shown in detail
+        progress.finish_item(0, Some(100));
+        assert_eq!(progress.current().unwrap().name, "b");
+        assert_eq!(progress.overall_ratio(), Some(0.65));
+    }
+
+    #[test]
+    fn test_skip_identical() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mock = MockClient {
+            remote_files: HashMap::from([
+                (PathBuf::from("/remote/same.txt"), b"same".to_vec()),
+                (PathBuf::from("/remote/touched.txt"), b"same".to_vec()),
+                (PathBuf::from("/remote/changed.txt"), b"older".to_vec()),
+            ]),
+            ..MockClient::new(Vec::new())
+        };
+        let write = |name: &str, contents: &str, modified: SystemTime| -> Result<PathBuf> {
+            let path = dir.path().join(name);
+            fs::write(&path, contents)?;
+            set_local_times(
+                &path,
+                Timestamps {
+                    accessed: modified,
+                    modified,
+                },
+            )?;
+            Ok(path)
+        };
+        let remote_modified = mock.remote_times.modified;
+        let sources = [
+            write("same.txt", "same", remote_modified)?,
+            // Same contents, but modified since the last copy
+            write(
+                "touched.txt",
+                "same",
+                remote_modified + Duration::from_secs(60),
+            )?,
+            write("changed.txt", "new", remote_modified)?,
+        ];
+        let items: Vec<TransferItem> = sources
+            .iter()
+            .map(|source| TransferItem {
+                source: source.clone(),
+                destination: Path::new("/remote").join(source.file_name().unwrap()),
+                direction: TransferDirection::Upload,
+                remove_source: false,
+                enabled: true,
+            })
+            .collect();
+
+        let run = |skip_identical| {
+            let client = mock.clone();
+            client.uploaded.lock().unwrap().clear();
+            let progress = Mutex::new(TransferProgress::new(items.len()));
+            let options = TransferOptions {
+                skip_identical,
+                ..TransferOptions::default()
+            };
+            let results = run_transfers(
+                &mut Arc::new(client),
+                || Err(anyhow::anyhow!("no reconnect expected")),
+                "server",
+                &items,
+                &progress,
+                options,
+                &AtomicBool::new(false),
+                &mut |_| {},
+            );
+            assert_eq!(
+                results[0].outcome,
+                match skip_identical {
+                    SkipIdentical::Off => TransferOutcome::Done,
+                    _ => TransferOutcome::Skipped("identical to the destination".to_string()),
+                }
+            );
+            mock.uploaded.lock().unwrap().clone()
+        };
+
+        assert_eq!(run(SkipIdentical::Off), sources.to_vec());
+        assert_eq!(
+            run(SkipIdentical::SizeAndTime),
+            vec![sources[1].clone(), sources[2].clone()]
+        );
+        assert_eq!(run(SkipIdentical::Checksum), vec![sources[2].clone()]);
+
+        // Moving onto an identical copy just removes the source
+        let moved = TransferItem {
+            remove_source: true,
+            ..items[0].clone()
+        };
+        let results = run_transfers(
+            &mut Arc::new(mock.clone()),
+            || Err(anyhow::anyhow!("no reconnect expected")),
+            "server",
+            std::slice::from_ref(&moved),
+            &Mutex::new(TransferProgress::new(1)),
+            TransferOptions {
+                skip_identical: SkipIdentical::Checksum,
+                ..TransferOptions::default()
+            },
+            &AtomicBool::new(false),
+            &mut |_| {},
+        );
+        assert_eq!(results[0].outcome, TransferOutcome::Done);
+        assert!(!sources[0].exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_uploads() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        // What each upload leaves on the server
+        let mock = MockClient {
+            remote_files: HashMap::from([
+                (PathBuf::from("/remote/good.txt"), b"good".to_vec()),
+                (PathBuf::from("/remote/garbled.txt"), b"g00d".to_vec()),
+                (PathBuf::from("/remote/short.txt"), b"go".to_vec()),
+            ]),
+            ..MockClient::new(Vec::new())
+        };
+        let items = ["good.txt", "garbled.txt", "short.txt"]
+            .into_iter()
+            .map(|name| -> Result<TransferItem> {
+                fs::write(dir.path().join(name), "good")?;
+                Ok(TransferItem {
+                    source: dir.path().join(name),
+                    destination: Path::new("/remote").join(name),
+                    direction: TransferDirection::Upload,
+                    remove_source: false,
+                    enabled: true,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let run = |verify_uploads, server_checksums| {
+            let client = MockClient {
+                server_checksums,
+                ..mock.clone()
+            };
+            let progress = Mutex::new(TransferProgress::new(items.len()));
+            let options = TransferOptions {
+                verify_uploads,
+                ..TransferOptions::default()
+            };
+            run_transfers(
+                &mut Arc::new(client),
+                || Err(anyhow::anyhow!("no reconnect expected")),
+                "server",
+                &items,
+                &progress,
+                options,
+                &AtomicBool::new(false),
+                &mut |_| {},
+            )
+            .into_iter()
+            .map(|result| result.outcome)
+            .collect::<Vec<_>>()
+        };
+        let checksum_failed = TransferOutcome::Failed(
+            "verification failed: checksum of /remote/garbled.txt doesn't match".to_string(),
+        );
+        let size_failed = TransferOutcome::Failed(
+            "verification failed: /remote/short.txt has 2 bytes instead of 4".to_string(),
+        );
+
+        assert_eq!(run(false, true), vec![TransferOutcome::Done; 3]);
+        assert_eq!(
+            run(true, true),
+            vec![TransferOutcome::Done, checksum_failed, size_failed.clone()]
+        );
+        // Without a checksum from the server only the size is compared
+        assert_eq!(
+            run(true, false),
+            vec![TransferOutcome::Done, TransferOutcome::Done, size_failed]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_same_content() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let local = dir.path().join("notes.txt");
+        fs::write(&local, "draft")?;
+        let mut client = MockClient::new(vec![]);
+        client
+            .remote_files
+            .insert(PathBuf::from("/srv/notes.txt"), b"draft".to_vec());
+        client
+            .remote_files
+            .insert(PathBuf::from("/srv/other.txt"), b"final".to_vec());
+
+        let check = same_content(&client, &local, Path::new("/srv/notes.txt"))?;
+        assert_eq!(
+            check,
+            ContentCheck {
+                identical: true,
+                server_hash_error: None
+            }
+        );
+        assert!(!same_content(&client, &local, Path::new("/srv/other.txt"))?.identical);
+
+        // A server that can't hash has its copy streamed back, and says why
+        client.server_checksums = false;
+        let check = same_content(&client, &local, Path::new("/srv/notes.txt"))?;
+        assert!(check.identical);
+        assert_eq!(
+            check.server_hash_error.as_deref(),
+            Some("sha256sum: command not found")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_directory_upload_merges_into_existing() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("a.txt"), "a")?;
+        fs::create_dir(dir.path().join("sub"))?;
+        fs::write(dir.path().join("sub/b.txt"), "b")?;
+        let client = MockClient::new(Vec::new());
+        // The target and its subdirectory are already on the server
+        client.directories.lock().unwrap().extend([
+            PathBuf::from("/remote"),
+            PathBuf::from("/remote/dir"),
+            PathBuf::from("/remote/dir/sub"),
+        ]);
+        let items = vec![TransferItem {
+            source: dir.path().to_path_buf(),
+            destination: PathBuf::from("/remote/dir"),
+            direction: TransferDirection::Upload,
+            remove_source: false,
+            enabled: true,
+        }];
+        let progress = Mutex::new(TransferProgress::new(items.len()));
+
+        let mut client = Arc::new(client);
+        let results = run_transfers(
+            &mut client,
+            || Err(anyhow::anyhow!("no reconnect expected")),
+            "server",
+            &items,
+            &progress,
+            TransferOptions::default(),
+            &AtomicBool::new(false),
+            &mut |_| {},
+        );
+
+        assert_eq!(outcomes(&results), vec![&TransferOutcome::Done]);
+        let mut uploaded = client.uploaded.lock().unwrap().clone();
+        uploaded.sort();
+        assert_eq!(
+            uploaded,
+            vec![dir.path().join("a.txt"), dir.path().join("sub/b.txt")]
+        );
+        assert_eq!(client.directories.lock().unwrap().len(), 4);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::app::TransferDirection;
+use crate::config::AppConfig;
+use crate::transfer::{TransferOutcome, TransferResult};
+
+/// Record of finished transfers, one JSON object per line, appended to
+/// `<config dir>/sftui/transfers.jsonl` when `transfer_log` is enabled.
+#[derive(Debug, Clone)]
+pub struct TransferLog {
+    path: PathBuf,
+}
+
+impl TransferLog {
+    /// The log in the config dir, if there is one.
+    pub fn open() -> Option<Self> {
+        AppConfig::dir().map(|dir| Self::at(&dir.join("transfers.jsonl")))
+    }
+
+    pub fn at(path: &Path) -> Self {
+        TransferLog {
+            path: path.to_path_buf(),
+        }
+    }
+
+    /// Appends a line for `result`, stamped with the current time.
+    pub fn record(&self, result: &TransferResult) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+
+        let mut line = Vec::new();
+        serde_json::to_writer(&mut line, &Record::new(result, timestamp))?;
+        line.push(b'\n');
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        // One write per line, so concurrent sessions don't interleave records
+        file.write_all(&line)?;
+        Ok(())
+    }
+}
+
+/// One line of the log: when the transfer finished (seconds since the Unix
+/// epoch), what was transferred where, its size if known, and how it ended.
+/// Failed and skipped items also carry the reason.
+#[derive(Debug, Serialize)]
+struct Record<'a> {
+    timestamp: u64,
+    direction: &'static str,
+    /// Paths that aren't UTF-8 have the offending bytes replaced.
+    source: Cow<'a, str>,
+    destination: Cow<'a, str>,
+    bytes: Option<u64>,
+    result: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<&'a str>,
+}
+
+impl<'a> Record<'a> {
+    fn new(result: &'a TransferResult, timestamp: u64) -> Self {
+        let item = &result.item;
+        let direction = match item.direction {
+            TransferDirection::Upload => "upload",
+            TransferDirection::Download => "download",
+        };
+        let (outcome, message) = match &result.outcome {
+            TransferOutcome::Done => ("done", None),
+            TransferOutcome::Failed(reason) => ("failed", Some(reason.as_str())),
+            TransferOutcome::Skipped(reason) => ("skipped", Some(reason.as_str())),
+            TransferOutcome::Cancelled { .. } => ("cancelled", None),
+        };
+
+        Record {
+            timestamp,
+            direction,
+            source: item.source.to_string_lossy(),
+            destination: item.destination.to_string_lossy(),
+            bytes: result.bytes,
+            result: outcome,
+            message,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::TransferItem;
+
+    fn result(direction: TransferDirection, outcome: TransferOutcome) -> TransferResult {
+        TransferResult {
+            item: TransferItem {
+                source: PathBuf::from("/home/me/report \"final\".pdf"),
+                destination: PathBuf::from("/srv/report \"final\".pdf"),
+                direction,
+                remove_source: false,
+                enabled: true,
+            },
+            outcome,
+            bytes: Some(2048),
+            hook: None,
+        }
+    }
+
+    #[test]
+    fn test_record_line() -> Result<()> {
+        let line = |result: &TransferResult, timestamp| {
+            serde_json::to_string(&Record::new(result, timestamp))
+        };
+        assert_eq!(
+            line(
+                &result(TransferDirection::Upload, TransferOutcome::Done),
+                1700000000
+            )?,
+            r#"{"timestamp":1700000000,"direction":"upload","source":"/home/me/report \"final\".pdf","destination":"/srv/report \"final\".pdf","bytes":2048,"result":"done"}"#
+        );
+
+        let mut failed = result(
+            TransferDirection::Download,
+            TransferOutcome::Failed("No such file\nor directory".to_string()),
+        );
+        failed.bytes = None;
+        assert_eq!(
+            line(&failed, 5)?,
+            r#"{"timestamp":5,"direction":"download","source":"/home/me/report \"final\".pdf","destination":"/srv/report \"final\".pdf","bytes":null,"result":"failed","message":"No such file\nor directory"}"#
+        );
+
+        // Control characters are escaped too
+        let skipped = result(
+            TransferDirection::Upload,
+            TransferOutcome::Skipped("tab\tbell\u{7}".to_string()),
+        );
+        assert!(line(&skipped, 5)?.ends_with(r#""message":"tab\tbell\u0007"}"#));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_appends_lines() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let log = TransferLog::at(&dir.path().join("state/transfers.jsonl"));
+
+        log.record(&result(TransferDirection::Upload, TransferOutcome::Done))?;
+        log.record(&result(
+            TransferDirection::Upload,
+            TransferOutcome::Cancelled { partial: true },
+        ))?;
+
+        let content = fs::read_to_string(dir.path().join("state/transfers.jsonl"))?;
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(r#"{"timestamp":"#));
+        assert!(lines[0].ends_with(r#""bytes":2048,"result":"done"}"#));
+        assert!(lines[1].ends_with(r#""result":"cancelled"}"#));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::sftp::FileInfo;
+
+/// A pane shown as a tree: expanded directories have their entries listed
+/// right below them, one level further in. A directory's entries are listed
+/// when it is expanded and kept until it is collapsed or the pane relisted.
+#[derive(Debug, Clone, Default)]
+pub struct TreeView {
+    /// The entries of each expanded directory, sorted like the pane.
+    expanded: BTreeMap<PathBuf, Vec<FileInfo>>,
+}
+
+impl TreeView {
+    pub fn is_expanded(&self, dir: &Path) -> bool {
+        self.expanded.contains_key(dir)
+    }
+
+    pub fn expand(&mut self, dir: PathBuf, entries: Vec<FileInfo>) {
+        self.expanded.insert(dir, entries);
+    }
+
+    /// Collapses `dir` along with everything expanded inside it.
+    pub fn collapse(&mut self, dir: &Path) {
+        self.expanded.retain(|path, _| !path.starts_with(dir));
+    }
+
+    /// The entries listed under `dir`, if it is expanded.
+    pub fn entries(&self, dir: &Path) -> Option<&[FileInfo]> {
+        self.expanded.get(dir).map(Vec::as_slice)
+    }
+
+    /// The expanded directories, each before the ones inside it.
+    pub fn expanded_dirs(&self) -> Vec<PathBuf> {
+        self.expanded.keys().cloned().collect()
+    }
+
+    /// Forgets what was expanded outside `root`, once the pane has moved
+    /// there. What is inside stays expanded.
+    pub fn retain_under(&mut self, root: &Path) {
+        self.expanded
+            .retain(|path, _| path != root && path.starts_with(root));
+    }
+
+    /// `entries` with the entries of each expanded directory after it, their
+    /// `depth` one more than the directory's. `..` is never expanded.
+    pub fn flatten(&self, entries: &[FileInfo]) -> Vec<FileInfo> {
+        let mut rows = Vec::new();
+        self.push_rows(entries, 0, &mut rows);
+        rows
+    }
+
+    fn push_rows(&self, entries: &[FileInfo], depth: usize, rows: &mut Vec<FileInfo>) {
+        for entry in entries {
+            rows.push(FileInfo {
+                depth,
+                ..entry.clone()
+            });
+            if entry.name != ".."
+                && let Some(children) = self.expanded.get(&entry.path)
+            {
+                self.push_rows(children, depth + 1, rows);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, is_dir: bool) -> FileInfo {
+        let path = PathBuf::from(path);
+        FileInfo {
+            name: path.file_name().map_or_else(
+                || "..".to_string(),
+                |name| name.to_string_lossy().into_owned(),
+            ),
+            path,
+            is_dir,
+            ..FileInfo::default()
+        }
+    }
+
+    fn rows(tree: &TreeView, entries: &[FileInfo]) -> Vec<(usize, String)> {
+        tree.flatten(entries)
+            .into_iter()
+            .map(|row| (row.depth, row.name))
+            .collect()
+    }
+
+    #[test]
+    fn test_flatten_with_expansion() {
+        let root = [
+            entry("/", true),
+            entry("/srv/app", true),
+            entry("/srv/logs", true),
+            entry("/srv/README", false),
+        ];
+        let mut tree = TreeView::default();
+
+        // Nothing expanded lists just the directory itself
+        assert_eq!(
+            rows(&tree, &root),
+            [(0, ".."), (0, "app"), (0, "logs"), (0, "README")]
+                .map(|(depth, name)| (depth, name.to_string()))
+        );
+
+        tree.expand(
+            PathBuf::from("/srv/app"),
+            vec![
+                entry("/srv/app/src", true),
+                entry("/srv/app/main.rs", false),
+            ],
+        );
+        tree.expand(
+            PathBuf::from("/srv/app/src"),
+            vec![entry("/srv/app/src/lib.rs", false)],
+        );
+        // Expanding `..` does nothing
+        tree.expand(PathBuf::from("/"), vec![entry("/etc", true)]);
+
+        assert_eq!(
+            rows(&tree, &root),
+            [
+                (0, ".."),
+                (0, "app"),
+                (1, "src"),
+                (2, "lib.rs"),
+                (1, "main.rs"),
+                (0, "logs"),
+                (0, "README"),
+            ]
+            .map(|(depth, name)| (depth, name.to_string()))
+        );
+
+        // Collapsing a directory collapses what is inside it too
+        tree.collapse(Path::new("/srv/app"));
+        assert!(!tree.is_expanded(Path::new("/srv/app/src")));
+        tree.expand(PathBuf::from("/srv/app"), vec![]);
+        assert_eq!(rows(&tree, &root).len(), 4);
+    }
+
+    #[test]
+    fn test_retain_under() {
+        let mut tree = TreeView::default();
+        for dir in [
+            "/srv",
+            "/srv/app",
+            "/srv/app/src",
+            "/srv/application",
+            "/etc",
+        ] {
+            tree.expand(PathBuf::from(dir), vec![]);
+        }
+
+        tree.retain_under(Path::new("/srv/app"));
+        assert_eq!(tree.expanded_dirs(), [PathBuf::from("/srv/app/src")]);
+    }
+}
@@ -0,0 +1,71 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+use crate::store::{Persist, Stored};
+
+/// Host key fingerprints the user has accepted, stored one `address
+/// fingerprint` pair per line in `<config dir>/sftui/trusted_hosts`.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedHosts {
+    keys: BTreeMap<String, String>,
+}
+
+impl TrustedHosts {
+    pub fn load() -> Stored<Self> {
+        Stored::load("trusted_hosts")
+    }
+
+    /// The fingerprint accepted for `address` (`hostname:port`), if any.
+    pub fn get(&self, address: &str) -> Option<&str> {
+        self.keys.get(address).map(String::as_str)
+    }
+
+    pub fn trust(&mut self, address: &str, fingerprint: &str) {
+        self.keys
+            .insert(address.to_string(), fingerprint.to_string());
+    }
+}
+
+impl Persist for TrustedHosts {
+    fn parse(content: &str) -> Self {
+        let keys = content
+            .lines()
+            .filter_map(|line| line.trim().split_once(' '))
+            .map(|(address, key)| (address.to_string(), key.trim().to_string()))
+            .collect();
+        TrustedHosts { keys }
+    }
+
+    fn serialize(&self) -> Result<String> {
+        Ok(self
+            .keys
+            .iter()
+            .map(|(address, key)| format!("{address} {key}\n"))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trust_save_and_load() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("sftui").join("trusted_hosts");
+
+        let mut trusted = Stored::<TrustedHosts>::from_path(&path);
+        assert!(trusted.get("example.com:22").is_none());
+        trusted.trust("example.com:22", "SHA256:abc");
+        trusted.trust("10.0.0.5:2222", "SHA256:def");
+        // A new key replaces the old one
+        trusted.trust("example.com:22", "SHA256:xyz");
+        trusted.save()?;
+
+        let loaded = Stored::<TrustedHosts>::from_path(&path);
+        assert_eq!(loaded.get("example.com:22"), Some("SHA256:xyz"));
+        assert_eq!(loaded.get("10.0.0.5:2222"), Some("SHA256:def"));
+
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use anyhow::Result;
 use crossterm::{
+    cursor,
     event::{DisableMouseCapture, EnableMouseCapture, Event, KeyEventKind},
     execute,
     terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
@@ -9,15 +10,30 @@ use ratatui::{
     backend::CrosstermBackend,
     layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
-    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, Gauge, List, ListItem, ListState, Paragraph, Tabs, Wrap},
 };
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::io;
+use std::ops::Range;
 use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::app::{App, Pane, TransferItem};
-use crate::sftp::FileInfo;
+use crate::app::{
+    App, BulkRename, DeepSearch, DestinationEdit, DestinationScope, LargeTransfer, Pane,
+    PendingHostKey, PendingHostSettings, TransferDirection, TransferItem,
+};
+use crate::compare::{self, Presence};
+use crate::hook::HookStatus;
+use crate::keymap::{Action, Keymap};
+use crate::rename::RenameStatus;
+use crate::search;
+use crate::sftp::{AuthMethod, DiskSpace, FileInfo};
 use crate::ssh_config::SshHost;
+use crate::transfer::{
+    TransferOutcome, TransferProgress, TransferResult, format_duration, format_size, format_speed,
+};
+use crate::tree::TreeView;
 
 pub struct Ui {
     terminal: Terminal<CrosstermBackend<io::Stdout>>,
@@ -34,7 +50,10 @@ impl Ui {
         Ok(Ui { terminal })
     }
 
-    pub fn draw(&mut self, app: &App) -> Result<()> {
+    /// Draws the interface and returns the areas of the local and remote
+    /// panes, which the grid view's cursor movement and looking up the
+    /// visible symlinks depend on.
+    pub fn draw(&mut self, app: &App) -> Result<[Rect; 2]> {
         let current_host = app.current_host.clone();
         let active_pane = app.active_pane.clone();
         let local_path = app.local_path.clone();
@@ -45,16 +64,76 @@ impl Ui {
         let remote_selected = app.remote_selected.clone();
         let show_connection_dialog = app.show_connection_dialog;
         let show_transfer_dialog = app.show_transfer_dialog;
-        let available_hosts = app.available_hosts.clone();
+        let available_hosts: Vec<SshHost> = app.connection_hosts().into_iter().cloned().collect();
+        let recent_hosts = app.recent_hosts.hosts().to_vec();
+        let connection_filter = app.connection_filter.clone();
         let connection_cursor = app.connection_cursor;
+        let custom_host_input = app.custom_host_input.clone();
+        let custom_host_error = app.custom_host_error.clone();
         let transfer_queue = app.transfer_queue.clone();
+        let queue_cursor = app.queue_cursor;
+        let tab_titles = app.tab_titles();
+        let active_tab = app.active_tab;
+        let transfer_progress = app
+            .transfer_progress
+            .as_ref()
+            .and_then(|progress| progress.lock().ok().map(|p| p.clone()));
+
+        let search =
+            (app.search_mode && !app.search_query.is_empty()).then_some(app.search_query.as_str());
+        let local_placeholder = empty_placeholder(app.get_current_local_files(), search);
+        // A disconnected pane isn't an empty directory
+        let remote_placeholder = app
+            .sftp_client
+            .as_ref()
+            .and_then(|_| empty_placeholder(app.get_current_remote_files(), search));
+        let remote_space = app
+            .sftp_client
+            .as_ref()
+            .map(|_| space_label(app.remote_space));
+
+        let local_home = dirs::home_dir();
+        let remote_home = app
+            .sftp_client
+            .as_ref()
+            .map(|client| client.home().to_path_buf());
+        let title_path = |path: &Path, home: Option<&Path>| {
+            if app.abbreviate_paths {
+                abbreviate_path(path, home)
+            } else {
+                path.display().to_string()
+            }
+        };
+        let tree_label = |tree: &Option<TreeView>| if tree.is_some() { " [tree]" } else { "" };
+        let local_title = title_path(&local_path, local_home.as_deref())
+            + app.local_type_filter.label()
+            + tree_label(&app.local_tree);
+        let remote_title = title_path(&remote_path, remote_home.as_deref())
+            + app.remote_type_filter.label()
+            + tree_label(&app.remote_tree);
+        let remote_real_title = app
+            .remote_realpath
+            .as_deref()
+            .filter(|real| *real != remote_path)
+            .map(|real| title_path(real, remote_home.as_deref()));
+
+        let compressed = app.sftp_client.as_ref().is_some_and(|c| c.compressed());
+        let auth_method = app.sftp_client.as_ref().map(|c| c.auth_method());
 
+        // Recomputed every frame, so it follows navigation and refreshes
+        let comparison = app
+            .compare_dirs
+            .then(|| compare::compare_listings(&app.local_files, &app.remote_files));
+
+        let mut pane_areas = [Rect::default(); 2];
+        let drawn_areas = &mut pane_areas;
         self.terminal.draw(move |f| {
             let chunks = Layout::default()
                 .direction(Direction::Vertical)
                 .constraints(
                     [
                         Constraint::Length(3),
+                        Constraint::Length(1),
                         Constraint::Min(0),
                         Constraint::Length(3),
                     ]
@@ -62,109 +141,270 @@ impl Ui {
                 )
                 .split(f.area());
 
-            Ui::draw_header(f, chunks[0], &current_host);
-            Ui::draw_panes(
+            Ui::draw_header(
+                f,
+                chunks[0],
+                &current_host,
+                app.readonly,
+                compressed,
+                auth_method,
+            );
+            Ui::draw_tab_bar(f, chunks[1], &tab_titles, active_tab);
+            *drawn_areas = Ui::draw_panes(
                 f,
-                chunks[1],
+                chunks[2],
                 &active_pane,
-                &local_path,
-                &remote_path,
+                &local_title,
+                &remote_title,
+                remote_real_title.as_deref(),
+                remote_space.as_deref(),
                 app.get_current_local_files(),
                 app.get_current_remote_files(),
                 local_cursor,
                 remote_cursor,
                 &local_selected,
                 &remote_selected,
+                local_placeholder.as_deref(),
+                remote_placeholder.as_deref(),
+                comparison.as_ref(),
+                app.config.ascii_icons,
+                app.grid_view,
+                app.vertical_split,
+                app.panes_swapped,
+            );
+            Ui::draw_footer(
+                f,
+                chunks[3],
+                app.search_editing,
+                &app.search_query,
+                app.search_position(),
+                app.search_counts(),
+                app.status_message.as_deref(),
+                &app.keymap,
+                app.grid_view,
             );
-            Ui::draw_footer(f, chunks[2], app.search_mode, &app.search_query);
 
             if show_connection_dialog {
-                Ui::draw_connection_dialog(f, &available_hosts, connection_cursor);
+                Ui::draw_connection_dialog(
+                    f,
+                    &available_hosts,
+                    connection_cursor,
+                    &connection_filter,
+                    &recent_hosts,
+                );
+
+                if let Some(input) = &custom_host_input {
+                    Ui::draw_custom_host_input(f, input, custom_host_error.as_deref());
+                }
+            }
+
+            if let Some(input) = &app.new_file_input {
+                Ui::draw_new_file_input(f, input, app.new_file_error.as_deref());
+            }
+
+            if let Some(file) = &app.file_details {
+                Ui::draw_file_details(f, file);
+            }
+
+            if let Some(rename) = &app.bulk_rename {
+                Ui::draw_bulk_rename(f, rename);
+            }
+
+            if let Some(deep_search) = &app.deep_search {
+                Ui::draw_deep_search(f, deep_search);
+            }
+
+            if let Some(results) = &app.transfer_results {
+                Ui::draw_transfer_summary(f, results, app.summary_cursor);
             }
 
             if show_transfer_dialog {
-                Ui::draw_transfer_dialog(f, &transfer_queue);
+                Ui::draw_transfer_dialog(
+                    f,
+                    &transfer_queue,
+                    queue_cursor,
+                    transfer_progress.as_ref(),
+                    &local_path,
+                    &remote_path,
+                );
+
+                if let Some(edit) = &app.destination_edit {
+                    Ui::draw_destination_edit(f, edit);
+                }
+
+                if let Some(large) = &app.large_transfer {
+                    Ui::draw_large_transfer_warning(f, large);
+                }
+            }
+
+            if let Some(connecting) = &app.connecting {
+                Ui::draw_connecting(f, &connecting.params.host.host);
+            }
+
+            if let Some(pending) = &app.pending_host_settings {
+                Ui::draw_host_settings_prompt(f, pending);
+            }
+
+            if let Some(pending) = &app.pending_host_key {
+                Ui::draw_host_key_prompt(f, pending);
             }
         })?;
 
-        Ok(())
+        Ok(pane_areas)
     }
 
-    fn draw_header(f: &mut Frame, area: Rect, current_host: &Option<String>) {
-        let title = format!(
+    fn draw_header(
+        f: &mut Frame,
+        area: Rect,
+        current_host: &Option<String>,
+        readonly: bool,
+        compressed: bool,
+        auth_method: Option<AuthMethod>,
+    ) {
+        let mut title = format!(
             "SFTP TUI - Connected to: {}",
             current_host
                 .as_ref()
                 .unwrap_or(&"Not Connected".to_string())
         );
+        if readonly {
+            title.push_str(" [read-only]");
+        }
+        if compressed {
+            title.push_str(" [compressed]");
+        }
+        if let Some(method) = auth_method {
+            title.push_str(&format!(" [auth: {method}]"));
+        }
         let header = Paragraph::new(title)
             .block(Block::default().borders(Borders::ALL))
             .style(Style::default().fg(Color::Yellow));
         f.render_widget(header, area);
     }
 
+    fn draw_tab_bar(f: &mut Frame, area: Rect, titles: &[String], active_tab: usize) {
+        let tabs = Tabs::new(titles.to_vec())
+            .select(active_tab)
+            .style(Style::default().fg(Color::DarkGray))
+            .highlight_style(
+                Style::default()
+                    .fg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD),
+            );
+        f.render_widget(tabs, area);
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn draw_panes(
         f: &mut Frame,
         area: Rect,
         active_pane: &Pane,
-        local_path: &Path,
-        remote_path: &Path,
+        local_title: &str,
+        remote_title: &str,
+        remote_real_title: Option<&str>,
+        remote_space: Option<&str>,
         local_files: &[FileInfo],
         remote_files: &[FileInfo],
         local_cursor: usize,
         remote_cursor: usize,
         local_selected: &HashSet<usize>,
         remote_selected: &HashSet<usize>,
-    ) {
-        let panes = Layout::default()
-            .direction(Direction::Horizontal)
-            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
-            .split(area);
+        local_placeholder: Option<&str>,
+        remote_placeholder: Option<&str>,
+        comparison: Option<&HashMap<String, Presence>>,
+        ascii_icons: bool,
+        grid_view: bool,
+        vertical_split: bool,
+        panes_swapped: bool,
+    ) -> [Rect; 2] {
+        let panes = pane_areas(area, vertical_split);
+        let local_area = panes[Pane::Local.column(panes_swapped)];
+        let remote_area = panes[Pane::Remote.column(panes_swapped)];
 
         Ui::draw_local_pane(
             f,
-            panes[0],
+            local_area,
             active_pane,
-            local_path,
+            local_title,
             local_files,
             local_cursor,
             local_selected,
+            local_placeholder,
+            comparison,
+            ascii_icons,
+            grid_view,
         );
         Ui::draw_remote_pane(
             f,
-            panes[1],
+            remote_area,
             active_pane,
-            remote_path,
+            remote_title,
+            remote_real_title,
+            remote_space,
             remote_files,
             remote_cursor,
             remote_selected,
+            remote_placeholder,
+            comparison,
+            ascii_icons,
+            grid_view,
         );
+
+        [local_area, remote_area]
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn draw_local_pane(
         f: &mut Frame,
         area: Rect,
         active_pane: &Pane,
-        local_path: &Path,
+        local_title: &str,
         local_files: &[FileInfo],
         local_cursor: usize,
         local_selected: &HashSet<usize>,
+        placeholder: Option<&str>,
+        comparison: Option<&HashMap<String, Presence>>,
+        ascii_icons: bool,
+        grid_view: bool,
     ) {
-        let title = format!("Local: {} ({})", local_path.display(), local_files.len());
+        let title = format!("Local: {local_title} ({})", local_files.len());
         let style = if *active_pane == Pane::Local {
             Style::default().fg(Color::Green)
         } else {
             Style::default()
         };
 
+        if grid_view {
+            let block = Block::default()
+                .borders(Borders::ALL)
+                .title(title)
+                .border_style(style);
+            Ui::draw_file_grid(
+                f,
+                area,
+                block,
+                local_files,
+                local_cursor,
+                local_selected,
+                placeholder,
+                comparison,
+                ascii_icons,
+            );
+            return;
+        }
+
+        // Inside the borders and the highlight symbol
+        let width = usize::from(area.width).saturating_sub(4);
+        // Only the rows on screen are built, however long the listing
+        let window = visible_window(local_cursor, local_files.len(), list_rows(area));
         let items: Vec<ListItem> = local_files
             .iter()
             .enumerate()
+            .skip(window.start)
+            .take(window.len())
             .map(|(i, file)| {
-                let prefix = " ";
-                let name = format!("{}{}", prefix, file.name);
-                let mut item_style = Style::default();
+                let name = file_line(file, width, ascii_icons);
+                let mut item_style = presence_style(comparison, &file.name);
 
                 if local_selected.contains(&i) {
                     item_style = item_style.bg(Color::Blue);
@@ -172,6 +412,7 @@ impl Ui {
 
                 ListItem::new(name).style(item_style)
             })
+            .chain(placeholder.map(placeholder_item))
             .collect();
 
         let list = List::new(items)
@@ -185,33 +426,78 @@ impl Ui {
             .highlight_symbol("> ");
 
         let mut state = ListState::default();
-        state.select(Some(local_cursor));
+        state.select(
+            Some(local_cursor)
+                .filter(|&cursor| cursor < local_files.len())
+                .map(|cursor| cursor - window.start),
+        );
         f.render_stateful_widget(list, area, &mut state);
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn draw_remote_pane(
         f: &mut Frame,
         area: Rect,
         active_pane: &Pane,
-        remote_path: &Path,
+        remote_title: &str,
+        remote_real_title: Option<&str>,
+        remote_space: Option<&str>,
         remote_files: &[FileInfo],
         remote_cursor: usize,
         remote_selected: &HashSet<usize>,
+        placeholder: Option<&str>,
+        comparison: Option<&HashMap<String, Presence>>,
+        ascii_icons: bool,
+        grid_view: bool,
     ) {
-        let title = format!("Remote: {} ({})", remote_path.display(), remote_files.len());
+        let title = match remote_real_title {
+            Some(real) => format!(
+                "Remote: {real} (via {remote_title}) ({})",
+                remote_files.len()
+            ),
+            None => format!("Remote: {remote_title} ({})", remote_files.len()),
+        };
+        let title = match remote_space {
+            Some(space) => format!("{title} [{space}]"),
+            None => title,
+        };
         let style = if *active_pane == Pane::Remote {
             Style::default().fg(Color::Green)
         } else {
             Style::default()
         };
 
+        if grid_view {
+            let block = Block::default()
+                .borders(Borders::ALL)
+                .title(title)
+                .border_style(style);
+            Ui::draw_file_grid(
+                f,
+                area,
+                block,
+                remote_files,
+                remote_cursor,
+                remote_selected,
+                placeholder,
+                comparison,
+                ascii_icons,
+            );
+            return;
+        }
+
+        // Inside the borders and the highlight symbol
+        let width = usize::from(area.width).saturating_sub(4);
+        // Only the rows on screen are built, however long the listing
+        let window = visible_window(remote_cursor, remote_files.len(), list_rows(area));
         let items: Vec<ListItem> = remote_files
             .iter()
             .enumerate()
+            .skip(window.start)
+            .take(window.len())
             .map(|(i, file)| {
-                let prefix = " ";
-                let name = format!("{}{}", prefix, file.name);
-                let mut item_style = Style::default();
+                let name = file_line(file, width, ascii_icons);
+                let mut item_style = presence_style(comparison, &file.name);
 
                 if remote_selected.contains(&i) {
                     item_style = item_style.bg(Color::Blue);
@@ -219,6 +505,7 @@ impl Ui {
 
                 ListItem::new(name).style(item_style)
             })
+            .chain(placeholder.map(placeholder_item))
             .collect();
 
         let list = List::new(items)
@@ -232,33 +519,111 @@ impl Ui {
             .highlight_symbol("> ");
 
         let mut state = ListState::default();
-        state.select(Some(remote_cursor));
+        state.select(
+            Some(remote_cursor)
+                .filter(|&cursor| cursor < remote_files.len())
+                .map(|cursor| cursor - window.start),
+        );
         f.render_stateful_widget(list, area, &mut state);
     }
 
-    fn draw_footer(f: &mut Frame, area: Rect, search_mode: bool, search_query: &str) {
-        let footer_text = if search_mode {
-            format!("Search: {search_query} | Esc: Cancel | Enter: Exit search")
+    /// Lays the entries out row by row in as many columns as fit, scrolled so
+    /// the cursor's row is visible.
+    #[allow(clippy::too_many_arguments)]
+    fn draw_file_grid(
+        f: &mut Frame,
+        area: Rect,
+        block: Block,
+        files: &[FileInfo],
+        cursor: usize,
+        selected: &HashSet<usize>,
+        placeholder: Option<&str>,
+        comparison: Option<&HashMap<String, Presence>>,
+        ascii_icons: bool,
+    ) {
+        let longest = longest_name(files);
+        let columns = grid_columns(area.width, longest);
+        // A lone column may still be too narrow for the longest name
+        let cell_width = (longest + GRID_MARKER).min(usize::from(area.width).saturating_sub(2));
+        let visible_rows = usize::from(area.height).saturating_sub(2).max(1);
+        let first_row = (cursor / columns).saturating_sub(visible_rows - 1);
+
+        let mut lines: Vec<Line> = files
+            .chunks(columns)
+            .enumerate()
+            .skip(first_row)
+            .take(visible_rows)
+            .map(|(row, entries)| {
+                let mut spans = Vec::new();
+                for (column, file) in entries.iter().enumerate() {
+                    let index = row * columns + column;
+                    if column > 0 {
+                        spans.push(Span::raw(" ".repeat(GRID_GAP)));
+                    }
+                    let cell: String = format!(
+                        "{} {}",
+                        FileKind::of(file).indicator(ascii_icons),
+                        file.name
+                    )
+                    .chars()
+                    .take(cell_width)
+                    .collect();
+                    let mut style = presence_style(comparison, &file.name);
+                    if selected.contains(&index) {
+                        style = style.bg(Color::Blue);
+                    }
+                    if index == cursor {
+                        style = style.add_modifier(Modifier::REVERSED);
+                    }
+                    spans.push(Span::styled(format!("{cell:cell_width$}"), style));
+                }
+                Line::from(spans)
+            })
+            .collect();
+        lines.extend(placeholder.map(|text| Line::styled(text.to_string(), placeholder_style())));
+
+        f.render_widget(Paragraph::new(lines).block(block), area);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn draw_footer(
+        f: &mut Frame,
+        area: Rect,
+        search_editing: bool,
+        search_query: &str,
+        search_position: Option<(usize, usize)>,
+        search_counts: Option<(usize, usize)>,
+        status_message: Option<&str>,
+        keymap: &Keymap,
+        grid_view: bool,
+    ) {
+        let counts = search_counts
+            .map(|(local, remote)| format!(" | {}", pane_counts(local, remote)))
+            .unwrap_or_default();
+        let footer_text = if search_editing {
+            format!(
+                "Search: {search_query}{counts} | Tab: Switch pane | Esc: Cancel | Enter: Exit search"
+            )
+        } else if let Some(message) = status_message {
+            message.to_string()
+        } else if let Some((current, count)) = search_position {
+            format!(
+                "Search: {search_query} | {}{counts} | n/N: Next/previous match | Esc: Clear search",
+                match_counter(current, count)
+            )
         } else {
-            [
-                "Tab: Switch panes",
-                "Space: Select/deselect",
-                "Enter: Change directory",
-                "T: Transfer files",
-                "C: Change connection",
-                "/: Search",
-                "Q: Quit",
-            ]
-            .join(" | ")
+            footer_hints(keymap, grid_view).join(" | ")
         };
 
         let footer = Paragraph::new(footer_text)
             .block(Block::default().borders(Borders::ALL))
-            .style(if search_mode {
-                Style::default().fg(Color::Yellow)
-            } else {
-                Style::default().fg(Color::Cyan)
-            });
+            .style(
+                if search_editing || search_position.is_some() || status_message.is_some() {
+                    Style::default().fg(Color::Yellow)
+                } else {
+                    Style::default().fg(Color::Cyan)
+                },
+            );
         f.render_widget(footer, area);
     }
 
@@ -266,25 +631,36 @@ impl Ui {
         f: &mut Frame,
         available_hosts: &[SshHost],
         connection_cursor: usize,
+        filter: &str,
+        recent_hosts: &[String],
     ) {
         let area = Ui::centered_rect(60, 20, f.area());
 
         f.render_widget(Clear, area);
 
-        let hosts: Vec<ListItem> = available_hosts
-            .iter()
-            .map(|host| {
-                let display = format!(
+        let custom_entry = ListItem::new("Enter custom host...")
+            .style(Style::default().add_modifier(Modifier::ITALIC));
+        let hosts: Vec<ListItem> = std::iter::once(custom_entry)
+            .chain(available_hosts.iter().map(|host| {
+                let mut display = format!(
                     "{} ({})",
                     host.host,
                     host.hostname.as_ref().unwrap_or(&host.host)
                 );
+                if recent_hosts.contains(&host.host) {
+                    display.push_str(" - recent");
+                }
                 ListItem::new(display)
-            })
+            }))
             .collect();
 
+        let title = if filter.is_empty() {
+            "Select Host (type to filter)".to_string()
+        } else {
+            format!("Select Host: {filter}")
+        };
         let list = List::new(hosts)
-            .block(Block::default().borders(Borders::ALL).title("Select Host"))
+            .block(Block::default().borders(Borders::ALL).title(title))
             .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
             .highlight_symbol("> ");
 
@@ -293,37 +669,568 @@ impl Ui {
         f.render_stateful_widget(list, area, &mut state);
     }
 
-    fn draw_transfer_dialog(f: &mut Frame, transfer_queue: &[TransferItem]) {
-        let area = Ui::centered_rect(80, 30, f.area());
+    fn draw_custom_host_input(f: &mut Frame, input: &str, error: Option<&str>) {
+        let area = Ui::centered_rect(50, 20, f.area());
+
+        f.render_widget(Clear, area);
+
+        let mut lines = vec![
+            format!("> {input}"),
+            String::new(),
+            "Format: [user@]hostname[:port]".to_string(),
+        ];
+        if let Some(error) = error {
+            lines.push(format!("Error: {error}"));
+        }
+
+        let style = if error.is_some() {
+            Style::default().fg(Color::Red)
+        } else {
+            Style::default().fg(Color::Yellow)
+        };
+
+        let input_box = Paragraph::new(lines.join("\n")).style(style).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Custom Host (Enter to connect, Esc to go back)"),
+        );
+        f.render_widget(input_box, area);
+    }
+
+    fn draw_new_file_input(f: &mut Frame, input: &str, error: Option<&str>) {
+        let area = Ui::centered_rect(50, 20, f.area());
 
         f.render_widget(Clear, area);
 
-        let items: Vec<ListItem> = transfer_queue
+        let mut lines = vec![format!("> {input}")];
+        if let Some(error) = error {
+            lines.push(String::new());
+            lines.push(format!("Error: {error}"));
+        }
+
+        let style = if error.is_some() {
+            Style::default().fg(Color::Red)
+        } else {
+            Style::default().fg(Color::Yellow)
+        };
+
+        let input_box = Paragraph::new(lines.join("\n")).style(style).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("New File, or dir/ to enter (Enter to create, Esc to cancel)"),
+        );
+        f.render_widget(input_box, area);
+    }
+
+    fn draw_destination_edit(f: &mut Frame, edit: &DestinationEdit) {
+        let area = Ui::centered_rect(60, 20, f.area());
+
+        f.render_widget(Clear, area);
+
+        let mut lines = vec![format!("> {}", edit.input)];
+        if let Some(error) = &edit.error {
+            lines.push(String::new());
+            lines.push(format!("Error: {error}"));
+        }
+
+        let style = if edit.error.is_some() {
+            Style::default().fg(Color::Red)
+        } else {
+            Style::default().fg(Color::Yellow)
+        };
+
+        let title = match &edit.scope {
+            DestinationScope::Item(_) => "Destination (Enter to apply, Esc to go back)",
+            DestinationScope::All(TransferDirection::Upload) => {
+                "Directory for all uploads (Enter to apply, Esc to go back)"
+            }
+            DestinationScope::All(TransferDirection::Download) => {
+                "Directory for all downloads (Enter to apply, Esc to go back)"
+            }
+        };
+        let input_box = Paragraph::new(lines.join("\n"))
+            .style(style)
+            .block(Block::default().borders(Borders::ALL).title(title));
+        f.render_widget(input_box, area);
+    }
+
+    fn draw_file_details(f: &mut Frame, file: &FileInfo) {
+        let area = Ui::centered_rect(70, 50, f.area());
+        f.render_widget(Clear, area);
+
+        let kind = match (&file.symlink_target, file.is_dir) {
+            (Some(_), true) => "symlink to a directory",
+            (Some(_), false) => "symlink",
+            (None, true) => "directory",
+            (None, false) => "file",
+        };
+        let owner = match file.owner_label() {
+            label if label.is_empty() => "unknown".to_string(),
+            label => label,
+        };
+        let mut rows = vec![
+            ("Name", file.name.clone()),
+            ("Path", file.path.display().to_string()),
+            ("Type", kind.to_string()),
+            (
+                "Size",
+                format!("{} bytes ({})", file.size, format_size(file.size)),
+            ),
+            (
+                "Mode",
+                format!(
+                    "{:04o} ({})",
+                    file.permissions & 0o7777,
+                    symbolic_permissions(file.permissions)
+                ),
+            ),
+            ("Owner", owner),
+            (
+                "Modified",
+                file.modified
+                    .map_or_else(|| "unknown".to_string(), format_timestamp),
+            ),
+        ];
+        if let Some(target) = &file.symlink_target {
+            rows.push(("Target", target.display().to_string()));
+        }
+
+        let text = rows
             .iter()
-            .map(|item| {
-                let direction = match item.direction {
-                    crate::app::TransferDirection::Upload => "",
-                    crate::app::TransferDirection::Download => "",
-                };
+            .map(|(label, value)| format!("{label:<9}{value}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let dialog = Paragraph::new(text).wrap(Wrap { trim: false }).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Details (Esc to close)"),
+        );
+        f.render_widget(dialog, area);
+    }
+
+    /// The pattern and replacement being typed, over a preview of every
+    /// marked name and what it becomes.
+    fn draw_bulk_rename(f: &mut Frame, rename: &BulkRename) {
+        let area = Ui::centered_rect(70, 60, f.area());
+        f.render_widget(Clear, area);
+
+        let field = |label: &str, value: &str, active: bool| {
+            let marker = if active { ">" } else { " " };
+            Line::styled(
+                format!("{marker} {label:<8} {value}"),
+                Style::default().fg(Color::Yellow),
+            )
+        };
+        let mut lines = vec![
+            field("Find:", &rename.find, !rename.editing_replace),
+            field("Replace:", &rename.replace, rename.editing_replace),
+        ];
+        if let Some(error) = &rename.error {
+            lines.push(Line::styled(
+                format!("Error: {error}"),
+                Style::default().fg(Color::Red),
+            ));
+        }
+        lines.push(Line::raw(""));
+
+        for entry in &rename.plan {
+            let (text, style) = match &entry.status {
+                RenameStatus::Rename => (
+                    format!("{} → {}", entry.from, entry.to),
+                    Style::default().fg(Color::Green),
+                ),
+                RenameStatus::Unchanged => (entry.from.clone(), Style::default()),
+                RenameStatus::Collision => (
+                    format!("{} → {} (exists, skipped)", entry.from, entry.to),
+                    Style::default().fg(Color::Red),
+                ),
+                RenameStatus::Invalid(reason) => (
+                    format!("{} → {} ({reason})", entry.from, entry.to),
+                    Style::default().fg(Color::Red),
+                ),
+            };
+            lines.push(Line::styled(text, style));
+        }
+
+        let dialog = Paragraph::new(lines).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Rename (Tab: switch field, Enter: rename, Esc: cancel)"),
+        );
+        f.render_widget(dialog, area);
+    }
+
+    fn draw_large_transfer_warning(f: &mut Frame, large: &LargeTransfer) {
+        let area = Ui::centered_rect(50, 20, f.area());
+        f.render_widget(Clear, area);
+
+        let total = match large.unsized_items {
+            0 => format!(
+                "This queue transfers {} in total.",
+                format_size(large.bytes)
+            ),
+            n => format!(
+                "This queue transfers {}, plus {n} items of unknown size.",
+                format_size(large.bytes)
+            ),
+        };
+        let warning = Paragraph::new(format!("{total}\n\nStart it anyway?"))
+            .style(Style::default().fg(Color::Red))
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title("Large Transfer (y: start, n/Esc: back)"),
+            );
+        f.render_widget(warning, area);
+    }
+
+    fn draw_host_key_prompt(f: &mut Frame, pending: &PendingHostKey) {
+        let area = Ui::centered_rect(70, 30, f.area());
+        f.render_widget(Clear, area);
+
+        let lines = [
+            format!("First connection to {}.", pending.address),
+            String::new(),
+            "Its host key fingerprint is:".to_string(),
+            format!("  {}", pending.fingerprint),
+            String::new(),
+            "Check it with the server's administrator before trusting it.".to_string(),
+            "If this key ever changes, connecting will be refused, not asked again.".to_string(),
+        ];
+        let prompt = Paragraph::new(lines.join("\n"))
+            .style(Style::default().fg(Color::Yellow))
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title("Unknown Host Key (y: trust and connect, n/Esc: cancel)"),
+            );
+        f.render_widget(prompt, area);
+    }
+
+    fn draw_connecting(f: &mut Frame, host: &str) {
+        let area = Ui::centered_rect(50, 20, f.area());
+        f.render_widget(Clear, area);
+
+        let message = Paragraph::new(format!("Connecting to {host}…"))
+            .style(Style::default().fg(Color::Yellow))
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title("Connecting (Esc to cancel)"),
+            );
+        f.render_widget(message, area);
+    }
+
+    fn draw_host_settings_prompt(f: &mut Frame, pending: &PendingHostSettings) {
+        let area = Ui::centered_rect(70, 40, f.area());
+        f.render_widget(Clear, area);
+
+        let prompt = Paragraph::new(host_settings_lines(pending).join("\n"))
+            .style(Style::default().fg(Color::Yellow))
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title("Connect With These Settings? (y/Enter: connect, n/Esc: back)"),
+            );
+        f.render_widget(prompt, area);
+    }
+
+    fn draw_deep_search(f: &mut Frame, deep_search: &DeepSearch) {
+        let area = Ui::centered_rect(80, 60, f.area());
+        f.render_widget(Clear, area);
+
+        let root = deep_search.root.display();
+        if deep_search.editing {
+            let input = Paragraph::new(format!("> {}", deep_search.query))
+                .style(Style::default().fg(Color::Yellow))
+                .block(Block::default().borders(Borders::ALL).title(format!(
+                    "Find under {root} (Enter to search, Esc to cancel)"
+                )));
+            f.render_widget(input, area);
+            return;
+        }
+
+        let results = deep_search
+            .results
+            .lock()
+            .map(|r| r.clone())
+            .unwrap_or_default();
+
+        let state = if !results.done {
+            format!("searching, {} dirs scanned", results.dirs_scanned)
+        } else if let Some(reason) = &results.stopped {
+            reason.clone()
+        } else {
+            "done".to_string()
+        };
+        let title = format!(
+            "Find '{}' under {root}: {} matches ({state}) Enter: go to, Esc: {}",
+            deep_search.query,
+            results.matches.len(),
+            if results.done { "close" } else { "stop" }
+        );
+
+        let items: Vec<ListItem> = results
+            .matches
+            .iter()
+            .map(|file| {
+                let path = search::relative_path(&deep_search.root, &file.path);
+                let suffix = if file.is_dir { "/" } else { "" };
+                ListItem::new(format!("{}{suffix}", path.display()))
+            })
+            .collect();
+
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(title)
+                    .border_style(Style::default().fg(Color::Yellow)),
+            )
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+            .highlight_symbol("> ");
+
+        let mut list_state = ListState::default();
+        list_state.select(Some(deep_search.cursor));
+        f.render_stateful_widget(list, area, &mut list_state);
+    }
+
+    fn draw_transfer_dialog(
+        f: &mut Frame,
+        transfer_queue: &[TransferItem],
+        queue_cursor: usize,
+        progress: Option<&TransferProgress>,
+        local_path: &Path,
+        remote_path: &Path,
+    ) {
+        let area = Ui::centered_rect(80, 30, f.area());
+
+        f.render_widget(Clear, area);
+
+        let (area, progress_area) = if progress.is_some() {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(0), Constraint::Length(6)])
+                .split(area);
+            (chunks[0], Some(chunks[1]))
+        } else {
+            (area, None)
+        };
+
+        // Borders, highlight symbol, checkbox, "move" and the arrow
+        let path_width = (area.width as usize).saturating_sub(20) / 2;
+
+        let mut items = Vec::new();
+        let mut cursor_row = None;
+        for (direction, indices) in queue_sections(transfer_queue) {
+            let (label, source_base, destination_base) = match direction {
+                TransferDirection::Upload => ("Uploads", local_path, remote_path),
+                TransferDirection::Download => ("Downloads", remote_path, local_path),
+            };
+            let enabled = indices
+                .iter()
+                .filter(|&&i| transfer_queue[i].enabled)
+                .count();
+            items.push(
+                ListItem::new(format!(
+                    "{label}: {} item(s), {enabled} to run",
+                    indices.len()
+                ))
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+            );
+
+            for index in indices {
+                let item = &transfer_queue[index];
+                if index == queue_cursor {
+                    cursor_row = Some(items.len());
+                }
+                let action = if item.remove_source { " move" } else { "" };
+                let checkbox = if item.enabled { "[x]" } else { "[ ]" };
                 let text = format!(
-                    "{} {} -> {}",
-                    direction,
-                    item.source.display(),
-                    item.destination.display()
+                    "  {}{} {} -> {}",
+                    checkbox,
+                    action,
+                    shorten_path(&item.source, source_base, path_width),
+                    shorten_path(&item.destination, destination_base, path_width)
                 );
-                ListItem::new(text)
+                let style = if item.enabled {
+                    Style::default()
+                } else {
+                    Style::default().fg(Color::DarkGray)
+                };
+                items.push(ListItem::new(text).style(style));
+            }
+        }
+
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(if progress.is_some() {
+                        "Transfer Queue (transferring... Esc: abort)"
+                    } else {
+                        "Transfer Queue (Enter: run, Space: toggle, d: remove, \
+                         e/E: destination, Esc: cancel)"
+                    }),
+            )
+            .style(Style::default().fg(Color::Yellow))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+            .highlight_symbol("> ");
+
+        // The cursor is only for editing, before the queue starts
+        let mut state = ListState::default();
+        if progress.is_none() {
+            state.select(cursor_row);
+        }
+        f.render_stateful_widget(list, area, &mut state);
+
+        if let (Some(progress), Some(progress_area)) = (progress, progress_area) {
+            Ui::draw_transfer_progress(f, progress_area, progress);
+        }
+    }
+
+    fn draw_transfer_summary(f: &mut Frame, results: &[TransferResult], cursor: usize) {
+        let area = Ui::centered_rect(80, 40, f.area());
+        f.render_widget(Clear, area);
+
+        let count = |matches: fn(&TransferOutcome) -> bool| {
+            results.iter().filter(|r| matches(&r.outcome)).count()
+        };
+        let done = count(|o| matches!(o, TransferOutcome::Done));
+        let failed = count(|o| matches!(o, TransferOutcome::Failed(_)));
+        let skipped = count(|o| matches!(o, TransferOutcome::Skipped(_)));
+        let cancelled = count(|o| matches!(o, TransferOutcome::Cancelled { .. }));
+
+        let items: Vec<ListItem> = results
+            .iter()
+            .map(|result| {
+                let source = result.item.source.display();
+                match &result.outcome {
+                    TransferOutcome::Done => match &result.hook {
+                        None => ListItem::new(format!("done    {source}"))
+                            .style(Style::default().fg(Color::Green)),
+                        Some(hook) => {
+                            let color = if *hook == HookStatus::Exited(0) {
+                                Color::Green
+                            } else {
+                                Color::Yellow
+                            };
+                            ListItem::new(format!("done    {source} ({})", hook.describe()))
+                                .style(Style::default().fg(color))
+                        }
+                    },
+                    TransferOutcome::Failed(error) => {
+                        ListItem::new(format!("failed  {source}: {error}"))
+                            .style(Style::default().fg(Color::Red))
+                    }
+                    TransferOutcome::Skipped(reason) => {
+                        ListItem::new(format!("skipped {source}: {reason}"))
+                            .style(Style::default().fg(Color::DarkGray))
+                    }
+                    TransferOutcome::Cancelled { partial: true } => ListItem::new(format!(
+                        "stopped {source}: {} is incomplete",
+                        result.item.destination.display()
+                    ))
+                    .style(Style::default().fg(Color::Yellow)),
+                    TransferOutcome::Cancelled { partial: false } => {
+                        ListItem::new(format!("stopped {source}: not started"))
+                            .style(Style::default().fg(Color::DarkGray))
+                    }
+                }
             })
             .collect();
 
+        let title = if cancelled > 0 {
+            format!(
+                "Transfers cancelled: {done} done, {cancelled} cancelled, {failed} failed (R: retry, Enter: close)"
+            )
+        } else if failed + skipped > 0 {
+            format!(
+                "Transfers: {done} done, {failed} failed, {skipped} skipped (R: retry, Enter: close)"
+            )
+        } else {
+            format!("Transfers: {done} done (Enter: close)")
+        };
+
         let list = List::new(items)
             .block(
                 Block::default()
                     .borders(Borders::ALL)
-                    .title("Transfer Queue (Enter to confirm, Esc to cancel)"),
+                    .title(title)
+                    .border_style(Style::default().fg(Color::Yellow)),
             )
-            .style(Style::default().fg(Color::Yellow));
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+            .highlight_symbol("> ");
+
+        let mut state = ListState::default();
+        state.select(Some(cursor));
+        f.render_stateful_widget(list, area, &mut state);
+    }
+
+    fn draw_transfer_progress(f: &mut Frame, area: Rect, progress: &TransferProgress) {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(3), Constraint::Length(3)])
+            .split(area);
+
+        let files = format!("{}/{} files", progress.items_finished, progress.total_items);
+        let mut overall_label = match progress.overall_ratio() {
+            Some(ratio) => format!("Overall: {:.0}% — {files}", ratio * 100.0),
+            None => format!("Overall: {files}"),
+        };
+        if progress.unsized_items > 0 {
+            overall_label = format!(
+                "{overall_label} ({} of unknown size)",
+                progress.unsized_items
+            );
+        }
+        if progress.active.len() > 1 {
+            overall_label = format!("{overall_label} at {}", format_speed(progress.speed()));
+        }
+        let overall = Gauge::default()
+            .block(Block::default().borders(Borders::ALL))
+            .gauge_style(Style::default().fg(Color::Blue))
+            .ratio(progress.overall_ratio().unwrap_or(0.0))
+            .label(overall_label);
+        f.render_widget(overall, chunks[0]);
+
+        // The longest-running item is shown in detail, the others counted
+        let current = progress.current();
+        let eta = current
+            .and_then(|item| item.eta())
+            .map(format_duration)
+            .unwrap_or_else(|| "--:--".to_string());
+        let mut title = match current {
+            Some(item) => match item.dir_files {
+                Some((done, files)) => format!(
+                    "{}/{}: uploading {}: {done}/{files} files",
+                    item.index + 1,
+                    progress.total_items,
+                    item.name
+                ),
+                None => format!("{}/{}: {}", item.index + 1, progress.total_items, item.name),
+            },
+            None => String::new(),
+        };
+        if progress.active.len() > 1 {
+            title = format!("{title} (+{} more running)", progress.active.len() - 1);
+        }
+        if let Some(status) = &progress.status {
+            title = format!("{title} - {status}");
+        }
+        let label = format!(
+            "{} (avg {}) | ETA {}",
+            format_speed(current.map_or(0.0, |item| item.speed())),
+            format_speed(current.map_or(0.0, |item| item.average_speed())),
+            eta
+        );
 
-        f.render_widget(list, area);
+        let gauge = Gauge::default()
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .gauge_style(Style::default().fg(Color::Green))
+            .ratio(current.and_then(|item| item.ratio()).unwrap_or(0.0))
+            .label(label);
+        f.render_widget(gauge, chunks[1]);
     }
 
     fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
@@ -346,6 +1253,24 @@ impl Ui {
             .split(popup_layout[1])[1]
     }
 
+    /// Gives the terminal to `run`, for example to launch an editor, and
+    /// takes it back afterwards. The interface is restored however `run`
+    /// ends, since a crashed editor can leave the terminal in any state.
+    pub fn suspend<T>(&mut self, run: impl FnOnce() -> T) -> Result<T> {
+        restore_terminal(self.terminal.backend_mut())?;
+        let result = run();
+
+        enable_raw_mode()?;
+        execute!(
+            self.terminal.backend_mut(),
+            EnterAlternateScreen,
+            EnableMouseCapture
+        )?;
+        // Whatever the program left on screen is unknown, so redraw it all
+        self.terminal.clear()?;
+        Ok(result)
+    }
+
     pub fn handle_events(&self) -> Result<Option<Event>> {
         if crossterm::event::poll(std::time::Duration::from_millis(100))? {
             let event = crossterm::event::read()?;
@@ -359,13 +1284,833 @@ impl Ui {
     }
 }
 
+/// Splits `area` in half for the local and remote panes: side by side, or
+/// local above remote when `vertical` is set.
+fn pane_areas(area: Rect, vertical: bool) -> [Rect; 2] {
+    let direction = if vertical {
+        Direction::Vertical
+    } else {
+        Direction::Horizontal
+    };
+    let panes = Layout::default()
+        .direction(direction)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+        .split(area);
+    [panes[0], panes[1]]
+}
+
+/// Columns taken by a grid entry's type marker and the space after it.
+const GRID_MARKER: usize = 2;
+/// Blank columns between grid entries.
+const GRID_GAP: usize = 2;
+
+/// Number of columns in the grid view: as many entries as fit side by side
+/// in a pane `width` columns wide (borders included), each as wide as the
+/// `longest` name. Always at least one.
+pub fn grid_columns(width: u16, longest: usize) -> usize {
+    let inner = usize::from(width).saturating_sub(2);
+    let cell = longest + GRID_MARKER;
+    // The last column needs no gap after it
+    ((inner + GRID_GAP) / (cell + GRID_GAP)).max(1)
+}
+
+/// The line to show in a pane whose listing has nothing but `..`: why it is
+/// empty, given the active search `query` if there is one.
+pub fn empty_placeholder(files: &[FileInfo], query: Option<&str>) -> Option<String> {
+    if files.iter().any(|file| file.name != "..") {
+        return None;
+    }
+    Some(match query {
+        Some(query) => format!("no matches for '{query}'"),
+        None => "(empty)".to_string(),
+    })
+}
+
+/// `ls -l` style permissions, e.g. `drwxr-xr-x`, including the setuid,
+/// setgid and sticky bits.
+fn symbolic_permissions(mode: u32) -> String {
+    let kind = match mode & 0o170000 {
+        0o040000 => 'd',
+        0o120000 => 'l',
+        0o020000 => 'c',
+        0o060000 => 'b',
+        0o010000 => 'p',
+        0o140000 => 's',
+        _ => '-',
+    };
+    let mut symbolic = String::from(kind);
+    for (shift, special, special_char) in [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')] {
+        let bits = (mode >> shift) & 0o7;
+        symbolic.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        symbolic.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        symbolic.push(match (bits & 0o1 != 0, mode & special != 0) {
+            (true, true) => special_char,
+            (false, true) => special_char.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    symbolic
+}
+
+/// `time` as `YYYY-MM-DD HH:MM:SS UTC`.
+fn format_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let (days, rest) = (secs / 86_400, secs % 86_400);
+
+    // Days since 1970-01-01 to a civil date, after Howard Hinnant's algorithm
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
+
+/// The remote pane title's note on free space, `n/a` when the server
+/// can't report it.
+fn space_label(space: Option<DiskSpace>) -> String {
+    match space {
+        Some(space) => format!(
+            "{} free of {}",
+            format_size(space.free),
+            format_size(space.total)
+        ),
+        None => "n/a".to_string(),
+    }
+}
+
+/// The queue's uploads, then its downloads, as indices into `queue` in queue
+/// order. Sections with nothing in them are left out.
+fn queue_sections(queue: &[TransferItem]) -> Vec<(TransferDirection, Vec<usize>)> {
+    [TransferDirection::Upload, TransferDirection::Download]
+        .into_iter()
+        .map(|direction| {
+            let indices: Vec<usize> = queue
+                .iter()
+                .enumerate()
+                .filter(|(_, item)| item.direction == direction)
+                .map(|(i, _)| i)
+                .collect();
+            (direction, indices)
+        })
+        .filter(|(_, indices)| !indices.is_empty())
+        .collect()
+}
+
+/// Lines a list pane has for entries, inside its borders.
+pub fn list_rows(area: Rect) -> usize {
+    usize::from(area.height).saturating_sub(2).max(1)
+}
+
+/// The entries a list of `len` shows in `rows` lines: from the top, until the
+/// cursor would fall off the bottom, then scrolled to keep it on the last line.
+pub fn visible_window(cursor: usize, len: usize, rows: usize) -> Range<usize> {
+    let start = (cursor + 1).saturating_sub(rows).min(len);
+    start..(start + rows).min(len)
+}
+
+/// What the footer hints at when there is nothing else to say, with the
+/// actions whose keys it lists for each.
+const FOOTER_HINTS: &[(&[Action], &str)] = &[
+    (&[Action::SwitchPane], "Switch panes"),
+    (&[Action::ToggleSelection], "Select/deselect"),
+    (&[Action::SelectAll], "Select all"),
+    (&[Action::Cancel], "Clear selection"),
+    (&[Action::Open, Action::Right], "Change directory"),
+    (&[Action::Parent, Action::Left], "Parent"),
+    (&[Action::Home], "Home"),
+    (&[Action::TransferCursor], "Transfer file"),
+    (&[Action::Transfer], "Transfer selection"),
+    (&[Action::Connect], "Change connection"),
+    (&[Action::Search], "Search"),
+    (&[Action::CycleTypeFilter], "Dirs/files only"),
+    (&[Action::FindInSubdirs], "Find in subdirs"),
+    (&[Action::ToggleHidden], "Hidden files"),
+    (&[Action::CompareFile], "Compare"),
+    (&[Action::Edit], "Edit"),
+    (&[Action::Info], "Info"),
+    (&[Action::ToggleGrid], "Grid"),
+    (&[Action::ToggleTree], "Tree"),
+    (&[Action::ToggleSplit], "Split"),
+    (&[Action::SwapPanes], "Swap panes"),
+    (&[Action::NewFile], "New file/dir"),
+    (&[Action::CompareDirs], "Diff dirs"),
+    (&[Action::FollowSymlinks], "Follow links"),
+    (&[Action::AbbreviatePaths], "Short paths"),
+    (&[Action::Rename], "Rename"),
+    (&[Action::Cut, Action::PasteCut], "Cut/Paste"),
+    (&[Action::Yank, Action::PasteCopy], "Copy/Paste"),
+    (&[Action::Duplicate], "Duplicate"),
+    (&[Action::Shell], "Shell"),
+    (&[Action::RevealLocal], "Reveal local"),
+    (&[Action::CopySshCommand], "Copy ssh command"),
+    (&[Action::NewTab, Action::CloseTab], "New/close tab"),
+    (&[Action::PreviousTab, Action::NextTab], "Switch tab"),
+    (&[Action::Quit], "Quit"),
+];
+
+/// The footer's key hints, naming the keys `keymap` has bound now. The grid
+/// view takes Left and Right for moving between columns, so they are only
+/// offered for directories outside it. A hint with no keys left is dropped.
+fn footer_hints(keymap: &Keymap, grid_view: bool) -> Vec<String> {
+    FOOTER_HINTS
+        .iter()
+        .filter_map(|(actions, label)| {
+            let keys: Vec<String> = actions
+                .iter()
+                .filter(|action| !(grid_view && matches!(action, Action::Left | Action::Right)))
+                .flat_map(|&action| {
+                    keymap
+                        .keys(action)
+                        .into_iter()
+                        .map(move |key| match action {
+                            // Copying takes two presses
+                            Action::Yank => format!("{key}{key}"),
+                            _ => key.to_string(),
+                        })
+                })
+                .collect();
+            (!keys.is_empty()).then(|| format!("{}: {label}", keys.join("/")))
+        })
+        .collect()
+}
+
+/// The footer's position among `/` search matches.
+fn match_counter(current: usize, count: usize) -> String {
+    if count == 0 {
+        "no matches".to_string()
+    } else {
+        format!("match {current} of {count}")
+    }
+}
+
+/// The footer's count of `/` search matches in each pane.
+fn pane_counts(local: usize, remote: usize) -> String {
+    format!("local: {local}, remote: {remote}")
+}
+
+/// The effective settings of a typed-in host, as shown before connecting.
+fn host_settings_lines(pending: &PendingHostSettings) -> Vec<String> {
+    let host = &pending.host;
+    let matched = if pending.matched.is_empty() {
+        "no ssh_config entries".to_string()
+    } else {
+        pending.matched.join(", ")
+    };
+    vec![
+        format!("Host:         {}", host.host),
+        format!(
+            "HostName:     {}",
+            host.hostname.as_deref().unwrap_or(&host.host)
+        ),
+        format!("User:         {}", host.user.as_deref().unwrap_or("(none)")),
+        format!("Port:         {}", host.port.unwrap_or(22)),
+        format!(
+            "IdentityFile: {}",
+            host.identity_file
+                .as_ref()
+                .map_or("(ssh-agent)".to_string(), |path| path.display().to_string())
+        ),
+        format!(
+            "ProxyJump:    {}",
+            host.proxy_jump.as_deref().unwrap_or("(none)")
+        ),
+        String::new(),
+        format!("Matched:      {matched}"),
+    ]
+}
+
+/// Colours an entry by whether the other pane has it, while directories are
+/// being compared.
+fn presence_style(comparison: Option<&HashMap<String, Presence>>, name: &str) -> Style {
+    let color = match comparison.and_then(|statuses| statuses.get(name)) {
+        None => return Style::default(),
+        Some(Presence::Both) => Color::Green,
+        Some(Presence::LocalOnly | Presence::RemoteOnly) => Color::Yellow,
+        Some(Presence::Different) => Color::Red,
+    };
+    Style::default().fg(color)
+}
+
+fn placeholder_style() -> Style {
+    Style::default()
+        .fg(Color::DarkGray)
+        .add_modifier(Modifier::ITALIC)
+}
+
+fn placeholder_item(text: &str) -> ListItem<'static> {
+    // Indented past the highlight symbol, in line with the entries
+    ListItem::new(format!("  {text}")).style(placeholder_style())
+}
+
+/// Length of the longest name in `files`, in characters.
+pub fn longest_name(files: &[FileInfo]) -> usize {
+    files
+        .iter()
+        .map(|file| file.name.chars().count())
+        .max()
+        .unwrap_or(0)
+}
+
+/// What kind of entry a pane line shows, for its type indicator.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FileKind {
+    Parent,
+    Directory,
+    Symlink,
+    Archive,
+    Image,
+    Code,
+    Other,
+}
+
+impl FileKind {
+    fn of(file: &FileInfo) -> Self {
+        if file.name == ".." {
+            return FileKind::Parent;
+        }
+        if file.is_symlink() {
+            return FileKind::Symlink;
+        }
+        if file.is_dir {
+            return FileKind::Directory;
+        }
+
+        let extension = Path::new(&file.name)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        match extension.as_str() {
+            "zip" | "tar" | "gz" | "tgz" | "bz2" | "xz" | "zst" | "7z" | "rar" => FileKind::Archive,
+            "png" | "jpg" | "jpeg" | "gif" | "bmp" | "svg" | "webp" | "ico" | "tiff" => {
+                FileKind::Image
+            }
+            "rs" | "py" | "js" | "ts" | "jsx" | "tsx" | "go" | "c" | "h" | "cpp" | "hpp"
+            | "java" | "kt" | "rb" | "php" | "sh" | "swift" | "lua" => FileKind::Code,
+            _ => FileKind::Other,
+        }
+    }
+
+    /// A one-column marker for the kind, in Unicode or plain ASCII.
+    fn indicator(self, ascii: bool) -> &'static str {
+        match (self, ascii) {
+            (FileKind::Parent, false) => "↑",
+            (FileKind::Parent, true) => "^",
+            (FileKind::Directory, false) => "▸",
+            (FileKind::Directory, true) => "/",
+            (FileKind::Symlink, false) => "↪",
+            (FileKind::Symlink, true) => "@",
+            (FileKind::Archive, false) => "▤",
+            (FileKind::Archive, true) => "#",
+            (FileKind::Image, false) => "▣",
+            (FileKind::Image, true) => "%",
+            (FileKind::Code, false) => "λ",
+            (FileKind::Code, true) => "*",
+            (FileKind::Other, _) => " ",
+        }
+    }
+}
+
+/// The fewest name characters worth keeping the owner column for.
+const MIN_NAME_WIDTH: usize = 12;
+
+/// A pane entry: the type indicator and name on the left and the owner
+/// column on the right, padded to `width` characters. Names too long for the
+/// line are cut in the middle; the owner is dropped when that would leave
+/// less than `MIN_NAME_WIDTH` of the name. Entries inside expanded tree
+/// directories are indented two spaces a level.
+fn file_line(file: &FileInfo, width: usize, ascii_icons: bool) -> String {
+    let indent = "  ".repeat(file.depth);
+    let width = width.saturating_sub(indent.len());
+    indent + &entry_line(file, width, ascii_icons)
+}
+
+fn entry_line(file: &FileInfo, width: usize, ascii_icons: bool) -> String {
+    let indicator = FileKind::of(file).indicator(ascii_icons);
+    let owner = file.owner_label();
+    // The indicator and its space, and the gap before the owner
+    let name_width = file.name.chars().count();
+    let owner_budget = width.saturating_sub(3 + owner.chars().count());
+    if owner.is_empty() || owner_budget < name_width.min(MIN_NAME_WIDTH) {
+        let budget = width.saturating_sub(2);
+        return format!("{indicator} {}", truncate_middle(&file.name, budget));
+    }
+
+    let name = truncate_middle(&file.name, owner_budget);
+    let padding = owner_budget - name.chars().count() + 1;
+    format!("{indicator} {name}{}{owner}", " ".repeat(padding))
+}
+
+/// Cuts characters out of the middle of `name` so it fits `max_width`,
+/// marking the cut with `…`. The start gets the extra character when the
+/// rest doesn't split evenly.
+fn truncate_middle(name: &str, max_width: usize) -> String {
+    let length = name.chars().count();
+    if length <= max_width {
+        return name.to_string();
+    }
+    if max_width == 0 {
+        return String::new();
+    }
+
+    let keep = max_width - 1;
+    let tail = keep / 2;
+    let head = keep - tail;
+    let start: String = name.chars().take(head).collect();
+    let end: String = name.chars().skip(length - tail).collect();
+    format!("{start}…{end}")
+}
+
+/// Shortens `path` for a pane title: `home` and what's inside it are shown
+/// from `~`, and everything between the root (or `~`) and the last
+/// directory is collapsed to `…`, e.g. `~/…/project`.
+fn abbreviate_path(path: &Path, home: Option<&Path>) -> String {
+    let (prefix, rest) = match home.and_then(|home| path.strip_prefix(home).ok()) {
+        Some(rest) => ("~", rest),
+        None => match path.strip_prefix("/") {
+            Ok(rest) => ("", rest),
+            Err(_) => (".", path),
+        },
+    };
+    let names: Vec<_> = rest.iter().map(|name| name.to_string_lossy()).collect();
+    match names.as_slice() {
+        [] if prefix.is_empty() => "/".to_string(),
+        [] => prefix.to_string(),
+        [name] => format!("{prefix}/{name}"),
+        [.., last] => format!("{prefix}/…/{last}"),
+    }
+}
+
+/// Shows `path` relative to `base` when it lies inside it, then cuts leading
+/// directories (and if need be the start of the name) to fit `max_width`
+/// characters, marking the cut with `…`.
+fn shorten_path(path: &Path, base: &Path, max_width: usize) -> String {
+    let shown = match path.strip_prefix(base) {
+        Ok(relative) if !relative.as_os_str().is_empty() => relative,
+        _ => path,
+    };
+    let shown = shown.to_string_lossy();
+    if shown.chars().count() <= max_width {
+        return shown.into_owned();
+    }
+
+    // Drop whole leading directories first, so the name stays readable
+    let mut rest = shown.as_ref();
+    while let Some((_, tail)) = rest.split_once('/') {
+        rest = tail;
+        if rest.chars().count() + 2 <= max_width {
+            return format!("…/{rest}");
+        }
+    }
+
+    let keep = max_width.saturating_sub(1);
+    let skip = rest.chars().count().saturating_sub(keep);
+    format!("…{}", rest.chars().skip(skip).collect::<String>())
+}
+
 impl Drop for Ui {
     fn drop(&mut self) {
-        let _ = disable_raw_mode();
-        let _ = execute!(
-            self.terminal.backend_mut(),
-            LeaveAlternateScreen,
-            DisableMouseCapture
+        let _ = restore_terminal(self.terminal.backend_mut());
+    }
+}
+
+/// Hands the terminal back to the shell: raw mode off, main screen, mouse
+/// released and cursor shown.
+fn restore_terminal(backend: &mut CrosstermBackend<io::Stdout>) -> io::Result<()> {
+    // Keep going after a failure so as much as possible is restored
+    let raw = disable_raw_mode();
+    let screen = execute!(
+        backend,
+        LeaveAlternateScreen,
+        DisableMouseCapture,
+        cursor::Show
+    );
+    raw.and(screen)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use std::time::Duration;
+
+    #[test]
+    fn test_file_line_owner_column() {
+        let file = FileInfo {
+            name: "app.log".to_string(),
+            uid: Some(1000),
+            gid: Some(50),
+            ..FileInfo::default()
+        };
+        assert_eq!(file_line(&file, 20, false), "  app.log    1000:50");
+        assert_eq!(file_line(&file, 20, false).chars().count(), 20);
+        // Too narrow for both: the name wins
+        assert_eq!(file_line(&file, 12, false), "  app.log");
+
+        let parent = FileInfo {
+            name: "..".to_string(),
+            is_dir: true,
+            ..FileInfo::default()
+        };
+        assert_eq!(file_line(&parent, 20, false), "↑ ..");
+        assert_eq!(file_line(&parent, 20, true), "^ ..");
+    }
+
+    #[test]
+    fn test_file_line_long_name() {
+        let file = FileInfo {
+            name: "quarterly-report-2024-final.pdf".to_string(),
+            uid: Some(1000),
+            gid: Some(50),
+            ..FileInfo::default()
+        };
+        // Room for the owner and a cut-down name
+        let line = file_line(&file, 30, false);
+        assert_eq!(line, "  quarterly-…final.pdf 1000:50");
+        assert_eq!(line.chars().count(), 30);
+        // Too little: the owner goes and the name gets the whole line
+        assert_eq!(file_line(&file, 20, false), "  quarterly…inal.pdf");
+        assert_eq!(file_line(&file, 40, false).chars().count(), 40);
+    }
+
+    #[test]
+    fn test_truncate_middle() {
+        assert_eq!(truncate_middle("short.txt", 20), "short.txt");
+        assert_eq!(truncate_middle("short.txt", 9), "short.txt");
+        assert_eq!(
+            truncate_middle("verylongprefix-suffix.txt", 22),
+            "verylongpre…suffix.txt"
+        );
+        assert_eq!(
+            truncate_middle("verylongprefix-suffix.txt", 21),
+            "verylongpr…suffix.txt"
+        );
+        assert_eq!(truncate_middle("abcdef", 5), "ab…ef");
+        assert_eq!(truncate_middle("abcdef", 4), "ab…f");
+        assert_eq!(truncate_middle("abcdef", 1), "…");
+        assert_eq!(truncate_middle("abcdef", 0), "");
+        // Counted in characters, not bytes
+        assert_eq!(truncate_middle("日本語のファイル名", 5), "日本…ル名");
+    }
+
+    #[test]
+    fn test_pane_areas() {
+        let area = Rect::new(0, 0, 80, 40);
+
+        let [local, remote] = pane_areas(area, false);
+        assert_eq!(local, Rect::new(0, 0, 40, 40));
+        assert_eq!(remote, Rect::new(40, 0, 40, 40));
+
+        let [local, remote] = pane_areas(area, true);
+        assert_eq!(local, Rect::new(0, 0, 80, 20));
+        assert_eq!(remote, Rect::new(0, 20, 80, 20));
+    }
+
+    #[test]
+    fn test_grid_columns() {
+        // 40 columns inside the borders hold three 8-character names (10
+        // with their markers) with 2-column gaps, but not four
+        assert_eq!(grid_columns(42, 8), 3);
+        assert_eq!(grid_columns(48, 8), 4);
+        assert_eq!(grid_columns(47, 8), 3);
+        // One long name takes the whole pane
+        assert_eq!(grid_columns(42, 100), 1);
+        assert_eq!(grid_columns(0, 8), 1);
+        assert_eq!(grid_columns(80, 0), 20);
+    }
+
+    #[test]
+    fn test_symbolic_permissions() {
+        assert_eq!(symbolic_permissions(0o100644), "-rw-r--r--");
+        assert_eq!(symbolic_permissions(0o040755), "drwxr-xr-x");
+        assert_eq!(symbolic_permissions(0o120777), "lrwxrwxrwx");
+        assert_eq!(symbolic_permissions(0o104755), "-rwsr-xr-x");
+        assert_eq!(symbolic_permissions(0o041777), "drwxrwxrwt");
+        assert_eq!(symbolic_permissions(0o102644), "-rw-r-Sr--");
+        // No type bits, as some servers send
+        assert_eq!(symbolic_permissions(0o600), "-rw-------");
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        assert_eq!(format_timestamp(at(0)), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_timestamp(at(951_782_400)), "2000-02-29 00:00:00 UTC");
+        assert_eq!(
+            format_timestamp(at(1_700_000_000)),
+            "2023-11-14 22:13:20 UTC"
+        );
+        assert_eq!(
+            format_timestamp(at(4_107_542_399)),
+            "2100-02-28 23:59:59 UTC"
+        );
+    }
+
+    #[test]
+    fn test_space_label() {
+        let space = DiskSpace {
+            free: 3 * 1024 * 1024 * 1024 / 2,
+            total: 100 * 1024 * 1024 * 1024,
+        };
+        assert_eq!(
+            space_label(Some(space)),
+            format!(
+                "{} free of {}",
+                format_size(space.free),
+                format_size(space.total)
+            )
+        );
+        assert_eq!(space_label(Some(space)), "1.5 GB free of 100.0 GB");
+        assert_eq!(space_label(None), "n/a");
+    }
+
+    #[test]
+    fn test_footer_hints_in_grid_view() {
+        let keymap = Keymap::default();
+        let flat = footer_hints(&keymap, false);
+        assert!(flat.contains(&"Enter/→/l: Change directory".to_string()));
+        assert!(flat.contains(&"Backspace/←/h: Parent".to_string()));
+        assert!(flat.contains(&"yy/p: Copy/Paste".to_string()));
+
+        let grid = footer_hints(&keymap, true);
+        assert!(grid.contains(&"Enter: Change directory".to_string()));
+        assert!(grid.contains(&"Backspace: Parent".to_string()));
+        assert_eq!(grid.len(), flat.len());
+    }
+
+    #[test]
+    fn test_footer_hints_follow_remapped_keys() -> Result<()> {
+        let config: AppConfig = toml::from_str(
+            r#"
+[keys]
+quit = ["ctrl+q"]
+select_all = ["X"]
+"#,
+        )?;
+        let hints = footer_hints(&Keymap::new(&config.keys), false);
+
+        assert!(hints.contains(&"ctrl+q: Quit".to_string()));
+        assert!(hints.contains(&"X: Select all".to_string()));
+        // Cut lost its key to select all
+        assert!(hints.contains(&"P: Cut/Paste".to_string()));
+        assert!(!hints.iter().any(|hint| hint.starts_with("q/Q")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_footer_hints_use_each_action_once() {
+        let mut seen = HashSet::new();
+        for (actions, label) in FOOTER_HINTS {
+            for action in *actions {
+                assert!(
+                    seen.insert(action),
+                    "{action:?} is hinted twice, again at {label}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_match_counter() {
+        assert_eq!(match_counter(2, 5), "match 2 of 5");
+        assert_eq!(match_counter(0, 0), "no matches");
+        assert_eq!(pane_counts(3, 5), "local: 3, remote: 5");
+    }
+
+    #[test]
+    fn test_queue_sections() {
+        let item = |name: &str, direction: TransferDirection| TransferItem {
+            source: name.into(),
+            destination: name.into(),
+            direction,
+            remove_source: false,
+            enabled: true,
+        };
+        let queue = [
+            item("a", TransferDirection::Download),
+            item("b", TransferDirection::Upload),
+            item("c", TransferDirection::Download),
+            item("d", TransferDirection::Upload),
+        ];
+
+        assert_eq!(
+            queue_sections(&queue),
+            [
+                (TransferDirection::Upload, vec![1, 3]),
+                (TransferDirection::Download, vec![0, 2]),
+            ]
+        );
+        assert_eq!(
+            queue_sections(&queue[..1]),
+            [(TransferDirection::Download, vec![0])]
+        );
+        assert!(queue_sections(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_visible_window() {
+        assert_eq!(visible_window(0, 100, 10), 0..10);
+        assert_eq!(visible_window(9, 100, 10), 0..10);
+        // Past the last line the cursor stays on it
+        assert_eq!(visible_window(10, 100, 10), 1..11);
+        assert_eq!(visible_window(99, 100, 10), 90..100);
+        assert_eq!(visible_window(2, 3, 10), 0..3);
+        assert_eq!(visible_window(0, 0, 10), 0..0);
+    }
+
+    #[test]
+    fn test_empty_placeholder() {
+        let file = |name: &str| FileInfo {
+            name: name.to_string(),
+            ..FileInfo::default()
+        };
+
+        assert_eq!(empty_placeholder(&[], None).as_deref(), Some("(empty)"));
+        assert_eq!(
+            empty_placeholder(&[file("..")], None).as_deref(),
+            Some("(empty)")
+        );
+        assert_eq!(
+            empty_placeholder(&[file("..")], Some("xyz")).as_deref(),
+            Some("no matches for 'xyz'")
+        );
+        assert_eq!(empty_placeholder(&[file(".."), file("a")], None), None);
+        assert_eq!(empty_placeholder(&[file("a")], Some("a")), None);
+    }
+
+    #[test]
+    fn test_longest_name() {
+        let file = |name: &str| FileInfo {
+            name: name.to_string(),
+            ..FileInfo::default()
+        };
+        assert_eq!(longest_name(&[]), 0);
+        assert_eq!(
+            longest_name(&[file(".."), file("résumé.pdf"), file("a")]),
+            10
+        );
+    }
+
+    #[test]
+    fn test_file_kind() {
+        let file = |name: &str, is_dir: bool| FileInfo {
+            name: name.to_string(),
+            is_dir,
+            ..FileInfo::default()
+        };
+        assert_eq!(FileKind::of(&file("..", true)), FileKind::Parent);
+        assert_eq!(FileKind::of(&file("src", true)), FileKind::Directory);
+        // A directory's name doesn't make it an archive
+        assert_eq!(FileKind::of(&file("backup.tar", true)), FileKind::Directory);
+        assert_eq!(
+            FileKind::of(&file("backup.tar.gz", false)),
+            FileKind::Archive
+        );
+        assert_eq!(FileKind::of(&file("Photo.JPG", false)), FileKind::Image);
+        assert_eq!(FileKind::of(&file("main.rs", false)), FileKind::Code);
+        assert_eq!(FileKind::of(&file("notes.txt", false)), FileKind::Other);
+        assert_eq!(FileKind::of(&file("Makefile", false)), FileKind::Other);
+        assert_eq!(FileKind::of(&file(".gz", false)), FileKind::Other);
+        let link = FileInfo {
+            symlink_target: Some("/srv/www".into()),
+            ..file("www", true)
+        };
+        assert_eq!(FileKind::of(&link), FileKind::Symlink);
+    }
+
+    #[test]
+    fn test_indicators() {
+        let kinds = [
+            FileKind::Parent,
+            FileKind::Directory,
+            FileKind::Symlink,
+            FileKind::Archive,
+            FileKind::Image,
+            FileKind::Code,
+            FileKind::Other,
+        ];
+        for kind in kinds {
+            // Every indicator takes one column, and the fallback is ASCII
+            assert_eq!(kind.indicator(false).chars().count(), 1);
+            assert!(kind.indicator(true).is_ascii());
+        }
+        assert_eq!(FileKind::Directory.indicator(true), "/");
+        assert_eq!(FileKind::Other.indicator(false), " ");
+    }
+
+    #[test]
+    fn test_abbreviate_path() {
+        let home = Some(Path::new("/home/alice"));
+        assert_eq!(abbreviate_path(Path::new("/home/alice"), home), "~");
+        assert_eq!(abbreviate_path(Path::new("/home/alice/src"), home), "~/src");
+        assert_eq!(
+            abbreviate_path(Path::new("/home/alice/src/rust/project"), home),
+            "~/…/project"
+        );
+        assert_eq!(abbreviate_path(Path::new("/home/bob/src"), home), "/…/src");
+        assert_eq!(abbreviate_path(Path::new("/"), home), "/");
+        assert_eq!(abbreviate_path(Path::new("/etc"), None), "/etc");
+        assert_eq!(
+            abbreviate_path(Path::new("/var/log/nginx"), None),
+            "/…/nginx"
+        );
+    }
+
+    #[test]
+    fn test_shorten_path_relative() {
+        assert_eq!(
+            shorten_path(Path::new("/home/me/src/main.rs"), Path::new("/home/me"), 40),
+            "src/main.rs"
+        );
+        // Paths outside the base stay absolute
+        assert_eq!(
+            shorten_path(Path::new("/srv/app.log"), Path::new("/home/me"), 40),
+            "/srv/app.log"
+        );
+    }
+
+    #[test]
+    fn test_shorten_path_already_short() {
+        assert_eq!(
+            shorten_path(Path::new("/srv/a.txt"), Path::new("/"), 40),
+            "srv/a.txt"
+        );
+        assert_eq!(
+            shorten_path(Path::new("/srv"), Path::new("/srv"), 40),
+            "/srv"
+        );
+    }
+
+    #[test]
+    fn test_shorten_path_ellipsis() {
+        let path = Path::new("/data/projects/client/releases/2024/build.tar.gz");
+        assert_eq!(
+            shorten_path(path, Path::new("/home"), 20),
+            "…/2024/build.tar.gz"
+        );
+        assert_eq!(shorten_path(path, Path::new("/home"), 10), "…ld.tar.gz");
+        assert_eq!(
+            shorten_path(path, Path::new("/home"), 10).chars().count(),
+            10
         );
     }
 }
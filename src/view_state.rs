@@ -0,0 +1,70 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::store::{Persist, Stored};
+
+/// View settings remembered from the last session, stored in
+/// `<config dir>/sftui/view_state.toml`. Anything never changed is left out
+/// so `config.toml` still decides it. Only the hidden-file toggle is kept:
+/// listings have no sort mode or order to change at runtime.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ViewState {
+    pub show_hidden: Option<bool>,
+}
+
+impl ViewState {
+    pub fn load() -> Stored<Self> {
+        Stored::load("view_state.toml")
+    }
+}
+
+impl Persist for ViewState {
+    fn parse(content: &str) -> Self {
+        toml::from_str(content).unwrap_or_default()
+    }
+
+    fn serialize(&self) -> Result<String> {
+        toml::to_string(self).context("Can't serialize view state")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_save_and_load() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("sftui").join("view_state.toml");
+
+        let mut state = Stored::<ViewState>::from_path(&path);
+        assert_eq!(state.show_hidden, None);
+        state.show_hidden = Some(true);
+        state.save()?;
+        assert_eq!(
+            Stored::<ViewState>::from_path(&path).show_hidden,
+            Some(true)
+        );
+
+        state.show_hidden = Some(false);
+        state.save()?;
+        assert_eq!(
+            Stored::<ViewState>::from_path(&path).show_hidden,
+            Some(false)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unreadable_state_is_ignored() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("view_state.toml");
+        fs::write(&path, "show_hidden = \"sometimes\"")?;
+
+        assert_eq!(Stored::<ViewState>::from_path(&path).show_hidden, None);
+
+        Ok(())
+    }
+}
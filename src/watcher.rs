@@ -0,0 +1,138 @@
+use anyhow::Result;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a directory has to stay quiet before it is relisted, so a build
+/// writing many files causes one refresh rather than hundreds.
+pub const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Watches the local pane's directory for changes made outside sftui.
+pub struct LocalWatcher {
+    watcher: RecommendedWatcher,
+    path: Option<PathBuf>,
+    last_change: Arc<Mutex<Option<Instant>>>,
+}
+
+impl LocalWatcher {
+    pub fn new() -> Result<Self> {
+        let last_change = Arc::new(Mutex::new(None));
+        let handler_change = Arc::clone(&last_change);
+
+        let watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            // Reading the directory to relist it must not count as a change
+            if let Ok(event) = event
+                && !matches!(event.kind, EventKind::Access(_))
+                && let Ok(mut last) = handler_change.lock()
+            {
+                *last = Some(Instant::now());
+            }
+        })?;
+
+        Ok(LocalWatcher {
+            watcher,
+            path: None,
+            last_change,
+        })
+    }
+
+    /// Watches `path` instead of the previous directory. Does nothing if it is
+    /// already being watched.
+    pub fn watch(&mut self, path: &Path) -> Result<()> {
+        if self.path.as_deref() == Some(path) {
+            return Ok(());
+        }
+
+        if let Some(old) = self.path.take() {
+            // The old directory may be gone already
+            let _ = self.watcher.unwatch(&old);
+        }
+        // Remember the path even if watching fails, so it isn't retried on
+        // every tick
+        self.path = Some(path.to_path_buf());
+        if let Ok(mut last) = self.last_change.lock() {
+            *last = None;
+        }
+
+        self.watcher.watch(path, RecursiveMode::NonRecursive)?;
+        Ok(())
+    }
+
+    /// Returns true, once, when the directory changed and has been quiet for
+    /// at least `debounce` since.
+    pub fn take_change(&self, debounce: Duration) -> bool {
+        let Ok(mut last) = self.last_change.lock() else {
+            return false;
+        };
+
+        match *last {
+            Some(at) if at.elapsed() >= debounce => {
+                *last = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::thread;
+
+    fn wait_for_change(watcher: &LocalWatcher) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if watcher.take_change(Duration::ZERO) {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        false
+    }
+
+    #[test]
+    fn test_reports_changes_in_watched_directory() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut watcher = LocalWatcher::new()?;
+        watcher.watch(dir.path())?;
+        assert!(!watcher.take_change(Duration::ZERO));
+
+        fs::write(dir.path().join("new.txt"), "hi")?;
+        assert!(wait_for_change(&watcher));
+
+        // Each change is reported once
+        assert!(!watcher.take_change(Duration::ZERO));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_change_waits_for_debounce() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut watcher = LocalWatcher::new()?;
+        watcher.watch(dir.path())?;
+
+        fs::write(dir.path().join("new.txt"), "hi")?;
+        thread::sleep(Duration::from_millis(200));
+        assert!(!watcher.take_change(Duration::from_secs(60)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_follows_new_directory() -> Result<()> {
+        let first = tempfile::tempdir()?;
+        let second = tempfile::tempdir()?;
+        let mut watcher = LocalWatcher::new()?;
+        watcher.watch(first.path())?;
+        watcher.watch(second.path())?;
+
+        fs::write(second.path().join("new.txt"), "hi")?;
+        assert!(wait_for_change(&watcher));
+
+        Ok(())
+    }
+}